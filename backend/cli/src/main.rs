@@ -0,0 +1,83 @@
+use std::path::PathBuf;
+
+use anyhow::{bail, Result};
+use clap::{Parser, Subcommand};
+use shared::{
+    epubcheck::check_file,
+    settings::{default_user_file, Settings, SettingsLoader},
+};
+
+#[derive(Debug, Parser)]
+#[command(author, version, about, long_about = None)]
+struct Cli {
+    /// User settings file, defaults to the one in the user config dir
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// Project dir which may contain a `wbook.toml`, defaults to the current dir
+    #[arg(long, global = true)]
+    project: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Inspect the layered settings
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    /// Check an EPUB file for conformance issues, exit with an error if it has errors
+    Check { file: PathBuf },
+}
+
+#[derive(Debug, Subcommand)]
+enum ConfigCommand {
+    /// Print the effective settings as toml
+    Show,
+    /// Validate the settings, exit with an error if they are invalid
+    Check,
+}
+
+impl Cli {
+    fn settings(&self) -> Result<Settings> {
+        let project = match &self.project {
+            Some(dir) => dir.clone(),
+            None => std::env::current_dir()?,
+        };
+        let settings = SettingsLoader::new()
+            .user_file(self.config.clone().or_else(default_user_file))
+            .project_dir(&project)
+            .load()?;
+        Ok(settings)
+    }
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    match &cli.command {
+        Command::Config(ConfigCommand::Show) => {
+            print!("{}", toml::to_string_pretty(&cli.settings()?)?);
+        }
+        Command::Config(ConfigCommand::Check) => {
+            cli.settings()?;
+            println!("settings are valid");
+        }
+        Command::Check { file } => {
+            let report = check_file(file)?;
+            for issue in report.issues.iter() {
+                let file = issue.file.as_deref().unwrap_or("-");
+                println!(
+                    "{:?} [{:?}] {}: {}",
+                    issue.severity, issue.category, file, issue.message
+                );
+            }
+            let errors = report.errors().count();
+            if errors > 0 {
+                bail!("{} error(s) found in {} entries", errors, report.files);
+            }
+            println!("no errors found in {} entries", report.files);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,92 @@
+// The server listens on localhost, which every local process and every web page in a browser could reach.
+// So every request must carry the secret of this launch, which only the app knows, and CORS only allows
+// the app's own origins.
+
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderValue, Method},
+    middleware::Next,
+    response::Response,
+};
+use rand::RngCore;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+use crate::{error::ApiError, state::AppState};
+
+/// Query parameter carrying the token, for websocket upgrades and links which can't set headers.
+pub const TOKEN_QUERY: &str = "token";
+
+#[derive(Debug, Clone)]
+pub struct Auth {
+    pub token: String,
+    pub origins: Vec<String>, // e.g. `tauri://localhost`, without trailing slash
+}
+
+impl Auth {
+    /// Generate a new random token, which should be used for a single launch only.
+    pub fn new(origins: Vec<String>) -> Self {
+        let mut bytes = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        Auth {
+            token: bytes.iter().map(|x| format!("{:02x}", x)).collect(),
+            origins,
+        }
+    }
+
+    /// Whether the request carries the token, in the `Authorization: Bearer` header or the `token` query.
+    pub fn verify(&self, request: &Request) -> bool {
+        let bearer = request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|x| x.to_str().ok())
+            .and_then(|x| x.strip_prefix("Bearer "));
+        let query = request.uri().query().and_then(|query| {
+            query
+                .split('&')
+                .filter_map(|x| x.split_once('='))
+                .find(|(key, _)| *key == TOKEN_QUERY)
+                .map(|(_, value)| value)
+        });
+        bearer
+            .or(query)
+            .is_some_and(|x| constant_time_eq(x.as_bytes(), self.token.as_bytes()))
+    }
+
+    /// CORS allowing the app's origins only.
+    pub fn cors(&self) -> CorsLayer {
+        let origins: Vec<HeaderValue> = self
+            .origins
+            .iter()
+            .filter_map(|x| HeaderValue::from_str(x).ok())
+            .collect();
+        CorsLayer::new()
+            .allow_origin(AllowOrigin::list(origins))
+            .allow_headers([header::AUTHORIZATION, header::CONTENT_TYPE])
+            .allow_methods(vec![
+                Method::GET,
+                Method::POST,
+                Method::PUT,
+                Method::PATCH,
+                Method::DELETE,
+            ])
+    }
+}
+
+///
+/// Reject requests without the token, including websocket upgrades.
+///
+pub async fn require_token(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    if !state.auth.verify(&request) {
+        return Err(ApiError::Unauthorized);
+    }
+    Ok(next.run(request).await)
+}
+
+/// Compare without short-circuiting, so the token could not be guessed by timing.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
@@ -0,0 +1,554 @@
+use std::path::Path;
+
+use axum::{
+    body::Bytes,
+    extract::{DefaultBodyLimit, Path as UrlPath, Query, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+    routing::{delete, get, post},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use shared::{
+    convert::Variant,
+    detect::{Candidate, Heading},
+    document::{decode, file_title, Document, PatchPreview, ReimportReport, SourceText},
+    export::{export, BookMeta, Format},
+    images::{ImageReport, ImageResolver},
+    lint::{LintReport, Linter},
+    notes::{Annotator, NoteReport},
+    search::{ReplaceReport, SearchQuery, SearchResult, Searcher},
+    settings::ExportSettings,
+    stats::BookStats,
+    toc::{JSONNode, JSONRoot, TocEdit, TocError},
+};
+use uuid::Uuid;
+
+use crate::{
+    error::ApiError,
+    state::{AppState, StoredDocument},
+};
+
+/// Web novels could be tens of megabytes, far beyond the default body limit.
+const MAX_UPLOAD_SIZE: usize = 256 * 1024 * 1024;
+
+///
+/// Routes for documents, nested under `/documents`.
+///
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route(
+            "/",
+            post(upload).layer(DefaultBodyLimit::max(MAX_UPLOAD_SIZE)),
+        )
+        .route(
+            "/combine",
+            post(combine).layer(DefaultBodyLimit::max(MAX_UPLOAD_SIZE)),
+        )
+        .route("/:id", delete(remove))
+        .route("/:id/detect", post(detect))
+        .route("/:id/detect/preview", get(preview_detection))
+        .route("/:id/detect/accept", post(accept_headings))
+        .route(
+            "/:id/reimport",
+            post(reimport).layer(DefaultBodyLimit::max(MAX_UPLOAD_SIZE)),
+        )
+        .route("/:id/toc", get(get_toc).patch(patch_toc))
+        .route("/:id/toc/uid/:uid", get(get_node_by_uid))
+        .route("/:id/toc/:node/text", get(get_text).put(edit_text))
+        .route("/:id/toc/:node/hunks/:index", delete(revert_hunk))
+        .route("/:id/notes", get(check_notes))
+        .route("/:id/images", get(check_images))
+        .route("/:id/lint", get(lint_toc))
+        .route("/:id/stats", get(book_stats))
+        .route("/:id/search", post(search_text))
+        .route("/:id/replace", post(replace_text))
+        .route("/:id/epub", get(download_epub))
+        .route("/:id/export", get(download))
+}
+
+#[derive(Debug, Deserialize)]
+struct UploadParams {
+    name: Option<String>, // file name of the upload, used as the book title
+    detect: Option<bool>, // run detection right after upload, default to true
+}
+
+#[derive(Debug, Serialize)]
+struct DocumentResponse {
+    id: Uuid,
+    title: String,
+    toc: JSONRoot,
+}
+
+///
+/// Upload a text file as the raw request body, returns the new document.
+///
+async fn upload(
+    State(state): State<AppState>,
+    Query(params): Query<UploadParams>,
+    body: Bytes,
+) -> Result<impl IntoResponse, ApiError> {
+    let title = params
+        .name
+        .as_deref()
+        .and_then(|x| Path::new(x).file_stem())
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_else(|| "Untitled".to_string());
+    let settings = state.settings.clone();
+    let document = blocking(move || {
+        let mut document = Document::new(decode(&body));
+        if params.detect.unwrap_or(true) {
+            document.detect(&settings.detection)?;
+        }
+        Ok(document)
+    })
+    .await?;
+    Ok((
+        StatusCode::CREATED,
+        Json(insert(&state, title, document).await),
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+struct CombineParams {
+    title: Option<String>, // default to the title of the first file
+    files: Vec<CombineFile>,
+    detect: Option<bool>, // run detection right after upload, default to true
+}
+
+#[derive(Debug, Deserialize)]
+struct CombineFile {
+    name: String, // file name, used as the volume title
+    text: String,
+}
+
+///
+/// Upload several texts as volumes of one book, in order. Returns the new document.
+///
+async fn combine(
+    State(state): State<AppState>,
+    Json(params): Json<CombineParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    let texts: Vec<_> = params
+        .files
+        .into_iter()
+        .map(|x| SourceText {
+            title: file_title(Path::new(&x.name)),
+            path: None,
+            text: x.text,
+        })
+        .collect();
+    let title = params
+        .title
+        .or_else(|| texts.first().map(|x| x.title.clone()))
+        .unwrap_or_else(|| "Untitled".to_string());
+    let settings = state.settings.clone();
+    let document = blocking(move || {
+        let mut document = Document::combine(texts)?;
+        if params.detect.unwrap_or(true) {
+            document.detect(&settings.detection)?;
+        }
+        Ok(document)
+    })
+    .await?;
+    Ok((
+        StatusCode::CREATED,
+        Json(insert(&state, title, document).await),
+    ))
+}
+
+async fn insert(state: &AppState, title: String, document: Document) -> DocumentResponse {
+    let id = Uuid::new_v4();
+    let response = DocumentResponse {
+        id,
+        title: title.clone(),
+        toc: JSONRoot::from(&document.toc),
+    };
+    state.documents.write().await.insert(
+        id,
+        StoredDocument {
+            title,
+            document,
+            revision: 0,
+        },
+    );
+    state.emit("document.created", json!({ "id": id }));
+    response
+}
+
+///
+/// Run CPU-bound work, e.g. detection, export or a scan of the whole text, on the blocking pool, so it
+/// stalls neither the async workers nor other requests.
+///
+async fn blocking<T, F>(f: F) -> Result<T, ApiError>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, ApiError> + Send + 'static,
+{
+    tokio::task::spawn_blocking(f).await?
+}
+
+/// A copy of a stored document, to work on without holding the lock.
+async fn snapshot(state: &AppState, id: Uuid) -> Result<StoredDocument, ApiError> {
+    let documents = state.documents.read().await;
+    let stored = documents.get(&id).ok_or(ApiError::DocumentNotFound(id))?;
+    Ok(stored.clone())
+}
+
+///
+/// Change a copy of a document on the blocking pool, then store it. Fails if the document is changed by
+/// another request in the meantime, rather than losing that change.
+///
+async fn update<T, F>(state: &AppState, id: Uuid, f: F) -> Result<T, ApiError>
+where
+    T: Send + 'static,
+    F: FnOnce(&mut Document) -> Result<T, ApiError> + Send + 'static,
+{
+    let stored = snapshot(state, id).await?;
+    let revision = stored.revision;
+    let (value, document) = blocking(move || {
+        let mut document = stored.document;
+        let value = f(&mut document)?;
+        Ok((value, document))
+    })
+    .await?;
+    let mut documents = state.documents.write().await;
+    let stored = documents
+        .get_mut(&id)
+        .ok_or(ApiError::DocumentNotFound(id))?;
+    if stored.revision != revision {
+        return Err(ApiError::DocumentChanged(id));
+    }
+    stored.document = document;
+    stored.revision += 1;
+    state.emit("document.updated", json!({ "id": id }));
+    Ok(value)
+}
+
+async fn remove(
+    State(state): State<AppState>,
+    UrlPath(id): UrlPath<Uuid>,
+) -> Result<StatusCode, ApiError> {
+    match state.documents.write().await.remove(&id) {
+        Some(_) => {
+            state.emit("document.removed", json!({ "id": id }));
+            Ok(StatusCode::NO_CONTENT)
+        }
+        None => Err(ApiError::DocumentNotFound(id)),
+    }
+}
+
+///
+/// Replace the toc by detecting headings again, all edits are discarded.
+///
+async fn detect(
+    State(state): State<AppState>,
+    UrlPath(id): UrlPath<Uuid>,
+) -> Result<Json<JSONRoot>, ApiError> {
+    let settings = state.settings.clone();
+    let toc = update(&state, id, move |document| {
+        document.detect(&settings.detection)?;
+        Ok(JSONRoot::from(&document.toc))
+    })
+    .await?;
+    Ok(Json(toc))
+}
+
+///
+/// Detect and score headings without touching the toc, the candidates to keep are posted to `accept`.
+///
+async fn preview_detection(
+    State(state): State<AppState>,
+    UrlPath(id): UrlPath<Uuid>,
+) -> Result<Json<Vec<Candidate>>, ApiError> {
+    let stored = snapshot(&state, id).await?;
+    let settings = state.settings.clone();
+    let candidates = blocking(move || Ok(stored.document.preview(&settings.detection)?)).await?;
+    Ok(Json(candidates))
+}
+
+///
+/// Replace the toc by one built from accepted headings, all edits are discarded.
+///
+async fn accept_headings(
+    State(state): State<AppState>,
+    UrlPath(id): UrlPath<Uuid>,
+    Json(headings): Json<Vec<Heading>>,
+) -> Result<Json<JSONRoot>, ApiError> {
+    let toc = update(&state, id, move |document| {
+        document.accept(&headings)?;
+        Ok(JSONRoot::from(&document.toc))
+    })
+    .await?;
+    Ok(Json(toc))
+}
+
+///
+/// Replace the text by a newer version uploaded as the raw request body, keeping the edited toc.
+/// Nodes are matched to the new chapters by their content, new chapters are added.
+///
+async fn reimport(
+    State(state): State<AppState>,
+    UrlPath(id): UrlPath<Uuid>,
+    body: Bytes,
+) -> Result<Json<ReimportReport>, ApiError> {
+    let settings = state.settings.clone();
+    let report = update(&state, id, move |document| {
+        Ok(document.reimport(decode(&body), &settings.detection)?)
+    })
+    .await?;
+    Ok(Json(report))
+}
+
+async fn get_toc(
+    State(state): State<AppState>,
+    UrlPath(id): UrlPath<Uuid>,
+) -> Result<Json<JSONRoot>, ApiError> {
+    let documents = state.documents.read().await;
+    let stored = documents.get(&id).ok_or(ApiError::DocumentNotFound(id))?;
+    Ok(Json(JSONRoot::from(&stored.document.toc)))
+}
+
+///
+/// A node and its subtree by its stable id, which stays valid after other nodes are removed.
+///
+async fn get_node_by_uid(
+    State(state): State<AppState>,
+    UrlPath((id, uid)): UrlPath<(Uuid, Uuid)>,
+) -> Result<Json<JSONNode>, ApiError> {
+    let documents = state.documents.read().await;
+    let stored = documents.get(&id).ok_or(ApiError::DocumentNotFound(id))?;
+    let toc = &stored.document.toc;
+    let node = toc.get_by_uid(uid).ok_or(TocError::UidNotFound(uid))?;
+    Ok(Json(JSONNode::from_toc_node(toc, node)))
+}
+
+///
+/// The text of a node with its patch applied, and the hunks of the patch to highlight.
+///
+async fn get_text(
+    State(state): State<AppState>,
+    UrlPath((id, node)): UrlPath<(Uuid, usize)>,
+) -> Result<Json<PatchPreview>, ApiError> {
+    let documents = state.documents.read().await;
+    let stored = documents.get(&id).ok_or(ApiError::DocumentNotFound(id))?;
+    Ok(Json(stored.document.preview_patch(node)?))
+}
+
+#[derive(Debug, Deserialize)]
+struct EditText {
+    text: String, // the whole text of the node, including its heading line
+}
+
+///
+/// Store the edited text of a node as a patch against the source text.
+///
+async fn edit_text(
+    State(state): State<AppState>,
+    UrlPath((id, node)): UrlPath<(Uuid, usize)>,
+    Json(body): Json<EditText>,
+) -> Result<Json<PatchPreview>, ApiError> {
+    let preview = update(&state, id, move |document| {
+        Ok(document.edit_text(node, &body.text)?)
+    })
+    .await?;
+    Ok(Json(preview))
+}
+
+///
+/// Undo a hunk of the patch of a node, by its index in the preview.
+///
+async fn revert_hunk(
+    State(state): State<AppState>,
+    UrlPath((id, node, index)): UrlPath<(Uuid, usize, usize)>,
+) -> Result<Json<PatchPreview>, ApiError> {
+    let preview = update(&state, id, move |document| {
+        Ok(document.revert_hunk(node, index)?)
+    })
+    .await?;
+    Ok(Json(preview))
+}
+
+///
+/// Link annotation markers to note bodies, and report the ones which can not be linked.
+///
+async fn check_notes(
+    State(state): State<AppState>,
+    UrlPath(id): UrlPath<Uuid>,
+) -> Result<Json<NoteReport>, ApiError> {
+    let annotator = Annotator::new(&state.settings.notes)?;
+    let stored = snapshot(&state, id).await?;
+    let report = blocking(move || Ok(annotator.check(&stored.document))).await?;
+    Ok(Json(report))
+}
+
+///
+/// Resolve image markers against the files next to the source text, and report the ones which can not be resolved.
+///
+async fn check_images(
+    State(state): State<AppState>,
+    UrlPath(id): UrlPath<Uuid>,
+) -> Result<Json<ImageReport>, ApiError> {
+    let resolver = ImageResolver::new(&state.settings.images)?;
+    let stored = snapshot(&state, id).await?;
+    let report = blocking(move || Ok(resolver.check(&stored.document))).await?;
+    Ok(Json(report))
+}
+
+///
+/// Check the toc for numbering gaps, duplicate titles and empty chapters, findings may carry edits to fix them.
+///
+async fn lint_toc(
+    State(state): State<AppState>,
+    UrlPath(id): UrlPath<Uuid>,
+) -> Result<Json<LintReport>, ApiError> {
+    let linter = Linter::new(&state.settings.lint);
+    let stored = snapshot(&state, id).await?;
+    let report = blocking(move || Ok(linter.lint(&stored.document))).await?;
+    Ok(Json(report))
+}
+
+///
+/// Word counts, chapter lengths and reading time of the book, from the toc.
+///
+async fn book_stats(
+    State(state): State<AppState>,
+    UrlPath(id): UrlPath<Uuid>,
+) -> Result<Json<BookStats>, ApiError> {
+    let stored = snapshot(&state, id).await?;
+    let settings = state.settings.clone();
+    let stats = blocking(move || Ok(BookStats::new(&stored.document, &settings.stats))).await?;
+    Ok(Json(stats))
+}
+
+///
+/// Find matches of a pattern in the text of the book, or of a subtree, grouped by node.
+///
+async fn search_text(
+    State(state): State<AppState>,
+    UrlPath(id): UrlPath<Uuid>,
+    Json(query): Json<SearchQuery>,
+) -> Result<Json<SearchResult>, ApiError> {
+    let searcher = Searcher::new(&query)?;
+    let stored = snapshot(&state, id).await?;
+    let result = blocking(move || Ok(searcher.search(&stored.document)?)).await?;
+    Ok(Json(result))
+}
+
+///
+/// Replace the matches of a pattern, recorded as patches of their nodes.
+///
+async fn replace_text(
+    State(state): State<AppState>,
+    UrlPath(id): UrlPath<Uuid>,
+    Json(query): Json<SearchQuery>,
+) -> Result<Json<ReplaceReport>, ApiError> {
+    let searcher = Searcher::new(&query)?;
+    let report = update(&state, id, move |document| Ok(searcher.replace(document)?)).await?;
+    Ok(Json(report))
+}
+
+///
+/// Apply edits in order. Either all of them are applied or, if one fails, none.
+///
+async fn patch_toc(
+    State(state): State<AppState>,
+    UrlPath(id): UrlPath<Uuid>,
+    Json(edits): Json<Vec<TocEdit>>,
+) -> Result<Json<JSONRoot>, ApiError> {
+    let toc = update(&state, id, move |document| {
+        document.apply_edits(&edits)?;
+        Ok(JSONRoot::from(&document.toc))
+    })
+    .await?;
+    Ok(Json(toc))
+}
+
+/// Book meta overrides, the defaults come from the stored title and settings.
+#[derive(Debug, Deserialize)]
+pub(crate) struct ExportParams {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub language: Option<String>,
+    pub convert: Option<Variant>,
+}
+
+impl ExportParams {
+    pub fn book(self, stored: &StoredDocument, settings: &ExportSettings) -> BookMeta {
+        let mut book = BookMeta::new(&stored.title, settings);
+        book.title = self.title.unwrap_or(book.title);
+        book.author = self.author.or(book.author);
+        book.language = self.language.unwrap_or(book.language);
+        book.convert = self.convert.unwrap_or(book.convert);
+        book
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct DownloadParams {
+    #[serde(default)]
+    format: Format,
+    #[serde(flatten)]
+    export: ExportParams,
+}
+
+async fn download_epub(
+    state: State<AppState>,
+    id: UrlPath<Uuid>,
+    Query(params): Query<ExportParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    let params = DownloadParams {
+        format: Format::Epub,
+        export: params,
+    };
+    download(state, id, Query(params)).await
+}
+
+async fn download(
+    State(state): State<AppState>,
+    UrlPath(id): UrlPath<Uuid>,
+    Query(params): Query<DownloadParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    let stored = snapshot(&state, id).await?;
+    let book = params.export.book(&stored, &state.settings.export);
+    let settings = state.settings.clone();
+    let format = params.format;
+    let (title, buf) = blocking(move || {
+        let buf = export(&stored.document, &settings, &book, format)?;
+        Ok((book.title, buf))
+    })
+    .await?;
+    Ok(attachment(&title, format, buf))
+}
+
+/// Respond with a file of the format, named after the book title.
+pub(crate) fn attachment(title: &str, format: Format, buf: impl IntoResponse) -> impl IntoResponse {
+    (
+        [
+            (header::CONTENT_TYPE, format.mime().to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!(
+                    "attachment; filename*=UTF-8''{}.{}",
+                    percent_encode(title),
+                    format.extension()
+                ),
+            ),
+        ],
+        buf,
+    )
+}
+
+/// Encode a header parameter value as RFC 5987 requires, titles are usually not ascii.
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|x| match x {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (x as char).to_string()
+            }
+            _ => format!("%{:02X}", x),
+        })
+        .collect()
+}
@@ -0,0 +1,154 @@
+use std::net::{AddrParseError, SocketAddr};
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+use shared::{
+    detect::DetectError, document::DocumentError, export::ExportError, images::ImageError,
+    notes::NotesError, search::SearchError, toc::TocError,
+};
+use thiserror::Error;
+use tokio::task::JoinError;
+use uuid::Uuid;
+
+///
+/// Errors of the http api, rendered as `{ kind, message }` json with a matching status code.
+///
+#[derive(Error, Debug)]
+pub enum ApiError {
+    #[error("the request is not authorized, the server token is missing or wrong")]
+    Unauthorized,
+
+    #[error("the document: `{0}` is not found")]
+    DocumentNotFound(Uuid),
+
+    #[error("the document: `{0}` is changed by another request meanwhile, try again")]
+    DocumentChanged(Uuid),
+
+    #[error("the job: `{0}` is not found")]
+    JobNotFound(Uuid),
+
+    #[error("the job: `{0}` is not finished, or failed")]
+    JobNotFinished(Uuid),
+
+    #[error(transparent)]
+    Detect(#[from] DetectError),
+
+    #[error(transparent)]
+    Document(DocumentError),
+
+    #[error(transparent)]
+    Toc(#[from] TocError),
+
+    #[error(transparent)]
+    Notes(#[from] NotesError),
+
+    #[error(transparent)]
+    Image(#[from] ImageError),
+
+    #[error(transparent)]
+    Export(#[from] ExportError),
+
+    #[error(transparent)]
+    Search(SearchError),
+
+    #[error("the task is aborted unexpectedly")]
+    Aborted(#[from] JoinError),
+}
+
+/// Toc errors of a document change, e.g. edits, keep their own kinds.
+impl From<DocumentError> for ApiError {
+    fn from(e: DocumentError) -> Self {
+        match e {
+            DocumentError::Toc(e) => ApiError::Toc(e),
+            e => ApiError::Document(e),
+        }
+    }
+}
+
+/// Toc and document errors of a search keep their own kinds.
+impl From<SearchError> for ApiError {
+    fn from(e: SearchError) -> Self {
+        match e {
+            SearchError::Toc(e) => ApiError::Toc(e),
+            SearchError::Document(e) => e.into(),
+            e => ApiError::Search(e),
+        }
+    }
+}
+
+impl ApiError {
+    pub fn kind(&self) -> (StatusCode, &'static str) {
+        match self {
+            ApiError::Unauthorized => (StatusCode::UNAUTHORIZED, "unauthorized"),
+            ApiError::DocumentNotFound(_) => (StatusCode::NOT_FOUND, "document_not_found"),
+            ApiError::DocumentChanged(_) => (StatusCode::CONFLICT, "document_changed"),
+            ApiError::JobNotFound(_) => (StatusCode::NOT_FOUND, "job_not_found"),
+            ApiError::JobNotFinished(_) => (StatusCode::CONFLICT, "job_not_finished"),
+            ApiError::Detect(_) | ApiError::Document(DocumentError::Detect(_)) => {
+                (StatusCode::UNPROCESSABLE_ENTITY, "invalid_detection_rule")
+            }
+            ApiError::Document(DocumentError::HunkNotFound { .. }) => {
+                (StatusCode::NOT_FOUND, "hunk_not_found")
+            }
+            ApiError::Document(
+                DocumentError::InvalidPatch { .. } | DocumentError::PatchConflict(_),
+            ) => (StatusCode::UNPROCESSABLE_ENTITY, "invalid_patch"),
+            ApiError::Document(_) => (StatusCode::UNPROCESSABLE_ENTITY, "invalid_document"),
+            ApiError::Toc(
+                TocError::NodeNotFound(_)
+                | TocError::NodeParentNotFound(_)
+                | TocError::UidNotFound(_),
+            ) => (StatusCode::NOT_FOUND, "node_not_found"),
+            ApiError::Toc(TocError::NoteNotFound { .. }) => {
+                (StatusCode::NOT_FOUND, "note_not_found")
+            }
+            ApiError::Toc(TocError::InvalidMove { .. }) => {
+                (StatusCode::UNPROCESSABLE_ENTITY, "invalid_move")
+            }
+            ApiError::Toc(_) => (StatusCode::UNPROCESSABLE_ENTITY, "invalid_toc"),
+            ApiError::Notes(_) => (StatusCode::UNPROCESSABLE_ENTITY, "invalid_notes_rule"),
+            ApiError::Image(_) => (StatusCode::UNPROCESSABLE_ENTITY, "invalid_image_rule"),
+            ApiError::Export(_) => (StatusCode::INTERNAL_SERVER_ERROR, "export_failed"),
+            ApiError::Search(_) => (StatusCode::UNPROCESSABLE_ENTITY, "invalid_search"),
+            ApiError::Aborted(_) => (StatusCode::INTERNAL_SERVER_ERROR, "aborted"),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let (status, kind) = self.kind();
+        if status.is_server_error() {
+            tracing::error!("{:?}", self);
+        }
+        (
+            status,
+            Json(json!({ "kind": kind, "message": self.to_string() })),
+        )
+            .into_response()
+    }
+}
+
+///
+/// Errors before the server is ready, it is not running if any of them happens.
+///
+#[derive(Error, Debug)]
+pub enum StartError {
+    #[error("the server host: `{host}` is not a valid ip address")]
+    InvalidHost {
+        host: String,
+        #[source]
+        source: AddrParseError,
+    },
+
+    #[error("failed to bind the server to `{addr}`: {source}")]
+    Bind {
+        addr: SocketAddr,
+        #[source]
+        source: std::io::Error,
+    },
+}
@@ -0,0 +1,65 @@
+use std::net::SocketAddr;
+
+use serde::Serialize;
+use tokio::{
+    sync::{oneshot, watch},
+    task::JoinHandle,
+};
+
+///
+/// Lifecycle of the server, as reported to the frontend.
+///
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum ServerStatus {
+    Ready { addr: SocketAddr },
+    Failed { message: String },
+    Stopped,
+}
+
+///
+/// A running server, returned by `start` once the address is bound.
+/// Dropping the handle stops the server gracefully without waiting for it, `shutdown` waits.
+///
+#[derive(Debug)]
+pub struct ServerHandle {
+    addr: SocketAddr,
+    status: watch::Receiver<ServerStatus>,
+    shutdown: oneshot::Sender<()>,
+    task: JoinHandle<()>,
+}
+
+impl ServerHandle {
+    pub(crate) fn new(
+        addr: SocketAddr,
+        status: watch::Receiver<ServerStatus>,
+        shutdown: oneshot::Sender<()>,
+        task: JoinHandle<()>,
+    ) -> Self {
+        ServerHandle {
+            addr,
+            status,
+            shutdown,
+            task,
+        }
+    }
+
+    /// The bound address, with the actual port if port `0` was asked for.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Subscribe to status changes, the current one is `borrow`ed from the receiver.
+    pub fn status(&self) -> watch::Receiver<ServerStatus> {
+        self.status.clone()
+    }
+
+    ///
+    /// Stop accepting connections and wait for the in-flight requests to finish.
+    /// Open websockets are not waited for, they are dropped with the server.
+    ///
+    pub async fn shutdown(self) {
+        let _ = self.shutdown.send(());
+        let _ = self.task.await;
+    }
+}
@@ -0,0 +1,332 @@
+// Conversions run off the request path. A submitted job waits in queue for a permit, then exports a snapshot
+// of the document in a blocking task. Progress and status changes are broadcast as websocket events:
+// `job.progress` with `{ id, progress: { stage, percent, chapter } }`, and `job.updated` with the whole job.
+// The result is kept in memory until the job is removed, EPUB jobs carry the `epubcheck` report of it.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use axum::{
+    extract::{Path as UrlPath, State},
+    http::StatusCode,
+    response::IntoResponse,
+    routing::{get, post},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use shared::{
+    document::Document,
+    epubcheck::CheckReport,
+    export::{export_checked, BookMeta, ExportError, Format, Progress, SkippedPatch, Stage},
+};
+use tokio::sync::Semaphore;
+use uuid::Uuid;
+
+use crate::{
+    documents::{attachment, ExportParams},
+    error::ApiError,
+    state::AppState,
+};
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Finished,
+    Failed { message: String },
+    Cancelled,
+}
+
+impl JobStatus {
+    pub fn is_done(&self) -> bool {
+        matches!(
+            self,
+            JobStatus::Finished | JobStatus::Failed { .. } | JobStatus::Cancelled
+        )
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JobInfo {
+    pub id: Uuid,
+    pub document: Uuid,
+    pub title: String,
+    pub format: Format,
+    pub status: JobStatus,
+    pub progress: Option<Progress>, // None until the job starts
+    pub check: Option<CheckReport>, // of finished EPUB jobs, if `export.check` is on
+    pub skipped: Vec<SkippedPatch>, // patches left out of finished jobs, since they no longer fit
+}
+
+#[derive(Debug)]
+struct Job {
+    info: JobInfo,
+    cancel: Arc<AtomicBool>,
+    result: Option<Arc<[u8]>>,
+}
+
+///
+/// Conversion jobs, at most `server.max_jobs` of them run at once.
+///
+#[derive(Debug, Clone)]
+pub struct JobQueue {
+    jobs: Arc<Mutex<HashMap<Uuid, Job>>>,
+    permits: Arc<Semaphore>,
+}
+
+impl JobQueue {
+    pub fn new(max_jobs: usize) -> Self {
+        JobQueue {
+            jobs: Arc::default(),
+            permits: Arc::new(Semaphore::new(max_jobs.max(1))),
+        }
+    }
+
+    pub fn list(&self) -> Vec<JobInfo> {
+        let jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        jobs.values().map(|x| x.info.clone()).collect()
+    }
+
+    pub fn get(&self, id: Uuid) -> Result<JobInfo, ApiError> {
+        self.update(id, |_| {})
+    }
+
+    ///
+    /// Cancel a job. A queued job is cancelled at once, a running one stops at the next chapter.
+    /// It is a no-op for jobs which are done already.
+    ///
+    pub fn cancel(&self, id: Uuid) -> Result<JobInfo, ApiError> {
+        self.update(id, |job| {
+            if job.info.status.is_done() {
+                return;
+            }
+            job.cancel.store(true, Ordering::Relaxed);
+            if job.info.status == JobStatus::Queued {
+                job.info.status = JobStatus::Cancelled;
+            }
+        })
+    }
+
+    /// Cancel a job if it is not done, and drop it with its result.
+    pub fn remove(&self, id: Uuid) -> Result<JobInfo, ApiError> {
+        let mut jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        let job = jobs.remove(&id).ok_or(ApiError::JobNotFound(id))?;
+        job.cancel.store(true, Ordering::Relaxed);
+        Ok(job.info)
+    }
+
+    pub fn result(&self, id: Uuid) -> Result<(JobInfo, Arc<[u8]>), ApiError> {
+        let jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        let job = jobs.get(&id).ok_or(ApiError::JobNotFound(id))?;
+        match &job.result {
+            Some(result) => Ok((job.info.clone(), result.clone())),
+            None => Err(ApiError::JobNotFinished(id)),
+        }
+    }
+
+    fn insert(&self, info: JobInfo, cancel: Arc<AtomicBool>) {
+        let mut jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        let job = Job {
+            info,
+            cancel,
+            result: None,
+        };
+        jobs.insert(job.info.id, job);
+    }
+
+    fn update(&self, id: Uuid, f: impl FnOnce(&mut Job)) -> Result<JobInfo, ApiError> {
+        let mut jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        let job = jobs.get_mut(&id).ok_or(ApiError::JobNotFound(id))?;
+        f(job);
+        Ok(job.info.clone())
+    }
+}
+
+///
+/// Queue a conversion of the document snapshot, returns the queued job.
+///
+pub fn submit(
+    state: &AppState,
+    document_id: Uuid,
+    document: Document,
+    book: BookMeta,
+    format: Format,
+) -> JobInfo {
+    let info = JobInfo {
+        id: Uuid::new_v4(),
+        document: document_id,
+        title: book.title.clone(),
+        format,
+        status: JobStatus::Queued,
+        progress: None,
+        check: None,
+        skipped: Vec::new(),
+    };
+    let cancel = Arc::new(AtomicBool::new(false));
+    state.jobs.insert(info.clone(), cancel.clone());
+    state.emit("job.updated", &info);
+    tokio::spawn(run(state.clone(), info.id, document, book, format, cancel));
+    info
+}
+
+async fn run(
+    state: AppState,
+    id: Uuid,
+    document: Document,
+    book: BookMeta,
+    format: Format,
+    cancel: Arc<AtomicBool>,
+) {
+    let Ok(_permit) = state.jobs.permits.clone().acquire_owned().await else {
+        return;
+    };
+    // Cancelled or removed while waiting in queue
+    if cancel.load(Ordering::Relaxed) {
+        return;
+    }
+    set_status(&state, id, JobStatus::Running, None);
+
+    let reporter = state.clone();
+    let flag = cancel.clone();
+    let settings = state.settings.clone();
+    let result = tokio::task::spawn_blocking(move || {
+        let mut last = None;
+        export_checked(&document, &settings, &book, format, |progress| {
+            // Books could have thousands of chapters, so only changes of the percentage are broadcast
+            let _ = reporter
+                .jobs
+                .update(id, |x| x.info.progress = Some(progress.clone()));
+            if last != Some((progress.stage, progress.percent)) {
+                last = Some((progress.stage, progress.percent));
+                reporter.emit("job.progress", json!({ "id": id, "progress": progress }));
+            }
+            !flag.load(Ordering::Relaxed)
+        })
+    })
+    .await;
+
+    match result {
+        Ok(Ok(exported)) => {
+            let _ = state.jobs.update(id, |x| {
+                x.result = Some(exported.buf.into());
+                x.info.check = exported.check;
+                x.info.skipped = exported.skipped;
+            });
+            let progress = Progress {
+                stage: Stage::Package,
+                percent: 100,
+                chapter: None,
+            };
+            set_status(&state, id, JobStatus::Finished, Some(progress));
+        }
+        Ok(Err(ExportError::Cancelled)) => set_status(&state, id, JobStatus::Cancelled, None),
+        Ok(Err(e)) => {
+            tracing::error!("job {} failed: {:?}", id, e);
+            let message = e.to_string();
+            set_status(&state, id, JobStatus::Failed { message }, None);
+        }
+        Err(e) => {
+            tracing::error!("job {} panicked: {:?}", id, e);
+            let message = "the job is aborted unexpectedly".to_string();
+            set_status(&state, id, JobStatus::Failed { message }, None);
+        }
+    }
+}
+
+fn set_status(state: &AppState, id: Uuid, status: JobStatus, progress: Option<Progress>) {
+    let updated = state.jobs.update(id, |x| {
+        x.info.status = status;
+        x.info.progress = progress.or(x.info.progress.take());
+    });
+    // The job may be removed in the meantime
+    if let Ok(info) = updated {
+        state.emit("job.updated", &info);
+    }
+}
+
+///
+/// Routes for jobs, nested under `/jobs`.
+///
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/", get(list).post(create))
+        .route("/:id", get(get_job).delete(remove))
+        .route("/:id/cancel", post(cancel))
+        .route("/:id/result", get(download))
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateParams {
+    document: Uuid,
+    #[serde(default)]
+    format: Format,
+    #[serde(flatten)]
+    export: ExportParams,
+}
+
+async fn create(
+    State(state): State<AppState>,
+    Json(params): Json<CreateParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    let documents = state.documents.read().await;
+    let stored = documents
+        .get(&params.document)
+        .ok_or(ApiError::DocumentNotFound(params.document))?;
+    let book = params.export.book(stored, &state.settings.export);
+    // Edits made after submitting don't affect the job
+    let info = submit(
+        &state,
+        params.document,
+        stored.document.clone(),
+        book,
+        params.format,
+    );
+    Ok((StatusCode::ACCEPTED, Json(info)))
+}
+
+async fn list(State(state): State<AppState>) -> Json<Vec<JobInfo>> {
+    Json(state.jobs.list())
+}
+
+async fn get_job(
+    State(state): State<AppState>,
+    UrlPath(id): UrlPath<Uuid>,
+) -> Result<Json<JobInfo>, ApiError> {
+    Ok(Json(state.jobs.get(id)?))
+}
+
+async fn cancel(
+    State(state): State<AppState>,
+    UrlPath(id): UrlPath<Uuid>,
+) -> Result<Json<JobInfo>, ApiError> {
+    let info = state.jobs.cancel(id)?;
+    state.emit("job.updated", &info);
+    Ok(Json(info))
+}
+
+async fn remove(
+    State(state): State<AppState>,
+    UrlPath(id): UrlPath<Uuid>,
+) -> Result<StatusCode, ApiError> {
+    state.jobs.remove(id)?;
+    state.emit("job.removed", json!({ "id": id }));
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn download(
+    State(state): State<AppState>,
+    UrlPath(id): UrlPath<Uuid>,
+) -> Result<impl IntoResponse, ApiError> {
+    let (info, buf) = state.jobs.result(id)?;
+    Ok(attachment(&info.title, info.format, buf.to_vec()))
+}
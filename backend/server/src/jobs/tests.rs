@@ -0,0 +1,104 @@
+use std::time::Duration;
+
+use shared::{
+    document::Document,
+    export::{BookMeta, Format, Stage},
+    settings::Settings,
+};
+use uuid::Uuid;
+
+use super::{submit, JobStatus};
+use crate::{auth::Auth, error::ApiError, socket::protocol::Event, state::AppState};
+
+fn state(max_jobs: usize) -> AppState {
+    let mut settings = Settings::default();
+    settings.server.max_jobs = max_jobs;
+    AppState::new(settings, Auth::new(Vec::new()))
+}
+
+fn document(settings: &Settings) -> Document {
+    let mut document = Document::new("第一章 开端\n甲\n第二章 发展\n乙\n".to_string());
+    document.detect(&settings.detection).unwrap();
+    document
+}
+
+#[tokio::test]
+async fn test_job_finished() {
+    let state = state(1);
+    let mut events = state.events.subscribe();
+    let book = BookMeta::new("书", &state.settings.export);
+    let job = submit(
+        &state,
+        Uuid::new_v4(),
+        document(&state.settings),
+        book,
+        Format::Epub,
+    );
+    assert_eq!(job.status, JobStatus::Queued);
+
+    let mut received: Vec<Event> = Vec::new();
+    loop {
+        let event = tokio::time::timeout(Duration::from_secs(10), events.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        received.push(event.clone());
+        if event.event == "job.updated" && event.data["status"]["state"] == "finished" {
+            break;
+        }
+    }
+    let states: Vec<_> = received
+        .iter()
+        .filter(|x| x.event == "job.updated")
+        .map(|x| x.data["status"]["state"].as_str().unwrap())
+        .collect();
+    assert_eq!(states, vec!["queued", "running", "finished"]);
+    let chapters: Vec<_> = received
+        .iter()
+        .filter(|x| x.event == "job.progress")
+        .filter_map(|x| x.data["progress"]["chapter"].as_str())
+        .collect();
+    assert_eq!(chapters, vec!["第一章 开端", "第二章 发展"]);
+
+    let info = state.jobs.get(job.id).unwrap();
+    let progress = info.progress.unwrap();
+    assert_eq!((progress.stage, progress.percent), (Stage::Package, 100));
+    assert!(info.check.unwrap().is_valid());
+    let (_, buf) = state.jobs.result(job.id).unwrap();
+    assert_eq!(&buf[..2], b"PK");
+}
+
+#[tokio::test]
+async fn test_cancel_queued_job() {
+    let state = state(1);
+    // Hold the only permit, so the job stays in queue
+    let permit = state.jobs.permits.clone().acquire_owned().await.unwrap();
+    let book = BookMeta::new("书", &state.settings.export);
+    let job = submit(
+        &state,
+        Uuid::new_v4(),
+        document(&state.settings),
+        book,
+        Format::Epub,
+    );
+    assert_eq!(
+        state.jobs.cancel(job.id).unwrap().status,
+        JobStatus::Cancelled
+    );
+
+    drop(permit);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let info = state.jobs.get(job.id).unwrap();
+    assert_eq!(info.status, JobStatus::Cancelled);
+    assert!(info.progress.is_none());
+    assert!(matches!(
+        state.jobs.result(job.id),
+        Err(ApiError::JobNotFinished(_))
+    ));
+
+    state.jobs.remove(job.id).unwrap();
+    assert!(matches!(
+        state.jobs.get(job.id),
+        Err(ApiError::JobNotFound(_))
+    ));
+}
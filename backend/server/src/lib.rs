@@ -1,26 +1,87 @@
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 
-use axum::{http::Method, Router};
-use tower_http::cors::{Any, CorsLayer};
-use tracing::info;
+use axum::{middleware, Router};
+use shared::settings::Settings;
+use tokio::{
+    net::TcpListener,
+    sync::{oneshot, watch},
+};
+use tracing::{error, info};
 
+use self::state::AppState;
+
+pub use self::auth::Auth;
+pub use self::error::StartError;
+pub use self::handle::{ServerHandle, ServerStatus};
+
+mod auth;
+mod documents;
+mod error;
+mod handle;
+mod jobs;
 mod router;
+mod socket;
+mod state;
+
+#[cfg(test)]
+mod tests;
 
-pub async fn start(port: u16) {
-    let app = router::register(Router::new()).layer(
-        CorsLayer::new().allow_origin(Any).allow_methods(vec![
-            Method::GET,
-            Method::POST,
-            Method::PUT,
-            Method::PATCH,
-            Method::DELETE,
-        ]),
-    );
-
-    let addr = SocketAddr::from(([127, 0, 0, 1], port));
-    info!("Backend is listening on http://{}", addr);
-    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    axum::serve(listener, app.into_make_service())
+fn app(settings: Settings, auth: Auth) -> Router {
+    let cors = auth.cors();
+    let state = AppState::new(settings, auth);
+    // CORS is the outer layer, so preflight requests, which never carry the token, are answered by it.
+    router::register(Router::new())
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::require_token,
+        ))
+        .with_state(state)
+        .layer(cors)
+}
+
+///
+/// Bind the address and serve the backend in background, only requests carrying `auth.token` are accepted.
+/// Port `0` binds a random free port, the bound one is reported by the returned handle.
+///
+pub async fn start(settings: Settings, port: u16, auth: Auth) -> Result<ServerHandle, StartError> {
+    let host: IpAddr = settings
+        .server
+        .host
+        .parse()
+        .map_err(|source| StartError::InvalidHost {
+            host: settings.server.host.clone(),
+            source,
+        })?;
+    let addr = SocketAddr::from((host, port));
+    let listener = TcpListener::bind(addr)
         .await
-        .unwrap();
+        .map_err(|source| StartError::Bind { addr, source })?;
+    let addr = listener
+        .local_addr()
+        .map_err(|source| StartError::Bind { addr, source })?;
+    let app = app(settings, auth);
+
+    let (status, receiver) = watch::channel(ServerStatus::Ready { addr });
+    let (shutdown, signal) = oneshot::channel::<()>();
+    let task = tokio::spawn(async move {
+        info!("Backend is listening on http://{}", addr);
+        let result = axum::serve(listener, app.into_make_service())
+            .with_graceful_shutdown(async {
+                let _ = signal.await;
+            })
+            .await;
+        let _ = status.send(match result {
+            Ok(_) => {
+                info!("Backend is stopped");
+                ServerStatus::Stopped
+            }
+            Err(e) => {
+                error!("Backend is stopped unexpectedly: {}", e);
+                ServerStatus::Failed {
+                    message: e.to_string(),
+                }
+            }
+        });
+    });
+    Ok(ServerHandle::new(addr, receiver, shutdown, task))
 }
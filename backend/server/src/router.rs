@@ -1,50 +1,38 @@
 use axum::{
-    extract::{
-        ws::{Message, WebSocket},
-        WebSocketUpgrade,
-    },
+    body::Bytes,
+    extract::DefaultBodyLimit,
     response::IntoResponse,
-    routing::{get, Router},
+    routing::{get, post, Router},
+    Json,
 };
+use shared::epubcheck::{check_epub, CheckReport};
+
+use crate::{documents, jobs, socket, state::AppState};
+
+/// Books with images are often larger than the default body limit of 2 MB.
+const MAX_EPUB_SIZE: usize = 256 * 1024 * 1024;
 
 ///
 /// This fn is used to register the routes for the backend.
 ///
-pub fn register(app: Router) -> Router {
-    app.route("/ws", get(ws_handler)).route("/", get(handler))
+pub fn register(app: Router<AppState>) -> Router<AppState> {
+    app.route("/ws", get(socket::ws_handler))
+        .route("/", get(handler))
+        .route(
+            "/check",
+            post(check).layer(DefaultBodyLimit::max(MAX_EPUB_SIZE)),
+        )
+        .nest("/documents", documents::router())
+        .nest("/jobs", jobs::router())
 }
 
 async fn handler() -> impl IntoResponse {
     "Hello, from backend!"
 }
 
-async fn ws_handler(ws: WebSocketUpgrade) -> impl IntoResponse {
-    ws.on_upgrade(handle_socket)
-}
-
-async fn handle_socket(mut socket: WebSocket) {
-    loop {
-        if let Some(msg) = socket.recv().await {
-            if let Ok(msg) = msg {
-                match msg {
-                    Message::Text(t) => {
-                        // Echo
-                        if socket
-                            .send(Message::Text(format!("Echo from backend: {}", t)))
-                            .await
-                            .is_err()
-                        {
-                            return;
-                        }
-                    }
-                    Message::Close(_) => {
-                        return;
-                    }
-                    _ => {}
-                }
-            } else {
-                return;
-            }
-        }
-    }
+///
+/// Check an EPUB file sent as the raw request body, e.g. one which is not exported by us.
+///
+async fn check(body: Bytes) -> Json<CheckReport> {
+    Json(check_epub(&body))
 }
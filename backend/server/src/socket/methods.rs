@@ -0,0 +1,57 @@
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+use shared::toc::JSONRoot;
+use uuid::Uuid;
+
+use super::protocol::{ErrorBody, PROTOCOL_VERSION};
+use crate::{error::ApiError, state::AppState};
+
+///
+/// Dispatch a request to its method, the result is sent back in the response frame.
+///
+pub async fn dispatch(state: &AppState, method: &str, params: Value) -> Result<Value, ErrorBody> {
+    match method {
+        "version" => Ok(json!({
+            "protocol": PROTOCOL_VERSION,
+            "server": env!("CARGO_PKG_VERSION"),
+        })),
+        "documents.list" => {
+            let documents = state.documents.read().await;
+            let list: Vec<Value> = documents
+                .iter()
+                .map(|(id, x)| json!({ "id": id, "title": x.title }))
+                .collect();
+            Ok(Value::Array(list))
+        }
+        "documents.get_toc" => {
+            let IdParams { id } = parse(params)?;
+            let documents = state.documents.read().await;
+            let stored = documents.get(&id).ok_or(ApiError::DocumentNotFound(id))?;
+            Ok(json!(JSONRoot::from(&stored.document.toc)))
+        }
+        "jobs.list" => Ok(json!(state.jobs.list())),
+        "jobs.get" => {
+            let IdParams { id } = parse(params)?;
+            Ok(json!(state.jobs.get(id)?))
+        }
+        "jobs.cancel" => {
+            let IdParams { id } = parse(params)?;
+            let info = state.jobs.cancel(id)?;
+            state.emit("job.updated", &info);
+            Ok(json!(info))
+        }
+        _ => Err(ErrorBody::new(
+            "method_not_found",
+            format!("the method `{}` is not found", method),
+        )),
+    }
+}
+
+#[derive(Deserialize)]
+struct IdParams {
+    id: Uuid,
+}
+
+fn parse<T: DeserializeOwned>(params: Value) -> Result<T, ErrorBody> {
+    serde_json::from_value(params).map_err(|e| ErrorBody::new("invalid_params", e))
+}
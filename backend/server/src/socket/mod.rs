@@ -0,0 +1,152 @@
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::{
+        ws::{Message, WebSocket},
+        State, WebSocketUpgrade,
+    },
+    response::IntoResponse,
+};
+use serde_json::Value;
+use tokio::{
+    sync::{broadcast::error::RecvError, mpsc},
+    task::JoinSet,
+};
+
+use self::protocol::{ClientFrame, Envelope, ErrorBody, ServerFrame, PROTOCOL_VERSION};
+use crate::state::AppState;
+
+mod methods;
+pub mod protocol;
+
+#[cfg(test)]
+mod tests;
+
+/// How often the server pings the client, to keep the connection alive through proxies.
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+/// The connection is closed if nothing, including pongs, is received for this long.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+/// Requests of a connection handled at once, further frames are not read until one of them is answered.
+const MAX_PENDING_REQUESTS: usize = 32;
+
+pub async fn ws_handler(ws: WebSocketUpgrade, State(state): State<AppState>) -> impl IntoResponse {
+    ws.on_upgrade(move |socket| handle_socket(socket, state))
+}
+
+///
+/// Requests are handled in tasks of their own, so a slow one holds up neither other requests, nor events and
+/// pings. Their replies come back through the outgoing channel, in the order they are ready. Pending requests
+/// are aborted when the connection closes.
+///
+async fn handle_socket(mut socket: WebSocket, state: AppState) {
+    let mut events = state.events.subscribe();
+    let (outgoing, mut replies) = mpsc::channel(MAX_PENDING_REQUESTS);
+    let mut requests = JoinSet::new();
+    let mut keepalive =
+        tokio::time::interval_at(tokio::time::Instant::now() + PING_INTERVAL, PING_INTERVAL);
+    let mut last_seen = Instant::now();
+    loop {
+        let reply = tokio::select! {
+            msg = socket.recv(), if requests.len() < MAX_PENDING_REQUESTS => {
+                // The stream ends when the client is gone
+                let Some(Ok(msg)) = msg else {
+                    return;
+                };
+                last_seen = Instant::now();
+                match msg {
+                    Message::Text(text) => {
+                        let (state, outgoing) = (state.clone(), outgoing.clone());
+                        requests.spawn(async move {
+                            let _ = outgoing.send(handle_text(&state, &text).await).await;
+                        });
+                        None
+                    }
+                    Message::Binary(_) => Some(ServerFrame::Error {
+                        id: None,
+                        error: ErrorBody::new("unsupported_frame", "binary frames are not supported"),
+                    }),
+                    Message::Close(_) => return,
+                    Message::Ping(_) | Message::Pong(_) => None,
+                }
+            }
+            Some(frame) = replies.recv() => Some(frame),
+            // Finished requests are reaped, their replies are already sent
+            Some(result) = requests.join_next() => {
+                if let Err(e) = result {
+                    tracing::error!("websocket request panicked: {:?}", e);
+                }
+                None
+            }
+            event = events.recv() => match event {
+                Ok(event) => Some(ServerFrame::Event(event)),
+                Err(RecvError::Lagged(n)) => {
+                    tracing::warn!("websocket client lagged behind, {} events are dropped", n);
+                    None
+                }
+                Err(RecvError::Closed) => return,
+            },
+            _ = keepalive.tick() => {
+                if last_seen.elapsed() > IDLE_TIMEOUT {
+                    let _ = socket.send(Message::Close(None)).await;
+                    return;
+                }
+                if socket.send(Message::Ping(Vec::new())).await.is_err() {
+                    return;
+                }
+                None
+            }
+        };
+        if let Some(frame) = reply {
+            let text = serde_json::to_string(&Envelope::new(frame)).unwrap();
+            if socket.send(Message::Text(text)).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+async fn handle_text(state: &AppState, text: &str) -> ServerFrame {
+    let value: Value = match serde_json::from_str(text) {
+        Ok(value) => value,
+        Err(e) => {
+            return ServerFrame::Error {
+                id: None,
+                error: ErrorBody::new("invalid_frame", e),
+            }
+        }
+    };
+    // Keep the id, so even a malformed request could be correlated
+    let id = value.get("id").and_then(Value::as_u64);
+    let version = value.get("v").and_then(Value::as_u64);
+    if version != Some(PROTOCOL_VERSION as u64) {
+        return ServerFrame::Error {
+            id,
+            error: ErrorBody::new(
+                "unsupported_version",
+                format!(
+                    "the protocol version `{}` is not supported, expected `{}`",
+                    version.map_or("none".to_string(), |x| x.to_string()),
+                    PROTOCOL_VERSION
+                ),
+            ),
+        };
+    }
+    match serde_json::from_value::<Envelope<ClientFrame>>(value) {
+        Ok(envelope) => match envelope.frame {
+            ClientFrame::Request { id, method, params } => {
+                match methods::dispatch(state, &method, params).await {
+                    Ok(result) => ServerFrame::Response { id, result },
+                    Err(error) => ServerFrame::Error {
+                        id: Some(id),
+                        error,
+                    },
+                }
+            }
+            ClientFrame::Ping => ServerFrame::Pong,
+        },
+        Err(e) => ServerFrame::Error {
+            id,
+            error: ErrorBody::new("invalid_frame", e),
+        },
+    }
+}
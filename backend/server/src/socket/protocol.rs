@@ -0,0 +1,86 @@
+// Every frame is a json object with the protocol version `v` and a `type` tag, e.g.
+// -> { "v": 1, "type": "request", "id": 1, "method": "version", "params": null }
+// <- { "v": 1, "type": "response", "id": 1, "result": { ... } }
+// <- { "v": 1, "type": "event", "event": "document.updated", "data": { ... } }
+// <- { "v": 1, "type": "error", "id": 1, "kind": "method_not_found", "message": "..." }
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::error::ApiError;
+
+pub const PROTOCOL_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Envelope<T> {
+    pub v: u32,
+    #[serde(flatten)]
+    pub frame: T,
+}
+
+impl<T> Envelope<T> {
+    pub fn new(frame: T) -> Self {
+        Envelope {
+            v: PROTOCOL_VERSION,
+            frame,
+        }
+    }
+}
+
+/// Frames sent by clients.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientFrame {
+    Request {
+        id: u64,
+        method: String,
+        #[serde(default)]
+        params: Value,
+    },
+    Ping,
+}
+
+/// Frames sent by the server.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerFrame {
+    Response {
+        id: u64,
+        result: Value,
+    },
+    Event(Event),
+    Error {
+        id: Option<u64>, // None if the failed frame could not be correlated
+        #[serde(flatten)]
+        error: ErrorBody,
+    },
+    Pong,
+}
+
+/// Events are broadcast to all connected clients.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Event {
+    pub event: String,
+    pub data: Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ErrorBody {
+    pub kind: String,
+    pub message: String,
+}
+
+impl ErrorBody {
+    pub fn new(kind: &str, message: impl ToString) -> Self {
+        ErrorBody {
+            kind: kind.to_string(),
+            message: message.to_string(),
+        }
+    }
+}
+
+impl From<ApiError> for ErrorBody {
+    fn from(e: ApiError) -> Self {
+        ErrorBody::new(e.kind().1, e)
+    }
+}
@@ -0,0 +1,221 @@
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::{connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+use super::protocol::{ClientFrame, Envelope, ErrorBody, Event, ServerFrame};
+use crate::tests::{test_app, TOKEN};
+
+type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Serve the app on a random port, returns the base address.
+async fn serve() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, test_app().into_make_service())
+            .await
+            .unwrap();
+    });
+    addr.to_string()
+}
+
+async fn connect(addr: &str) -> Client {
+    connect_async(format!("ws://{}/ws?token={}", addr, TOKEN))
+        .await
+        .unwrap()
+        .0
+}
+
+async fn send(client: &mut Client, frame: Value) {
+    client.send(Message::Text(frame.to_string())).await.unwrap();
+}
+
+/// The next text frame, control frames are skipped.
+async fn recv(client: &mut Client) -> Value {
+    loop {
+        match client.next().await.unwrap().unwrap() {
+            Message::Text(text) => return serde_json::from_str(&text).unwrap(),
+            Message::Binary(_) | Message::Close(_) => panic!("unexpected frame"),
+            _ => continue,
+        }
+    }
+}
+
+#[test]
+fn test_frame_encoding() {
+    let frame = Envelope::new(ServerFrame::Error {
+        id: Some(3),
+        error: ErrorBody::new("method_not_found", "oops"),
+    });
+    assert_eq!(
+        serde_json::to_value(&frame).unwrap(),
+        json!({ "v": 1, "type": "error", "id": 3, "kind": "method_not_found", "message": "oops" })
+    );
+    let frame = Envelope::new(ServerFrame::Event(Event {
+        event: "document.created".to_string(),
+        data: json!({ "id": 1 }),
+    }));
+    assert_eq!(
+        serde_json::to_value(&frame).unwrap(),
+        json!({ "v": 1, "type": "event", "event": "document.created", "data": { "id": 1 } })
+    );
+
+    let frame: Envelope<ClientFrame> =
+        serde_json::from_value(json!({ "v": 1, "type": "request", "id": 7, "method": "version" }))
+            .unwrap();
+    assert!(matches!(
+        frame.frame,
+        ClientFrame::Request { id: 7, ref method, params: Value::Null } if method == "version"
+    ));
+    let frame: Envelope<ClientFrame> =
+        serde_json::from_value(json!({ "v": 1, "type": "ping" })).unwrap();
+    assert!(matches!(frame.frame, ClientFrame::Ping));
+}
+
+#[tokio::test]
+async fn test_request_response() {
+    let addr = serve().await;
+    let mut client = connect(&addr).await;
+
+    send(
+        &mut client,
+        json!({ "v": 1, "type": "request", "id": 1, "method": "version" }),
+    )
+    .await;
+    let reply = recv(&mut client).await;
+    assert_eq!(reply["type"], "response");
+    assert_eq!(reply["id"], 1);
+    assert_eq!(reply["result"]["protocol"], 1);
+
+    send(&mut client, json!({ "v": 1, "type": "ping" })).await;
+    assert_eq!(recv(&mut client).await["type"], "pong");
+
+    send(
+        &mut client,
+        json!({ "v": 1, "type": "request", "id": 2, "method": "documents.list" }),
+    )
+    .await;
+    let reply = recv(&mut client).await;
+    assert_eq!(reply["id"], 2);
+    assert_eq!(reply["result"], json!([]));
+}
+
+#[tokio::test]
+async fn test_concurrent_requests() {
+    let addr = serve().await;
+    let mut client = connect(&addr).await;
+
+    // Requests are answered in the order they are ready, each reply carries the id of its request
+    for id in 1..=5 {
+        send(
+            &mut client,
+            json!({ "v": 1, "type": "request", "id": id, "method": "documents.list" }),
+        )
+        .await;
+    }
+    let mut ids = Vec::new();
+    for _ in 1..=5 {
+        let reply = recv(&mut client).await;
+        assert_eq!(reply["type"], "response");
+        assert_eq!(reply["result"], json!([]));
+        ids.push(reply["id"].as_u64().unwrap());
+    }
+    ids.sort();
+    assert_eq!(ids, vec![1, 2, 3, 4, 5]);
+}
+
+#[tokio::test]
+async fn test_errors() {
+    let addr = serve().await;
+    let mut client = connect(&addr).await;
+
+    send(&mut client, json!({ "v": 2, "type": "ping", "id": 1 })).await;
+    let reply = recv(&mut client).await;
+    assert_eq!(reply["kind"], "unsupported_version");
+    assert_eq!(reply["id"], 1);
+
+    client.send(Message::Text("{".to_string())).await.unwrap();
+    let reply = recv(&mut client).await;
+    assert_eq!(reply["kind"], "invalid_frame");
+    assert_eq!(reply["id"], Value::Null);
+
+    send(
+        &mut client,
+        json!({ "v": 1, "type": "request", "id": 2, "method": "nope" }),
+    )
+    .await;
+    let reply = recv(&mut client).await;
+    assert_eq!(reply["kind"], "method_not_found");
+    assert_eq!(reply["id"], 2);
+
+    send(
+        &mut client,
+        json!({ "v": 1, "type": "request", "id": 3, "method": "documents.get_toc", "params": {} }),
+    )
+    .await;
+    assert_eq!(recv(&mut client).await["kind"], "invalid_params");
+
+    let id = uuid::Uuid::new_v4();
+    send(
+        &mut client,
+        json!({ "v": 1, "type": "request", "id": 4, "method": "documents.get_toc", "params": { "id": id } }),
+    )
+    .await;
+    assert_eq!(recv(&mut client).await["kind"], "document_not_found");
+}
+
+#[tokio::test]
+async fn test_events() {
+    let addr = serve().await;
+    let mut client = connect(&addr).await;
+    // Make sure the connection is subscribed before the upload
+    send(&mut client, json!({ "v": 1, "type": "ping" })).await;
+    recv(&mut client).await;
+
+    let body = http_upload(&addr, "第一章 开端\n甲\n").await;
+    let id = body["id"].clone();
+    let event = recv(&mut client).await;
+    assert_eq!(event["type"], "event");
+    assert_eq!(event["event"], "document.created");
+    assert_eq!(event["data"]["id"], id);
+
+    send(
+        &mut client,
+        json!({ "v": 1, "type": "request", "id": 1, "method": "documents.get_toc", "params": { "id": id } }),
+    )
+    .await;
+    let reply = recv(&mut client).await;
+    assert_eq!(reply["result"][0]["title"], "第一章 开端");
+}
+
+/// Upload through plain http, there is no http client in dev dependencies.
+async fn http_upload(addr: &str, text: &str) -> Value {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    let request = format!(
+        "POST /documents?name=book.txt HTTP/1.1\r\nhost: {}\r\nauthorization: Bearer {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+        addr,
+        TOKEN,
+        text.len(),
+        text
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    let (_, body) = response.split_once("\r\n\r\n").unwrap();
+    serde_json::from_str(body).unwrap()
+}
+
+#[tokio::test]
+async fn test_upgrade_requires_token() {
+    let addr = serve().await;
+    let error = connect_async(format!("ws://{}/ws", addr))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        tokio_tungstenite::tungstenite::Error::Http(ref response) if response.status() == 401
+    ));
+}
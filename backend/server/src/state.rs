@@ -0,0 +1,56 @@
+use std::{collections::HashMap, sync::Arc};
+
+use serde::Serialize;
+use shared::{document::Document, settings::Settings};
+use tokio::sync::{broadcast, RwLock};
+use uuid::Uuid;
+
+use crate::{auth::Auth, jobs::JobQueue, socket::protocol::Event};
+
+/// Events kept for slow websocket clients before they start to lag.
+const EVENT_CAPACITY: usize = 256;
+
+///
+/// A document uploaded through the http api, the title is the uploaded file name without extension.
+/// The revision is bumped by every change, so work done on a copy outside the lock is not stored over
+/// changes made meanwhile.
+///
+#[derive(Debug, Clone)]
+pub struct StoredDocument {
+    pub title: String,
+    pub document: Document,
+    pub revision: u64,
+}
+
+pub type DocumentStore = Arc<RwLock<HashMap<Uuid, StoredDocument>>>;
+
+#[derive(Debug, Clone)]
+pub struct AppState {
+    pub settings: Arc<Settings>,
+    pub auth: Arc<Auth>,
+    pub documents: DocumentStore,
+    pub jobs: JobQueue,
+    pub events: broadcast::Sender<Event>,
+}
+
+impl AppState {
+    pub fn new(settings: Settings, auth: Auth) -> Self {
+        AppState {
+            jobs: JobQueue::new(settings.server.max_jobs),
+            settings: Arc::new(settings),
+            auth: Arc::new(auth),
+            documents: DocumentStore::default(),
+            events: broadcast::channel(EVENT_CAPACITY).0,
+        }
+    }
+
+    ///
+    /// Broadcast an event to all websocket clients, it is dropped if there is none.
+    ///
+    pub fn emit(&self, event: &str, data: impl Serialize) {
+        let _ = self.events.send(Event {
+            event: event.to_string(),
+            data: serde_json::to_value(data).unwrap_or_default(),
+        });
+    }
+}
@@ -0,0 +1,584 @@
+use axum::{
+    body::{to_bytes, Body},
+    http::{Method, Request, StatusCode},
+    Router,
+};
+use serde_json::{json, Value};
+use shared::settings::Settings;
+use tower::ServiceExt;
+
+use super::{app, start, Auth, ServerStatus, StartError};
+
+const TEXT: &str = "第一章 开端\n甲\n第二章 发展\n乙\n";
+pub(crate) const TOKEN: &str = "secret";
+const APP_ORIGIN: &str = "tauri://localhost";
+
+pub(crate) fn test_app() -> Router {
+    let auth = Auth {
+        token: TOKEN.to_string(),
+        origins: vec![APP_ORIGIN.to_string()],
+    };
+    app(Settings::default(), auth)
+}
+
+async fn send(app: &Router, method: Method, uri: &str, body: Body) -> (StatusCode, Vec<u8>) {
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("content-type", "application/json")
+                .header("authorization", format!("Bearer {}", TOKEN))
+                .body(body)
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let status = response.status();
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, body.to_vec())
+}
+
+async fn upload(app: &Router) -> Value {
+    let (status, body) = send(
+        app,
+        Method::POST,
+        "/documents?name=book.txt",
+        Body::from(TEXT),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    serde_json::from_slice(&body).unwrap()
+}
+
+#[tokio::test]
+async fn test_upload_and_get_toc() {
+    let app = test_app();
+    let document = upload(&app).await;
+    assert_eq!(document["title"], "book");
+    assert_eq!(document["toc"].as_array().unwrap().len(), 2);
+
+    let uri = format!("/documents/{}/toc", document["id"].as_str().unwrap());
+    let (status, body) = send(&app, Method::GET, &uri, Body::empty()).await;
+    assert_eq!(status, StatusCode::OK);
+    let toc: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(toc, document["toc"]);
+}
+
+#[tokio::test]
+async fn test_patch_toc_is_atomic() {
+    let app = test_app();
+    let document = upload(&app).await;
+    let uri = format!("/documents/{}/toc", document["id"].as_str().unwrap());
+    let first = document["toc"][0]["id"].clone();
+    let second = document["toc"][1]["id"].clone();
+
+    let edits = json!([
+        { "op": "retitle", "id": first, "title": "序章" },
+        { "op": "move", "id": second, "to": { "type": "belong_to", "parent": 99 } },
+    ]);
+    let (status, body) = send(&app, Method::PATCH, &uri, Body::from(edits.to_string())).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let error: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(error["kind"], "node_not_found");
+
+    let edits = json!([
+        { "op": "retitle", "id": first, "title": "序章" },
+        { "op": "move", "id": second, "to": { "type": "belong_to", "parent": first } },
+    ]);
+    let (status, body) = send(&app, Method::PATCH, &uri, Body::from(edits.to_string())).await;
+    assert_eq!(status, StatusCode::OK);
+    let toc: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(toc.as_array().unwrap().len(), 1);
+    assert_eq!(toc[0]["title"], "序章");
+    assert_eq!(toc[0]["children"][0]["id"], second);
+}
+
+#[tokio::test]
+async fn test_node_by_uid() {
+    let app = test_app();
+    let document = upload(&app).await;
+    let uri = format!("/documents/{}/toc", document["id"].as_str().unwrap());
+    let first = document["toc"][0].clone();
+    let second = document["toc"][1].clone();
+
+    let edits = json!([{ "op": "remove", "id": first["id"] }]);
+    let (status, _) = send(&app, Method::PATCH, &uri, Body::from(edits.to_string())).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, body) = send(
+        &app,
+        Method::GET,
+        &format!("{}/uid/{}", uri, second["uid"].as_str().unwrap()),
+        Body::empty(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let node: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(node["id"], second["id"]);
+    assert_eq!(node["title"], "第二章 发展");
+
+    let (status, body) = send(
+        &app,
+        Method::GET,
+        &format!("{}/uid/{}", uri, first["uid"].as_str().unwrap()),
+        Body::empty(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let error: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(error["kind"], "node_not_found");
+}
+
+#[tokio::test]
+async fn test_preview_and_accept_detection() {
+    let app = test_app();
+    let document = upload(&app).await;
+    let uri = format!("/documents/{}/detect", document["id"].as_str().unwrap());
+
+    let (status, body) = send(
+        &app,
+        Method::GET,
+        &format!("{}/preview", uri),
+        Body::empty(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let candidates: Value = serde_json::from_slice(&body).unwrap();
+    let candidates = candidates.as_array().unwrap();
+    assert_eq!(candidates.len(), 2);
+    assert_eq!(candidates[1]["title"], "第二章 发展");
+    assert_eq!(candidates[1]["line"], 3);
+    assert!(candidates[1]["score"].as_f64().unwrap() > 0.0);
+    assert!(candidates[1]["signals"]["numbering"].is_number());
+
+    let accepted = json!([candidates[1]]);
+    let (status, body) = send(
+        &app,
+        Method::POST,
+        &format!("{}/accept", uri),
+        Body::from(accepted.to_string()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let toc: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(toc.as_array().unwrap().len(), 1);
+    assert_eq!(toc[0]["title"], "第二章 发展");
+
+    let mut invalid = candidates[1].clone();
+    invalid["offset"] = json!(1);
+    let (status, body) = send(
+        &app,
+        Method::POST,
+        &format!("{}/accept", uri),
+        Body::from(json!([invalid]).to_string()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    let error: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(error["kind"], "invalid_document");
+}
+
+#[tokio::test]
+async fn test_lint_and_merge() {
+    let app = test_app();
+    let body = Body::from("第一章 开端\n甲\n第一章 开端\n乙\n第二章 结局\n");
+    let (status, body) = send(&app, Method::POST, "/documents?name=book.txt", body).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let document: Value = serde_json::from_slice(&body).unwrap();
+    let uri = format!("/documents/{}", document["id"].as_str().unwrap());
+
+    let (status, body) = send(&app, Method::GET, &format!("{}/lint", uri), Body::empty()).await;
+    assert_eq!(status, StatusCode::OK);
+    let report: Value = serde_json::from_slice(&body).unwrap();
+    let rules: Vec<_> = report["findings"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|x| x["rule"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        rules,
+        vec!["short_chapter", "repeated_heading", "empty_chapter"]
+    );
+    let repeated = &report["findings"][1];
+    assert_eq!(repeated["fix"]["edits"][0]["op"], "merge");
+
+    let edits = repeated["fix"]["edits"].to_string();
+    let (status, body) = send(
+        &app,
+        Method::PATCH,
+        &format!("{}/toc", uri),
+        Body::from(edits),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let toc: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(toc.as_array().unwrap().len(), 2);
+    assert_eq!(toc[0]["meta"]["range"][1], toc[1]["meta"]["range"][0]);
+}
+
+#[tokio::test]
+async fn test_book_stats() {
+    let app = test_app();
+    let document = upload(&app).await;
+    let uri = format!("/documents/{}/stats", document["id"].as_str().unwrap());
+    let (status, body) = send(&app, Method::GET, &uri, Body::empty()).await;
+    assert_eq!(status, StatusCode::OK);
+    let stats: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(stats["words"], 2);
+    assert_eq!(stats["chapters"], 2);
+    assert_eq!(stats["levels"][0]["nodes"], 2);
+    assert_eq!(stats["longest"][0]["title"], "第一章 开端");
+    assert_eq!(stats["distribution"]["buckets"][0]["chapters"], 2);
+}
+
+#[tokio::test]
+async fn test_reimport() {
+    let app = test_app();
+    let document = upload(&app).await;
+    let uri = format!("/documents/{}", document["id"].as_str().unwrap());
+    let text = format!("{}第三章 结局\n丙\n", TEXT);
+    let (status, body) = send(
+        &app,
+        Method::POST,
+        &format!("{}/reimport", uri),
+        Body::from(text),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let report: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(report["matched"], 2);
+    assert_eq!(report["added"].as_array().unwrap().len(), 1);
+    assert!(report["changed"].as_array().unwrap().is_empty());
+
+    let (_, body) = send(&app, Method::GET, &format!("{}/toc", uri), Body::empty()).await;
+    let toc: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(toc.as_array().unwrap().len(), 3);
+    assert_eq!(toc[0]["id"], document["toc"][0]["id"]);
+    assert_eq!(toc[2]["title"], "第三章 结局");
+}
+
+#[tokio::test]
+async fn test_edit_text_and_revert() {
+    let app = test_app();
+    let document = upload(&app).await;
+    let node = document["toc"][0]["id"].as_u64().unwrap();
+    let uri = format!(
+        "/documents/{}/toc/{}",
+        document["id"].as_str().unwrap(),
+        node
+    );
+
+    let body = json!({ "text": "第一章 开端\n甲改\n" });
+    let (status, body) = send(
+        &app,
+        Method::PUT,
+        &format!("{}/text", uri),
+        Body::from(body.to_string()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let preview: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(preview["text"], "第一章 开端\n甲改\n");
+    let lines = &preview["hunks"][0]["lines"];
+    assert_eq!(lines[1], json!({ "kind": "delete", "text": "甲\n" }));
+    assert_eq!(lines[2], json!({ "kind": "insert", "text": "甲改\n" }));
+
+    let (status, _) = send(
+        &app,
+        Method::DELETE,
+        &format!("{}/hunks/1", uri),
+        Body::empty(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, body) = send(
+        &app,
+        Method::DELETE,
+        &format!("{}/hunks/0", uri),
+        Body::empty(),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let preview: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(preview["text"], "第一章 开端\n甲\n");
+    assert!(preview["hunks"].as_array().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_search_and_replace() {
+    let app = test_app();
+    let document = upload(&app).await;
+    let uri = format!("/documents/{}", document["id"].as_str().unwrap());
+
+    let body = json!({ "pattern": "[甲乙]", "regex": true, "replacement": "丙" });
+    let (status, body) = send(
+        &app,
+        Method::POST,
+        &format!("{}/search", uri),
+        Body::from(body.to_string()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let result: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(result["matches"], 2);
+    assert_eq!(result["nodes"][1]["title"], "第二章 发展");
+    assert_eq!(result["nodes"][1]["matches"][0]["text"], "乙");
+    assert_eq!(result["nodes"][1]["matches"][0]["replacement"], "丙");
+
+    let body = json!({ "pattern": "乙", "replacement": "丙" });
+    let (status, body) = send(
+        &app,
+        Method::POST,
+        &format!("{}/replace", uri),
+        Body::from(body.to_string()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let report: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(report["replacements"], 1);
+    let node = report["nodes"][0].as_u64().unwrap();
+    let (_, body) = send(
+        &app,
+        Method::GET,
+        &format!("{}/toc/{}/text", uri, node),
+        Body::empty(),
+    )
+    .await;
+    let preview: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(preview["text"], "第二章 发展\n丙\n");
+
+    let body = json!({ "pattern": "(", "regex": true });
+    let (status, body) = send(
+        &app,
+        Method::POST,
+        &format!("{}/search", uri),
+        Body::from(body.to_string()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    let error: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(error["kind"], "invalid_search");
+}
+
+#[tokio::test]
+async fn test_download_epub() {
+    let app = test_app();
+    let document = upload(&app).await;
+    let uri = format!("/documents/{}/epub", document["id"].as_str().unwrap());
+    let (status, body) = send(&app, Method::GET, &uri, Body::empty()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(&body[..2], b"PK");
+}
+
+#[tokio::test]
+async fn test_combine_files() {
+    let app = test_app();
+    let body = json!({
+        "files": [
+            { "name": "卷一.txt", "text": TEXT },
+            { "name": "卷二.txt", "text": TEXT },
+        ]
+    });
+    let (status, body) = send(
+        &app,
+        Method::POST,
+        "/documents/combine",
+        Body::from(body.to_string()),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CREATED);
+    let document: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(document["title"], "卷一");
+    let toc = document["toc"].as_array().unwrap();
+    assert_eq!(toc.len(), 2);
+    assert_eq!(toc[1]["title"], "卷二");
+    assert_eq!(toc[1]["kind"], "volume");
+    assert_eq!(toc[1]["meta"]["file"], 1);
+    assert_eq!(toc[1]["children"].as_array().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn test_download_formats() {
+    let app = test_app();
+    let document = upload(&app).await;
+    let id = document["id"].as_str().unwrap();
+    for (format, start) in [
+        ("html", &b"<!DOCTYPE html>"[..]),
+        ("markdown", "# 书".as_bytes()),
+        ("fb2", b"<?xml"),
+        ("txt_zip", b"PK"),
+    ] {
+        let uri = format!("/documents/{}/export?format={}&title=%E4%B9%A6", id, format);
+        let (status, body) = send(&app, Method::GET, &uri, Body::empty()).await;
+        assert_eq!(status, StatusCode::OK, "{}", format);
+        assert!(body.starts_with(start), "{}", format);
+    }
+    let uri = format!("/documents/{}/export?format=pdf", id);
+    let (status, _) = send(&app, Method::GET, &uri, Body::empty()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_check_epub() {
+    let app = test_app();
+    let document = upload(&app).await;
+    let uri = format!("/documents/{}/epub", document["id"].as_str().unwrap());
+    let (_, epub) = send(&app, Method::GET, &uri, Body::empty()).await;
+    let (status, body) = send(&app, Method::POST, "/check", Body::from(epub)).await;
+    assert_eq!(status, StatusCode::OK);
+    let report: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(report["issues"], json!([]));
+
+    let (status, body) = send(&app, Method::POST, "/check", Body::from("not a book")).await;
+    assert_eq!(status, StatusCode::OK);
+    let report: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(report["issues"][0]["category"], "archive");
+}
+
+#[tokio::test]
+async fn test_document_not_found() {
+    let app = test_app();
+    let uri = format!("/documents/{}/toc", uuid::Uuid::new_v4());
+    let (status, _) = send(&app, Method::GET, &uri, Body::empty()).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_token_required() {
+    let app = test_app();
+    let request = |uri: &str, authorization: Option<&str>| {
+        let mut builder = Request::builder().uri(uri);
+        if let Some(value) = authorization {
+            builder = builder.header("authorization", value);
+        }
+        app.clone().oneshot(builder.body(Body::empty()).unwrap())
+    };
+
+    let response = request("/", None).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let error: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(error["kind"], "unauthorized");
+
+    let response = request("/", Some("Bearer wrong")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = request("/ws", None).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    // The token is checked before the route, so unknown documents are not leaked either.
+    let uri = format!("/documents/{}/toc", uuid::Uuid::new_v4());
+    let response = request(&uri, None).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = request("/", Some("Bearer secret")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = request("/?token=secret", None).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_cors_allows_app_origin_only() {
+    let app = test_app();
+    let preflight = |origin: &str| {
+        app.clone().oneshot(
+            Request::builder()
+                .method(Method::OPTIONS)
+                .uri("/documents")
+                .header("origin", origin)
+                .header("access-control-request-method", "POST")
+                .header("access-control-request-headers", "authorization")
+                .body(Body::empty())
+                .unwrap(),
+        )
+    };
+
+    let response = preflight(APP_ORIGIN).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()["access-control-allow-origin"],
+        APP_ORIGIN
+    );
+    let response = preflight("https://example.com").await.unwrap();
+    assert!(!response
+        .headers()
+        .contains_key("access-control-allow-origin"));
+}
+
+#[tokio::test]
+async fn test_start_and_shutdown() {
+    let auth = Auth::new(Vec::new());
+    let handle = start(Settings::default(), 0, auth.clone()).await.unwrap();
+    let addr = handle.addr();
+    assert_ne!(addr.port(), 0);
+    let mut status = handle.status();
+    assert_eq!(*status.borrow(), ServerStatus::Ready { addr });
+
+    // The port is taken until the server is shut down
+    let error = start(Settings::default(), addr.port(), auth.clone())
+        .await
+        .unwrap_err();
+    assert!(matches!(error, StartError::Bind { .. }));
+
+    handle.shutdown().await;
+    status.changed().await.unwrap();
+    assert_eq!(*status.borrow(), ServerStatus::Stopped);
+    let handle = start(Settings::default(), addr.port(), auth).await.unwrap();
+    handle.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_drop_handle_stops_server() {
+    let handle = start(Settings::default(), 0, Auth::new(Vec::new()))
+        .await
+        .unwrap();
+    let addr = handle.addr();
+    let mut status = handle.status();
+    drop(handle);
+    status.changed().await.unwrap();
+    assert_eq!(*status.borrow(), ServerStatus::Stopped);
+    let handle = start(Settings::default(), addr.port(), Auth::new(Vec::new()))
+        .await
+        .unwrap();
+    handle.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_start_invalid_host() {
+    let mut settings = Settings::default();
+    settings.server.host = "localhost:80".to_string();
+    let error = start(settings, 0, Auth::new(Vec::new())).await.unwrap_err();
+    assert!(matches!(error, StartError::InvalidHost { .. }));
+}
+
+#[tokio::test]
+async fn test_conversion_job() {
+    let app = test_app();
+    let document = upload(&app).await;
+    let body = json!({ "document": document["id"], "title": "新书" });
+    let (status, body) = send(&app, Method::POST, "/jobs", Body::from(body.to_string())).await;
+    assert_eq!(status, StatusCode::ACCEPTED);
+    let job: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(job["title"], "新书");
+
+    let uri = format!("/jobs/{}", job["id"].as_str().unwrap());
+    for _ in 0..100 {
+        let (_, body) = send(&app, Method::GET, &uri, Body::empty()).await;
+        let job: Value = serde_json::from_slice(&body).unwrap();
+        if job["status"]["state"] == "finished" {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+    let (status, body) = send(&app, Method::GET, &format!("{}/result", uri), Body::empty()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(&body[..2], b"PK");
+
+    let (status, _) = send(&app, Method::DELETE, &uri, Body::empty()).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (status, body) = send(&app, Method::GET, &format!("{}/result", uri), Body::empty()).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let error: Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(error["kind"], "job_not_found");
+}
@@ -0,0 +1,278 @@
+// Aozora Bunko markup of Japanese source texts. Annotations in `［＃…］` drive the structure:
+// `［＃「第一章」は大見出し］` and the `［＃大見出し］…［＃大見出し終わり］` forms are headings of level 0 to 2
+// for 大, 中 and 小, and `［＃改ページ］` starts a new untitled node unless a heading follows anyway.
+// On export the annotations are stripped from the text, gaiji with a code point become the character, and
+// implicit ruby `漢字《かんじ》` is made explicit, so the typesetter renders every ruby as `<ruby>`.
+// The notation block at the start of the text, which explains these symbols, is dropped.
+
+use regex::Regex;
+
+use crate::{
+    detect::{Detector, Heading},
+    export::Chapter,
+    notes::Span,
+    toc::NodeKind,
+};
+
+#[cfg(test)]
+mod tests;
+
+const ANNOTATION: &str = r"［＃[^］\n]*］";
+const HEADING: &str = r"［＃「(?P<title>[^」\n]+)」は(?:同行|窓)?(?P<size>[大中小])見出し］";
+const BLOCK_HEADING: &str = r"［＃(?:同行|窓)?(?P<size>[大中小])見出し］(?P<title>.+?)［＃(?:同行|窓)?[大中小]見出し終わり］";
+const OPEN_HEADING: &str = r"［＃ここから(?:同行|窓)?(?P<size>[大中小])見出し］";
+const PAGE_BREAK: &str = r"^(?:［＃改(?:ページ|丁|見開き|段)］)+$";
+const GAIJI: &str = r"※［＃[^］\n]*?U\+(?P<code>[0-9A-Fa-f]{4,6})[^］\n]*］";
+const RUBY: &str = r"《[^《》\n]+》";
+const NOTATION: &str = "【テキスト中に現れる記号について】";
+
+/// Whether a text uses Aozora Bunko markup, by its annotations or its notation block.
+pub fn is_aozora(text: &str) -> bool {
+    text.contains("［＃") || text.contains(NOTATION)
+}
+
+#[derive(Debug, Clone)]
+pub struct AozoraParser {
+    annotation: Regex,
+    heading: Regex,
+    block_heading: Regex,
+    open_heading: Regex,
+    page_break: Regex,
+    gaiji: Regex,
+    ruby: Regex,
+}
+
+impl Default for AozoraParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AozoraParser {
+    pub fn new() -> Self {
+        let regex = |x| Regex::new(x).unwrap();
+        AozoraParser {
+            annotation: regex(ANNOTATION),
+            heading: regex(HEADING),
+            block_heading: regex(BLOCK_HEADING),
+            open_heading: regex(OPEN_HEADING),
+            page_break: regex(PAGE_BREAK),
+            gaiji: regex(GAIJI),
+            ruby: regex(RUBY),
+        }
+    }
+
+    ///
+    /// Headings and page breaks of `text`. Without any heading annotation, the headings are detected by
+    /// the rules instead. Kinds come from the kind rules of the detector.
+    ///
+    pub fn detect(&self, text: &str, detector: &Detector) -> Vec<Heading> {
+        let mut headings = self.headings(text);
+        if headings.is_empty() {
+            headings = detector.detect(text);
+        }
+        for heading in headings.iter_mut() {
+            heading.title = self.title(&heading.title);
+            if let Some(kind) = detector.kind(&heading.title) {
+                heading.kind = kind;
+            }
+        }
+        self.page_breaks(text, headings)
+    }
+
+    /// Headings marked by annotations, the line of a heading is the one with its title.
+    pub fn headings(&self, text: &str) -> Vec<Heading> {
+        let mut headings = Vec::new();
+        let mut open: Option<usize> = None; // level of a `ここから` heading whose title is on the next line
+        let mut offset = 0;
+        for (i, line) in text.split_inclusive('\n').enumerate() {
+            let cleaned = self.clean(line);
+            let found = match open {
+                Some(level) if !cleaned.trim().is_empty() => {
+                    open = None;
+                    Some((cleaned.trim().to_string(), level))
+                }
+                _ => self
+                    .heading
+                    .captures(line)
+                    .or_else(|| self.block_heading.captures(line))
+                    .map(|x| (x["title"].to_string(), level(&x["size"]))),
+            };
+            if let Some(x) = self.open_heading.captures(line) {
+                open = Some(level(&x["size"]));
+            }
+            if let Some((title, level)) = found {
+                headings.push(Heading {
+                    title,
+                    level,
+                    kind: NodeKind::Chapter,
+                    rule: 0, // not detected by a rule, but marked by an annotation
+                    line: i + 1,
+                    offset,
+                });
+            }
+            offset += line.len();
+        }
+        headings
+    }
+
+    ///
+    /// Add an untitled node at each page break followed by text, nested under the heading before it.
+    /// Breaks before the first text, or right before a heading, add nothing.
+    ///
+    fn page_breaks(&self, text: &str, headings: Vec<Heading>) -> Vec<Heading> {
+        let mut merged = Vec::with_capacity(headings.len());
+        let mut headings = headings.into_iter().peekable();
+        let mut level = 0; // of the nodes of page breaks, below the last heading
+        let mut has_text = false; // since the last heading or page break
+        let mut pending: Option<(usize, usize)> = None; // (line, offset) of the first unused break
+        let mut offset = 0;
+        for (i, line) in text.split_inclusive('\n').enumerate() {
+            if headings.peek().is_some_and(|x| x.offset == offset) {
+                let heading = headings.next().unwrap();
+                level = heading.level + 1;
+                has_text = false;
+                pending = None;
+                merged.push(heading);
+            } else if self.page_break.is_match(line.trim()) {
+                if has_text && pending.is_none() {
+                    pending = Some((i + 1, offset));
+                }
+            } else if !self.clean(line).trim().is_empty() {
+                if let Some((line, offset)) = pending.take() {
+                    merged.push(Heading {
+                        title: String::new(),
+                        level,
+                        kind: NodeKind::Chapter,
+                        rule: 0,
+                        line,
+                        offset,
+                    });
+                }
+                has_text = true;
+            }
+            offset += line.len();
+        }
+        merged.extend(headings);
+        merged
+    }
+
+    /// Strip annotations and ruby readings from a title.
+    pub fn title(&self, title: &str) -> String {
+        let title = self.annotation.replace_all(title, "");
+        let title = self.ruby.replace_all(&title, "");
+        title.replace(['｜', '|'], "").trim().to_string()
+    }
+
+    ///
+    /// Turn a line of markup into text: gaiji with a code point become the character, other annotations
+    /// are stripped, and implicit ruby is marked with `｜`.
+    ///
+    pub fn clean(&self, line: &str) -> String {
+        let line = self.gaiji.replace_all(line, |x: &regex::Captures| {
+            u32::from_str_radix(&x["code"], 16)
+                .ok()
+                .and_then(char::from_u32)
+                .map(|x| x.to_string())
+                .unwrap_or_else(|| "※".to_string())
+        });
+        let line = self.annotation.replace_all(&line, "");
+        self.explicit_ruby(&line)
+    }
+
+    /// Clean the paragraphs of chapters and drop the notation block, paragraphs left empty are dropped.
+    pub fn apply(&self, chapters: &mut [Chapter]) {
+        for chapter in chapters.iter_mut() {
+            let mut paragraphs = Vec::with_capacity(chapter.paragraphs.len());
+            let mut notation = false;
+            for (i, paragraph) in chapter.paragraphs.iter().enumerate() {
+                let is_rule = paragraph.len() >= 10 && paragraph.chars().all(|x| x == '-');
+                if is_rule
+                    && (notation
+                        || chapter
+                            .paragraphs
+                            .get(i + 1)
+                            .is_some_and(|x| x.contains(NOTATION)))
+                {
+                    notation = !notation;
+                    continue;
+                }
+                if notation {
+                    continue;
+                }
+                let paragraph = self.clean(paragraph);
+                if !paragraph.trim().is_empty() {
+                    paragraphs.push(paragraph.trim().to_string());
+                }
+            }
+            chapter.spans = paragraphs.iter().map(|x| vec![Span::text(x)]).collect();
+            chapter.paragraphs = paragraphs;
+        }
+    }
+
+    ///
+    /// Mark the base of implicit ruby with `｜`, the base being the run of characters of the same script
+    /// before `《`. Ruby after a `｜` is explicit already.
+    ///
+    fn explicit_ruby(&self, text: &str) -> String {
+        let mut result = String::with_capacity(text.len());
+        let mut last = 0;
+        for ruby in self.ruby.find_iter(text) {
+            let before = &text[last..ruby.start()];
+            match before.contains(['｜', '|']) {
+                true => result.push_str(before),
+                false => {
+                    let base = before.chars().next_back().and_then(script);
+                    let start = before
+                        .char_indices()
+                        .rev()
+                        .take_while(|(_, x)| base.is_some() && script(*x) == base)
+                        .last()
+                        .map_or(before.len(), |x| x.0);
+                    result.push_str(&before[..start]);
+                    if start < before.len() {
+                        result.push('｜');
+                    }
+                    result.push_str(&before[start..]);
+                }
+            }
+            result.push_str(ruby.as_str());
+            last = ruby.end();
+        }
+        result.push_str(&text[last..]);
+        result
+    }
+}
+
+fn level(size: &str) -> usize {
+    match size {
+        "大" => 0,
+        "中" => 1,
+        _ => 2,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Script {
+    Kanji,
+    Hiragana,
+    Katakana,
+    FullwidthLatin,
+    Latin,
+}
+
+/// The script of a character, for the base of implicit ruby.
+fn script(c: char) -> Option<Script> {
+    match c {
+        '々' | '〆' | '〇' | 'ヶ' | '仝' => Some(Script::Kanji),
+        '\u{3400}'..='\u{4DBF}'
+        | '\u{4E00}'..='\u{9FFF}'
+        | '\u{F900}'..='\u{FAFF}'
+        | '\u{20000}'..='\u{2FA1F}' => Some(Script::Kanji),
+        'ぁ'..='ゖ' | 'ゝ' | 'ゞ' => Some(Script::Hiragana),
+        'ァ'..='ヺ' | 'ー' | 'ヽ' | 'ヾ' => Some(Script::Katakana),
+        'Ａ'..='Ｚ' | 'ａ'..='ｚ' | '０'..='９' => Some(Script::FullwidthLatin),
+        _ if c.is_ascii_alphanumeric() => Some(Script::Latin),
+        _ => None,
+    }
+}
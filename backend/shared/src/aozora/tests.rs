@@ -0,0 +1,169 @@
+use std::io::{Cursor, Read};
+
+use super::{is_aozora, AozoraParser};
+use crate::{
+    detect::Detector,
+    document::Document,
+    epubcheck::check_epub,
+    export::{export, BookMeta, Format},
+    settings::{HeadingRule, Settings},
+    toc::NodeKind,
+};
+
+const TEXT: &str = "吾輩は猫である
+夏目漱石
+
+-------------------------------------------------------
+【テキスト中に現れる記号について】
+
+《》：ルビ
+（例）吾輩《わがはい》
+-------------------------------------------------------
+
+［＃３字下げ］一［＃「一」は大見出し］
+
+　｜吾輩《わがはい》は猫である。名前はまだ無い。
+　どこで生れたか頓《とん》と見当《けんとう》がつかぬ。
+［＃改ページ］
+　※［＃「木＋觜」、U+6A36、12-3］の下で、ニャーと鳴いた。［＃「ニャー」に傍点］
+
+［＃改ページ］
+
+［＃ここから中見出し］
+二
+［＃ここで中見出し終わり］
+　吾輩は主人の書斎《しょさい》で眠った。
+［＃改丁］
+［＃小見出し］後記［＃小見出し終わり］
+　おわり
+";
+
+fn parse() -> Document {
+    let mut document = Document::new(TEXT.to_string());
+    document.detect(&Settings::default().detection).unwrap();
+    document
+}
+
+#[test]
+fn test_is_aozora() {
+    assert!(is_aozora(TEXT));
+    assert!(!is_aozora("第一章 开始\n《三国演义》\n"));
+}
+
+#[test]
+fn test_headings() {
+    let document = parse();
+    let nodes: Vec<_> = document
+        .toc
+        .iter()
+        .map(|(depth, x)| (depth, x.title.as_str(), x.kind))
+        .collect();
+    assert_eq!(
+        nodes,
+        vec![
+            (0, "一", NodeKind::Chapter),
+            (1, "", NodeKind::Chapter),
+            (1, "二", NodeKind::Chapter),
+            (2, "後記", NodeKind::Back),
+        ]
+    );
+    let texts: Vec<_> = document
+        .toc
+        .iter()
+        .map(|(_, x)| document.node_text(x).unwrap())
+        .collect();
+    assert!(texts[0].starts_with("［＃３字下げ］一［＃「一」は大見出し］\n"));
+    assert!(texts[1].starts_with("［＃改ページ］\n　※"));
+    // The break right before a heading adds no node
+    assert!(texts[1].ends_with("［＃改ページ］\n\n［＃ここから中見出し］\n"));
+    assert!(texts[2].starts_with("二\n"));
+    assert!(texts[3].starts_with("［＃小見出し］後記"));
+}
+
+#[test]
+fn test_fallback_to_rules() {
+    let mut settings = Settings::default().detection;
+    settings.presets.clear();
+    settings.rules = vec![HeadingRule {
+        pattern: "^第[一二三]章".to_string(),
+        level: 1,
+        kind: None,
+    }];
+    let detector = Detector::new(&settings).unwrap();
+    let text = "第一章 ｜始《はじ》まり\n本文\n［＃改ページ］\n続き\n第二章\n終わり\n";
+    let headings = AozoraParser::new().detect(text, &detector);
+    let found: Vec<_> = headings
+        .iter()
+        .map(|x| (x.title.as_str(), x.level, x.line))
+        .collect();
+    assert_eq!(
+        found,
+        vec![("第一章 始まり", 1, 1), ("", 2, 3), ("第二章", 1, 5)]
+    );
+
+    settings.aozora = false;
+    let mut document = Document::new(text.to_string());
+    document.detect(&settings).unwrap();
+    assert_eq!(document.toc.iter().count(), 2);
+}
+
+#[test]
+fn test_clean() {
+    let parser = AozoraParser::new();
+    assert_eq!(
+        parser.clean("｜吾輩《わがはい》と見当《けんとう》とカタカナ《かたかな》"),
+        "｜吾輩《わがはい》と｜見当《けんとう》と｜カタカナ《かたかな》"
+    );
+    assert_eq!(
+        parser.clean("ＡＢＣ《エービーシー》とabc《えーびーしー》"),
+        "｜ＡＢＣ《エービーシー》と｜abc《えーびーしー》"
+    );
+    assert_eq!(parser.clean("「《》」"), "「《》」");
+    assert_eq!(parser.clean("。《よみ》"), "。《よみ》");
+    assert_eq!(
+        parser.clean(
+            "［＃２字下げ］※［＃「木＋觜」、U+6A36、12-3］と※［＃「てへん＋劣」、第3水準1-84-77］"
+        ),
+        "樶と※"
+    );
+    assert_eq!(
+        parser.title("｜第一《だいいち》章［＃「第一章」は中見出し］"),
+        "第一章"
+    );
+}
+
+#[test]
+fn test_export() {
+    let settings = Settings::default();
+    let document = parse();
+    let book = BookMeta::new("吾輩は猫である", &settings.export);
+
+    let epub = export(&document, &settings, &book, Format::Epub).unwrap();
+    let report = check_epub(&epub);
+    assert!(report.issues.is_empty(), "{:?}", report.issues);
+    let mut archive = zip::ZipArchive::new(Cursor::new(epub)).unwrap();
+    let mut read = |name: &str| {
+        let mut text = String::new();
+        archive
+            .by_name(name)
+            .unwrap()
+            .read_to_string(&mut text)
+            .unwrap();
+        text
+    };
+    let front = read("OEBPS/chapter_0000.xhtml");
+    assert!(front.contains("<p>夏目漱石</p>"));
+    assert!(!front.contains("テキスト中に現れる記号"));
+    let chapter = read("OEBPS/chapter_0001.xhtml");
+    assert!(chapter.contains("<h1>一</h1>"));
+    assert!(chapter.contains("<p><ruby>吾輩<rt>わがはい</rt></ruby>は猫である。"));
+    assert!(chapter.contains("<ruby>頓<rt>とん</rt></ruby>と<ruby>見当<rt>けんとう</rt></ruby>"));
+    let page = read("OEBPS/chapter_0002.xhtml");
+    assert!(!page.contains("<h2>"));
+    assert!(
+        page.contains("<p>樶の下で、ニャーと鳴いた。</p>"),
+        "{}",
+        page
+    );
+    assert!(!read("OEBPS/nav.xhtml").contains("chapter_0002.xhtml"));
+}
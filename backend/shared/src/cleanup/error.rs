@@ -0,0 +1,7 @@
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum CleanupError {
+    #[error("the pattern of cleanup rule `{index}` is invalid: {source}")]
+    InvalidPattern { index: usize, source: regex::Error },
+}
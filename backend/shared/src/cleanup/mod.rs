@@ -0,0 +1,77 @@
+// Cleanup normalizes chapter text before it is rendered: user rules first, then whitespace handling.
+
+use regex::Regex;
+
+use crate::settings::CleanupSettings;
+
+pub use self::error::CleanupError;
+
+mod error;
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Debug, Clone)]
+pub struct Cleaner {
+    rules: Vec<(Regex, String)>,
+    trim_lines: bool,
+    collapse_blank_lines: bool,
+}
+
+impl Cleaner {
+    pub fn new(settings: &CleanupSettings) -> Result<Self, CleanupError> {
+        let rules = settings
+            .rules
+            .iter()
+            .enumerate()
+            .map(|(index, rule)| {
+                Regex::new(&rule.pattern)
+                    .map(|x| (x, rule.replace.clone()))
+                    .map_err(|source| CleanupError::InvalidPattern { index, source })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Cleaner {
+            rules,
+            trim_lines: settings.trim_lines,
+            collapse_blank_lines: settings.collapse_blank_lines,
+        })
+    }
+
+    pub fn clean(&self, text: &str) -> String {
+        let mut text = text.replace("\r\n", "\n");
+        for (re, replace) in self.rules.iter() {
+            text = re.replace_all(&text, replace.as_str()).into_owned();
+        }
+        let mut lines: Vec<&str> = Vec::new();
+        for line in text.lines() {
+            let line = match self.trim_lines {
+                true => line.trim_matches(|c: char| c.is_whitespace()),
+                false => line,
+            };
+            let is_blank = line.trim().is_empty();
+            if self.collapse_blank_lines
+                && is_blank
+                && lines.last().is_none_or(|x| x.trim().is_empty())
+            {
+                continue;
+            }
+            lines.push(line);
+        }
+        if self.collapse_blank_lines {
+            while lines.last().is_some_and(|x| x.trim().is_empty()) {
+                lines.pop();
+            }
+        }
+        lines.join("\n")
+    }
+
+    /// Clean the text and split it into non-empty paragraphs, one per line.
+    pub fn paragraphs(&self, text: &str) -> Vec<String> {
+        self.clean(text)
+            .lines()
+            .map(|x| x.trim())
+            .filter(|x| !x.is_empty())
+            .map(|x| x.to_string())
+            .collect()
+    }
+}
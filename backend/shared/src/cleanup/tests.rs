@@ -0,0 +1,40 @@
+use super::Cleaner;
+use crate::settings::{CleanupRule, Settings};
+
+#[test]
+fn test_clean() {
+    let cleaner = Cleaner::new(&Settings::default().cleanup).unwrap();
+    assert_eq!(
+        cleaner.clean("\n\n　　第一段 \r\n\n\n\n  第二段\n\n"),
+        "第一段\n\n第二段"
+    );
+}
+
+#[test]
+fn test_rules() {
+    let mut settings = Settings::default().cleanup;
+    settings.rules = vec![
+        CleanupRule {
+            pattern: "本章完.*".to_string(),
+            replace: String::new(),
+        },
+        CleanupRule {
+            pattern: r"(\d+)年".to_string(),
+            replace: "$1 年".to_string(),
+        },
+    ];
+    let cleaner = Cleaner::new(&settings).unwrap();
+    assert_eq!(
+        cleaner.paragraphs("2024年\n本章完，求票\n下一段"),
+        vec!["2024 年", "下一段"]
+    );
+}
+
+#[test]
+fn test_keep_whitespace() {
+    let mut settings = Settings::default().cleanup;
+    settings.trim_lines = false;
+    settings.collapse_blank_lines = false;
+    let cleaner = Cleaner::new(&settings).unwrap();
+    assert_eq!(cleaner.clean("  a\n\n\nb"), "  a\n\n\nb");
+}
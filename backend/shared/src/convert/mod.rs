@@ -0,0 +1,169 @@
+// Conversion between simplified and traditional Chinese on export, so one source text serves both
+// zh-Hans and zh-Hant readers. The built-in tables of zhconv (OpenCC and MediaWiki) map phrases before
+// characters, e.g. "软件" becomes "軟體" for Taiwan but "軟件" for Hong Kong. Phrases of the settings take
+// precedence over the built-in ones. The book language follows the target.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use zhconv::{get_builtin_converter, get_builtin_tables, ZhConverter, ZhConverterBuilder};
+
+use crate::{
+    export::{BookMeta, Chapter},
+    notes::Span,
+    settings::Phrase,
+};
+
+#[cfg(test)]
+mod tests;
+
+/// Target of the conversion, `none` keeps the text as it is.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Variant {
+    #[default]
+    None,
+    Hans, // simplified, without regional phrases
+    Hant, // traditional, without regional phrases
+    Cn,   // simplified, mainland China
+    Tw,   // traditional, Taiwan
+    Hk,   // traditional, Hong Kong
+}
+
+impl Variant {
+    /// BCP 47 tag of the converted text, for the language of the book.
+    pub fn language(self) -> Option<&'static str> {
+        match self {
+            Variant::None => None,
+            Variant::Hans => Some("zh-Hans"),
+            Variant::Hant => Some("zh-Hant"),
+            Variant::Cn => Some("zh-CN"),
+            Variant::Tw => Some("zh-TW"),
+            Variant::Hk => Some("zh-HK"),
+        }
+    }
+
+    fn target(self) -> Option<zhconv::Variant> {
+        match self {
+            Variant::None => None,
+            Variant::Hans => Some(zhconv::Variant::ZhHans),
+            Variant::Hant => Some(zhconv::Variant::ZhHant),
+            Variant::Cn => Some(zhconv::Variant::ZhCN),
+            Variant::Tw => Some(zhconv::Variant::ZhTW),
+            Variant::Hk => Some(zhconv::Variant::ZhHK),
+        }
+    }
+}
+
+enum Tables {
+    Builtin(&'static ZhConverter),
+    Custom(Box<ZhConverter>), // the built-in tables with the phrases of the settings
+}
+
+pub struct Converter {
+    variant: Variant,
+    tables: Tables,
+}
+
+impl fmt::Debug for Converter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Converter")
+            .field("variant", &self.variant)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Converter {
+    ///
+    /// A converter to the variant, None for `Variant::None`.
+    /// Without phrases the shared built-in converter is used, which is only built once.
+    ///
+    pub fn new(variant: Variant, phrases: &[Phrase]) -> Option<Self> {
+        let target = variant.target()?;
+        let tables = match phrases.is_empty() {
+            true => Tables::Builtin(get_builtin_converter(target)),
+            false => Tables::Custom(Box::new(
+                ZhConverterBuilder::new()
+                    .target(target)
+                    .tables(get_builtin_tables(target))
+                    .conv_pairs(
+                        phrases
+                            .iter()
+                            .filter(|x| !x.from.is_empty())
+                            .map(|x| (x.from.as_str(), x.to.as_str())),
+                    )
+                    .build(),
+            )),
+        };
+        Some(Converter { variant, tables })
+    }
+
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+
+    pub fn convert(&self, text: &str) -> String {
+        match &self.tables {
+            Tables::Builtin(x) => x.convert(text),
+            Tables::Custom(x) => x.convert(text),
+        }
+    }
+
+    /// Convert the title and author, and set the language to the variant.
+    pub fn convert_book(&self, book: &mut BookMeta) {
+        book.title = self.convert(&book.title);
+        book.author = book.author.as_deref().map(|x| self.convert(x));
+        if let Some(language) = self.variant.language() {
+            book.language = language.to_string();
+        }
+    }
+
+    /// Convert titles, text and footnotes. Images keep their names, which refer to files.
+    pub fn convert_chapters(&self, chapters: &mut [Chapter]) {
+        for chapter in chapters.iter_mut() {
+            chapter.title = self.convert(&chapter.title);
+            for title in chapter.path.iter_mut() {
+                *title = self.convert(title);
+            }
+            for paragraph in chapter.paragraphs.iter_mut() {
+                *paragraph = self.convert(paragraph);
+            }
+            for spans in chapter.spans.iter_mut() {
+                self.convert_spans(spans);
+            }
+            for note in chapter.notes.iter_mut() {
+                note.text = self.convert(&note.text);
+            }
+        }
+    }
+
+    ///
+    /// Convert the spans of a paragraph as one text, so phrases split by noterefs or ruby are still
+    /// converted as phrases, then split it again where the spans were. A boundary is placed after the
+    /// conversion of the text before it, phrases across it may change length. Images break the text.
+    ///
+    fn convert_spans(&self, spans: &mut [Span]) {
+        for run in spans.split_mut(|x| x.image.is_some()) {
+            let text: String = run.iter().map(|x| x.text.as_str()).collect();
+            let converted = self.convert(&text);
+            let offset = |chars: usize| {
+                converted
+                    .char_indices()
+                    .nth(chars)
+                    .map_or(converted.len(), |x| x.0)
+            };
+            let mut prefix = String::new();
+            let mut start = 0;
+            let last = run.len().saturating_sub(1);
+            for (i, span) in run.iter_mut().enumerate() {
+                prefix.push_str(&span.text);
+                let end = match i == last {
+                    true => converted.len(),
+                    false => offset(self.convert(&prefix).chars().count()).max(start),
+                };
+                span.text = converted[start..end].to_string();
+                start = end;
+            }
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use std::io::{Cursor, Read};
+
+use super::{Converter, Variant};
+use crate::{
+    document::Document,
+    export::{export, BookMeta, Format},
+    notes::Span,
+    settings::{Phrase, Settings},
+};
+
+#[test]
+fn test_none() {
+    assert!(Converter::new(Variant::None, &[]).is_none());
+}
+
+#[test]
+fn test_regional_phrases() {
+    let convert = |variant| Converter::new(variant, &[]).unwrap().convert("软件和头发");
+    assert_eq!(convert(Variant::Hant), "軟件和頭髮");
+    assert_eq!(convert(Variant::Tw), "軟體和頭髮");
+    assert_eq!(convert(Variant::Hk), "軟件和頭髮");
+
+    let convert = |variant| Converter::new(variant, &[]).unwrap().convert("軟體和頭髮");
+    assert_eq!(convert(Variant::Hans), "软体和头发");
+    assert_eq!(convert(Variant::Cn), "软件和头发");
+}
+
+#[test]
+fn test_custom_phrases() {
+    let phrases = vec![
+        Phrase {
+            from: "张三".to_string(),
+            to: "張三丰".to_string(),
+        },
+        Phrase {
+            from: "软件".to_string(),
+            to: "程式".to_string(),
+        },
+    ];
+    let converter = Converter::new(Variant::Tw, &phrases).unwrap();
+    assert_eq!(converter.variant(), Variant::Tw);
+    assert_eq!(converter.convert("张三的软件和头发"), "張三丰的程式和頭髮");
+}
+
+#[test]
+fn test_convert_spans() {
+    let phrases = vec![Phrase {
+        from: "张三".to_string(),
+        to: "張三丰".to_string(),
+    }];
+    let converter = Converter::new(Variant::Tw, &phrases).unwrap();
+    let mut spans = vec![
+        Span::text("用软"),
+        Span::text("件"),
+        Span {
+            image: Some("images/1.png".to_string()),
+            ..Span::text("软件")
+        },
+        Span::text("张"),
+        Span::text("三的头发"),
+    ];
+    converter.convert_spans(&mut spans);
+    let texts: Vec<_> = spans.iter().map(|x| x.text.as_str()).collect();
+    // Phrases across spans are converted as a whole, images keep their alt text and break the text
+    assert_eq!(texts, vec!["用軟", "體", "软件", "張", "三丰的頭髮"]);
+}
+
+#[test]
+fn test_export() {
+    let mut settings = Settings::default();
+    settings.export.convert = Variant::Tw;
+    let mut document =
+        Document::new("第一卷 开始\n第一章 软件\n头发（注1）\n注1：鼠标\n<插图1>\n".to_string());
+    document.detect(&settings.detection).unwrap();
+    let book = BookMeta::new("后台", &settings.export);
+    assert_eq!(book.convert, Variant::Tw);
+
+    let markdown = export(&document, &settings, &book, Format::Markdown).unwrap();
+    let markdown = String::from_utf8(markdown).unwrap();
+    assert!(markdown.starts_with("# 後台\n"), "{}", markdown);
+    assert!(markdown.contains("## 第一卷 開始\n"));
+    assert!(markdown.contains("### 第一章 軟體\n\n頭髮[^c1-note-1]\n"));
+    assert!(markdown.contains("[^c1-note-1]: 滑鼠\n"));
+    // Markers of missing images are left in the text, so they are converted as well
+    assert!(markdown.contains("插圖1"));
+
+    let epub = export(&document, &settings, &book, Format::Epub).unwrap();
+    let mut archive = zip::ZipArchive::new(Cursor::new(epub)).unwrap();
+    let mut opf = String::new();
+    archive
+        .by_name("OEBPS/content.opf")
+        .unwrap()
+        .read_to_string(&mut opf)
+        .unwrap();
+    assert!(opf.contains("<dc:language>zh-TW</dc:language>"), "{}", opf);
+    assert!(opf.contains("後台"));
+}
@@ -0,0 +1,13 @@
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum DetectError {
+    #[error("the pattern of rule `{index}` is invalid: {source}")]
+    InvalidPattern { index: usize, source: regex::Error },
+
+    #[error("the pattern of kind rule `{index}` is invalid: {source}")]
+    InvalidKindPattern { index: usize, source: regex::Error },
+
+    #[error("there is no detection preset named `{0}`")]
+    UnknownPreset(String),
+}
@@ -0,0 +1,156 @@
+// Heading detection splits a plain text into a toc by matching each line against the configured rules.
+// A node's range starts at its heading line and ends where the next heading (of any level) starts,
+// so a volume node only covers its own intro text, the chapters under it cover the rest.
+// The kind of a heading comes from the first kind rule matching its title, or else from its heading rule.
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    document::count_words,
+    settings::DetectionSettings,
+    toc::{NodeKind, Toc, TocRoot, TreeNodeMeta},
+};
+
+pub use self::error::DetectError;
+pub use self::presets::Preset;
+pub use self::preview::{number, numbering, renumber, Candidate, Signals};
+
+mod error;
+mod presets;
+mod preview;
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Heading {
+    pub title: String,
+    pub level: usize,
+    pub kind: NodeKind,
+    pub rule: usize,   // index of the matched rule
+    pub line: usize,   // 1-based line number
+    pub offset: usize, // byte offset of the line start
+}
+
+#[derive(Debug, Clone)]
+pub struct Detector {
+    rules: Vec<(Regex, usize, Option<NodeKind>)>,
+    kinds: Vec<(Regex, NodeKind)>,
+    max_title_length: usize,
+    min_score: f32,
+}
+
+impl Detector {
+    ///
+    /// The rules of the settings come first, then the ones of each preset in order,
+    /// indices of matched rules count in this order.
+    ///
+    pub fn new(settings: &DetectionSettings) -> Result<Self, DetectError> {
+        let presets = settings
+            .presets
+            .iter()
+            .map(|x| Preset::get(x))
+            .collect::<Result<Vec<_>, _>>()?;
+        let rules = settings
+            .rules
+            .iter()
+            .chain(presets.iter().flat_map(|x| x.rules.iter()))
+            .enumerate()
+            .map(|(index, rule)| {
+                Regex::new(&rule.pattern)
+                    .map(|x| (x, rule.level, rule.kind))
+                    .map_err(|source| DetectError::InvalidPattern { index, source })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let kinds = settings
+            .kinds
+            .iter()
+            .chain(presets.iter().flat_map(|x| x.kinds.iter()))
+            .enumerate()
+            .map(|(index, rule)| {
+                Regex::new(&rule.pattern)
+                    .map(|x| (x, rule.kind))
+                    .map_err(|source| DetectError::InvalidKindPattern { index, source })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Detector {
+            rules,
+            kinds,
+            max_title_length: settings.max_title_length,
+            min_score: settings.min_score,
+        })
+    }
+
+    /// Find all heading lines in `text`, in order. The first matching rule wins.
+    pub fn detect(&self, text: &str) -> Vec<Heading> {
+        let mut headings = Vec::new();
+        let mut offset = 0;
+        for (i, line) in text.split_inclusive('\n').enumerate() {
+            let title = line.trim();
+            if !title.is_empty() && title.chars().count() <= self.max_title_length {
+                if let Some((rule, (_, level, kind))) = self
+                    .rules
+                    .iter()
+                    .enumerate()
+                    .find(|(_, (re, _, _))| re.is_match(title))
+                {
+                    headings.push(Heading {
+                        title: title.to_string(),
+                        level: *level,
+                        kind: self.kind(title).or(*kind).unwrap_or_default(),
+                        rule,
+                        line: i + 1,
+                        offset,
+                    });
+                }
+            }
+            offset += line.len();
+        }
+        headings
+    }
+
+    /// The kind of the first kind rule matching `title`.
+    pub fn kind(&self, title: &str) -> Option<NodeKind> {
+        self.kinds
+            .iter()
+            .find(|(re, _)| re.is_match(title))
+            .map(|x| x.1)
+    }
+
+    /// Detect headings and build a toc from them.
+    pub fn build_toc(&self, text: &str) -> TocRoot {
+        build_toc(text, &self.detect(text))
+    }
+}
+
+/// Build a toc from headings sorted by offset. A heading becomes a child of the nearest previous heading with a lower level.
+pub fn build_toc(text: &str, headings: &[Heading]) -> TocRoot {
+    let mut toc = TocRoot::new();
+    let mut stack: Vec<(usize, usize)> = Vec::new(); // (level, node id)
+    for (i, heading) in headings.iter().enumerate() {
+        let end = headings.get(i + 1).map(|x| x.offset).unwrap_or(text.len());
+        while stack
+            .last()
+            .is_some_and(|(level, _)| *level >= heading.level)
+        {
+            stack.pop();
+        }
+        let body = &text[heading.offset..end];
+        let body = body.split_once('\n').map(|x| x.1).unwrap_or_default();
+        let id = toc
+            .add_with_meta(
+                &heading.title,
+                Some(TreeNodeMeta::new(
+                    count_words(body),
+                    (heading.offset as u128, end as u128),
+                )),
+                stack.last().map(|x| x.1),
+            )
+            .expect("parent is always in the toc")
+            .id;
+        toc.get_mut(id).unwrap().kind = heading.kind;
+        stack.push((heading.level, id));
+    }
+    toc
+}
@@ -0,0 +1,49 @@
+// Built-in detection presets, one per heading style of a language or source. Settings pick presets by name
+// in `detection.presets`; their rules follow the rules of the settings, so user rules win and extend them.
+
+use serde::{Deserialize, Serialize};
+
+use crate::settings::{HeadingRule, KindRule};
+
+use super::DetectError;
+
+const PRESETS: &[(&str, &str)] = &[
+    ("zh", include_str!("presets/zh.toml")),
+    ("en", include_str!("presets/en.toml")),
+    ("ja", include_str!("presets/ja.toml")),
+    ("ko", include_str!("presets/ko.toml")),
+];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Preset {
+    #[serde(default)]
+    pub name: String,
+    pub description: String,
+    pub rules: Vec<HeadingRule>,
+    #[serde(default)]
+    pub kinds: Vec<KindRule>,
+}
+
+impl Preset {
+    /// A built-in preset by name.
+    pub fn get(name: &str) -> Result<Self, DetectError> {
+        let (name, source) = PRESETS
+            .iter()
+            .find(|x| x.0 == name)
+            .ok_or_else(|| DetectError::UnknownPreset(name.to_string()))?;
+        let preset: Preset = toml::from_str(source).expect("built-in presets are valid");
+        Ok(Preset {
+            name: name.to_string(),
+            ..preset
+        })
+    }
+
+    /// All built-in presets.
+    pub fn all() -> Vec<Self> {
+        PRESETS.iter().map(|x| Preset::get(x.0).unwrap()).collect()
+    }
+
+    pub fn names() -> impl Iterator<Item = &'static str> {
+        PRESETS.iter().map(|x| x.0)
+    }
+}
@@ -0,0 +1,408 @@
+// A dry run of detection: every heading found by the rules becomes a candidate with a confidence score,
+// so users can accept or reject candidates before the toc is built. Rules alone can't tell a heading from
+// a line like "第二回见到她的时候，她正在哭。", so the score weighs signals of the line and its surroundings.
+// Each signal is in 0..=1, and the score is their weighted mean.
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::document::count_words;
+
+use super::{Detector, Heading};
+
+const WEIGHTS: Signals = Signals {
+    line: 0.3,
+    blank_lines: 0.15,
+    numbering: 0.3,
+    gap: 0.25,
+};
+
+/// Punctuation which ends sentences or quotes dialogue, rarely seen in titles.
+const SENTENCE_MARKS: &[char] = &[
+    '。', '，', '、', '；', '！', '？', '…', '“', '”', '「', '」', '『', '』', '"', ',', ';',
+];
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Candidate {
+    #[serde(flatten)]
+    pub heading: Heading,
+    pub score: f32,
+    pub signals: Signals,
+    pub accepted: bool, // the score reaches `detection.min_score`
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Signals {
+    pub line: f32,        // short lines without sentence punctuation
+    pub blank_lines: f32, // blank lines around the heading
+    pub numbering: f32, // the number follows the previous heading of its rule, or leads to the next one
+    pub gap: f32,       // the text since the previous heading of its level is not unusually short
+}
+
+impl Signals {
+    fn score(&self) -> f32 {
+        let weights = WEIGHTS;
+        (self.line * weights.line
+            + self.blank_lines * weights.blank_lines
+            + self.numbering * weights.numbering
+            + self.gap * weights.gap)
+            / (weights.line + weights.blank_lines + weights.numbering + weights.gap)
+    }
+}
+
+impl Detector {
+    /// Detect headings in `text` and score them, without building a toc.
+    pub fn preview(&self, text: &str) -> Vec<Candidate> {
+        self.score(text, self.detect(text))
+    }
+
+    /// Score headings sorted by offset, e.g. the ones detected per file of a combined document.
+    pub fn score(&self, text: &str, headings: Vec<Heading>) -> Vec<Candidate> {
+        let lines: Vec<&str> = text.lines().collect();
+        let has_blank_lines = lines.iter().any(|x| x.trim().is_empty());
+        let numbers: Vec<_> = headings
+            .iter()
+            .map(|x| (number(&x.title), (x.rule, x.level, numbering(&x.title))))
+            .collect();
+        let gaps = gap_signals(text, &headings);
+        let mut candidates = Vec::with_capacity(headings.len());
+        for (i, heading) in headings.into_iter().enumerate() {
+            let signals = Signals {
+                line: self.line_signal(&heading.title),
+                blank_lines: match has_blank_lines {
+                    true => blank_lines_signal(&lines, heading.line),
+                    false => 0.7, // the text has no blank lines at all, so they tell nothing
+                },
+                numbering: numbering_signal(i, &numbers),
+                gap: gaps[i],
+            };
+            let score = signals.score();
+            candidates.push(Candidate {
+                heading,
+                score,
+                signals,
+                accepted: score >= self.min_score,
+            });
+        }
+        candidates
+    }
+
+    fn line_signal(&self, title: &str) -> f32 {
+        let length = title.chars().count() as f32 / self.max_title_length.max(1) as f32;
+        let signal = 1.0 - length.min(1.0) * 0.5;
+        match title.contains(SENTENCE_MARKS) {
+            true => signal * 0.3,
+            false => signal,
+        }
+    }
+}
+
+fn blank_lines_signal(lines: &[&str], line: usize) -> f32 {
+    let is_blank = |x: Option<&&str>| x.is_none_or(|x| x.trim().is_empty());
+    let before = line < 2 || is_blank(lines.get(line - 2));
+    let after = is_blank(lines.get(line));
+    match (before, after) {
+        (true, true) => 1.0,
+        (true, false) | (false, true) => 0.7,
+        (false, false) => 0.4,
+    }
+}
+
+///
+/// 1 if the number follows the previous numbered heading of the same rule, level and numbering, or leads to
+/// the next one,
+/// 0.75 for a restart at 1, 0.5 without a number or any other numbered heading, 0.25 otherwise.
+///
+fn numbering_signal(i: usize, numbers: &[(Option<u64>, NumberingKey)]) -> f32 {
+    let (Some(n), key) = &numbers[i] else {
+        return 0.5;
+    };
+    let previous = numbers[..i]
+        .iter()
+        .rev()
+        .filter(|x| x.1 == *key)
+        .find_map(|x| x.0);
+    let next = numbers[i + 1..]
+        .iter()
+        .filter(|x| x.1 == *key)
+        .find_map(|x| x.0);
+    if previous.is_some_and(|x| x + 1 == *n) || next.is_some_and(|x| n + 1 == x) {
+        1.0
+    } else if *n == 1 {
+        0.75
+    } else if previous.is_none() && next.is_none() {
+        0.5
+    } else {
+        0.25
+    }
+}
+
+type NumberingKey = (usize, usize, String); // rule, level and numbering
+
+///
+/// The title around its number, e.g. "第#章" for "第十二章 开端", so chapters are not numbered along with
+/// "第二回" of the same rule.
+///
+pub fn numbering(title: &str) -> String {
+    let is_number = |x: char| is_digit(x) || chinese_digit(x).is_some();
+    let Some(start) = title.find(is_number) else {
+        return String::new();
+    };
+    let rest = title[start..].trim_start_matches(is_number);
+    let unit: String = rest.chars().take(1).collect();
+    format!("{}#{}", &title[..start], unit)
+}
+
+///
+/// Words since the previous heading of the same level, relative to the median of the level.
+/// Gaps of at least a quarter of the median are as good as any, the first heading of a level has no gap.
+///
+fn gap_signals(text: &str, headings: &[Heading]) -> Vec<f32> {
+    let mut last: HashMap<usize, usize> = HashMap::new(); // level -> offset of its last heading
+    let words: Vec<Option<u128>> = headings
+        .iter()
+        .map(|x| {
+            let previous = last.insert(x.level, x.offset)?;
+            Some(count_words(
+                text.get(previous..x.offset).unwrap_or_default(),
+            ))
+        })
+        .collect();
+    let mut medians: HashMap<usize, u128> = HashMap::new();
+    for level in headings.iter().map(|x| x.level) {
+        if medians.contains_key(&level) {
+            continue;
+        }
+        let mut gaps: Vec<_> = headings
+            .iter()
+            .zip(words.iter())
+            .filter(|x| x.0.level == level)
+            .filter_map(|x| *x.1)
+            .collect();
+        gaps.sort_unstable();
+        medians.insert(level, gaps.get(gaps.len() / 2).copied().unwrap_or_default());
+    }
+    headings
+        .iter()
+        .zip(words)
+        .map(|(heading, words)| match (words, medians[&heading.level]) {
+            (Some(words), median) if median > 0 => (words as f32 * 4.0 / median as f32).min(1.0),
+            _ => 1.0,
+        })
+        .collect()
+}
+
+///
+/// The number of a title: the first run of arabic or fullwidth digits, or of Chinese numerals,
+/// e.g. 12 for "第十二章" and "第12章". Otherwise the second word as a roman or English number,
+/// e.g. 2 for "Part II" and 21 for "Chapter Twenty-One".
+///
+pub fn number(title: &str) -> Option<u64> {
+    let is_number = |x: char| is_digit(x) || chinese_digit(x).is_some();
+    let Some(start) = title.find(is_number) else {
+        let word = title
+            .split_whitespace()
+            .nth(1)?
+            .trim_end_matches(|x: char| !x.is_alphanumeric());
+        return roman_number(word).or_else(|| english_number(word));
+    };
+    let run: Vec<char> = title[start..]
+        .chars()
+        .take_while(|x| is_number(*x))
+        .collect();
+    match run.iter().all(|x| is_digit(*x)) {
+        true => run
+            .iter()
+            .map(|x| x.to_digit(10).unwrap_or_else(|| *x as u32 - '０' as u32))
+            .try_fold(0u64, |n, x| n.checked_mul(10)?.checked_add(x as u64)),
+        false => chinese_number(&run),
+    }
+}
+
+///
+/// Replace the number of a title by `n`, written the same way: arabic, fullwidth or Chinese.
+/// None if the title has no such number, e.g. "Part II".
+///
+pub fn renumber(title: &str, n: u64) -> Option<String> {
+    let is_number = |x: char| is_digit(x) || chinese_digit(x).is_some();
+    let start = title.find(is_number)?;
+    let end = title[start..]
+        .find(|x| !is_number(x))
+        .map_or(title.len(), |x| start + x);
+    let run = &title[start..end];
+    let number = match run.chars().next()? {
+        '0'..='9' if run.bytes().all(|x| x.is_ascii_digit()) => n.to_string(),
+        '０'..='９' if run.chars().all(|x| ('０'..='９').contains(&x)) => n
+            .to_string()
+            .chars()
+            .map(|x| char::from_u32(x as u32 - '0' as u32 + '０' as u32).unwrap())
+            .collect(),
+        _ if run.chars().all(|x| chinese_digit(x).is_some()) => chinese(n),
+        _ => return None,
+    };
+    Some(format!("{}{}{}", &title[..start], number, &title[end..]))
+}
+
+/// Write a number in Chinese numerals, e.g. "十二" and "一百零五".
+fn chinese(n: u64) -> String {
+    const DIGITS: [char; 10] = ['零', '一', '二', '三', '四', '五', '六', '七', '八', '九'];
+    const UNITS: [&str; 4] = ["", "十", "百", "千"];
+    if n >= 10000 {
+        let rest = n % 10000;
+        let gap = match rest {
+            0 => "",
+            1..=999 => "零",
+            _ => "",
+        };
+        return format!(
+            "{}万{}{}",
+            chinese(n / 10000),
+            gap,
+            chinese(rest).trim_start_matches('零')
+        );
+    }
+    if n == 0 {
+        return "零".to_string();
+    }
+    let digits: Vec<usize> = n.to_string().bytes().map(|x| (x - b'0') as usize).collect();
+    let mut result = String::new();
+    let mut zero = false;
+    for (i, digit) in digits.iter().enumerate() {
+        let unit = UNITS[digits.len() - 1 - i];
+        match digit {
+            0 => zero = !result.is_empty(),
+            _ => {
+                if zero {
+                    result.push('零');
+                    zero = false;
+                }
+                // "十二" rather than "一十二"
+                if !(*digit == 1 && unit == "十" && result.is_empty()) {
+                    result.push(DIGITS[*digit]);
+                }
+                result.push_str(unit);
+            }
+        }
+    }
+    result
+}
+
+fn is_digit(c: char) -> bool {
+    c.is_ascii_digit() || ('０'..='９').contains(&c)
+}
+
+/// The value of a Chinese numeral, digits are 0 to 9 and units are 10 and above.
+fn chinese_digit(c: char) -> Option<u64> {
+    match c {
+        '零' | '〇' => Some(0),
+        '一' => Some(1),
+        '二' | '两' => Some(2),
+        '三' => Some(3),
+        '四' => Some(4),
+        '五' => Some(5),
+        '六' => Some(6),
+        '七' => Some(7),
+        '八' => Some(8),
+        '九' => Some(9),
+        '十' => Some(10),
+        '百' => Some(100),
+        '千' => Some(1000),
+        '万' => Some(10000),
+        _ => None,
+    }
+}
+
+/// Either positional, e.g. "二〇二四", or with units, e.g. "一千零二十".
+fn chinese_number(run: &[char]) -> Option<u64> {
+    let values: Vec<u64> = run
+        .iter()
+        .map(|x| chinese_digit(*x))
+        .collect::<Option<_>>()?;
+    if values.iter().all(|x| *x < 10) {
+        return values
+            .iter()
+            .try_fold(0u64, |n, x| n.checked_mul(10)?.checked_add(*x));
+    }
+    let (mut total, mut section, mut digit) = (0, 0, 0);
+    for value in values {
+        match value {
+            0..=9 => digit = value,
+            10000 => {
+                total += (section + digit) * 10000;
+                section = 0;
+                digit = 0;
+            }
+            unit => {
+                section += digit.max(1) * unit;
+                digit = 0;
+            }
+        }
+    }
+    Some(total + section + digit)
+}
+
+fn roman_number(word: &str) -> Option<u64> {
+    let values: Vec<u64> = word
+        .chars()
+        .map(|x| match x.to_ascii_uppercase() {
+            'I' => Some(1),
+            'V' => Some(5),
+            'X' => Some(10),
+            'L' => Some(50),
+            'C' => Some(100),
+            'D' => Some(500),
+            'M' => Some(1000),
+            _ => None,
+        })
+        .collect::<Option<_>>()?;
+    let mut n = 0;
+    for (i, value) in values.iter().enumerate() {
+        match values.get(i + 1).is_some_and(|x| x > value) {
+            true => n -= *value as i64,
+            false => n += *value as i64,
+        }
+    }
+    u64::try_from(n).ok().filter(|x| *x > 0)
+}
+
+/// Spelled out numbers up to ninety-nine.
+fn english_number(word: &str) -> Option<u64> {
+    const UNITS: &[&str] = &[
+        "zero",
+        "one",
+        "two",
+        "three",
+        "four",
+        "five",
+        "six",
+        "seven",
+        "eight",
+        "nine",
+        "ten",
+        "eleven",
+        "twelve",
+        "thirteen",
+        "fourteen",
+        "fifteen",
+        "sixteen",
+        "seventeen",
+        "eighteen",
+        "nineteen",
+    ];
+    const TENS: &[&str] = &[
+        "twenty", "thirty", "forty", "fifty", "sixty", "seventy", "eighty", "ninety",
+    ];
+    let word = word.to_lowercase();
+    let value = |x: &str| {
+        UNITS
+            .iter()
+            .position(|y| *y == x)
+            .or_else(|| TENS.iter().position(|y| *y == x).map(|x| (x + 2) * 10))
+            .map(|x| x as u64)
+    };
+    match word.split_once('-') {
+        Some((tens, units)) => Some(value(tens)? + value(units).filter(|x| *x < 10)?),
+        None => value(&word),
+    }
+}
@@ -0,0 +1,332 @@
+use super::{number, renumber, Detector, Preset};
+use crate::{
+    settings::{HeadingRule, KindRule, Settings},
+    toc::{NodeKind, Toc},
+};
+
+const TEXT: &str = "书名\n\n第一卷 起始\n卷首语\n第一章 开端\n正文一\n第二章 发展\n正文二。\n第二卷 终局\n第三章 结局\n全文完\n";
+
+#[test]
+fn test_detect() {
+    let detector = Detector::new(&Settings::default().detection).unwrap();
+    let headings = detector.detect(TEXT);
+    let titles: Vec<_> = headings
+        .iter()
+        .map(|x| (x.title.as_str(), x.level))
+        .collect();
+    assert_eq!(
+        titles,
+        vec![
+            ("第一卷 起始", 0),
+            ("第一章 开端", 1),
+            ("第二章 发展", 1),
+            ("第二卷 终局", 0),
+            ("第三章 结局", 1),
+        ]
+    );
+    assert_eq!(headings[0].line, 3);
+    assert_eq!(
+        &TEXT[headings[0].offset..headings[1].offset],
+        "第一卷 起始\n卷首语\n"
+    );
+}
+
+#[test]
+fn test_ignore_long_lines() {
+    let detector = Detector::new(&Settings::default().detection).unwrap();
+    let line = format!("第一章{}\n", "长".repeat(60));
+    assert!(detector.detect(&line).is_empty());
+}
+
+#[test]
+fn test_build_toc() {
+    let detector = Detector::new(&Settings::default().detection).unwrap();
+    let toc = detector.build_toc(TEXT);
+    assert_eq!(toc.children().len(), 2);
+    let volume = toc.get(toc.children()[0]).unwrap();
+    assert_eq!(volume.title, "第一卷 起始");
+    assert_eq!(volume.children.len(), 2);
+    assert_eq!(volume.meta.words, 3);
+    let chapter = toc.get(volume.children[1]).unwrap();
+    assert_eq!(chapter.title, "第二章 发展");
+    assert_eq!(chapter.meta.words, 3);
+    let (start, end) = chapter.meta.range;
+    assert_eq!(
+        &TEXT[start as usize..end as usize],
+        "第二章 发展\n正文二。\n"
+    );
+    let last = toc
+        .get(toc.get(toc.children()[1]).unwrap().children[0])
+        .unwrap();
+    assert_eq!(last.meta.range.1 as usize, TEXT.len());
+}
+
+#[test]
+fn test_chapter_without_volume() {
+    let detector = Detector::new(&Settings::default().detection).unwrap();
+    let toc = detector.build_toc("第1章\na\n第2章\nb\n");
+    assert_eq!(toc.children().len(), 2);
+    assert!(toc.iter().all(|(depth, _)| depth == 0));
+}
+
+#[test]
+fn test_kinds() {
+    let detector = Detector::new(&Settings::default().detection).unwrap();
+    let text =
+        "序言\n写在前面\n第一卷 起始\n第一章 开端\n正文\n第二章 上架感言\n求订阅\n后记\n完\n";
+    let kinds: Vec<_> = detector
+        .detect(text)
+        .into_iter()
+        .map(|x| (x.title, x.kind))
+        .collect();
+    assert_eq!(
+        kinds,
+        vec![
+            ("序言".to_string(), NodeKind::Front),
+            ("第一卷 起始".to_string(), NodeKind::Volume),
+            ("第一章 开端".to_string(), NodeKind::Chapter),
+            ("第二章 上架感言".to_string(), NodeKind::Hidden),
+            ("后记".to_string(), NodeKind::Back),
+        ]
+    );
+    let toc = detector.build_toc(text);
+    let kinds: Vec<_> = toc.iter().map(|(_, x)| x.kind).collect();
+    assert_eq!(kinds[3], NodeKind::Hidden);
+
+    let mut settings = Settings::default().detection;
+    settings.kinds.push(KindRule {
+        pattern: "(".to_string(),
+        kind: NodeKind::Back,
+    });
+    assert!(matches!(
+        Detector::new(&settings),
+        Err(super::DetectError::InvalidKindPattern { index: 0, .. })
+    ));
+}
+
+#[test]
+fn test_number() {
+    assert_eq!(number("第12章 开端"), Some(12));
+    assert_eq!(number("第１２章"), Some(12));
+    assert_eq!(number("第十二章"), Some(12));
+    assert_eq!(number("第一百零五章"), Some(105));
+    assert_eq!(number("第两千三百章"), Some(2300));
+    assert_eq!(number("第一万零一章"), Some(10001));
+    assert_eq!(number("第二〇二四章"), Some(2024));
+    assert_eq!(number("序言"), None);
+}
+
+#[test]
+fn test_renumber() {
+    assert_eq!(renumber("第9章 开端", 12).as_deref(), Some("第12章 开端"));
+    assert_eq!(renumber("第９章", 12).as_deref(), Some("第１２章"));
+    assert_eq!(
+        renumber("第九章 开端", 12).as_deref(),
+        Some("第十二章 开端")
+    );
+    assert_eq!(renumber("第九章", 105).as_deref(), Some("第一百零五章"));
+    assert_eq!(renumber("第九章", 10001).as_deref(), Some("第一万零一章"));
+    for n in [1, 10, 20, 101, 1010, 2300, 20000, 123456] {
+        assert_eq!(number(&renumber("第一章", n).unwrap()), Some(n));
+    }
+    assert_eq!(renumber("Chapter IX", 10), None);
+}
+
+#[test]
+fn test_preview() {
+    let body = "正文。".repeat(50);
+    let text = format!(
+        "第一章 开端\n{body}\n\n第二章 相遇\n{body}\n第二回见到她的时候，她正在哭。\n{body}\n\n第三章 离别\n{body}\n"
+    );
+    let detector = Detector::new(&Settings::default().detection).unwrap();
+    let candidates = detector.preview(&text);
+    let found: Vec<_> = candidates
+        .iter()
+        .map(|x| (x.heading.title.as_str(), x.heading.line, x.accepted))
+        .collect();
+    assert_eq!(
+        found,
+        vec![
+            ("第一章 开端", 1, true),
+            ("第二章 相遇", 4, true),
+            ("第二回见到她的时候，她正在哭。", 6, false),
+            ("第三章 离别", 9, true),
+        ]
+    );
+    let dialogue = &candidates[2];
+    assert_eq!(dialogue.heading.rule, 1);
+    assert!(dialogue.signals.line < 0.3);
+    assert_eq!(dialogue.signals.numbering, 0.5, "a numbering of its own");
+    assert!(candidates[1].signals.blank_lines > dialogue.signals.blank_lines);
+    assert!(candidates.iter().all(|x| (0.0..=1.0).contains(&x.score)));
+    assert!(candidates[3].score > dialogue.score);
+}
+
+fn preset(names: &[&str]) -> Detector {
+    let mut settings = Settings::default().detection;
+    settings.presets = names.iter().map(|x| x.to_string()).collect();
+    Detector::new(&settings).unwrap()
+}
+
+fn outline(detector: &Detector, text: &str) -> Vec<(String, usize, NodeKind)> {
+    detector
+        .detect(text)
+        .into_iter()
+        .map(|x| (x.title, x.level, x.kind))
+        .collect()
+}
+
+#[test]
+fn test_preset_zh() {
+    let text = "序言\n写在前面\n第一卷 潜龙\n第1章 少年\n正文\n第二章 请假\n今天请假\n第十二回 再会\n后记\n";
+    assert_eq!(
+        outline(&preset(&["zh"]), text),
+        vec![
+            ("序言".to_string(), 1, NodeKind::Front),
+            ("第一卷 潜龙".to_string(), 0, NodeKind::Volume),
+            ("第1章 少年".to_string(), 1, NodeKind::Chapter),
+            ("第二章 请假".to_string(), 1, NodeKind::Hidden),
+            ("第十二回 再会".to_string(), 1, NodeKind::Chapter),
+            ("后记".to_string(), 1, NodeKind::Back),
+        ]
+    );
+}
+
+#[test]
+fn test_preset_zh_kinds_match_whole_title() {
+    // Chapters mentioning a kind word in their name stay chapters
+    let text =
+        "第三章 前言不搭后语\n甲\n第五章 死亡通知书\n乙\n第六章 请假条\n丙\n第七章 后记之谜\n丁\n";
+    let outline = outline(&preset(&["zh"]), text);
+    assert!(
+        outline.iter().all(|x| x.2 == NodeKind::Chapter),
+        "{:?}",
+        outline
+    );
+}
+
+#[test]
+fn test_preset_en() {
+    let text = "Prologue\nIt was dark.\nPart II: The Return\nChapter 12\nShe ran.\n\
+                CHAPTER TWENTY-ONE. Home\nChapter and verse were quoted.\nChapter XIV - Alone\n\
+                Part one of the plan failed\nEpilogue\nAuthor's Note: thanks\n";
+    assert_eq!(
+        outline(&preset(&["en"]), text),
+        vec![
+            ("Prologue".to_string(), 1, NodeKind::Front),
+            ("Part II: The Return".to_string(), 0, NodeKind::Volume),
+            ("Chapter 12".to_string(), 1, NodeKind::Chapter),
+            ("CHAPTER TWENTY-ONE. Home".to_string(), 1, NodeKind::Chapter),
+            ("Chapter XIV - Alone".to_string(), 1, NodeKind::Chapter),
+            // Rules can't tell this one from a heading, previews score it lower
+            (
+                "Part one of the plan failed".to_string(),
+                0,
+                NodeKind::Volume
+            ),
+            ("Epilogue".to_string(), 1, NodeKind::Back),
+        ]
+    );
+    assert_eq!(number("Part II: The Return"), Some(2));
+    assert_eq!(number("Chapter XIV - Alone"), Some(14));
+    assert_eq!(number("CHAPTER TWENTY-ONE. Home"), Some(21));
+    assert_eq!(number("Prologue"), None);
+}
+
+#[test]
+fn test_preset_en_roman_numerals() {
+    // Words made of roman numeral letters are not numbers
+    let text = "Chapter did not end well\nPart mid-way through\nBook civil war\nChapter - notes\n\
+                Chapter MMXXIV\nBook iv: Return\n";
+    assert_eq!(
+        outline(&preset(&["en"]), text),
+        vec![
+            ("Chapter MMXXIV".to_string(), 1, NodeKind::Chapter),
+            ("Book iv: Return".to_string(), 0, NodeKind::Volume),
+        ]
+    );
+    assert_eq!(number("Chapter MMXXIV"), Some(2024));
+}
+
+#[test]
+fn test_preset_ja() {
+    let text = "プロローグ\n静かな夜。\n第一部 出会い\n第一話 転校生\n本文\n第十二話　雨\n第2章 再会\n閑話 休日\nエピローグ\nあとがき\n";
+    assert_eq!(
+        outline(&preset(&["ja"]), text),
+        vec![
+            ("プロローグ".to_string(), 1, NodeKind::Front),
+            ("第一部 出会い".to_string(), 0, NodeKind::Volume),
+            ("第一話 転校生".to_string(), 1, NodeKind::Chapter),
+            ("第十二話　雨".to_string(), 1, NodeKind::Chapter),
+            ("第2章 再会".to_string(), 1, NodeKind::Chapter),
+            ("閑話 休日".to_string(), 1, NodeKind::Chapter),
+            ("エピローグ".to_string(), 1, NodeKind::Back),
+            ("あとがき".to_string(), 1, NodeKind::Back),
+        ]
+    );
+}
+
+#[test]
+fn test_preset_ko() {
+    let text = "프롤로그\n어두운 밤.\n제1권 시작\n제1화 만남\n본문\n제 2 화 이별\n15화\n작가의 말\n에필로그\n외전 1\n";
+    assert_eq!(
+        outline(&preset(&["ko"]), text),
+        vec![
+            ("프롤로그".to_string(), 1, NodeKind::Front),
+            ("제1권 시작".to_string(), 0, NodeKind::Volume),
+            ("제1화 만남".to_string(), 1, NodeKind::Chapter),
+            ("제 2 화 이별".to_string(), 1, NodeKind::Chapter),
+            ("15화".to_string(), 1, NodeKind::Chapter),
+            ("작가의 말".to_string(), 1, NodeKind::Hidden),
+            ("에필로그".to_string(), 1, NodeKind::Back),
+            ("외전 1".to_string(), 1, NodeKind::Chapter),
+        ]
+    );
+}
+
+#[test]
+fn test_combine_and_extend_presets() {
+    let text = "第一章 开端\nChapter 2\n番外 春节\n";
+    let titles = |detector: &Detector| -> Vec<String> {
+        detector.detect(text).into_iter().map(|x| x.title).collect()
+    };
+    assert_eq!(titles(&preset(&["zh"])), vec!["第一章 开端"]);
+    assert_eq!(
+        titles(&preset(&["zh", "en"])),
+        vec!["第一章 开端", "Chapter 2"]
+    );
+
+    let mut settings = Settings::default().detection;
+    settings.rules.push(HeadingRule {
+        pattern: "^番外".to_string(),
+        level: 1,
+        kind: Some(NodeKind::Back),
+    });
+    let detector = Detector::new(&settings).unwrap();
+    let headings = detector.detect(text);
+    assert_eq!(headings[1].title, "番外 春节");
+    assert_eq!(headings[1].kind, NodeKind::Back);
+    assert_eq!(headings[1].rule, 0, "rules of the settings come first");
+    assert_eq!(headings[0].rule, 2);
+
+    settings.presets = vec!["xx".to_string()];
+    assert!(matches!(
+        Detector::new(&settings),
+        Err(super::DetectError::UnknownPreset(x)) if x == "xx"
+    ));
+}
+
+#[test]
+fn test_all_presets() {
+    let presets = Preset::all();
+    assert_eq!(
+        presets.iter().map(|x| x.name.as_str()).collect::<Vec<_>>(),
+        vec!["zh", "en", "ja", "ko"]
+    );
+    for x in presets {
+        assert!(!x.description.is_empty());
+        assert!(!x.rules.is_empty(), "{}", x.name);
+        // Every preset compiles on its own
+        preset(&[&x.name]);
+    }
+}
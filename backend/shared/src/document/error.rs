@@ -0,0 +1,44 @@
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+use crate::{detect::DetectError, toc::TocError};
+
+#[derive(Error, Debug)]
+pub enum DocumentError {
+    #[error("failed to access `{}`: {source}", path.display())]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("the document has no source file")]
+    NoSource,
+
+    #[error("the range of node `{0}` is out of the source text, or splits a character")]
+    RangeOutOfText(usize),
+
+    #[error("the heading at `{0}` does not start a line of the source text, or is repeated")]
+    InvalidHeading(usize),
+
+    #[error("the patch of node `{id}` is invalid: {message}")]
+    InvalidPatch { id: usize, message: String },
+
+    #[error("the patch of node `{0}` does not fit its text, which may have changed")]
+    PatchConflict(usize),
+
+    #[error("the patch of node `{id}` has no hunk at index `{index}`")]
+    HunkNotFound { id: usize, index: usize },
+
+    #[error("documents combined from several files can not be re-imported")]
+    Combined,
+
+    #[error(transparent)]
+    Detect(#[from] DetectError),
+
+    #[error(transparent)]
+    Toc(#[from] TocError),
+
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
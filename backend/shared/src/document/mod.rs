@@ -0,0 +1,461 @@
+// A document is a source text and the toc built on it. Node ranges are byte offsets into `text`.
+// It is saved as a project file, which refers to the source text by path instead of embedding it.
+// A book spread over several files is combined into one text, where each file starts with a title line and
+// becomes a top-level volume. Nodes keep the id of their file in `meta.file`, set from their range by every
+// change of the toc made through `Document`. File ranges, like node ranges, are offsets into the combined text,
+// which includes the injected title lines, not into the files on disk.
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    aozora::{is_aozora, AozoraParser},
+    detect::{build_toc, Candidate, Detector, Heading},
+    settings::DetectionSettings,
+    toc::{JSONRoot, NodeKind, Toc, TocEdit, TocNode, TocRoot},
+};
+
+pub use self::error::DocumentError;
+pub use self::patch::{DiffHunk, DiffLine, PatchPreview};
+pub use self::reimport::{ChangedChapter, MissingChapter, ReimportReport};
+
+mod error;
+mod patch;
+mod reimport;
+
+#[cfg(test)]
+mod tests;
+
+pub const PROJECT_EXTENSION: &str = "json";
+
+#[derive(Debug, Clone)]
+pub struct Document {
+    pub source: Option<PathBuf>, // the first file of combined documents
+    pub files: Vec<SourceFile>,  // empty unless the document is combined from several files
+    pub text: String,
+    pub toc: TocRoot,
+}
+
+/// Byte offsets where the lines of a text start.
+#[derive(Debug, Clone)]
+pub struct LineIndex(Vec<usize>);
+
+impl LineIndex {
+    pub fn new(text: &str) -> Self {
+        let starts = text.match_indices('\n').map(|(i, _)| i + 1);
+        LineIndex(std::iter::once(0).chain(starts).collect())
+    }
+
+    /// The 1-based line number of a byte offset, as `Document::line_of` counts it.
+    pub fn line_of(&self, offset: usize) -> usize {
+        self.0.partition_point(|x| *x <= offset)
+    }
+}
+
+/// A file of a combined document.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SourceFile {
+    pub id: usize, // index in `Document::files`
+    pub title: String,
+    pub path: Option<PathBuf>,
+    pub range: (usize, usize), // byte offsets into the combined `Document::text`, starting with the title line
+}
+
+/// Text of a file to be combined, with the title of its volume.
+#[derive(Debug, Clone)]
+pub struct SourceText {
+    pub title: String,
+    pub path: Option<PathBuf>,
+    pub text: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Project {
+    source: PathBuf,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    files: Vec<ProjectFile>, // all files of a combined document, `source` is the first one
+    toc: JSONRoot,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ProjectFile {
+    title: String,
+    path: PathBuf,
+}
+
+impl Document {
+    pub fn new(text: String) -> Self {
+        Document {
+            source: None,
+            files: Vec::new(),
+            text,
+            toc: TocRoot::new(),
+        }
+    }
+
+    ///
+    /// Combine texts in order, without detecting headings. Each text is preceded by its title line,
+    /// which is the heading of its volume.
+    ///
+    pub fn combine(texts: Vec<SourceText>) -> Result<Self, DocumentError> {
+        if texts.is_empty() {
+            return Err(DocumentError::NoSource);
+        }
+        let mut document = Document::new(String::new());
+        document.source = texts[0].path.clone();
+        for (id, source) in texts.into_iter().enumerate() {
+            let start = document.text.len();
+            // Titles are single lines, otherwise they would be split as text
+            let title = source
+                .title
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ");
+            document.text.push_str(&title);
+            document.text.push('\n');
+            document.text.push_str(&source.text);
+            if !document.text.ends_with('\n') {
+                document.text.push('\n');
+            }
+            document.files.push(SourceFile {
+                id,
+                title,
+                path: source.path,
+                range: (start, document.text.len()),
+            });
+        }
+        Ok(document)
+    }
+
+    /// Open text files as volumes of one book in order, titled by their file names, with an empty toc.
+    pub fn open_files(paths: &[PathBuf]) -> Result<Self, DocumentError> {
+        let texts = paths
+            .iter()
+            .map(|path| {
+                let document = Document::open(path)?;
+                Ok(SourceText {
+                    title: file_title(path),
+                    path: document.source,
+                    text: document.text,
+                })
+            })
+            .collect::<Result<Vec<_>, DocumentError>>()?;
+        Document::combine(texts)
+    }
+
+    /// Open a text file with an empty toc.
+    pub fn open(path: &Path) -> Result<Self, DocumentError> {
+        let bytes = std::fs::read(path).map_err(|source| DocumentError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        Ok(Document {
+            source: Some(path.to_path_buf()),
+            ..Document::new(decode(&bytes))
+        })
+    }
+
+    /// Load a project file saved by `save`, the source text is read again from its path.
+    pub fn load(path: &Path) -> Result<Self, DocumentError> {
+        let mut buf = std::fs::read(path).map_err(|source| DocumentError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let project: Project =
+            simd_json::from_slice(&mut buf).map_err(|e| DocumentError::Other(e.into()))?;
+        let resolve = |source: PathBuf| match source.is_relative() {
+            true => path.parent().unwrap_or(Path::new("")).join(source),
+            false => source,
+        };
+        let document = match project.files.is_empty() {
+            true => Document::open(&resolve(project.source))?,
+            false => {
+                let texts = project
+                    .files
+                    .into_iter()
+                    .map(|file| {
+                        let document = Document::open(&resolve(file.path))?;
+                        Ok(SourceText {
+                            title: file.title,
+                            path: document.source,
+                            text: document.text,
+                        })
+                    })
+                    .collect::<Result<Vec<_>, DocumentError>>()?;
+                Document::combine(texts)?
+            }
+        };
+        let document = Document {
+            toc: TocRoot::try_from(project.toc)?,
+            ..document
+        };
+        document.check_ranges()?;
+        Ok(document)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), DocumentError> {
+        let source = self.source.clone().ok_or(DocumentError::NoSource)?;
+        let files = self
+            .files
+            .iter()
+            .map(|x| match &x.path {
+                Some(path) => Ok(ProjectFile {
+                    title: x.title.clone(),
+                    path: path.clone(),
+                }),
+                None => Err(DocumentError::NoSource),
+            })
+            .collect::<Result<_, _>>()?;
+        let project = Project {
+            source,
+            files,
+            toc: JSONRoot::from(&self.toc),
+        };
+        let buf =
+            simd_json::to_string_pretty(&project).map_err(|e| DocumentError::Other(e.into()))?;
+        std::fs::write(path, buf).map_err(|source| DocumentError::Io {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+
+    ///
+    /// Replace the toc by detecting headings in the text.
+    /// Headings of a combined document are detected per file, and nested under the volume of their file.
+    ///
+    pub fn detect(&mut self, settings: &DetectionSettings) -> Result<(), DocumentError> {
+        let detector = Detector::new(settings)?;
+        let headings = self.headings(&detector, settings);
+        self.toc = build_toc(&self.text, &headings);
+        self.assign_files();
+        Ok(())
+    }
+
+    ///
+    /// Detect headings as `detect` does, and score them without touching the toc.
+    /// The candidates to keep, possibly edited, are passed to `accept` afterwards.
+    ///
+    pub fn preview(&self, settings: &DetectionSettings) -> Result<Vec<Candidate>, DocumentError> {
+        let detector = Detector::new(settings)?;
+        Ok(detector.score(&self.text, self.headings(&detector, settings)))
+    }
+
+    ///
+    /// Replace the toc by one built from accepted headings, e.g. candidates of `preview`.
+    /// Each heading must start a line, their lines are counted again.
+    ///
+    pub fn accept(&mut self, headings: &[Heading]) -> Result<(), DocumentError> {
+        let mut headings = headings.to_vec();
+        headings.sort_by_key(|x| x.offset);
+        for (i, heading) in headings.iter().enumerate() {
+            let starts_line =
+                heading.offset == 0 || self.text.as_bytes().get(heading.offset - 1) == Some(&b'\n');
+            let is_duplicate = i > 0 && headings[i - 1].offset == heading.offset;
+            if heading.offset >= self.text.len() || !starts_line || is_duplicate {
+                return Err(DocumentError::InvalidHeading(heading.offset));
+            }
+        }
+        let lines = self.line_index();
+        for heading in headings.iter_mut() {
+            heading.line = lines.line_of(heading.offset);
+        }
+        self.toc = build_toc(&self.text, &headings);
+        self.assign_files();
+        Ok(())
+    }
+
+    ///
+    /// Apply toc edits in order, either all of them or, if one fails, none. Returns the id of the node each
+    /// edit changed or added. Nodes moved across files by their new ranges, e.g. merged ones, get their file again.
+    /// Ranges must stay within the text, on character boundaries, or the project could not be loaded again.
+    ///
+    pub fn apply_edits(&mut self, edits: &[TocEdit]) -> Result<Vec<usize>, DocumentError> {
+        let mut toc = self.toc.clone();
+        let ids = edits
+            .iter()
+            .map(|x| toc.apply(x))
+            .collect::<Result<_, _>>()?;
+        let previous = std::mem::replace(&mut self.toc, toc);
+        if let Err(e) = self.check_ranges() {
+            self.toc = previous;
+            return Err(e);
+        }
+        self.assign_files();
+        Ok(ids)
+    }
+
+    fn headings(&self, detector: &Detector, settings: &DetectionSettings) -> Vec<Heading> {
+        let aozora = settings.aozora.then(AozoraParser::new);
+        let detect = |text: &str| match &aozora {
+            Some(parser) if is_aozora(text) => parser.detect(text, detector),
+            _ => detector.detect(text),
+        };
+        if self.files.is_empty() {
+            return detect(&self.text);
+        }
+        let mut headings = Vec::new();
+        for file in self.files.iter() {
+            let (start, end) = file.range;
+            let content = start + file.title.len() + 1; // after the title line
+            let line = self.line_of(start);
+            headings.push(Heading {
+                title: file.title.clone(),
+                level: 0,
+                kind: NodeKind::Volume,
+                rule: 0, // not detected by a rule, but the title line of the file
+                line,
+                offset: start,
+            });
+            headings.extend(
+                detect(&self.text[content..end])
+                    .into_iter()
+                    .map(|x| Heading {
+                        level: x.level + 1,
+                        line: x.line + line,
+                        offset: x.offset + content,
+                        ..x
+                    }),
+            );
+        }
+        headings
+    }
+
+    /// The file a node belongs to, `None` unless the document is combined.
+    pub fn file_of(&self, node: &TocNode) -> Option<&SourceFile> {
+        self.files
+            .get(node.meta.file)
+            .filter(|_| !self.files.is_empty())
+    }
+
+    ///
+    /// The dir next to the text of a node, or the text before the first node if `node` is `None`.
+    /// Files referred to from the text, e.g. images, are resolved against it.
+    ///
+    pub fn base_dir(&self, node: Option<usize>) -> Option<&Path> {
+        let file = node
+            .and_then(|x| self.toc.get(x))
+            .and_then(|x| self.file_of(x))
+            .and_then(|x| x.path.as_deref());
+        file.or(self.source.as_deref()).and_then(|x| x.parent())
+    }
+
+    ///
+    /// Set the file of each node by the start of its range, after every change of the toc.
+    /// It is a no-op unless the document is combined.
+    ///
+    fn assign_files(&mut self) {
+        if self.files.is_empty() {
+            return;
+        }
+        let starts: Vec<_> = self.files.iter().map(|x| x.range.0 as u128).collect();
+        let ids: Vec<_> = self.toc.iter().map(|(_, x)| x.id).collect();
+        for id in ids {
+            let node = self.toc.get_mut(id).unwrap();
+            node.meta.file = starts
+                .iter()
+                .rposition(|x| *x <= node.meta.range.0)
+                .unwrap_or_default();
+        }
+    }
+
+    /// The text covered by the node's range, including its heading line.
+    pub fn node_text(&self, node: &TocNode) -> Option<&str> {
+        let (start, end) = node.meta.range;
+        self.text.get(start as usize..end as usize)
+    }
+
+    ///
+    /// The text before the first node, then the text of each node in document order,
+    /// as `(node id, byte offset, text)`. The first one has no node id, and may be empty.
+    ///
+    pub fn segments(&self) -> Vec<(Option<usize>, usize, &str)> {
+        let first = self
+            .toc
+            .iter()
+            .map(|(_, x)| x.meta.range.0 as usize)
+            .min()
+            .unwrap_or(self.text.len());
+        let mut segments = vec![(None, 0, self.text.get(..first).unwrap_or_default())];
+        for (_, node) in self.toc.iter() {
+            if let Some(text) = self.node_text(node) {
+                segments.push((Some(node.id), node.meta.range.0 as usize, text));
+            }
+        }
+        segments
+    }
+
+    /// The 1-based line number of a byte offset, used to point users at the text.
+    /// It scans the text up to the offset, `line_index` is for many offsets.
+    pub fn line_of(&self, offset: usize) -> usize {
+        self.text.as_bytes()[..offset.min(self.text.len())]
+            .iter()
+            .filter(|x| **x == b'\n')
+            .count()
+            + 1
+    }
+
+    /// Index of the line starts of the text, to look up line numbers of many offsets.
+    pub fn line_index(&self) -> LineIndex {
+        LineIndex::new(&self.text)
+    }
+
+    fn check_ranges(&self) -> Result<(), DocumentError> {
+        match self.toc.iter().find(|(_, x)| self.node_text(x).is_none()) {
+            Some((_, node)) => Err(DocumentError::RangeOutOfText(node.id)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Title of a file as a volume, its name without the extension.
+pub fn file_title(path: &Path) -> String {
+    path.file_stem()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// Decode a text file, honoring a BOM. Without a BOM, UTF-8 is tried first and GB18030 is the fallback.
+pub fn decode(bytes: &[u8]) -> String {
+    if let Some((encoding, bom_length)) = encoding_rs::Encoding::for_bom(bytes) {
+        return encoding
+            .decode_without_bom_handling(&bytes[bom_length..])
+            .0
+            .into_owned();
+    }
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        Err(_) => encoding_rs::GB18030.decode(bytes).0.into_owned(),
+    }
+}
+
+/// Count words the way readers of CJK texts expect: every CJK character is a word,
+/// while a run of other letters or digits is one word.
+pub fn count_words(text: &str) -> u128 {
+    let mut count = 0;
+    let mut in_word = false;
+    for c in text.chars() {
+        if is_cjk(c) {
+            count += 1;
+            in_word = false;
+        } else if c.is_alphanumeric() {
+            if !in_word {
+                count += 1;
+            }
+            in_word = true;
+        } else {
+            in_word = false;
+        }
+    }
+    count
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c as u32,
+        0x3040..=0x30FF // kana
+        | 0x3400..=0x4DBF
+        | 0x4E00..=0x9FFF
+        | 0xF900..=0xFAFF
+        | 0xAC00..=0xD7AF // hangul
+        | 0x20000..=0x2FA1F)
+}
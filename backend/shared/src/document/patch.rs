@@ -0,0 +1,133 @@
+// Editors change the text of a chapter without touching the source text: the change is stored on its node
+// as a unified diff against the text of the node's range, and applied whenever the chapter is exported.
+// Hunks are located by their context, so a patch survives its node moving around, e.g. by a re-import, as
+// long as the lines around the change are left alone.
+
+use diffy::{Line, Patch};
+use serde::Serialize;
+
+use crate::toc::{Toc, TocError, TocNode};
+
+use super::{Document, DocumentError};
+
+/// A node's text with its patch applied, and the patch split into hunks to highlight the changes.
+#[derive(Debug, Clone, Serialize)]
+pub struct PatchPreview {
+    pub node: usize,
+    pub text: String,
+    pub hunks: Vec<DiffHunk>, // empty without a patch
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DiffHunk {
+    pub old_start: usize, // 1-based lines of the node's text
+    pub old_lines: usize,
+    pub new_start: usize, // 1-based lines of the patched text
+    pub new_lines: usize,
+    pub lines: Vec<DiffLine>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", content = "text", rename_all = "snake_case")]
+pub enum DiffLine {
+    Context(String),
+    Insert(String),
+    Delete(String),
+}
+
+impl Document {
+    /// The text of a node with its patch applied, including its heading line.
+    pub fn patched_text(&self, node: &TocNode) -> Result<String, DocumentError> {
+        let original = self
+            .node_text(node)
+            .ok_or(DocumentError::RangeOutOfText(node.id))?;
+        match &node.patch {
+            Some(patch) => apply(node.id, original, &parse(node.id, patch)?),
+            None => Ok(original.to_string()),
+        }
+    }
+
+    ///
+    /// Store the edited text of a node, including its heading line, as a patch against the text of its
+    /// range. The patch is removed if the text is the original one.
+    ///
+    pub fn edit_text(&mut self, id: usize, text: &str) -> Result<PatchPreview, DocumentError> {
+        let node = self.toc.get(id).ok_or(TocError::NodeNotFound(id))?;
+        let original = self
+            .node_text(node)
+            .ok_or(DocumentError::RangeOutOfText(id))?;
+        let patch = (original != text).then(|| diffy::create_patch(original, text).to_string());
+        self.toc.get_mut(id).unwrap().patch = patch;
+        self.preview_patch(id)
+    }
+
+    pub fn preview_patch(&self, id: usize) -> Result<PatchPreview, DocumentError> {
+        let node = self.toc.get(id).ok_or(TocError::NodeNotFound(id))?;
+        let hunks = match &node.patch {
+            Some(patch) => hunks(&parse(id, patch)?),
+            None => Vec::new(),
+        };
+        Ok(PatchPreview {
+            node: id,
+            text: self.patched_text(node)?,
+            hunks,
+        })
+    }
+
+    /// Undo a hunk of the patch of a node, by its index in `PatchPreview::hunks`.
+    pub fn revert_hunk(&mut self, id: usize, index: usize) -> Result<PatchPreview, DocumentError> {
+        let node = self.toc.get(id).ok_or(TocError::NodeNotFound(id))?;
+        let patch = node.patch.as_deref().unwrap_or_default();
+        // Hunk headers are the only lines starting with "@@", the ones of the hunks start with ' ', '+', '-' or '\'
+        let mut blocks: Vec<String> = Vec::new();
+        for line in patch.split_inclusive('\n') {
+            match blocks.last_mut() {
+                Some(block) if !line.starts_with("@@") => block.push_str(line),
+                _ => blocks.push(line.to_string()),
+            }
+        }
+        if index + 1 >= blocks.len() {
+            return Err(DocumentError::HunkNotFound { id, index });
+        }
+        blocks.remove(index + 1); // after the file header
+        let kept = blocks.concat();
+        let original = self
+            .node_text(node)
+            .ok_or(DocumentError::RangeOutOfText(id))?;
+        let text = apply(id, original, &parse(id, &kept)?)?;
+        self.edit_text(id, &text)
+    }
+}
+
+fn parse(id: usize, patch: &str) -> Result<Patch<'_, str>, DocumentError> {
+    Patch::from_str(patch).map_err(|e| DocumentError::InvalidPatch {
+        id,
+        message: e.to_string(),
+    })
+}
+
+fn apply(id: usize, original: &str, patch: &Patch<'_, str>) -> Result<String, DocumentError> {
+    diffy::apply(original, patch).map_err(|_| DocumentError::PatchConflict(id))
+}
+
+fn hunks(patch: &Patch<'_, str>) -> Vec<DiffHunk> {
+    patch
+        .hunks()
+        .iter()
+        .map(|hunk| DiffHunk {
+            old_start: hunk.old_range().start(),
+            old_lines: hunk.old_range().len(),
+            new_start: hunk.new_range().start(),
+            new_lines: hunk.new_range().len(),
+            lines: hunk
+                .lines()
+                .iter()
+                .map(|line| match line {
+                    Line::Context(x) => DiffLine::Context(x.to_string()),
+                    Line::Insert(x) => DiffLine::Insert(x.to_string()),
+                    Line::Delete(x) => DiffLine::Delete(x.to_string()),
+                })
+                .collect(),
+        })
+        .collect()
+}
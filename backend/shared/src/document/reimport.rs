@@ -0,0 +1,259 @@
+// Re-import replaces the text of a document by a newer version of it, e.g. a serial novel which grew by a
+// few chapters, and keeps the edited toc. Offsets of the old and new text have nothing in common, so each node
+// is located in the new text by its content, in the order of their text: first by its heading line, then by
+// the lines of its body, in case the heading changed upstream. Nodes keep their titles, edits and patches,
+// including the ones added by hand or merged, which detection would not find again. Detection only runs on
+// the text past the last located node, for the chapters added upstream. Nodes whose body changed upstream
+// are reported, patches may no longer fit them.
+
+use std::{
+    collections::{HashMap, HashSet},
+    hash::{DefaultHasher, Hash, Hasher},
+};
+
+use serde::Serialize;
+
+use crate::{
+    detect::{build_toc, Detector},
+    settings::DetectionSettings,
+    toc::Toc,
+};
+
+use super::{count_words, Document, DocumentError};
+
+/// Share of lines a node must have in common with the text after a line of its body to be located by it.
+const MIN_SIMILARITY: f32 = 0.5;
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReimportReport {
+    pub matched: usize,
+    pub changed: Vec<ChangedChapter>, // matched nodes whose text changed upstream
+    pub added: Vec<usize>,            // ids of the nodes of new chapters
+    pub missing: Vec<MissingChapter>, // nodes not found in the new text, kept with an empty range
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ChangedChapter {
+    pub node: usize,
+    pub title: String,
+    pub similarity: f32, // share of lines in common, from 0 to 1
+    pub patched: bool,   // the node has a patch, which may no longer fit
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MissingChapter {
+    pub node: usize,
+    pub title: String,
+    pub words: u128, // in the old text
+}
+
+/// What the text of a node is recognized by, whatever its offset.
+#[derive(Debug)]
+struct Fingerprint {
+    heading: String,
+    body: u64,
+    lines: HashSet<u64>,
+    first: Option<String>, // the first non-blank line of the body
+}
+
+impl Fingerprint {
+    fn new(text: &str) -> Self {
+        let (heading, body) = text.split_once('\n').unwrap_or((text, ""));
+        let lines: Vec<&str> = body
+            .lines()
+            .map(|x| x.trim())
+            .filter(|x| !x.is_empty())
+            .collect();
+        Fingerprint {
+            heading: heading.trim().to_string(),
+            body: hash(&lines),
+            lines: lines.iter().map(hash).collect(),
+            first: lines.first().map(|x| x.to_string()),
+        }
+    }
+
+    fn similarity(&self, other: &Fingerprint) -> f32 {
+        let union = self.lines.union(&other.lines).count();
+        match union {
+            0 => 1.0,
+            _ => self.lines.intersection(&other.lines).count() as f32 / union as f32,
+        }
+    }
+
+    /// Whether `line` is a line of the text, e.g. the heading of a chapter merged into the node.
+    fn contains(&self, line: &str) -> bool {
+        self.heading == line || self.lines.contains(&hash(line))
+    }
+}
+
+fn hash<T: Hash + ?Sized>(x: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    x.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Lines of the new text, trimmed, with their offsets.
+struct Lines<'a> {
+    text: &'a str,
+    lines: Vec<(usize, &'a str)>,
+}
+
+impl<'a> Lines<'a> {
+    fn new(text: &'a str) -> Self {
+        let mut offset = 0;
+        let lines = text
+            .split_inclusive('\n')
+            .map(|line| {
+                offset += line.len();
+                (offset - line.len(), line.trim())
+            })
+            .collect();
+        Lines { text, lines }
+    }
+
+    fn offset(&self, line: usize) -> usize {
+        self.lines.get(line).map_or(self.text.len(), |x| x.0)
+    }
+
+    /// The line a node starts at, from line `from` on: its heading line, or the line before its body.
+    fn locate(&self, fingerprint: &Fingerprint, from: usize) -> Option<usize> {
+        let find = |line: &str| (from..self.lines.len()).find(|x| self.lines[*x].1 == line);
+        if !fingerprint.heading.is_empty() {
+            if let Some(line) = find(&fingerprint.heading) {
+                return Some(line);
+            }
+        }
+        let body = find(fingerprint.first.as_deref()?)?;
+        let end = (body + fingerprint.lines.len()).min(self.lines.len());
+        let lines =
+            Fingerprint::new(&self.text[self.offset(body.saturating_sub(1))..self.offset(end)]);
+        (fingerprint.similarity(&lines) >= MIN_SIMILARITY).then(|| body.saturating_sub(1).max(from))
+    }
+}
+
+impl Document {
+    ///
+    /// Replace the text by a newer version of it, keeping the toc. Chapters past the last node found in the
+    /// new text are detected and added at the end of their parent, which is the node of the volume they are
+    /// detected in, if any. Nodes which are not found are kept with an empty range, to be removed by hand.
+    ///
+    pub fn reimport(
+        &mut self,
+        text: String,
+        settings: &DetectionSettings,
+    ) -> Result<ReimportReport, DocumentError> {
+        if !self.files.is_empty() {
+            return Err(DocumentError::Combined);
+        }
+        let detector = Detector::new(settings)?;
+        let fresh = Document::new(text);
+        let lines = Lines::new(&fresh.text);
+
+        // Nodes in the order of their text, hand-added ones may not be in the order of the toc
+        let mut old: Vec<_> = self
+            .toc
+            .iter()
+            .map(|(_, x)| {
+                (
+                    x.id,
+                    x.meta.range,
+                    Fingerprint::new(self.node_text(x).unwrap_or_default()),
+                )
+            })
+            .collect();
+        old.sort_by_key(|x| x.1 .0);
+        let mut starts: HashMap<usize, usize> = HashMap::new(); // node id -> offset in the new text
+        let mut from = 0;
+        for (id, _, fingerprint) in old.iter() {
+            if let Some(line) = lines.locate(fingerprint, from) {
+                starts.insert(*id, lines.offset(line));
+                from = line + 1;
+            }
+        }
+
+        // The last located node ends at the first detected heading which is not in its old text
+        let chapters = build_toc(&fresh.text, &fresh.headings(&detector, settings));
+        let last = old.iter().rev().find(|x| starts.contains_key(&x.0));
+        let cut = chapters
+            .iter()
+            .map(|(_, x)| x.meta.range.0 as usize)
+            .filter(|x| last.is_none_or(|last| *x > starts[&last.0]))
+            .find(|x| {
+                let heading = fresh.text[*x..].lines().next().unwrap_or_default().trim();
+                last.is_none_or(|last| !last.2.contains(heading))
+            })
+            .unwrap_or(fresh.text.len());
+
+        // Ends are kept where the next node started, or the text ended, and moved to the next node otherwise
+        let new_starts: HashMap<u128, usize> = old
+            .iter()
+            .filter_map(|(id, range, _)| starts.get(id).map(|x| (range.0, *x)))
+            .collect();
+        let mut report = ReimportReport::default();
+        for (i, (id, (_, end), fingerprint)) in old.iter().enumerate() {
+            let next = old[i + 1..]
+                .iter()
+                .find_map(|x| starts.get(&x.0).copied())
+                .unwrap_or(cut);
+            let node = self.toc.get_mut(*id).unwrap();
+            let Some(new_start) = starts.get(id).copied() else {
+                report.missing.push(MissingChapter {
+                    node: *id,
+                    title: node.title.clone(),
+                    words: node.meta.words,
+                });
+                node.meta.range = (next as u128, next as u128);
+                node.meta.words = 0;
+                continue;
+            };
+            let new_end = match *end as usize == self.text.len() {
+                true => cut,
+                false => new_starts
+                    .get(end)
+                    .copied()
+                    .filter(|x| *x >= new_start)
+                    .unwrap_or(next),
+            }
+            .max(new_start);
+            let text = &fresh.text[new_start..new_end];
+            node.meta.range = (new_start as u128, new_end as u128);
+            node.meta.words = count_words(text.split_once('\n').map(|x| x.1).unwrap_or_default());
+            report.matched += 1;
+            let new_fingerprint = Fingerprint::new(text);
+            if fingerprint.body != new_fingerprint.body {
+                report.changed.push(ChangedChapter {
+                    node: *id,
+                    title: node.title.clone(),
+                    similarity: fingerprint.similarity(&new_fingerprint),
+                    patched: node.patch.is_some(),
+                });
+            }
+        }
+
+        // Detected chapter id -> its node, to nest new chapters under the node of their volume
+        let located: HashMap<usize, usize> = starts.iter().map(|(id, x)| (*x, *id)).collect();
+        let mut nodes: HashMap<usize, usize> = HashMap::new();
+        for (_, chapter) in chapters.iter() {
+            let start = chapter.meta.range.0 as usize;
+            if start < cut {
+                if let Some(id) = located.get(&start) {
+                    nodes.insert(chapter.id, *id);
+                }
+                continue;
+            }
+            let parent = chapter.parent.and_then(|x| nodes.get(&x).copied());
+            let id = self
+                .toc
+                .add_with_meta(&chapter.title, Some(chapter.meta.clone()), parent)?
+                .id;
+            self.toc.get_mut(id).unwrap().kind = chapter.kind;
+            nodes.insert(chapter.id, id);
+            report.added.push(id);
+        }
+
+        self.text = fresh.text;
+        self.assign_files();
+        self.check_ranges()?;
+        Ok(report)
+    }
+}
@@ -0,0 +1,398 @@
+use super::{count_words, decode, DiffLine, Document, DocumentError, SourceText};
+use crate::{
+    settings::Settings,
+    toc::{NodeKind, Toc, TocEdit, TocError},
+};
+
+#[test]
+fn test_count_words() {
+    assert_eq!(count_words("你好，世界"), 4);
+    assert_eq!(count_words("Hello, world 2024!"), 3);
+    assert_eq!(count_words("第12章 Hello"), 4);
+}
+
+#[test]
+fn test_decode() {
+    assert_eq!(decode("第一章".as_bytes()), "第一章");
+    assert_eq!(decode(b"\xEF\xBB\xBFabc"), "abc");
+    let (gbk, _, _) = encoding_rs::GB18030.encode("第一章");
+    assert_eq!(decode(&gbk), "第一章");
+}
+
+#[test]
+fn test_save_and_load() {
+    let dir = std::env::temp_dir().join(format!("wbook-document-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let source = dir.join("book.txt");
+    std::fs::write(&source, "第一章\n甲\n第二章\n乙\n").unwrap();
+
+    let mut document = Document::open(&source).unwrap();
+    document.detect(&Settings::default().detection).unwrap();
+    let id = document.toc.children()[1];
+    document.toc.get_mut(id).unwrap().title = "第二章 改".to_string();
+    let project = dir.join("book.json");
+    document.save(&project).unwrap();
+
+    let loaded = Document::load(&project).unwrap();
+    assert_eq!(loaded.toc.dump().unwrap(), document.toc.dump().unwrap());
+    let node = loaded.toc.get(id).unwrap();
+    assert_eq!(node.title, "第二章 改");
+    assert_eq!(loaded.node_text(node), Some("第二章\n乙\n"));
+}
+
+#[test]
+fn test_combine() {
+    let texts = vec![
+        SourceText {
+            title: "第一卷 起".to_string(),
+            path: None,
+            text: "楔子\n第一章\n甲\n".to_string(),
+        },
+        SourceText {
+            title: "第二卷\n承".to_string(),
+            path: None,
+            text: "第一章\n乙".to_string(),
+        },
+    ];
+    let mut document = Document::combine(texts).unwrap();
+    document.detect(&Settings::default().detection).unwrap();
+    assert_eq!(
+        document.text,
+        "第一卷 起\n楔子\n第一章\n甲\n第二卷 承\n第一章\n乙\n"
+    );
+
+    let nodes: Vec<_> = document
+        .toc
+        .iter()
+        .map(|(depth, x)| (depth, x.title.as_str(), x.kind, x.meta.file))
+        .collect();
+    assert_eq!(
+        nodes,
+        vec![
+            (0, "第一卷 起", NodeKind::Volume, 0),
+            (1, "第一章", NodeKind::Chapter, 0),
+            (0, "第二卷 承", NodeKind::Volume, 1),
+            (1, "第一章", NodeKind::Chapter, 1),
+        ]
+    );
+    let volume = document.toc.get(document.toc.children()[0]).unwrap();
+    assert_eq!(document.node_text(volume), Some("第一卷 起\n楔子\n"));
+    assert_eq!(document.file_of(volume).unwrap().title, "第一卷 起");
+    assert!(Document::combine(Vec::new()).is_err());
+}
+
+#[test]
+fn test_line_index() {
+    let document = Document::new("甲\n\n乙丙\n丁".to_string());
+    let lines = document.line_index();
+    for offset in 0..=document.text.len() + 1 {
+        assert_eq!(
+            lines.line_of(offset),
+            document.line_of(offset),
+            "{}",
+            offset
+        );
+    }
+    assert_eq!(lines.line_of(document.text.len()), 4);
+}
+
+#[test]
+fn test_apply_edits() {
+    let texts = vec![
+        SourceText {
+            title: "第一卷".to_string(),
+            path: None,
+            text: "第一章\n甲\n".to_string(),
+        },
+        SourceText {
+            title: "第二卷".to_string(),
+            path: None,
+            text: "第一章\n乙\n".to_string(),
+        },
+    ];
+    let mut document = Document::combine(texts).unwrap();
+    document.detect(&Settings::default().detection).unwrap();
+    let start = document.files[1].range.0 as u128;
+
+    // Added nodes get the file their range starts in
+    let ids = document
+        .apply_edits(&[TocEdit::Add {
+            title: "插曲".to_string(),
+            range: (start, start),
+            parent: None,
+        }])
+        .unwrap();
+    assert_eq!(document.toc.get(ids[0]).unwrap().meta.file, 1);
+
+    // Either all edits are applied, or none
+    let before = document.toc.dump().unwrap();
+    let edits = [
+        TocEdit::Retitle {
+            id: ids[0],
+            title: "间章".to_string(),
+        },
+        TocEdit::Remove { id: 999 },
+    ];
+    assert!(matches!(
+        document.apply_edits(&edits),
+        Err(DocumentError::Toc(TocError::NodeNotFound(999)))
+    ));
+    assert_eq!(document.toc.dump().unwrap(), before);
+
+    // Ranges out of the text, or splitting a character, would not load again
+    let len = document.text.len() as u128;
+    for range in [(start, len + 1), (start + 1, start + 4), (start + 3, start)] {
+        let edit = TocEdit::Add {
+            title: "越界".to_string(),
+            range,
+            parent: None,
+        };
+        assert!(matches!(
+            document.apply_edits(&[edit]),
+            Err(DocumentError::RangeOutOfText(_))
+        ));
+        assert_eq!(document.toc.dump().unwrap(), before);
+    }
+}
+
+#[test]
+fn test_save_and_load_files() {
+    let dir = std::env::temp_dir().join(format!("wbook-document-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let paths = vec![dir.join("卷一.txt"), dir.join("卷二.txt")];
+    std::fs::write(&paths[0], "第一章\n甲\n").unwrap();
+    std::fs::write(&paths[1], "第一章\n乙\n").unwrap();
+
+    let mut document = Document::open_files(&paths).unwrap();
+    document.detect(&Settings::default().detection).unwrap();
+    assert_eq!(document.source.as_deref(), Some(paths[0].as_path()));
+    let project = dir.join("book.json");
+    document.save(&project).unwrap();
+
+    let loaded = Document::load(&project).unwrap();
+    assert_eq!(loaded.text, document.text);
+    assert_eq!(loaded.files, document.files);
+    assert_eq!(loaded.toc.dump().unwrap(), document.toc.dump().unwrap());
+    let node = loaded.toc.get(loaded.toc.children()[1]).unwrap();
+    assert_eq!(node.title, "卷二");
+    assert_eq!(node.meta.file, 1);
+    assert_eq!(loaded.base_dir(Some(node.id)), Some(dir.as_path()));
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_preview_and_accept() {
+    let settings = Settings::default().detection;
+    let mut document = Document::new("第一章\n甲\n第二章 说\n乙\n第三章\n丙\n".to_string());
+    let candidates = document.preview(&settings).unwrap();
+    assert_eq!(candidates.len(), 3);
+    assert!(
+        document.toc.iter().next().is_none(),
+        "the toc is left as it is"
+    );
+
+    let mut headings: Vec<_> = candidates
+        .into_iter()
+        .filter(|x| x.heading.title != "第二章 说")
+        .map(|x| x.heading)
+        .collect();
+    headings[1].level = 0;
+    headings.reverse();
+    document.accept(&headings).unwrap();
+    let titles: Vec<_> = document.toc.iter().map(|(_, x)| x.title.as_str()).collect();
+    assert_eq!(titles, vec!["第一章", "第三章"]);
+    let first = document.toc.get(document.toc.children()[0]).unwrap();
+    assert_eq!(
+        document.node_text(first),
+        Some("第一章\n甲\n第二章 说\n乙\n")
+    );
+
+    headings[0].offset += 1;
+    assert!(matches!(
+        document.accept(&headings),
+        Err(DocumentError::InvalidHeading(_))
+    ));
+}
+
+#[test]
+fn test_reimport() {
+    let settings = Settings::default().detection;
+    let mut document = Document::new(
+        "第一卷 起\n第一章 开端\n甲甲\n第二章 发展\n乙乙\n乙乙二\n第三章 高潮\n丙\n第四章 旧\n丁\n"
+            .to_string(),
+    );
+    document.detect(&settings).unwrap();
+    let id = |document: &Document, title: &str| {
+        document
+            .toc
+            .iter()
+            .find(|(_, x)| x.title == title)
+            .map(|(_, x)| x.id)
+            .unwrap()
+    };
+    let first = id(&document, "第一章 开端");
+    let second = id(&document, "第二章 发展");
+    document.toc.get_mut(first).unwrap().patch = Some("patch".to_string());
+    document.toc.get_mut(second).unwrap().title = "第二章 改".to_string();
+
+    let text = "书名\n第一卷 起\n第一章 开端\n甲甲\n第二章 发展\n乙乙\n乙乙二\n新增\n\
+        第三章 高潮（上）\n丙\n第五章 新\n戊\n第二卷 承\n第六章 终\n己\n";
+    let report = document.reimport(text.to_string(), &settings).unwrap();
+    assert_eq!(report.matched, 4);
+    assert_eq!(report.changed.len(), 1);
+    assert_eq!(report.changed[0].node, second);
+    assert!((report.changed[0].similarity - 2.0 / 3.0).abs() < 1e-6);
+    assert_eq!(report.missing.len(), 1);
+    assert_eq!(report.missing[0].title, "第四章 旧");
+    assert_eq!(report.missing[0].words, 1);
+    assert_eq!(report.added.len(), 3);
+
+    let nodes: Vec<_> = document
+        .toc
+        .iter()
+        .map(|(depth, x)| (depth, x.title.as_str()))
+        .collect();
+    assert_eq!(
+        nodes,
+        vec![
+            (0, "第一卷 起"),
+            (1, "第一章 开端"),
+            (1, "第二章 改"),
+            (1, "第三章 高潮"),
+            (1, "第四章 旧"),
+            (1, "第五章 新"),
+            (0, "第二卷 承"),
+            (1, "第六章 终"),
+        ]
+    );
+    let node = document.toc.get(first).unwrap();
+    assert_eq!(node.patch.as_deref(), Some("patch"));
+    assert_eq!(document.node_text(node), Some("第一章 开端\n甲甲\n"));
+    let node = document.toc.get(second).unwrap();
+    assert_eq!(
+        document.node_text(node),
+        Some("第二章 发展\n乙乙\n乙乙二\n新增\n")
+    );
+    assert_eq!(node.meta.words, 7);
+    let node = document.toc.get(id(&document, "第六章 终")).unwrap();
+    assert_eq!(document.node_text(node), Some("第六章 终\n己\n"));
+    // Missing nodes are kept, without text
+    let node = document.toc.get(id(&document, "第四章 旧")).unwrap();
+    assert_eq!(document.node_text(node), Some(""));
+}
+
+#[test]
+fn test_reimport_keeps_edits() {
+    let settings = Settings::default().detection;
+    let text = "第一章 开端\n甲\n插曲\n乙\n第二章 发展\n丙\n第三章 高潮\n丁\n第四章 结局\n戊\n";
+    let mut document = Document::new(text.to_string());
+    document.detect(&settings).unwrap();
+    let [first, second, third, fourth] = document.toc.children()[..] else {
+        panic!("four chapters are detected");
+    };
+    // A node added by hand, which detection does not find, and a merged one
+    let start = text.find("插曲").unwrap() as u128;
+    let end = text.find("第二章").unwrap() as u128;
+    let interlude = document.toc.add("插曲", (start, end), None).unwrap().id;
+    document.toc.merge(third).unwrap();
+
+    let text = "书名\n第一章 开端\n甲\n插曲\n乙改\n第二章 发展\n丙\n第三章 高潮\n丁\n第四章 结局\n戊\n第五章 新\n己\n";
+    let report = document.reimport(text.to_string(), &settings).unwrap();
+    assert_eq!(report.matched, 4);
+    assert!(report.missing.is_empty());
+    assert_eq!(report.added.len(), 1);
+    let changed: Vec<_> = report.changed.iter().map(|x| x.node).collect();
+    assert_eq!(changed, vec![first, interlude]);
+
+    let text = |id: usize| document.node_text(document.toc.get(id).unwrap());
+    assert_eq!(text(first), Some("第一章 开端\n甲\n插曲\n乙改\n"));
+    assert_eq!(text(interlude), Some("插曲\n乙改\n"));
+    assert_eq!(text(second), Some("第二章 发展\n丙\n第三章 高潮\n丁\n"));
+    assert_eq!(text(fourth), Some("第四章 结局\n戊\n"));
+    assert_eq!(text(report.added[0]), Some("第五章 新\n己\n"));
+    let titles: Vec<_> = document.toc.iter().map(|(_, x)| x.title.as_str()).collect();
+    assert_eq!(
+        titles,
+        vec![
+            "第一章 开端",
+            "第二章 发展",
+            "第四章 结局",
+            "插曲",
+            "第五章 新"
+        ]
+    );
+}
+
+#[test]
+fn test_reimport_combined() {
+    let mut document = Document::combine(vec![SourceText {
+        title: "第一卷".to_string(),
+        path: None,
+        text: "第一章\n甲\n".to_string(),
+    }])
+    .unwrap();
+    assert!(matches!(
+        document.reimport(String::new(), &Settings::default().detection),
+        Err(DocumentError::Combined)
+    ));
+}
+
+#[test]
+fn test_edit_text() {
+    let body: String = (1..=12).map(|x| format!("第{}行\n", x)).collect();
+    let mut document = Document::new(format!("第一章 开端\n{}第二章\n乙\n", body));
+    document.detect(&Settings::default().detection).unwrap();
+    let id = document.toc.children()[0];
+    let original = document
+        .node_text(document.toc.get(id).unwrap())
+        .unwrap()
+        .to_string();
+
+    let edited = original
+        .replace("第2行\n", "第二行\n")
+        .replace("第11行\n", "第11行\n新增\n");
+    let preview = document.edit_text(id, &edited).unwrap();
+    assert_eq!(preview.text, edited);
+    assert_eq!(preview.hunks.len(), 2);
+    assert_eq!(preview.hunks[0].old_start, 1);
+    assert!(preview.hunks[0]
+        .lines
+        .contains(&DiffLine::Delete("第2行\n".to_string())));
+    assert!(preview.hunks[0]
+        .lines
+        .contains(&DiffLine::Insert("第二行\n".to_string())));
+    let node = document.toc.get(id).unwrap();
+    assert!(node.patch.as_deref().unwrap().starts_with("--- original\n"));
+    assert_eq!(document.node_text(node), Some(original.as_str()));
+    assert_eq!(document.patched_text(node).unwrap(), edited);
+
+    let preview = document.revert_hunk(id, 0).unwrap();
+    assert_eq!(preview.text, original.replace("第11行\n", "第11行\n新增\n"));
+    assert_eq!(preview.hunks.len(), 1);
+    assert!(matches!(
+        document.revert_hunk(id, 1),
+        Err(DocumentError::HunkNotFound { index: 1, .. })
+    ));
+    let preview = document.revert_hunk(id, 0).unwrap();
+    assert_eq!(preview.text, original);
+    assert!(preview.hunks.is_empty());
+    assert!(document.toc.get(id).unwrap().patch.is_none());
+}
+
+#[test]
+fn test_patch_conflict() {
+    let mut document = Document::new("第一章\n甲\n乙\n".to_string());
+    document.detect(&Settings::default().detection).unwrap();
+    let id = document.toc.children()[0];
+    document.edit_text(id, "第一章\n甲\n丙\n").unwrap();
+    document.text = "第一章\n丁\n戊\n".to_string();
+    assert!(matches!(
+        document.preview_patch(id),
+        Err(DocumentError::PatchConflict(_))
+    ));
+    document.toc.get_mut(id).unwrap().patch = Some("@@ bad".to_string());
+    assert!(matches!(
+        document.preview_patch(id),
+        Err(DocumentError::InvalidPatch { .. })
+    ));
+}
@@ -0,0 +1,708 @@
+// A conformance check of EPUB archives, covering what breaks reading systems most often: the mimetype entry,
+// the container and the package document, manifest and spine consistency, nav and NCX targets,
+// well-formed XHTML and unique ids. It works on any EPUB file, and runs after every EPUB export.
+// Problems are collected as issues instead of stopping at the first one, a broken file only skips
+// the checks which depend on it. Entries are read up to a cap of their uncompressed size, a few kilobytes of
+// zip could inflate to gigabytes.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    io::{Cursor, Read},
+    path::Path,
+};
+
+use quick_xml::{events::BytesStart, events::Event, Reader};
+use serde::Serialize;
+use zip::{CompressionMethod, ZipArchive};
+
+pub use self::error::CheckError;
+
+mod error;
+
+#[cfg(test)]
+mod tests;
+
+const MIMETYPE: &str = "mimetype";
+const EPUB_MIME: &str = "application/epub+zip";
+const CONTAINER: &str = "META-INF/container.xml";
+const OPF_MIME: &str = "application/oebps-package+xml";
+const XHTML_MIME: &str = "application/xhtml+xml";
+const NCX_MIME: &str = "application/x-dtbncx+xml";
+
+/// Uncompressed size of an entry, and of all of them, above which the rest is not read.
+const MAX_ENTRY_SIZE: u64 = 64 * 1024 * 1024;
+const MAX_ARCHIVE_SIZE: u64 = 512 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Error,   // reading systems may reject the book
+    Warning, // the book is readable, but not as intended
+}
+
+/// What an issue is about, in the order the checks run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Category {
+    Archive,
+    Mimetype,
+    Container,
+    Package,
+    Manifest,
+    Spine,
+    Nav,
+    Ncx,
+    Xhtml,
+    Id,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CheckIssue {
+    pub severity: Severity,
+    pub category: Category,
+    pub file: Option<String>, // path in the archive, None for the archive itself
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CheckReport {
+    pub files: usize, // entries in the archive
+    pub issues: Vec<CheckIssue>,
+}
+
+impl CheckReport {
+    /// No errors are found, warnings are allowed.
+    pub fn is_valid(&self) -> bool {
+        self.errors().next().is_none()
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &CheckIssue> {
+        self.issues.iter().filter(|x| x.severity == Severity::Error)
+    }
+}
+
+/// Check an EPUB archive in memory.
+pub fn check_epub(buf: &[u8]) -> CheckReport {
+    let mut checker = Checker::default();
+    let files = checker.read_archive(buf);
+    if let Some(files) = &files {
+        checker.check_package(files);
+    }
+    CheckReport {
+        files: files.map_or(0, |x| x.len()),
+        issues: checker.issues,
+    }
+}
+
+/// Check an EPUB file.
+pub fn check_file(path: &Path) -> Result<CheckReport, CheckError> {
+    let buf = std::fs::read(path).map_err(|source| CheckError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    Ok(check_epub(&buf))
+}
+
+/// An element of a parsed XML file, with the local names of its ancestors.
+#[derive(Debug)]
+struct Element {
+    name: String,
+    attributes: Vec<(String, String)>, // qualified names, e.g. `epub:type`
+    ancestors: Vec<String>,
+}
+
+impl Element {
+    fn attribute(&self, key: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|x| x.0 == key)
+            .map(|x| x.1.as_str())
+    }
+
+    fn parent(&self) -> Option<&str> {
+        self.ancestors.last().map(|x| x.as_str())
+    }
+}
+
+#[derive(Debug)]
+struct Item {
+    id: String,
+    path: String, // resolved against the package document
+    media_type: String,
+    properties: Vec<String>,
+}
+
+#[derive(Debug)]
+struct Checker {
+    issues: Vec<CheckIssue>,
+    max_entry_size: u64,
+    max_archive_size: u64,
+}
+
+impl Default for Checker {
+    fn default() -> Self {
+        Checker {
+            issues: Vec::new(),
+            max_entry_size: MAX_ENTRY_SIZE,
+            max_archive_size: MAX_ARCHIVE_SIZE,
+        }
+    }
+}
+
+impl Checker {
+    fn error(&mut self, category: Category, file: Option<&str>, message: String) {
+        self.push(Severity::Error, category, file, message);
+    }
+
+    fn warning(&mut self, category: Category, file: Option<&str>, message: String) {
+        self.push(Severity::Warning, category, file, message);
+    }
+
+    fn push(
+        &mut self,
+        severity: Severity,
+        category: Category,
+        file: Option<&str>,
+        message: String,
+    ) {
+        self.issues.push(CheckIssue {
+            severity,
+            category,
+            file: file.map(|x| x.to_string()),
+            message,
+        });
+    }
+
+    ///
+    /// Read all entries, checking the mimetype one on the way. An entry above the size cap is skipped,
+    /// the entries after the one which reaches the cap of the archive are not read.
+    ///
+    fn read_archive(&mut self, buf: &[u8]) -> Option<BTreeMap<String, Vec<u8>>> {
+        let mut archive = match ZipArchive::new(Cursor::new(buf)) {
+            Ok(x) => x,
+            Err(e) => {
+                self.error(Category::Archive, None, format!("not a zip archive: {}", e));
+                return None;
+            }
+        };
+        let mut files = BTreeMap::new();
+        let mut total = 0;
+        for i in 0..archive.len() {
+            let mut file = match archive.by_index(i) {
+                Ok(x) => x,
+                Err(e) => {
+                    let message = format!("entry {} is unreadable: {}", i, e);
+                    self.error(Category::Archive, None, message);
+                    continue;
+                }
+            };
+            let name = file.name().to_string();
+            let compression = file.compression();
+            // Sizes in the archive could be forged, so the cap is applied while reading
+            let left = self.max_archive_size - total;
+            let limit = self.max_entry_size.min(left);
+            let mut content = Vec::new();
+            if let Err(e) = (&mut file).take(limit + 1).read_to_end(&mut content) {
+                self.error(
+                    Category::Archive,
+                    Some(&name),
+                    format!("is unreadable: {}", e),
+                );
+                continue;
+            }
+            if content.len() as u64 > limit {
+                if limit == left {
+                    let message = format!(
+                        "the archive is larger than {} bytes uncompressed, entries from `{}` on are not checked",
+                        self.max_archive_size, name
+                    );
+                    self.error(Category::Archive, None, message);
+                    break;
+                }
+                let message = format!(
+                    "is larger than {} bytes uncompressed, and not checked",
+                    self.max_entry_size
+                );
+                self.error(Category::Archive, Some(&name), message);
+                continue;
+            }
+            total += content.len() as u64;
+            if name == MIMETYPE {
+                if i != 0 {
+                    let message = "must be the first entry of the archive".to_string();
+                    self.error(Category::Mimetype, Some(&name), message);
+                }
+                if compression != CompressionMethod::Stored {
+                    let message = "must be stored without compression".to_string();
+                    self.error(Category::Mimetype, Some(&name), message);
+                }
+                if content != EPUB_MIME.as_bytes() {
+                    let message = format!("must contain exactly `{}`", EPUB_MIME);
+                    self.error(Category::Mimetype, Some(&name), message);
+                }
+            }
+            if files.insert(name.clone(), content).is_some() {
+                self.error(Category::Archive, Some(&name), "is duplicated".to_string());
+            }
+        }
+        if !files.contains_key(MIMETYPE) {
+            self.error(
+                Category::Mimetype,
+                None,
+                "the mimetype entry is missing".into(),
+            );
+        }
+        Some(files)
+    }
+
+    fn check_package(&mut self, files: &BTreeMap<String, Vec<u8>>) {
+        let Some(opf) = self.rootfile(files) else {
+            return;
+        };
+        let Some(package) = files
+            .get(&opf)
+            .and_then(|x| self.parse(Category::Package, &opf, x))
+        else {
+            return;
+        };
+        let version = package
+            .iter()
+            .find(|x| x.name == "package")
+            .and_then(|x| x.attribute("version"))
+            .unwrap_or_default()
+            .to_string();
+        if version.is_empty() {
+            let message = "the package has no version".to_string();
+            self.error(Category::Package, Some(&opf), message);
+        }
+
+        let items = self.check_manifest(files, &opf, &package);
+        self.check_spine(&opf, &package, &items);
+
+        // Ids of the content documents, for the fragments of nav and NCX targets
+        let mut parsed = HashMap::new();
+        let mut ids: HashMap<&str, HashSet<String>> = HashMap::new();
+        for item in items.values().filter(|x| x.media_type == XHTML_MIME) {
+            let Some(elements) = files
+                .get(&item.path)
+                .and_then(|x| self.parse(Category::Xhtml, &item.path, x))
+            else {
+                continue;
+            };
+            if elements.first().is_some_and(|x| x.name != "html") {
+                let message = "the root element is not `html`".to_string();
+                self.warning(Category::Xhtml, Some(&item.path), message);
+            }
+            ids.insert(&item.path, self.collect_ids(&item.path, &elements));
+            parsed.insert(item.path.as_str(), elements);
+        }
+        let manifest: HashSet<&str> = items.values().map(|x| x.path.as_str()).collect();
+        let targets = Targets {
+            manifest: &manifest,
+            ids: &ids,
+        };
+
+        let navs: Vec<&Item> = items
+            .values()
+            .filter(|x| x.properties.iter().any(|x| x == "nav"))
+            .collect();
+        match navs.as_slice() {
+            [] if version.starts_with('3') => {
+                let message = "no manifest item has the `nav` property".to_string();
+                self.error(Category::Nav, Some(&opf), message);
+            }
+            [nav] => self.check_nav(nav, parsed.get(nav.path.as_str()), &targets),
+            [] => {}
+            _ => {
+                let message = "more than one manifest item has the `nav` property".to_string();
+                self.error(Category::Nav, Some(&opf), message);
+            }
+        }
+        self.check_ncx(files, &opf, &version, &package, &items, &targets);
+    }
+
+    /// Path of the package document, from the container.
+    fn rootfile(&mut self, files: &BTreeMap<String, Vec<u8>>) -> Option<String> {
+        let Some(container) = files.get(CONTAINER) else {
+            let message = format!("`{}` is missing", CONTAINER);
+            self.error(Category::Container, None, message);
+            return None;
+        };
+        let elements = self.parse(Category::Container, CONTAINER, container)?;
+        let rootfiles: Vec<&Element> = elements.iter().filter(|x| x.name == "rootfile").collect();
+        let path = rootfiles
+            .iter()
+            .find(|x| x.attribute("media-type") == Some(OPF_MIME))
+            .or(rootfiles.first())
+            .and_then(|x| x.attribute("full-path"));
+        let Some(path) = path else {
+            let message = "no rootfile refers to a package document".to_string();
+            self.error(Category::Container, Some(CONTAINER), message);
+            return None;
+        };
+        let path = resolve("", path).0;
+        if !files.contains_key(&path) {
+            let message = format!("the package document `{}` is missing", path);
+            self.error(Category::Container, Some(CONTAINER), message);
+            return None;
+        }
+        Some(path)
+    }
+
+    fn check_manifest(
+        &mut self,
+        files: &BTreeMap<String, Vec<u8>>,
+        opf: &str,
+        package: &[Element],
+    ) -> BTreeMap<String, Item> {
+        let mut items: BTreeMap<String, Item> = BTreeMap::new();
+        let mut paths: HashSet<String> = HashSet::new();
+        let elements = package
+            .iter()
+            .filter(|x| x.name == "item" && x.parent() == Some("manifest"));
+        for element in elements {
+            let attributes = (
+                element.attribute("id"),
+                element.attribute("href"),
+                element.attribute("media-type"),
+            );
+            let (Some(id), Some(href), Some(media_type)) = attributes else {
+                let message = "an item lacks `id`, `href` or `media-type`".to_string();
+                self.error(Category::Manifest, Some(opf), message);
+                continue;
+            };
+            if items.contains_key(id) {
+                let message = format!("the item id `{}` is duplicated", id);
+                self.error(Category::Manifest, Some(opf), message);
+                continue;
+            }
+            let path = resolve(opf, href).0;
+            if !files.contains_key(&path) {
+                let message = format!("the item `{}` refers to a missing file `{}`", id, href);
+                self.error(Category::Manifest, Some(opf), message);
+            }
+            if !paths.insert(path.clone()) {
+                let message = format!("`{}` is listed more than once", href);
+                self.warning(Category::Manifest, Some(opf), message);
+            }
+            let properties = element
+                .attribute("properties")
+                .unwrap_or_default()
+                .split_whitespace()
+                .map(|x| x.to_string())
+                .collect();
+            let item = Item {
+                id: id.to_string(),
+                path,
+                media_type: media_type.to_string(),
+                properties,
+            };
+            items.insert(item.id.clone(), item);
+        }
+        let unlisted = files.keys().filter(|x| {
+            !x.ends_with('/')
+                && *x != MIMETYPE
+                && !x.starts_with("META-INF/")
+                && *x != opf
+                && !paths.contains(*x)
+        });
+        for file in unlisted {
+            let message = "is not listed in the manifest".to_string();
+            self.warning(Category::Manifest, Some(file), message);
+        }
+        items
+    }
+
+    fn check_spine(&mut self, opf: &str, package: &[Element], items: &BTreeMap<String, Item>) {
+        let itemrefs: Vec<&Element> = package
+            .iter()
+            .filter(|x| x.name == "itemref" && x.parent() == Some("spine"))
+            .collect();
+        if itemrefs.is_empty() {
+            self.error(Category::Spine, Some(opf), "the spine is empty".to_string());
+        }
+        let mut seen = HashSet::new();
+        for itemref in itemrefs {
+            let Some(idref) = itemref.attribute("idref") else {
+                let message = "an itemref lacks `idref`".to_string();
+                self.error(Category::Spine, Some(opf), message);
+                continue;
+            };
+            let Some(item) = items.get(idref) else {
+                let message = format!("the itemref `{}` is not in the manifest", idref);
+                self.error(Category::Spine, Some(opf), message);
+                continue;
+            };
+            if !seen.insert(idref) {
+                let message = format!("the itemref `{}` is duplicated", idref);
+                self.error(Category::Spine, Some(opf), message);
+            }
+            if item.media_type != XHTML_MIME && item.media_type != "image/svg+xml" {
+                let message = format!(
+                    "the itemref `{}` is not a content document but `{}`",
+                    idref, item.media_type
+                );
+                self.warning(Category::Spine, Some(opf), message);
+            }
+        }
+    }
+
+    fn check_nav(&mut self, nav: &Item, elements: Option<&Vec<Element>>, targets: &Targets) {
+        // A broken nav document is reported as xhtml already
+        let Some(elements) = elements else {
+            return;
+        };
+        let toc = elements.iter().any(|x| {
+            x.name == "nav"
+                && x.attribute("epub:type")
+                    .is_some_and(|x| x.split_whitespace().any(|x| x == "toc"))
+        });
+        if !toc {
+            let message = "no `nav` element has `epub:type=\"toc\"`".to_string();
+            self.error(Category::Nav, Some(&nav.path), message);
+        }
+        let links = elements
+            .iter()
+            .filter(|x| x.name == "a" && x.ancestors.iter().any(|x| x == "nav"))
+            .filter_map(|x| x.attribute("href"));
+        for href in links {
+            self.check_target(Category::Nav, &nav.path, href, targets);
+        }
+    }
+
+    fn check_ncx(
+        &mut self,
+        files: &BTreeMap<String, Vec<u8>>,
+        opf: &str,
+        version: &str,
+        package: &[Element],
+        items: &BTreeMap<String, Item>,
+        targets: &Targets,
+    ) {
+        let toc = package
+            .iter()
+            .find(|x| x.name == "spine")
+            .and_then(|x| x.attribute("toc"));
+        let Some(toc) = toc else {
+            // The NCX is required by EPUB 2 only
+            if version.starts_with('2') {
+                let message = "the spine has no `toc` for the NCX".to_string();
+                self.error(Category::Ncx, Some(opf), message);
+            }
+            return;
+        };
+        let Some(item) = items.get(toc).filter(|x| x.media_type == NCX_MIME) else {
+            let message = format!("the spine toc `{}` is not an NCX item", toc);
+            self.error(Category::Ncx, Some(opf), message);
+            return;
+        };
+        let Some(elements) = files
+            .get(&item.path)
+            .and_then(|x| self.parse(Category::Ncx, &item.path, x))
+        else {
+            return;
+        };
+        let sources = elements
+            .iter()
+            .filter(|x| x.name == "content")
+            .filter_map(|x| x.attribute("src"));
+        for src in sources {
+            self.check_target(Category::Ncx, &item.path, src, targets);
+        }
+    }
+
+    /// A link must refer to a manifest item, and to an existing id of it if it has a fragment.
+    fn check_target(&mut self, category: Category, from: &str, href: &str, targets: &Targets) {
+        if is_external(href) {
+            return;
+        }
+        let (path, fragment) = resolve(from, href);
+        if !targets.manifest.contains(path.as_str()) {
+            let message = format!("`{}` does not refer to a manifest item", href);
+            self.error(category, Some(from), message);
+            return;
+        }
+        let Some(fragment) = fragment.filter(|x| !x.is_empty()) else {
+            return;
+        };
+        if let Some(ids) = targets.ids.get(path.as_str()) {
+            if !ids.contains(&fragment) {
+                let message = format!("`{}` refers to a missing id", href);
+                self.error(category, Some(from), message);
+            }
+        }
+    }
+
+    fn collect_ids(&mut self, file: &str, elements: &[Element]) -> HashSet<String> {
+        let mut ids = HashSet::new();
+        let values = elements.iter().flat_map(|x| {
+            x.attributes
+                .iter()
+                .filter(|x| x.0 == "id" || x.0 == "xml:id")
+                .map(|x| x.1.as_str())
+        });
+        for id in values {
+            if !ids.insert(id.to_string()) {
+                self.error(
+                    Category::Id,
+                    Some(file),
+                    format!("the id `{}` is duplicated", id),
+                );
+            }
+        }
+        ids
+    }
+
+    fn parse(&mut self, category: Category, file: &str, buf: &[u8]) -> Option<Vec<Element>> {
+        let text = match std::str::from_utf8(buf) {
+            Ok(x) => x,
+            Err(e) => {
+                self.error(category, Some(file), format!("is not valid UTF-8: {}", e));
+                return None;
+            }
+        };
+        match parse_xml(text) {
+            Ok(x) => Some(x),
+            Err(message) => {
+                self.error(
+                    category,
+                    Some(file),
+                    format!("is not well-formed: {}", message),
+                );
+                None
+            }
+        }
+    }
+}
+
+/// What nav and NCX links may refer to.
+struct Targets<'a> {
+    manifest: &'a HashSet<&'a str>,
+    ids: &'a HashMap<&'a str, HashSet<String>>,
+}
+
+/// Parse a whole XML document into its elements in document order, or describe why it is not well-formed.
+fn parse_xml(text: &str) -> Result<Vec<Element>, String> {
+    let at = |position: usize, message: String| {
+        let line = text[..position.min(text.len())].matches('\n').count() + 1;
+        format!("line {}: {}", line, message)
+    };
+    let mut reader = Reader::from_str(text);
+    reader.check_end_names(true);
+    let mut elements = Vec::new();
+    let mut stack: Vec<String> = Vec::new();
+    let mut roots = 0;
+    loop {
+        let position = reader.buffer_position();
+        let event = reader
+            .read_event()
+            .map_err(|e| at(reader.buffer_position(), e.to_string()))?;
+        match event {
+            Event::Start(ref e) | Event::Empty(ref e) => {
+                if stack.is_empty() {
+                    roots += 1;
+                    if roots > 1 {
+                        return Err(at(position, "more than one root element".into()));
+                    }
+                }
+                let element = element(e, &stack).map_err(|x| at(position, x))?;
+                if matches!(event, Event::Start(_)) {
+                    stack.push(element.name.clone());
+                }
+                elements.push(element);
+            }
+            Event::End(_) => {
+                stack.pop();
+            }
+            Event::Text(e) => {
+                let text = e.unescape().map_err(|x| at(position, x.to_string()))?;
+                if stack.is_empty() && !text.trim().is_empty() {
+                    return Err(at(position, "text outside the root element".into()));
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    if let Some(name) = stack.last() {
+        return Err(at(text.len(), format!("`<{}>` is not closed", name)));
+    }
+    if roots == 0 {
+        return Err(at(0, "no root element".into()));
+    }
+    Ok(elements)
+}
+
+fn element(start: &BytesStart, ancestors: &[String]) -> Result<Element, String> {
+    let mut attributes = Vec::new();
+    for attribute in start.attributes() {
+        let attribute = attribute.map_err(|e| e.to_string())?;
+        let key = String::from_utf8_lossy(attribute.key.as_ref()).into_owned();
+        let value = attribute.unescape_value().map_err(|e| e.to_string())?;
+        attributes.push((key, value.into_owned()));
+    }
+    Ok(Element {
+        name: String::from_utf8_lossy(start.local_name().as_ref()).into_owned(),
+        attributes,
+        ancestors: ancestors.to_vec(),
+    })
+}
+
+/// Links with a scheme, e.g. `https:` or `mailto:`, are not part of the book.
+fn is_external(href: &str) -> bool {
+    href.split(['/', '?', '#'])
+        .next()
+        .is_some_and(|x| x.contains(':'))
+}
+
+///
+/// Resolve a link of a file in the archive to `(path, fragment)`, both percent-decoded.
+/// A link with only a fragment refers to the file itself.
+///
+fn resolve(from: &str, href: &str) -> (String, Option<String>) {
+    let (path, fragment) = match href.split_once('#') {
+        Some((path, fragment)) => (path, Some(percent_decode(fragment))),
+        None => (href, None),
+    };
+    if path.is_empty() {
+        return (from.to_string(), fragment);
+    }
+    let mut parts: Vec<&str> = match from.rsplit_once('/') {
+        Some((dir, _)) => dir.split('/').collect(),
+        None => Vec::new(),
+    };
+    let path = percent_decode(path);
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            x => parts.push(x),
+        }
+    }
+    (parts.join("/"), fragment)
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|x| std::str::from_utf8(x).ok())
+            .and_then(|x| u8::from_str_radix(x, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(x)) => {
+                decoded.push(x);
+                i += 3;
+            }
+            (x, _) => {
+                decoded.push(x);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
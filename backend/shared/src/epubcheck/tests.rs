@@ -0,0 +1,366 @@
+use std::io::{Cursor, Write};
+
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+use super::{check_epub, resolve, Category, CheckReport, Checker, Severity};
+use crate::{
+    document::Document,
+    export::{export_epub, BookMeta},
+    settings::Settings,
+};
+
+const CONTAINER: &str = r#"<?xml version="1.0"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>"#;
+
+const OPF: &str = r#"<?xml version="1.0"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="id">
+  <manifest>
+    <item id="nav" href="nav.xhtml" media-type="application/xhtml+xml" properties="nav"/>
+    <item id="ncx" href="toc.ncx" media-type="application/x-dtbncx+xml"/>
+    <item id="c1" href="text/chapter%201.xhtml" media-type="application/xhtml+xml"/>
+  </manifest>
+  <spine toc="ncx">
+    <itemref idref="c1"/>
+  </spine>
+</package>"#;
+
+const NAV: &str = r#"<?xml version="1.0"?>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops">
+  <body>
+    <nav epub:type="toc"><ol><li><a href="text/chapter%201.xhtml#top">一</a></li></ol></nav>
+  </body>
+</html>"#;
+
+const NCX: &str = r#"<?xml version="1.0"?>
+<ncx xmlns="http://www.daisy.org/z3986/2005/ncx/" version="2005-1">
+  <navMap>
+    <navPoint id="p1"><navLabel><text>一</text></navLabel><content src="text/chapter%201.xhtml"/></navPoint>
+  </navMap>
+</ncx>"#;
+
+const CHAPTER: &str = r#"<?xml version="1.0"?>
+<html xmlns="http://www.w3.org/1999/xhtml"><body><h1 id="top">一</h1><p>甲 &amp; 乙</p></body></html>"#;
+
+/// Entries of a valid book, replaced or dropped by name in the tests.
+fn entries() -> Vec<(&'static str, &'static str)> {
+    vec![
+        ("mimetype", "application/epub+zip"),
+        ("META-INF/container.xml", CONTAINER),
+        ("OEBPS/content.opf", OPF),
+        ("OEBPS/nav.xhtml", NAV),
+        ("OEBPS/toc.ncx", NCX),
+        ("OEBPS/text/chapter 1.xhtml", CHAPTER),
+    ]
+}
+
+fn with(name: &'static str, content: &'static str) -> Vec<(&'static str, &'static str)> {
+    let mut entries = entries();
+    entries.iter_mut().find(|x| x.0 == name).unwrap().1 = content;
+    entries
+}
+
+fn zip(entries: &[(&str, &str)], mimetype: CompressionMethod) -> Vec<u8> {
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, content) in entries {
+        let method = match *name {
+            "mimetype" => mimetype,
+            _ => CompressionMethod::Deflated,
+        };
+        let options = FileOptions::default().compression_method(method);
+        writer.start_file(*name, options).unwrap();
+        writer.write_all(content.as_bytes()).unwrap();
+    }
+    writer.finish().unwrap().into_inner()
+}
+
+fn check(entries: &[(&str, &str)]) -> CheckReport {
+    check_epub(&zip(entries, CompressionMethod::Stored))
+}
+
+fn issues(report: &CheckReport) -> Vec<(Severity, Category, Option<&str>)> {
+    report
+        .issues
+        .iter()
+        .map(|x| (x.severity, x.category, x.file.as_deref()))
+        .collect()
+}
+
+#[test]
+fn test_valid() {
+    let report = check(&entries());
+    assert_eq!(report.files, 6);
+    assert!(report.issues.is_empty(), "{:?}", report.issues);
+    assert!(report.is_valid());
+}
+
+#[test]
+fn test_exported_book() {
+    let mut document = Document::new(
+        "序\n\n第一卷 起\n第一章 甲\n甲文（注1）\n注1：说明 & <注>\n\n第二章 乙\n乙文\n"
+            .to_string(),
+    );
+    let settings = Settings::default();
+    document.detect(&settings.detection).unwrap();
+    let book = BookMeta::new("书", &settings.export);
+    let report = check_epub(&export_epub(&document, &settings, &book).unwrap());
+    assert!(report.issues.is_empty(), "{:?}", report.issues);
+}
+
+#[test]
+fn test_archive() {
+    let report = check_epub(b"not a zip");
+    assert_eq!(
+        issues(&report),
+        vec![(Severity::Error, Category::Archive, None)]
+    );
+    assert!(!report.is_valid());
+}
+
+#[test]
+fn test_size_caps() {
+    let big = "x".repeat(600);
+    let mut entries: Vec<(&str, &str)> = entries();
+    entries.push(("OEBPS/big.txt", &big));
+    let buf = zip(&entries, CompressionMethod::Stored);
+
+    // Entries above their cap are skipped, the rest is checked
+    let mut checker = Checker {
+        max_entry_size: 500,
+        ..Default::default()
+    };
+    let files = checker.read_archive(&buf).unwrap();
+    assert_eq!(files.len(), 6);
+    let found: Vec<_> = checker
+        .issues
+        .iter()
+        .map(|x| (x.category, x.file.as_deref()))
+        .collect();
+    assert_eq!(found, vec![(Category::Archive, Some("OEBPS/big.txt"))]);
+
+    // Reading stops once the archive reaches its cap
+    let size: usize = entries[..6].iter().map(|x| x.1.len()).sum();
+    let mut checker = Checker {
+        max_archive_size: size as u64 + 100,
+        ..Default::default()
+    };
+    let files = checker.read_archive(&buf).unwrap();
+    assert_eq!(files.len(), 6);
+    assert_eq!(checker.issues.len(), 1);
+    assert_eq!(checker.issues[0].file, None);
+    assert!(checker.issues[0].message.contains("OEBPS/big.txt"));
+}
+
+#[test]
+fn test_mimetype() {
+    let report = check_epub(&zip(&entries(), CompressionMethod::Deflated));
+    assert_eq!(
+        issues(&report),
+        vec![(Severity::Error, Category::Mimetype, Some("mimetype"))]
+    );
+
+    let mut entries = entries();
+    entries.rotate_left(1);
+    assert_eq!(
+        issues(&check(&entries)),
+        vec![(Severity::Error, Category::Mimetype, Some("mimetype"))]
+    );
+
+    let report = check(&with("mimetype", "application/zip"));
+    assert!(report.issues[0].message.contains("application/epub+zip"));
+}
+
+#[test]
+fn test_container() {
+    let entries: Vec<_> = entries()
+        .into_iter()
+        .filter(|x| x.0 != "META-INF/container.xml")
+        .collect();
+    let report = check(&entries);
+    assert_eq!(report.issues[0].category, Category::Container);
+    // Nothing else could be checked without the package document
+    assert!(report
+        .issues
+        .iter()
+        .all(|x| x.category == Category::Container || x.severity == Severity::Warning));
+
+    let report = check(&with(
+        "META-INF/container.xml",
+        r#"<container><rootfiles><rootfile full-path="content.opf"/></rootfiles></container>"#,
+    ));
+    assert_eq!(
+        issues(&report),
+        vec![(
+            Severity::Error,
+            Category::Container,
+            Some("META-INF/container.xml")
+        )]
+    );
+}
+
+#[test]
+fn test_manifest_and_spine() {
+    let opf = r#"<package version="3.0">
+  <manifest>
+    <item id="nav" href="nav.xhtml" media-type="application/xhtml+xml" properties="nav"/>
+    <item id="ncx" href="toc.ncx" media-type="application/x-dtbncx+xml"/>
+    <item id="c1" href="text/chapter%201.xhtml" media-type="application/xhtml+xml"/>
+    <item id="c2" href="text/missing.xhtml" media-type="application/xhtml+xml"/>
+  </manifest>
+  <spine toc="ncx"><itemref idref="c1"/><itemref idref="c3"/><itemref idref="ncx"/></spine>
+</package>"#;
+    let mut entries = with("OEBPS/content.opf", opf);
+    entries.push(("OEBPS/extra.css", "p {}"));
+    let report = check(&entries);
+    let found: Vec<_> = report
+        .issues
+        .iter()
+        .map(|x| (x.severity, x.category, x.message.as_str()))
+        .collect();
+    assert_eq!(
+        found,
+        vec![
+            (
+                Severity::Error,
+                Category::Manifest,
+                "the item `c2` refers to a missing file `text/missing.xhtml`"
+            ),
+            (
+                Severity::Warning,
+                Category::Manifest,
+                "is not listed in the manifest"
+            ),
+            (
+                Severity::Error,
+                Category::Spine,
+                "the itemref `c3` is not in the manifest"
+            ),
+            (
+                Severity::Warning,
+                Category::Spine,
+                "the itemref `ncx` is not a content document but `application/x-dtbncx+xml`"
+            ),
+        ]
+    );
+    assert_eq!(report.issues[1].file.as_deref(), Some("OEBPS/extra.css"));
+}
+
+#[test]
+fn test_nav_and_ncx_targets() {
+    let nav = r#"<html xmlns:epub="http://www.idpf.org/2007/ops"><body><nav epub:type="toc"><ol>
+<li><a href="text/chapter%201.xhtml#top">一</a></li>
+<li><a href="text/chapter%201.xhtml#gone">二</a></li>
+<li><a href="text/chapter%202.xhtml">三</a></li>
+<li><a href="https://example.com/">四</a></li>
+</ol></nav></body></html>"#;
+    let ncx = r#"<ncx><navMap>
+<navPoint><content src="../chapter%201.xhtml"/></navPoint>
+</navMap></ncx>"#;
+    let mut entries = with("OEBPS/nav.xhtml", nav);
+    entries
+        .iter_mut()
+        .find(|x| x.0 == "OEBPS/toc.ncx")
+        .unwrap()
+        .1 = ncx;
+    let report = check(&entries);
+    let found: Vec<_> = report
+        .issues
+        .iter()
+        .map(|x| (x.category, x.message.as_str()))
+        .collect();
+    assert_eq!(
+        found,
+        vec![
+            (
+                Category::Nav,
+                "`text/chapter%201.xhtml#gone` refers to a missing id"
+            ),
+            (
+                Category::Nav,
+                "`text/chapter%202.xhtml` does not refer to a manifest item"
+            ),
+            (
+                Category::Ncx,
+                "`../chapter%201.xhtml` does not refer to a manifest item"
+            ),
+        ]
+    );
+}
+
+#[test]
+fn test_missing_nav() {
+    let report = check(&with(
+        "OEBPS/content.opf",
+        r#"<package version="3.0"><manifest>
+<item id="c1" href="text/chapter%201.xhtml" media-type="application/xhtml+xml"/>
+</manifest><spine><itemref idref="c1"/></spine></package>"#,
+    ));
+    let found: Vec<_> = issues(&report)
+        .into_iter()
+        .filter(|x| x.0 == Severity::Error)
+        .collect();
+    assert_eq!(
+        found,
+        vec![(Severity::Error, Category::Nav, Some("OEBPS/content.opf"))]
+    );
+}
+
+#[test]
+fn test_xhtml() {
+    for chapter in [
+        "<html><body><p>甲</body></html>",
+        "<html><body><p>甲</p></body>",
+        "<html><body><p>&nbsp;</p></body></html>",
+        "<html><body><p a=\"1\" a=\"2\">甲</p></body></html>",
+        "<html></html><html></html>",
+    ] {
+        let entries = with("OEBPS/text/chapter 1.xhtml", chapter);
+        let report = check(&entries);
+        let found: Vec<_> = report.issues.iter().map(|x| x.category).collect();
+        // The nav target could not be checked without the ids of the chapter
+        assert_eq!(found, vec![Category::Xhtml], "{}", chapter);
+        assert!(report.issues[0]
+            .message
+            .starts_with("is not well-formed: line 1:"));
+    }
+}
+
+#[test]
+fn test_duplicate_ids() {
+    let report = check(&with(
+        "OEBPS/text/chapter 1.xhtml",
+        r#"<html><body><h1 id="top">一</h1><p id="top">甲</p></body></html>"#,
+    ));
+    assert_eq!(
+        issues(&report),
+        vec![(
+            Severity::Error,
+            Category::Id,
+            Some("OEBPS/text/chapter 1.xhtml")
+        )]
+    );
+    assert_eq!(report.issues[0].message, "the id `top` is duplicated");
+}
+
+#[test]
+fn test_resolve() {
+    assert_eq!(
+        resolve("OEBPS/nav.xhtml", "text/a%20b.xhtml#c%31"),
+        ("OEBPS/text/a b.xhtml".to_string(), Some("c1".to_string()))
+    );
+    assert_eq!(
+        resolve("OEBPS/text/a.xhtml", "../b.xhtml"),
+        ("OEBPS/b.xhtml".to_string(), None)
+    );
+    assert_eq!(
+        resolve("OEBPS/text/a.xhtml", "#x"),
+        ("OEBPS/text/a.xhtml".to_string(), Some("x".to_string()))
+    );
+    assert_eq!(
+        resolve("", "content.opf"),
+        ("content.opf".to_string(), None)
+    );
+}
@@ -0,0 +1,129 @@
+use std::collections::HashSet;
+
+use epub_builder::{EpubBuilder, EpubContent, EpubVersion, ReferenceType, ZipLibrary};
+
+use super::{
+    export, export_with_progress, BookMeta, Contents, ExportError, Exporter, Format, Progress,
+    Reporter, Stage,
+};
+use crate::{document::Document, settings::Settings, toc::NodeKind, typography::PageDirection};
+
+/// Build an EPUB 3 book in memory.
+pub fn export_epub(
+    document: &Document,
+    settings: &Settings,
+    book: &BookMeta,
+) -> Result<Vec<u8>, ExportError> {
+    export(document, settings, book, Format::Epub)
+}
+
+///
+/// Build an EPUB 3 book in memory, reporting progress before every stage and chapter.
+/// The export is cancelled with `ExportError::Cancelled` once `on_progress` returns false.
+///
+pub fn export_epub_with_progress(
+    document: &Document,
+    settings: &Settings,
+    book: &BookMeta,
+    on_progress: impl FnMut(&Progress) -> bool,
+) -> Result<Vec<u8>, ExportError> {
+    export_with_progress(document, settings, book, Format::Epub, on_progress)
+}
+
+/// EPUB 3 books, chapters are rendered with the templates.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EpubExporter;
+
+impl Exporter for EpubExporter {
+    fn format(&self) -> Format {
+        Format::Epub
+    }
+
+    fn write(&self, contents: &Contents, progress: &mut Reporter) -> Result<Vec<u8>, ExportError> {
+        let book = &contents.book;
+        let templates = contents.templates();
+        let mut builder =
+            EpubBuilder::new(ZipLibrary::new().map_err(epub_error)?).map_err(epub_error)?;
+        builder.epub_version(EpubVersion::V30);
+        builder.set_title(book.title.as_str());
+        builder.set_lang(book.language.as_str());
+        if contents.typography().direction() == PageDirection::Rtl {
+            builder.metadata("direction", "rtl").map_err(epub_error)?;
+        }
+        if let Some(author) = &book.author {
+            builder.add_author(author.as_str());
+        }
+        builder
+            .stylesheet(templates.stylesheet.as_bytes())
+            .map_err(epub_error)?;
+
+        let chapters = &contents.chapters;
+        let mut landmarks = Vec::new();
+        let mut file_names = HashSet::new();
+        for (i, chapter) in chapters.iter().enumerate() {
+            progress.chapter(i, chapters.len(), chapter)?;
+            let html = templates.render_chapter(book, chapter)?;
+            // Custom file names must be unique and safe to be used in the package as is
+            let file_name = match &chapter.file_name {
+                Some(name) if is_valid_file_name(name) && !file_names.contains(name) => {
+                    name.clone()
+                }
+                _ => format!("chapter_{:04}.xhtml", i),
+            };
+            file_names.insert(file_name.clone());
+            let mut content = EpubContent::new(file_name, html.as_bytes());
+            // Untitled and hidden content is kept in the spine but not in the toc.
+            if !chapter.title.is_empty() && chapter.kind != NodeKind::Hidden {
+                content = content
+                    .title(chapter.title.as_str())
+                    .level(chapter.depth as i32 + 1);
+                // Only the first one of each kind is a landmark
+                if let Some(reftype) = reference_type(chapter.kind) {
+                    if !landmarks.contains(&reftype) {
+                        landmarks.push(reftype);
+                        content = content.reftype(reftype);
+                    }
+                }
+            }
+            builder.add_content(content).map_err(epub_error)?;
+        }
+
+        progress.report(Stage::Package, 90, None)?;
+        for image in contents.images.iter() {
+            let buf = contents.load_image(image)?;
+            builder
+                .add_resource(image.href.as_str(), buf.as_slice(), image.mime.as_str())
+                .map_err(epub_error)?;
+        }
+        let mut buf = Vec::new();
+        builder.generate(&mut buf).map_err(epub_error)?;
+        Ok(buf)
+    }
+}
+
+///
+/// Landmarks (EPUB 3) and guide references (EPUB 2) of a kind.
+/// epub-builder has no afterword or backmatter type, colophon is the closest one at the end of a book.
+///
+fn reference_type(kind: NodeKind) -> Option<ReferenceType> {
+    match kind {
+        NodeKind::Front => Some(ReferenceType::Preface),
+        NodeKind::Volume | NodeKind::Chapter => Some(ReferenceType::Text),
+        NodeKind::Back => Some(ReferenceType::Colophon),
+        NodeKind::Hidden => None,
+    }
+}
+
+/// Plain ascii `.xhtml` names, which never collide with the generated `chapter_*` ones.
+fn is_valid_file_name(name: &str) -> bool {
+    name.strip_suffix(".xhtml").is_some_and(|x| {
+        !x.is_empty()
+            && !x.starts_with("chapter_")
+            && x.chars()
+                .all(|x| x.is_ascii_alphanumeric() || matches!(x, '-' | '_' | '.'))
+    })
+}
+
+fn epub_error(e: impl std::fmt::Display) -> ExportError {
+    ExportError::Epub(e.to_string())
+}
@@ -0,0 +1,38 @@
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+use crate::{cleanup::CleanupError, images::ImageError, notes::NotesError};
+
+#[derive(Error, Debug)]
+pub enum ExportError {
+    #[error("failed to read `{}`: {source}", path.display())]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("the template `{0}` is not found")]
+    TemplateNotFound(String),
+
+    #[error("failed to render template: {0}")]
+    Template(#[from] tera::Error),
+
+    #[error("failed to build epub: {0}")]
+    Epub(String),
+
+    #[error("the export is cancelled")]
+    Cancelled,
+
+    #[error(transparent)]
+    Cleanup(#[from] CleanupError),
+
+    #[error(transparent)]
+    Notes(#[from] NotesError),
+
+    #[error(transparent)]
+    Image(#[from] ImageError),
+
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
@@ -0,0 +1,146 @@
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use tera::escape_html as escape;
+use uuid::Uuid;
+
+use super::{note_id, Chapter, Contents, ExportError, Exporter, Format, Reporter, Stage};
+
+/// FictionBook 2, sections are nested as the toc, footnotes go to the notes body and images are binaries.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Fb2Exporter;
+
+impl Exporter for Fb2Exporter {
+    fn format(&self) -> Format {
+        Format::Fb2
+    }
+
+    fn write(&self, contents: &Contents, progress: &mut Reporter) -> Result<Vec<u8>, ExportError> {
+        let book = &contents.book;
+        let author = book.author.as_deref().unwrap_or("Unknown");
+        let date = chrono::Local::now().format("%Y-%m-%d");
+        let mut xml = String::new();
+        xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str("<FictionBook xmlns=\"http://www.gribuser.ru/xml/fictionbook/2.0\" xmlns:l=\"http://www.w3.org/1999/xlink\">\n");
+        xml.push_str("<description>\n<title-info>\n<genre>prose</genre>\n");
+        xml.push_str(&format!(
+            "<author><nickname>{}</nickname></author>\n",
+            escape(author)
+        ));
+        xml.push_str(&format!(
+            "<book-title>{}</book-title>\n",
+            escape(&book.title)
+        ));
+        xml.push_str(&format!(
+            "<lang>{}</lang>\n</title-info>\n",
+            escape(&book.language)
+        ));
+        xml.push_str("<document-info>\n<author><nickname>wbook</nickname></author>\n");
+        xml.push_str("<program-used>wbook</program-used>\n");
+        xml.push_str(&format!("<date value=\"{0}\">{0}</date>\n", date));
+        xml.push_str(&format!(
+            "<id>{}</id>\n<version>1.0</version>\n",
+            Uuid::new_v4()
+        ));
+        xml.push_str("</document-info>\n</description>\n");
+
+        xml.push_str(&format!(
+            "<body>\n<title><p>{}</p></title>\n",
+            escape(&book.title)
+        ));
+        let chapters = &contents.chapters;
+        let mut open: Vec<usize> = Vec::new(); // depths of the open sections
+        for (i, chapter) in chapters.iter().enumerate() {
+            progress.chapter(i, chapters.len(), chapter)?;
+            while open.last().is_some_and(|x| *x >= chapter.depth) {
+                xml.push_str("</section>\n");
+                open.pop();
+            }
+            xml.push_str(&format!("<section id=\"chapter-{}\">\n", i));
+            if !chapter.title.is_empty() {
+                xml.push_str(&format!(
+                    "<title><p>{}</p></title>\n",
+                    escape(&chapter.title)
+                ));
+            }
+            // A section has either subsections or paragraphs, the text of a volume goes to an untitled one
+            let parent = chapters.get(i + 1).is_some_and(|x| x.depth > chapter.depth);
+            match (parent, chapter.spans.is_empty()) {
+                (true, true) => {}
+                (true, false) => {
+                    xml.push_str("<section>\n");
+                    write_paragraphs(&mut xml, i, chapter);
+                    xml.push_str("</section>\n");
+                }
+                (false, true) => xml.push_str("<empty-line/>\n"),
+                (false, false) => write_paragraphs(&mut xml, i, chapter),
+            }
+            open.push(chapter.depth);
+        }
+        for _ in open {
+            xml.push_str("</section>\n");
+        }
+        xml.push_str("</body>\n");
+
+        if chapters.iter().any(|x| !x.notes.is_empty()) {
+            xml.push_str("<body name=\"notes\">\n<title><p>Notes</p></title>\n");
+            for (i, chapter) in chapters.iter().enumerate() {
+                for note in chapter.notes.iter() {
+                    xml.push_str(&format!(
+                        "<section id=\"{}\">\n<title><p>{}</p></title>\n<p>{}</p>\n</section>\n",
+                        note_id(i, &note.id),
+                        escape(&note.label),
+                        escape(&note.text)
+                    ));
+                }
+            }
+            xml.push_str("</body>\n");
+        }
+
+        progress.report(Stage::Package, 90, None)?;
+        for image in contents.images.iter() {
+            let data = BASE64.encode(contents.load_image(image)?);
+            xml.push_str(&format!(
+                "<binary id=\"{}\" content-type=\"{}\">{}</binary>\n",
+                binary_id(&image.href),
+                image.mime,
+                data
+            ));
+        }
+        xml.push_str("</FictionBook>\n");
+        Ok(xml.into_bytes())
+    }
+}
+
+fn write_paragraphs(xml: &mut String, index: usize, chapter: &Chapter) {
+    for spans in chapter.spans.iter() {
+        if let [span] = spans.as_slice() {
+            if let Some(href) = &span.image {
+                xml.push_str(&format!(
+                    "<image l:href=\"#{}\" alt=\"{}\"/>\n",
+                    binary_id(href),
+                    escape(&span.text)
+                ));
+                continue;
+            }
+        }
+        xml.push_str("<p>");
+        for span in spans.iter() {
+            match (&span.image, &span.note) {
+                (Some(href), _) => {
+                    xml.push_str(&format!("<image l:href=\"#{}\"/>", binary_id(href)))
+                }
+                (None, Some(note)) => xml.push_str(&format!(
+                    "<a l:href=\"#{}\" type=\"note\">{}</a>",
+                    note_id(index, note),
+                    escape(&span.text)
+                )),
+                (None, None) => xml.push_str(&escape(&span.plain())),
+            }
+        }
+        xml.push_str("</p>\n");
+    }
+}
+
+/// Binaries are referred to by id, the file name of the image is unique already.
+fn binary_id(href: &str) -> &str {
+    href.rsplit('/').next().unwrap_or(href)
+}
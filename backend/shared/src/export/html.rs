@@ -0,0 +1,147 @@
+use std::collections::HashMap;
+
+use tera::escape_html;
+
+use super::{note_id, Chapter, Contents, ExportError, Exporter, Format, Reporter, Stage};
+use crate::toc::NodeKind;
+
+/// A single self-contained html file, with the stylesheet of the template and images inlined.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HtmlExporter;
+
+impl Exporter for HtmlExporter {
+    fn format(&self) -> Format {
+        Format::Html
+    }
+
+    fn write(&self, contents: &Contents, progress: &mut Reporter) -> Result<Vec<u8>, ExportError> {
+        let book = &contents.book;
+        let images = contents.data_urls()?;
+        let mut html = String::new();
+        html.push_str("<!DOCTYPE html>\n");
+        html.push_str(&format!(
+            "<html lang=\"{}\">\n<head>\n",
+            escape_html(&book.language)
+        ));
+        html.push_str("  <meta charset=\"utf-8\" />\n");
+        html.push_str(&format!("  <title>{}</title>\n", escape_html(&book.title)));
+        html.push_str(&format!(
+            "  <style>\n{}\n  </style>\n</head>\n<body>\n",
+            contents.templates().stylesheet
+        ));
+        html.push_str(&format!(
+            "  <h1 class=\"book-title\">{}</h1>\n",
+            escape_html(&book.title)
+        ));
+        if let Some(author) = &book.author {
+            html.push_str(&format!(
+                "  <p class=\"author\">{}</p>\n",
+                escape_html(author)
+            ));
+        }
+
+        // Same as the EPUB nav, untitled and hidden chapters are left out.
+        html.push_str("  <nav id=\"toc\">\n    <ol>\n");
+        for (i, chapter) in contents.chapters.iter().enumerate() {
+            if !chapter.title.is_empty() && chapter.kind != NodeKind::Hidden {
+                html.push_str(&format!(
+                    "      <li class=\"depth-{}\"><a href=\"#chapter-{}\">{}</a></li>\n",
+                    chapter.depth,
+                    i,
+                    escape_html(&chapter.title)
+                ));
+            }
+        }
+        html.push_str("    </ol>\n  </nav>\n");
+
+        let chapters = &contents.chapters;
+        for (i, chapter) in chapters.iter().enumerate() {
+            progress.chapter(i, chapters.len(), chapter)?;
+            write_chapter(&mut html, i, chapter, &images);
+        }
+        progress.report(Stage::Package, 90, None)?;
+        html.push_str("</body>\n</html>\n");
+        Ok(html.into_bytes())
+    }
+}
+
+fn write_chapter(
+    html: &mut String,
+    index: usize,
+    chapter: &Chapter,
+    images: &HashMap<String, String>,
+) {
+    let mut class = format!("chapter depth-{} {}", chapter.depth, chapter.kind.as_str());
+    if let Some(extra) = &chapter.class {
+        class.push(' ');
+        class.push_str(extra);
+    }
+    html.push_str(&format!(
+        "  <section id=\"chapter-{}\" class=\"{}\">\n",
+        index,
+        escape_html(&class)
+    ));
+    if !chapter.title.is_empty() {
+        // The book title is the only h1
+        let level = (chapter.depth + 2).min(6);
+        html.push_str(&format!(
+            "    <h{0}>{1}</h{0}>\n",
+            level,
+            escape_html(&chapter.title)
+        ));
+    }
+    for spans in chapter.spans.iter() {
+        if let [span] = spans.as_slice() {
+            if let Some(url) = span.image.as_ref().and_then(|x| images.get(x)) {
+                html.push_str(&format!(
+                    "    <div class=\"illustration\"><img src=\"{}\" alt=\"{}\" /></div>\n",
+                    url,
+                    escape_html(&span.text)
+                ));
+                continue;
+            }
+        }
+        html.push_str("    <p>");
+        for span in spans.iter() {
+            match (&span.image, &span.note) {
+                (Some(href), _) => html.push_str(&format!(
+                    "<img class=\"inline\" src=\"{}\" alt=\"{}\" />",
+                    images.get(href).map(|x| x.as_str()).unwrap_or_default(),
+                    escape_html(&span.text)
+                )),
+                (None, Some(note)) => html.push_str(&format!(
+                    "<a class=\"noteref\" href=\"#{}\" id=\"{}\">{}</a>",
+                    note_id(index, note),
+                    note_id(index, span.id.as_deref().unwrap_or_default()),
+                    escape_html(&span.text)
+                )),
+                (None, None) => match &span.ruby {
+                    Some(ruby) => html.push_str(&format!(
+                        "<ruby>{}<rt>{}</rt></ruby>",
+                        escape_html(&span.text),
+                        escape_html(ruby)
+                    )),
+                    None if span.upright => html.push_str(&format!(
+                        "<span class=\"tcy\">{}</span>",
+                        escape_html(&span.text)
+                    )),
+                    None => html.push_str(&escape_html(&span.text)),
+                },
+            }
+        }
+        html.push_str("</p>\n");
+    }
+    if !chapter.notes.is_empty() {
+        html.push_str("    <section class=\"notes\">\n");
+        for note in chapter.notes.iter() {
+            html.push_str(&format!(
+                "      <aside class=\"footnote\" id=\"{}\"><p>{}. {}</p></aside>\n",
+                note_id(index, &note.id),
+                escape_html(&note.label),
+                escape_html(&note.text)
+            ));
+        }
+        html.push_str("    </section>\n");
+    }
+    html.push_str("  </section>\n");
+}
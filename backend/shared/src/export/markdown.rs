@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+
+use super::{note_id, Chapter, Contents, ExportError, Exporter, Format, Reporter, Stage};
+
+/// A single markdown file for static sites, with footnotes and images inlined as data urls.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MarkdownExporter;
+
+impl Exporter for MarkdownExporter {
+    fn format(&self) -> Format {
+        Format::Markdown
+    }
+
+    fn write(&self, contents: &Contents, progress: &mut Reporter) -> Result<Vec<u8>, ExportError> {
+        let book = &contents.book;
+        let images = contents.data_urls()?;
+        let mut markdown = format!("# {}\n", escape(&book.title));
+        if let Some(author) = &book.author {
+            markdown.push_str(&format!("\n*{}*\n", escape(author)));
+        }
+        let chapters = &contents.chapters;
+        for (i, chapter) in chapters.iter().enumerate() {
+            progress.chapter(i, chapters.len(), chapter)?;
+            write_chapter(&mut markdown, i, chapter, &images);
+        }
+        progress.report(Stage::Package, 90, None)?;
+        Ok(markdown.into_bytes())
+    }
+}
+
+fn write_chapter(
+    markdown: &mut String,
+    index: usize,
+    chapter: &Chapter,
+    images: &HashMap<String, String>,
+) {
+    if !chapter.title.is_empty() {
+        // The book title is the only level 1 heading
+        let level = (chapter.depth + 2).min(6);
+        markdown.push_str(&format!(
+            "\n{} {}\n",
+            "#".repeat(level),
+            escape(&chapter.title)
+        ));
+    }
+    for spans in chapter.spans.iter() {
+        markdown.push('\n');
+        for span in spans.iter() {
+            match (&span.image, &span.note) {
+                (Some(href), _) => markdown.push_str(&format!(
+                    "![{}]({})",
+                    escape(&span.text),
+                    images.get(href).map(|x| x.as_str()).unwrap_or_default()
+                )),
+                (None, Some(note)) => markdown.push_str(&format!("[^{}]", note_id(index, note))),
+                (None, None) => markdown.push_str(&escape(&span.plain())),
+            }
+        }
+        markdown.push('\n');
+    }
+    if !chapter.notes.is_empty() {
+        markdown.push('\n');
+        for note in chapter.notes.iter() {
+            markdown.push_str(&format!(
+                "[^{}]: {}\n",
+                note_id(index, &note.id),
+                escape(&note.text)
+            ));
+        }
+    }
+}
+
+/// Escape characters which could start markdown syntax, the text is shown as it is.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    let digits = text.chars().take_while(|x| x.is_ascii_digit()).count();
+    for (i, c) in text.chars().enumerate() {
+        let special = matches!(
+            c,
+            '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '!' | '|' | '~'
+        ) || (i == 0 && matches!(c, '#' | '-' | '+' | '='))
+            || (i == digits && i > 0 && matches!(c, '.' | ')')); // ordered lists
+        if special {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
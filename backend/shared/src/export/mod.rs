@@ -0,0 +1,513 @@
+// Export turns a document into a book. Chapters are extracted by walking the toc in document order,
+// cleaned up, annotated, illustrated and converted by the shared pipeline, then written by the exporter
+// of the format. EPUB chapters are rendered with the chosen template, the other formats are written directly.
+
+use std::collections::HashMap;
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::{Deserialize, Serialize};
+use tera::{Context, Tera};
+
+use crate::{
+    aozora::{is_aozora, AozoraParser},
+    cleanup::Cleaner,
+    convert::{Converter, Variant},
+    document::Document,
+    epubcheck::{check_epub, CheckReport},
+    images::{Image, ImageResolver},
+    notes::{Annotator, Footnote, Span},
+    settings::{ExportSettings, Phrase, Settings, TypographySettings},
+    toc::{NodeKind, Toc},
+    typography::Typesetter,
+};
+
+pub use self::epub::{export_epub, export_epub_with_progress, EpubExporter};
+pub use self::error::ExportError;
+pub use self::fb2::Fb2Exporter;
+pub use self::html::HtmlExporter;
+pub use self::markdown::MarkdownExporter;
+pub use self::txt::TxtZipExporter;
+
+mod epub;
+mod error;
+mod fb2;
+mod html;
+mod markdown;
+mod txt;
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BookMeta {
+    pub title: String,
+    pub author: Option<String>,
+    pub language: String,
+    pub convert: Variant, // the language is set to the variant on export, unless it is `none`
+}
+
+impl BookMeta {
+    /// Book meta with the author, language and conversion defaults from settings.
+    pub fn new(title: &str, settings: &ExportSettings) -> Self {
+        BookMeta {
+            title: title.to_string(),
+            author: settings.author.clone(),
+            language: settings.language.clone(),
+            convert: settings.convert,
+        }
+    }
+}
+
+/// Output formats, each one is written by its own exporter.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Format {
+    #[default]
+    Epub,
+    Html,     // a single self-contained file, with the stylesheet and images inlined
+    Markdown, // a single file, with images inlined as data urls
+    Fb2,
+    TxtZip, // a txt file per chapter, named by its path in the toc
+}
+
+impl Format {
+    pub fn exporter(self) -> Box<dyn Exporter> {
+        match self {
+            Format::Epub => Box::new(EpubExporter),
+            Format::Html => Box::new(HtmlExporter),
+            Format::Markdown => Box::new(MarkdownExporter),
+            Format::Fb2 => Box::new(Fb2Exporter),
+            Format::TxtZip => Box::new(TxtZipExporter),
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Epub => "epub",
+            Format::Html => "html",
+            Format::Markdown => "md",
+            Format::Fb2 => "fb2",
+            Format::TxtZip => "zip",
+        }
+    }
+
+    pub fn mime(self) -> &'static str {
+        match self {
+            Format::Epub => "application/epub+zip",
+            Format::Html => "text/html; charset=utf-8",
+            Format::Markdown => "text/markdown; charset=utf-8",
+            Format::Fb2 => "application/x-fictionbook+xml",
+            Format::TxtZip => "application/zip",
+        }
+    }
+}
+
+///
+/// Writes a book in one format. Chapters are prepared by the shared pipeline, so every format gets the
+/// same cleaned up text, footnotes and images.
+///
+pub trait Exporter {
+    fn format(&self) -> Format;
+
+    /// Write the whole book, reporting each chapter with `progress.chapter` before it is written.
+    fn write(&self, contents: &Contents, progress: &mut Reporter) -> Result<Vec<u8>, ExportError>;
+}
+
+/// Chapters and images of a document, prepared once for an exporter.
+pub struct Contents<'a> {
+    pub book: BookMeta, // converted, see `convert`
+    pub chapters: Vec<Chapter>,
+    pub images: Vec<Image>,
+    pipeline: &'a Pipeline,
+}
+
+impl Contents<'_> {
+    pub fn templates(&self) -> &Templates {
+        &self.pipeline.templates
+    }
+
+    pub fn typography(&self) -> &TypographySettings {
+        &self.pipeline.typography
+    }
+
+    /// Read an image to be packaged, downscaled as the settings ask.
+    pub fn load_image(&self, image: &Image) -> Result<Vec<u8>, ExportError> {
+        match &self.pipeline.images {
+            Some(resolver) => Ok(resolver.load(image)?),
+            None => Ok(Vec::new()), // unreachable, no images are found if they are disabled
+        }
+    }
+
+    /// Images as `data:` urls keyed by their hrefs, for single-file formats.
+    pub fn data_urls(&self) -> Result<HashMap<String, String>, ExportError> {
+        self.images
+            .iter()
+            .map(|image| {
+                let data = BASE64.encode(self.load_image(image)?);
+                let url = format!("data:{};base64,{}", image.mime, data);
+                Ok((image.href.clone(), url))
+            })
+            .collect()
+    }
+}
+
+/// Footnote ids are unique in a chapter, prefix them to be unique in single-file formats.
+fn note_id(chapter: usize, id: &str) -> String {
+    format!("c{}-{}", chapter, id)
+}
+
+/// Forwards progress to the caller, and turns a cancellation into `ExportError::Cancelled`.
+pub struct Reporter<'a> {
+    on_progress: &'a mut dyn FnMut(&Progress) -> bool,
+}
+
+impl<'a> Reporter<'a> {
+    pub fn new(on_progress: &'a mut dyn FnMut(&Progress) -> bool) -> Self {
+        Reporter { on_progress }
+    }
+
+    pub fn report(
+        &mut self,
+        stage: Stage,
+        percent: u8,
+        chapter: Option<&str>,
+    ) -> Result<(), ExportError> {
+        let progress = Progress {
+            stage,
+            percent,
+            chapter: chapter.map(|x| x.to_string()),
+        };
+        match (self.on_progress)(&progress) {
+            true => Ok(()),
+            false => Err(ExportError::Cancelled),
+        }
+    }
+
+    /// Report the `index`th of `total` chapters. Writing chapters takes most of the time, from 10% to 90%.
+    pub fn chapter(
+        &mut self,
+        index: usize,
+        total: usize,
+        chapter: &Chapter,
+    ) -> Result<(), ExportError> {
+        let percent = 10 + (80 * index / total.max(1)) as u8;
+        let title = Some(chapter.title.as_str()).filter(|x| !x.is_empty());
+        self.report(Stage::Render, percent, title)
+    }
+}
+
+/// Export a document in the format.
+pub fn export(
+    document: &Document,
+    settings: &Settings,
+    book: &BookMeta,
+    format: Format,
+) -> Result<Vec<u8>, ExportError> {
+    export_with_progress(document, settings, book, format, |_| true)
+}
+
+///
+/// Export a document in the format, reporting progress before every stage and chapter.
+/// The export is cancelled with `ExportError::Cancelled` once `on_progress` returns false.
+///
+pub fn export_with_progress(
+    document: &Document,
+    settings: &Settings,
+    book: &BookMeta,
+    format: Format,
+    on_progress: impl FnMut(&Progress) -> bool,
+) -> Result<Vec<u8>, ExportError> {
+    export_checked(document, settings, book, format, on_progress).map(|x| x.buf)
+}
+
+///
+/// An exported book, with the report of `epubcheck` for EPUB books if `export.check` is on,
+/// and the patches which were left out of it.
+///
+#[derive(Debug, Clone)]
+pub struct Exported {
+    pub buf: Vec<u8>,
+    pub check: Option<CheckReport>,
+    pub skipped: Vec<SkippedPatch>,
+}
+
+/// A patch of a node which does not fit its text any more, the node is exported unpatched.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SkippedPatch {
+    pub id: usize,
+    pub title: String,
+    pub message: String,
+}
+
+///
+/// Same as `export_with_progress`, but keeps the check report and the skipped patches.
+/// Both are logged as well, they never fail the export.
+///
+pub fn export_checked(
+    document: &Document,
+    settings: &Settings,
+    book: &BookMeta,
+    format: Format,
+    mut on_progress: impl FnMut(&Progress) -> bool,
+) -> Result<Exported, ExportError> {
+    let mut progress = Reporter::new(&mut on_progress);
+    progress.report(Stage::Clean, 0, None)?;
+    let pipeline = Pipeline::new(settings)?;
+    let contents = pipeline.prepare(document, book);
+    let buf = format.exporter().write(&contents, &mut progress)?;
+    let skipped: Vec<_> = contents
+        .chapters
+        .iter()
+        .filter_map(|x| {
+            let id = x.id?;
+            Some(SkippedPatch {
+                id,
+                title: document.toc.get(id)?.title.clone(), // the chapter title may be converted
+                message: x.patch_error.clone()?,
+            })
+        })
+        .collect();
+    for patch in &skipped {
+        tracing::warn!(
+            "patch of `{}` is left out of `{}`: {}",
+            patch.title,
+            book.title,
+            patch.message
+        );
+    }
+    let check = (format == Format::Epub && settings.export.check).then(|| check_epub(&buf));
+    for issue in check.iter().flat_map(|x| x.issues.iter()) {
+        tracing::warn!(
+            "epubcheck of `{}`: {:?} {:?} in {}: {}",
+            book.title,
+            issue.severity,
+            issue.category,
+            issue.file.as_deref().unwrap_or("the archive"),
+            issue.message
+        );
+    }
+    Ok(Exported {
+        buf,
+        check,
+        skipped,
+    })
+}
+
+/// Stages of an export, in the order they are reported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    Clean,
+    Render,
+    Package,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Progress {
+    pub stage: Stage,
+    pub percent: u8,
+    pub chapter: Option<String>, // the chapter being rendered
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Chapter {
+    pub id: Option<usize>, // None for the text before the first heading
+    pub title: String,
+    pub kind: NodeKind, // hidden if the node or any of its ancestors is hidden
+    pub class: Option<String>,
+    pub file_name: Option<String>, // chosen by the editor, the exporter names the file otherwise
+    pub depth: usize,
+    pub path: Vec<String>, // titles of the ancestors, from the top level
+    pub paragraphs: Vec<String>,
+    pub spans: Vec<Vec<Span>>, // paragraphs split at noterefs, without the note bodies
+    pub notes: Vec<Footnote>,
+    #[serde(skip)]
+    pub patch_error: Option<String>, // why the patch of the node was left out, if it does not fit
+}
+
+/// Extract chapters in reading order. Text before the first node becomes an untitled chapter, if there is any.
+pub fn chapters(document: &Document, cleaner: &Cleaner) -> Vec<Chapter> {
+    let mut chapters = Vec::new();
+    let first = document
+        .toc
+        .iter()
+        .map(|(_, x)| x.meta.range.0 as usize)
+        .min()
+        .unwrap_or(document.text.len());
+    let paragraphs = cleaner.paragraphs(document.text.get(..first).unwrap_or_default());
+    if !paragraphs.is_empty() {
+        chapters.push(Chapter {
+            id: None,
+            title: String::new(),
+            kind: NodeKind::Chapter,
+            class: None,
+            file_name: None,
+            depth: 0,
+            path: Vec::new(),
+            spans: plain(&paragraphs),
+            notes: Vec::new(),
+            patch_error: None,
+            paragraphs,
+        });
+    }
+    let mut hidden: Option<usize> = None; // depth of the hidden ancestor
+    let mut path: Vec<String> = Vec::new();
+    for (depth, node) in document.toc.iter() {
+        path.truncate(depth);
+        if hidden.is_some_and(|x| depth <= x) {
+            hidden = None;
+        }
+        if hidden.is_none() && node.kind == NodeKind::Hidden {
+            hidden = Some(depth);
+        }
+        // A patch which does not fit is left out rather than failing the export, and reported with it
+        let (text, patch_error) = match document.patched_text(node) {
+            Ok(text) => (text, None),
+            Err(e) => (
+                document.node_text(node).unwrap_or_default().to_string(),
+                Some(e.to_string()),
+            ),
+        };
+        // The range starts with the heading line, which is rendered from the title instead.
+        let body = text.split_once('\n').map(|x| x.1).unwrap_or_default();
+        let paragraphs = cleaner.paragraphs(body);
+        chapters.push(Chapter {
+            id: Some(node.id),
+            title: node.title.clone(),
+            kind: match hidden {
+                Some(_) => NodeKind::Hidden,
+                None => node.kind,
+            },
+            class: node.meta.class().map(|x| x.to_string()),
+            file_name: node.meta.file_name().map(|x| x.to_string()),
+            depth,
+            path: path.clone(),
+            spans: plain(&paragraphs),
+            notes: Vec::new(),
+            patch_error,
+            paragraphs,
+        });
+        path.push(node.title.clone());
+    }
+    chapters
+}
+
+fn plain(paragraphs: &[String]) -> Vec<Vec<Span>> {
+    paragraphs.iter().map(|x| vec![Span::text(x)]).collect()
+}
+
+#[derive(Debug)]
+pub struct Templates {
+    tera: Tera,
+    pub stylesheet: String,
+}
+
+impl Templates {
+    const CHAPTER: &'static str = "chapter.xhtml";
+    const STYLESHEET: &'static str = "style.css";
+
+    /// Load `export.template`, from `export.template_dir` if it is set, otherwise from the built-in ones.
+    pub fn load(settings: &ExportSettings) -> Result<Self, ExportError> {
+        let (chapter, stylesheet) = match &settings.template_dir {
+            Some(dir) => {
+                let dir = dir.join(&settings.template);
+                let read = |name: &str| {
+                    let path = dir.join(name);
+                    std::fs::read_to_string(&path)
+                        .map_err(|source| ExportError::Io { path, source })
+                };
+                (read(Self::CHAPTER)?, read(Self::STYLESHEET)?)
+            }
+            None => match settings.template.as_str() {
+                "default" => (
+                    include_str!("templates/default/chapter.xhtml").to_string(),
+                    include_str!("templates/default/style.css").to_string(),
+                ),
+                name => return Err(ExportError::TemplateNotFound(name.to_string())),
+            },
+        };
+        let mut tera = Tera::default();
+        tera.autoescape_on(vec![".xhtml"]);
+        tera.add_raw_template(Self::CHAPTER, &chapter)?;
+        Ok(Templates { tera, stylesheet })
+    }
+
+    pub fn render_chapter(
+        &self,
+        book: &BookMeta,
+        chapter: &Chapter,
+    ) -> Result<String, ExportError> {
+        let mut context = Context::new();
+        context.insert("book", book);
+        context.insert("language", &book.language);
+        context.insert("chapter", chapter);
+        context.insert("heading", &(chapter.depth + 1).min(6));
+        Ok(self.tera.render(Self::CHAPTER, &context)?)
+    }
+}
+
+/// Everything needed to render chapters, built once per export.
+pub(crate) struct Pipeline {
+    pub cleaner: Cleaner,
+    pub aozora: Option<AozoraParser>, // None if Aozora Bunko markup is not parsed
+    pub templates: Templates,
+    pub annotator: Option<Annotator>,  // None if notes are disabled
+    pub images: Option<ImageResolver>, // None if images are disabled
+    pub phrases: Vec<Phrase>,
+    pub typography: TypographySettings,
+    pub typesetter: Typesetter,
+}
+
+impl Pipeline {
+    pub fn new(settings: &Settings) -> Result<Self, ExportError> {
+        let typography = settings.export.typography.clone();
+        let mut templates = Templates::load(&settings.export)?;
+        templates.stylesheet.push_str(&typography.stylesheet());
+        Ok(Pipeline {
+            cleaner: Cleaner::new(&settings.cleanup)?,
+            aozora: settings.detection.aozora.then(AozoraParser::new),
+            templates,
+            annotator: match settings.notes.enabled {
+                true => Some(Annotator::new(&settings.notes)?),
+                false => None,
+            },
+            images: match settings.images.enabled {
+                true => Some(ImageResolver::new(&settings.images)?),
+                false => None,
+            },
+            phrases: settings.export.phrases.clone(),
+            typesetter: Typesetter::new(&typography),
+            typography,
+        })
+    }
+
+    ///
+    /// Extract chapters and strip Aozora Bunko markup, then turn notes and images into footnotes and images.
+    /// The text is converted then typeset last, since notes and images are matched by the source text.
+    ///
+    pub fn prepare<'a>(&'a self, document: &Document, book: &BookMeta) -> Contents<'a> {
+        let mut chapters = chapters(document, &self.cleaner);
+        if let Some(parser) = self.aozora.as_ref().filter(|_| is_aozora(&document.text)) {
+            parser.apply(&mut chapters);
+        }
+        if let Some(annotator) = &self.annotator {
+            annotator.annotate(&mut chapters);
+        }
+        let images = match &self.images {
+            Some(resolver) => resolver.embed(document, &mut chapters),
+            None => Vec::new(),
+        };
+        let mut book = book.clone();
+        if let Some(converter) = Converter::new(book.convert, &self.phrases) {
+            converter.convert_book(&mut book);
+            converter.convert_chapters(&mut chapters);
+        }
+        self.typesetter.typeset(&mut chapters);
+        Contents {
+            book,
+            chapters,
+            images,
+            pipeline: self,
+        }
+    }
+}
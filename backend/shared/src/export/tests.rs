@@ -0,0 +1,323 @@
+use super::{
+    chapters, export, export_checked, export_epub, export_epub_with_progress, BookMeta,
+    ExportError, Format, Stage, Templates,
+};
+use std::io::{Cursor, Read};
+
+use crate::{
+    cleanup::Cleaner,
+    document::{Document, SourceText},
+    settings::Settings,
+    toc::{NodeKind, Toc, TocEdit, ATTR_CLASS, ATTR_FILE_NAME},
+};
+
+fn document() -> Document {
+    let mut document =
+        Document::new("序\n\n第一卷 起\n第一章 甲\n  甲文<1>\n\n第二章 乙\n乙文\n".to_string());
+    document.detect(&Settings::default().detection).unwrap();
+    document
+}
+
+#[test]
+fn test_chapters() {
+    let settings = Settings::default();
+    let chapters = chapters(&document(), &Cleaner::new(&settings.cleanup).unwrap());
+    let titles: Vec<_> = chapters
+        .iter()
+        .map(|x| (x.title.as_str(), x.depth))
+        .collect();
+    assert_eq!(
+        titles,
+        vec![
+            ("", 0),
+            ("第一卷 起", 0),
+            ("第一章 甲", 1),
+            ("第二章 乙", 1)
+        ]
+    );
+    assert_eq!(chapters[0].paragraphs, vec!["序"]);
+    assert!(chapters[1].paragraphs.is_empty());
+    assert_eq!(chapters[2].paragraphs, vec!["甲文<1>"]);
+}
+
+#[test]
+fn test_patched_chapters() {
+    let settings = Settings::default();
+    let cleaner = Cleaner::new(&settings.cleanup).unwrap();
+    let mut document = document();
+    let id = document.toc.iter().nth(2).unwrap().1.id;
+    document.edit_text(id, "第二章 乙\n乙文改\n新段\n").unwrap();
+    let patched = chapters(&document, &cleaner);
+    assert_eq!(patched[3].paragraphs, vec!["乙文改", "新段"]);
+    assert_eq!(patched[3].patch_error, None);
+
+    // A patch which no longer fits leaves the text as it is
+    document.toc.get_mut(id).unwrap().patch =
+        Some("--- original\n+++ modified\n@@ -2 +2 @@\n-丙\n+丁\n".to_string());
+    let unpatched = chapters(&document, &cleaner);
+    assert_eq!(unpatched[3].paragraphs, vec!["乙文"]);
+    assert!(unpatched[3].patch_error.is_some());
+
+    // And is reported by the export
+    let book = BookMeta::new("书", &settings.export);
+    let exported = export_checked(&document, &settings, &book, Format::Markdown, |_| true).unwrap();
+    assert_eq!(exported.skipped.len(), 1);
+    assert_eq!(
+        (exported.skipped[0].id, exported.skipped[0].title.as_str()),
+        (id, "第二章 乙")
+    );
+}
+
+#[test]
+fn test_render_escapes() {
+    let settings = Settings::default();
+    let templates = Templates::load(&settings.export).unwrap();
+    let chapters = chapters(&document(), &Cleaner::new(&settings.cleanup).unwrap());
+    let html = templates
+        .render_chapter(&BookMeta::new("书", &settings.export), &chapters[2])
+        .unwrap();
+    assert!(html.contains("<h2>第一章 甲</h2>"));
+    assert!(html.contains("<p>甲文&lt;1&gt;</p>"));
+    assert!(html.contains("lang=\"zh-CN\""));
+}
+
+#[test]
+fn test_export_epub() {
+    let settings = Settings::default();
+    let buf = export_epub(
+        &document(),
+        &settings,
+        &BookMeta::new("书", &settings.export),
+    )
+    .unwrap();
+    assert_eq!(&buf[..2], b"PK");
+    assert_eq!(&buf[30..38], b"mimetype");
+}
+
+#[test]
+fn test_export_progress() {
+    let settings = Settings::default();
+    let book = BookMeta::new("书", &settings.export);
+    let mut reports = Vec::new();
+    export_epub_with_progress(&document(), &settings, &book, |x| {
+        reports.push(x.clone());
+        true
+    })
+    .unwrap();
+    let stages: Vec<_> = reports.iter().map(|x| x.stage).collect();
+    assert_eq!(
+        stages,
+        vec![
+            Stage::Clean,
+            Stage::Render,
+            Stage::Render,
+            Stage::Render,
+            Stage::Render,
+            Stage::Package
+        ]
+    );
+    assert_eq!(reports[3].chapter.as_deref(), Some("第一章 甲"));
+    assert!(reports.windows(2).all(|x| x[0].percent <= x[1].percent));
+
+    // Cancelled at the second chapter
+    let mut count = 0;
+    let result = export_epub_with_progress(&document(), &settings, &book, |_| {
+        count += 1;
+        count < 3
+    });
+    assert!(matches!(result, Err(ExportError::Cancelled)));
+}
+
+/// Read a file of an epub.
+fn read_entry(buf: &[u8], name: &str) -> String {
+    let mut archive = zip::ZipArchive::new(Cursor::new(buf)).unwrap();
+    let mut content = String::new();
+    archive
+        .by_name(name)
+        .unwrap()
+        .read_to_string(&mut content)
+        .unwrap();
+    content
+}
+
+#[test]
+fn test_node_kinds() {
+    let settings = Settings::default();
+    let mut document = Document::new(
+        "序言\n写在前面\n第一卷 起\n第一章 甲\n甲文\n第二章 上架感言\n求订阅\n后记\n完\n"
+            .to_string(),
+    );
+    document.detect(&settings.detection).unwrap();
+    let volume = document.toc.children()[1];
+    let cleaner = Cleaner::new(&settings.cleanup).unwrap();
+    let kinds: Vec<_> = chapters(&document, &cleaner)
+        .iter()
+        .map(|x| x.kind)
+        .collect();
+    assert_eq!(
+        kinds,
+        vec![
+            NodeKind::Front,
+            NodeKind::Volume,
+            NodeKind::Chapter,
+            NodeKind::Hidden,
+            NodeKind::Back
+        ]
+    );
+
+    let buf = export_epub(&document, &settings, &BookMeta::new("书", &settings.export)).unwrap();
+    let nav = read_entry(&buf, "OEBPS/nav.xhtml");
+    assert!(nav.contains("epub:type=\"preface\""));
+    assert!(nav.contains("epub:type=\"bodymatter\""));
+    assert!(!nav.contains("上架感言"));
+    let opf = read_entry(&buf, "OEBPS/content.opf");
+    assert!(opf.contains("<reference type=\"preface\""));
+    // Hidden text stays in the book
+    assert!(read_entry(&buf, "OEBPS/chapter_0003.xhtml").contains("求订阅"));
+    assert!(read_entry(&buf, "OEBPS/chapter_0000.xhtml").contains("epub:type=\"frontmatter\""));
+
+    // Children of a hidden node are hidden too, the afterword is nested in the volume
+    document.toc.get_mut(volume).unwrap().kind = NodeKind::Hidden;
+    let kinds: Vec<_> = chapters(&document, &cleaner)
+        .iter()
+        .map(|x| x.kind)
+        .collect();
+    assert_eq!(kinds[0], NodeKind::Front);
+    assert_eq!(kinds[1..], [NodeKind::Hidden; 4]);
+}
+
+#[test]
+fn test_node_attributes() {
+    let settings = Settings::default();
+    let mut document = document();
+    let ids: Vec<_> = document.toc.iter().map(|(_, x)| x.id).collect();
+    let edits = [
+        (ids[1], ATTR_FILE_NAME, "chapter-one.xhtml"),
+        (ids[1], ATTR_CLASS, "letter"),
+        // Duplicated and invalid names fall back to generated ones
+        (ids[2], ATTR_FILE_NAME, "chapter-one.xhtml"),
+        (ids[0], ATTR_FILE_NAME, "../volume.xhtml"),
+    ];
+    for (id, key, value) in edits {
+        document
+            .toc
+            .apply(&TocEdit::SetAttribute {
+                id,
+                key: key.to_string(),
+                value: Some(value.to_string()),
+            })
+            .unwrap();
+    }
+    let buf = export_epub(&document, &settings, &BookMeta::new("书", &settings.export)).unwrap();
+    let chapter = read_entry(&buf, "OEBPS/chapter-one.xhtml");
+    assert!(chapter.contains("第一章 甲"));
+    assert!(chapter.contains("class=\"chapter depth-1 chapter letter\""));
+    assert!(read_entry(&buf, "OEBPS/chapter_0001.xhtml").contains("第一卷 起"));
+    assert!(read_entry(&buf, "OEBPS/chapter_0003.xhtml").contains("第二章 乙"));
+}
+
+#[test]
+fn test_footnotes() {
+    let mut settings = Settings::default();
+    let mut document = Document::new("第一章 甲\n甲文（注1）\n注1：注释\n".to_string());
+    document.detect(&settings.detection).unwrap();
+    let book = BookMeta::new("书", &settings.export);
+    let chapter = read_entry(
+        &export_epub(&document, &settings, &book).unwrap(),
+        "OEBPS/chapter_0000.xhtml",
+    );
+    assert!(chapter.contains(
+        "<a epub:type=\"noteref\" class=\"noteref\" href=\"#note-1\" id=\"note-1-ref-1\">（注1）</a>"
+    ));
+    assert!(chapter.contains("<aside epub:type=\"footnote\" class=\"footnote\" id=\"note-1\">"));
+    assert!(!chapter.contains("<p>注1：注释</p>"));
+
+    settings.notes.enabled = false;
+    let chapter = read_entry(
+        &export_epub(&document, &settings, &book).unwrap(),
+        "OEBPS/chapter_0000.xhtml",
+    );
+    assert!(chapter.contains("<p>甲文（注1）</p>"));
+    assert!(chapter.contains("<p>注1：注释</p>"));
+}
+
+#[test]
+fn test_export_combined() {
+    let settings = Settings::default();
+    let texts = ["第一章 甲\n甲文\n", "第一章 乙\n乙文\n"]
+        .iter()
+        .enumerate()
+        .map(|(i, text)| SourceText {
+            title: format!("卷{}", i + 1),
+            path: None,
+            text: text.to_string(),
+        })
+        .collect();
+    let mut document = Document::combine(texts).unwrap();
+    document.detect(&settings.detection).unwrap();
+    let buf = export_epub(&document, &settings, &BookMeta::new("书", &settings.export)).unwrap();
+    let nav = read_entry(&buf, "OEBPS/nav.xhtml");
+    let positions: Vec<_> = ["卷1", "第一章 甲", "卷2", "第一章 乙"]
+        .iter()
+        .map(|x| nav.find(x).unwrap())
+        .collect();
+    assert!(positions.windows(2).all(|x| x[0] < x[1]));
+    assert!(read_entry(&buf, "OEBPS/chapter_0003.xhtml").contains("<p>乙文</p>"));
+}
+
+#[test]
+fn test_formats() {
+    let settings = Settings::default();
+    let mut document = Document::new(
+        "序\n\n第一卷 起\n第一章 甲\n  甲文<1>（注1）\n注1：注释\n\n第二章 乙\n乙文\n".to_string(),
+    );
+    document.detect(&settings.detection).unwrap();
+    let book = BookMeta::new("书", &settings.export);
+    let export = |format| export(&document, &settings, &book, format).unwrap();
+    let text = |format| String::from_utf8(export(format)).unwrap();
+
+    let html = text(Format::Html);
+    assert!(html.contains("<li class=\"depth-1\"><a href=\"#chapter-2\">第一章 甲</a></li>"));
+    assert!(html.contains("<h3>第一章 甲</h3>"));
+    assert!(html.contains(
+        "<p>甲文&lt;1&gt;<a class=\"noteref\" href=\"#c2-note-1\" id=\"c2-note-1-ref-1\">（注1）</a></p>"
+    ));
+    assert!(html.contains("<aside class=\"footnote\" id=\"c2-note-1\"><p>1. 注释</p></aside>"));
+
+    let markdown = text(Format::Markdown);
+    assert!(markdown.starts_with("# 书\n"));
+    assert!(markdown.contains("\n### 第一章 甲\n\n甲文\\<1\\>[^c2-note-1]\n"));
+    assert!(markdown.contains("\n[^c2-note-1]: 注释\n"));
+
+    // Volumes nest their chapters, and notes go to their own body
+    let fb2 = text(Format::Fb2);
+    assert!(fb2.contains(
+        "<section id=\"chapter-1\">\n<title><p>第一卷 起</p></title>\n<section id=\"chapter-2\">"
+    ));
+    assert!(fb2.contains("<a l:href=\"#c2-note-1\" type=\"note\">（注1）</a>"));
+    assert!(fb2.contains("<body name=\"notes\">"));
+    assert_eq!(
+        fb2.matches("<section").count(),
+        fb2.matches("</section>").count()
+    );
+
+    let zip = export(Format::TxtZip);
+    let mut archive = zip::ZipArchive::new(Cursor::new(&zip)).unwrap();
+    let mut names: Vec<_> = archive.file_names().map(|x| x.to_string()).collect();
+    names.sort();
+    assert_eq!(
+        names,
+        vec![
+            "0000 书.txt",
+            "0001 第一卷 起/0002 第一章 甲.txt",
+            "0001 第一卷 起/0003 第二章 乙.txt",
+        ]
+    );
+    let mut chapter = String::new();
+    archive
+        .by_name("0001 第一卷 起/0002 第一章 甲.txt")
+        .unwrap()
+        .read_to_string(&mut chapter)
+        .unwrap();
+    assert_eq!(chapter, "第一章 甲\n\n甲文<1>（注1）\n注1：注释\n");
+}
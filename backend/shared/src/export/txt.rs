@@ -0,0 +1,80 @@
+use std::io::{Cursor, Write};
+
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+use super::{Chapter, Contents, ExportError, Exporter, Format, Reporter, Stage};
+
+/// A zip of plain text chapters for archiving, e.g. `0001 第一卷/0002 第一章.txt`.
+/// Volumes become dirs, and the text is kept as it is written, with note markers and bodies.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TxtZipExporter;
+
+impl Exporter for TxtZipExporter {
+    fn format(&self) -> Format {
+        Format::TxtZip
+    }
+
+    fn write(&self, contents: &Contents, progress: &mut Reporter) -> Result<Vec<u8>, ExportError> {
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+        let chapters = &contents.chapters;
+        let mut dirs: Vec<String> = Vec::new(); // names of the ancestors, numbered as the files
+        for (i, chapter) in chapters.iter().enumerate() {
+            progress.chapter(i, chapters.len(), chapter)?;
+            let name = format!(
+                "{:04} {}",
+                i,
+                file_name(match chapter.title.is_empty() {
+                    true => &contents.book.title,
+                    false => &chapter.title,
+                })
+            );
+            dirs.truncate(chapter.path.len());
+            if !chapter.paragraphs.is_empty() || chapter.title.is_empty() {
+                let mut path = dirs.join("/");
+                if !path.is_empty() {
+                    path.push('/');
+                }
+                path.push_str(&name);
+                path.push_str(".txt");
+                zip.start_file(path, options).map_err(zip_error)?;
+                zip.write_all(text(chapter).as_bytes())
+                    .map_err(|e| ExportError::Other(e.into()))?;
+            }
+            dirs.push(name);
+        }
+        progress.report(Stage::Package, 90, None)?;
+        let buf = zip.finish().map_err(zip_error)?;
+        Ok(buf.into_inner())
+    }
+}
+
+fn text(chapter: &Chapter) -> String {
+    let mut text = String::new();
+    if !chapter.title.is_empty() {
+        text.push_str(&chapter.title);
+        text.push_str("\n\n");
+    }
+    for paragraph in chapter.paragraphs.iter() {
+        text.push_str(paragraph);
+        text.push('\n');
+    }
+    text
+}
+
+/// Titles as file names, characters which are not allowed on common file systems are replaced.
+fn file_name(title: &str) -> String {
+    let name: String = title
+        .chars()
+        .map(|x| match x {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            x if x.is_control() => '_',
+            x => x,
+        })
+        .collect();
+    name.trim().trim_end_matches('.').to_string()
+}
+
+fn zip_error(e: zip::result::ZipError) -> ExportError {
+    ExportError::Other(e.into())
+}
@@ -0,0 +1,24 @@
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ImageError {
+    #[error("the pattern of image marker `{index}` is invalid: {source}")]
+    InvalidPattern { index: usize, source: regex::Error },
+
+    #[error("the pattern of image marker `{index}` must have a `name` group")]
+    MissingGroup { index: usize },
+
+    #[error("failed to read image `{}`: {source}", path.display())]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("failed to downscale image `{}`: {source}", path.display())]
+    Decode {
+        path: PathBuf,
+        source: image::ImageError,
+    },
+}
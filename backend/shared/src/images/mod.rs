@@ -0,0 +1,325 @@
+// Images shipped along with the text, referred to by markers such as `[img:cover.jpg]` or `<插图01>`.
+// A marker names a file, with or without an extension, which is looked up in the image dirs next to the
+// source file, or next to its own file in combined documents. Found images are packaged under `images/` with
+// generated names, since the original ones are often not ascii. Markers which can not be resolved are
+// reported, and left in the text as they are.
+
+use std::{
+    collections::HashMap,
+    io::{Cursor, Read},
+    path::{Component, Path, PathBuf},
+};
+
+use image::{codecs::jpeg::JpegEncoder, imageops::FilterType, ImageFormat};
+use regex::Regex;
+use serde::Serialize;
+
+use crate::{document::Document, export::Chapter, notes::Span, settings::ImageSettings};
+
+pub use self::error::ImageError;
+
+mod error;
+
+#[cfg(test)]
+mod tests;
+
+/// Extensions tried in order for markers without one.
+const EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "gif", "webp", "svg"];
+/// Core media types of EPUB 3 for images.
+const SUPPORTED: &[&str] = &[
+    "image/jpeg",
+    "image/png",
+    "image/gif",
+    "image/webp",
+    "image/svg+xml",
+];
+/// Quality of re-encoded jpeg images.
+const JPEG_QUALITY: u8 = 85;
+
+/// An image file found for a marker.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Image {
+    pub name: String, // as written in the marker
+    pub path: PathBuf,
+    pub href: String, // path in the book, e.g. `images/image_0001.jpg`
+    pub mime: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ImageProblem {
+    Missing,                      // no file is found in the image dirs
+    InvalidName,                  // names must not leave the image dirs, e.g. `../secret.jpg`
+    Unsupported { mime: String }, // not an EPUB core media type
+    Invalid { mime: String },     // the content does not look like its extension
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ImageIssue {
+    pub name: String,
+    pub problem: ImageProblem,
+    pub node: Option<usize>, // None for the text before the first node
+    pub line: usize,         // 1-based line number in the document
+    pub offset: usize,       // byte offset in the document
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ImageReport {
+    pub found: Vec<Image>, // each file once, in the order of the first marker referring to it
+    pub issues: Vec<ImageIssue>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ImageResolver {
+    markers: Vec<Regex>,
+    dirs: Vec<PathBuf>,
+    max_width: Option<u32>,
+    max_height: Option<u32>,
+}
+
+impl ImageResolver {
+    pub fn new(settings: &ImageSettings) -> Result<Self, ImageError> {
+        let markers = settings
+            .markers
+            .iter()
+            .enumerate()
+            .map(|(index, pattern)| {
+                let re = Regex::new(pattern)
+                    .map_err(|source| ImageError::InvalidPattern { index, source })?;
+                match re.capture_names().any(|x| x == Some("name")) {
+                    true => Ok(re),
+                    false => Err(ImageError::MissingGroup { index }),
+                }
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(ImageResolver {
+            markers,
+            dirs: settings.dirs.clone(),
+            max_width: settings.max_width,
+            max_height: settings.max_height,
+        })
+    }
+
+    /// Resolve all markers in the document, and report the ones which can not be resolved.
+    pub fn check(&self, document: &Document) -> ImageReport {
+        let mut resolved = Resolved::default();
+        let mut report = ImageReport::default();
+        for (node, start, text) in document.segments() {
+            for (offset, _, name) in self.find(text) {
+                if let Err(problem) = resolved.get(self, document.base_dir(node), name) {
+                    let offset = start + offset;
+                    report.issues.push(ImageIssue {
+                        name: name.to_string(),
+                        problem,
+                        node,
+                        line: document.line_of(offset),
+                        offset,
+                    });
+                }
+            }
+        }
+        report.found = resolved.images;
+        report
+    }
+
+    ///
+    /// Turn resolved markers in `spans` into images, returns the images to be packaged.
+    /// Spans of noterefs and images are left as they are.
+    ///
+    pub fn embed(&self, document: &Document, chapters: &mut [Chapter]) -> Vec<Image> {
+        let mut resolved = Resolved::default();
+        for chapter in chapters.iter_mut() {
+            let base = document.base_dir(chapter.id);
+            for spans in chapter.spans.iter_mut() {
+                let mut split = Vec::with_capacity(spans.len());
+                for span in spans.drain(..) {
+                    if span.note.is_some() || span.image.is_some() {
+                        split.push(span);
+                        continue;
+                    }
+                    let mut last = 0;
+                    for (start, end, name) in self.find(&span.text) {
+                        let Ok(image) = resolved.get(self, base, name) else {
+                            continue;
+                        };
+                        if start > last {
+                            split.push(Span::text(&span.text[last..start]));
+                        }
+                        split.push(Span {
+                            text: name.to_string(),
+                            note: None,
+                            id: None,
+                            image: Some(image.href.clone()),
+                            ruby: None,
+                            upright: false,
+                        });
+                        last = end;
+                    }
+                    if last < span.text.len() || last == 0 {
+                        split.push(Span::text(&span.text[last..]));
+                    }
+                }
+                *spans = split;
+            }
+        }
+        resolved.images
+    }
+
+    /// Read an image to be packaged, downscaled if it is larger than the max size.
+    pub fn load(&self, image: &Image) -> Result<Vec<u8>, ImageError> {
+        let buf = std::fs::read(&image.path).map_err(|source| ImageError::Io {
+            path: image.path.clone(),
+            source,
+        })?;
+        let format = match image.mime.as_str() {
+            "image/jpeg" => ImageFormat::Jpeg,
+            "image/png" => ImageFormat::Png,
+            _ => return Ok(buf),
+        };
+        if self.max_width.is_none() && self.max_height.is_none() {
+            return Ok(buf);
+        }
+        let decode_error = |source| ImageError::Decode {
+            path: image.path.clone(),
+            source,
+        };
+        let decoded = image::load_from_memory_with_format(&buf, format).map_err(decode_error)?;
+        let (width, height) = (
+            self.max_width.unwrap_or(u32::MAX),
+            self.max_height.unwrap_or(u32::MAX),
+        );
+        if decoded.width() <= width && decoded.height() <= height {
+            return Ok(buf);
+        }
+        // `resize` keeps the aspect ratio, and fits the image into the bounds
+        let resized = decoded.resize(width, height, FilterType::Lanczos3);
+        let mut out = Vec::new();
+        match format {
+            ImageFormat::Jpeg => resized
+                .to_rgb8()
+                .write_with_encoder(JpegEncoder::new_with_quality(&mut out, JPEG_QUALITY)),
+            _ => resized.write_to(&mut Cursor::new(&mut out), format),
+        }
+        .map_err(decode_error)?;
+        Ok(out)
+    }
+
+    /// Markers in the text, as `(start, end, name)` in order. Overlapped ones of later patterns are skipped.
+    fn find<'a>(&self, text: &'a str) -> Vec<(usize, usize, &'a str)> {
+        let mut found: Vec<(usize, usize, &str)> = Vec::new();
+        for marker in self.markers.iter() {
+            for captures in marker.captures_iter(text) {
+                let whole = captures.get(0).unwrap();
+                let name = captures.name("name").unwrap().as_str().trim();
+                if !found
+                    .iter()
+                    .any(|x| whole.start() < x.1 && x.0 < whole.end())
+                {
+                    found.push((whole.start(), whole.end(), name));
+                }
+            }
+        }
+        found.sort_by_key(|x| x.0);
+        found
+    }
+
+    /// Look up the file of a marker in the image dirs.
+    fn locate(&self, base: Option<&Path>, name: &str) -> Result<Image, ImageProblem> {
+        let relative = Path::new(name);
+        if !relative
+            .components()
+            .all(|x| matches!(x, Component::Normal(_)))
+        {
+            return Err(ImageProblem::InvalidName);
+        }
+        let candidates: Vec<PathBuf> = match relative.extension() {
+            Some(_) => vec![relative.to_path_buf()],
+            None => EXTENSIONS
+                .iter()
+                .map(|x| relative.with_extension(x))
+                .collect(),
+        };
+        let dirs = self.dirs.iter().filter_map(|dir| match dir.is_absolute() {
+            true => Some(dir.clone()),
+            false => base.map(|x| x.join(dir)),
+        });
+        let path = dirs
+            .flat_map(|dir| candidates.iter().map(move |x| dir.join(x)))
+            .find(|x| x.is_file())
+            .ok_or(ImageProblem::Missing)?;
+
+        let mime = mime_guess::from_path(&path)
+            .first_or_octet_stream()
+            .essence_str()
+            .to_string();
+        if !SUPPORTED.contains(&mime.as_str()) {
+            return Err(ImageProblem::Unsupported { mime });
+        }
+        if !looks_like(&path, &mime) {
+            return Err(ImageProblem::Invalid { mime });
+        }
+        Ok(Image {
+            name: name.to_string(),
+            path,
+            href: String::new(),
+            mime,
+        })
+    }
+}
+
+///
+/// Images resolved so far, a file referred to by several markers is packaged once.
+/// Names are resolved per base dir, since each file of a combined document may have its own images.
+///
+#[derive(Debug, Default)]
+struct Resolved {
+    names: HashMap<(Option<PathBuf>, String), Result<usize, ImageProblem>>,
+    images: Vec<Image>,
+}
+
+impl Resolved {
+    fn get(
+        &mut self,
+        resolver: &ImageResolver,
+        base: Option<&Path>,
+        name: &str,
+    ) -> Result<&Image, ImageProblem> {
+        let key = (base.map(|x| x.to_path_buf()), name.to_string());
+        if !self.names.contains_key(&key) {
+            let result = resolver.locate(base, name).map(|mut image| {
+                match self.images.iter().position(|x| x.path == image.path) {
+                    Some(index) => index,
+                    None => {
+                        let extension = image.path.extension().unwrap_or_default();
+                        image.href = format!(
+                            "images/image_{:04}.{}",
+                            self.images.len(),
+                            extension.to_string_lossy().to_ascii_lowercase()
+                        );
+                        self.images.push(image);
+                        self.images.len() - 1
+                    }
+                }
+            });
+            self.names.insert(key.clone(), result);
+        }
+        match &self.names[&key] {
+            Ok(index) => Ok(&self.images[*index]),
+            Err(problem) => Err(problem.clone()),
+        }
+    }
+}
+
+/// Check the magic bytes of raster images, svg is text and is taken as is.
+fn looks_like(path: &Path, mime: &str) -> bool {
+    let expected = match mime {
+        "image/jpeg" => ImageFormat::Jpeg,
+        "image/png" => ImageFormat::Png,
+        "image/gif" => ImageFormat::Gif,
+        "image/webp" => ImageFormat::WebP,
+        _ => return true,
+    };
+    let mut header = Vec::with_capacity(16);
+    let read = std::fs::File::open(path).and_then(|x| x.take(16).read_to_end(&mut header));
+    read.is_ok() && image::guess_format(&header).is_ok_and(|x| x == expected)
+}
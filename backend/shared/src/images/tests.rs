@@ -0,0 +1,173 @@
+use std::path::{Path, PathBuf};
+
+use image::{ImageFormat, RgbImage};
+
+use super::{ImageProblem, ImageResolver};
+use crate::{
+    cleanup::Cleaner,
+    document::Document,
+    export::{chapters, export_epub, BookMeta},
+    notes::Span,
+    settings::Settings,
+};
+
+const TEXT: &str = "[img:cover.png]\n第一章 甲\n<插图01>\n甲文[img:cover.png]乙\n\
+                    [img:fake.png][img:map.bmp][img:none.jpg][img:../book.txt]\n";
+
+/// A source text with an `images` and an `插图` dir next to it.
+fn document() -> (PathBuf, Document) {
+    let dir = std::env::temp_dir().join(format!("wbook-images-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(dir.join("images")).unwrap();
+    std::fs::create_dir_all(dir.join("插图")).unwrap();
+    let image = RgbImage::new(400, 200);
+    image.save(dir.join("images/cover.png")).unwrap();
+    image.save(dir.join("插图/插图01.jpg")).unwrap();
+    std::fs::write(dir.join("images/fake.png"), "not a png").unwrap();
+    std::fs::write(dir.join("images/map.bmp"), "BM").unwrap();
+    std::fs::write(dir.join("book.txt"), TEXT).unwrap();
+
+    let mut document = Document::open(&dir.join("book.txt")).unwrap();
+    document.detect(&Settings::default().detection).unwrap();
+    (dir, document)
+}
+
+#[test]
+fn test_check() {
+    let (dir, document) = document();
+    let resolver = ImageResolver::new(&Settings::default().images).unwrap();
+    let report = resolver.check(&document);
+    let found: Vec<_> = report
+        .found
+        .iter()
+        .map(|x| (x.name.as_str(), x.href.as_str(), x.mime.as_str()))
+        .collect();
+    assert_eq!(
+        found,
+        vec![
+            ("cover.png", "images/image_0000.png", "image/png"),
+            ("插图01", "images/image_0001.jpg", "image/jpeg"),
+        ]
+    );
+    assert_eq!(report.found[1].path, dir.join("插图/插图01.jpg"));
+
+    let issues: Vec<_> = report
+        .issues
+        .iter()
+        .map(|x| (x.name.as_str(), x.problem.clone(), x.line))
+        .collect();
+    assert_eq!(
+        issues,
+        vec![
+            (
+                "fake.png",
+                ImageProblem::Invalid {
+                    mime: "image/png".to_string()
+                },
+                5
+            ),
+            (
+                "map.bmp",
+                ImageProblem::Unsupported {
+                    mime: "image/bmp".to_string()
+                },
+                5
+            ),
+            ("none.jpg", ImageProblem::Missing, 5),
+            ("../book.txt", ImageProblem::InvalidName, 5),
+        ]
+    );
+    assert!(report.issues.iter().all(|x| x.node.is_some()));
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_embed() {
+    let (dir, document) = document();
+    let settings = Settings::default();
+    let mut chapters = chapters(&document, &Cleaner::new(&settings.cleanup).unwrap());
+    let resolver = ImageResolver::new(&settings.images).unwrap();
+    let images = resolver.embed(&document, &mut chapters);
+    assert_eq!(images.len(), 2);
+
+    let image = |name: &str, href: &str| Span {
+        text: name.to_string(),
+        note: None,
+        id: None,
+        image: Some(href.to_string()),
+        ruby: None,
+        upright: false,
+    };
+    assert_eq!(
+        chapters[0].spans,
+        vec![vec![image("cover.png", "images/image_0000.png")]]
+    );
+    assert_eq!(
+        chapters[1].spans[..2],
+        [
+            vec![image("插图01", "images/image_0001.jpg")],
+            vec![
+                Span::text("甲文"),
+                image("cover.png", "images/image_0000.png"),
+                Span::text("乙"),
+            ],
+        ]
+    );
+    // Markers which can not be resolved are kept as text
+    assert_eq!(
+        chapters[1].spans[2],
+        vec![Span::text(
+            "[img:fake.png][img:map.bmp][img:none.jpg][img:../book.txt]"
+        )]
+    );
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_downscale() {
+    let (dir, document) = document();
+    let mut settings = Settings::default().images;
+    let dimensions = |resolver: &ImageResolver, path: &Path| {
+        let report = resolver.check(&document);
+        let image = report.found.iter().find(|x| x.path == path).unwrap();
+        let buf = resolver.load(image).unwrap();
+        let format = ImageFormat::from_mime_type(&image.mime).unwrap();
+        let decoded = image::load_from_memory_with_format(&buf, format).unwrap();
+        (decoded.width(), decoded.height())
+    };
+    let (png, jpeg) = (dir.join("images/cover.png"), dir.join("插图/插图01.jpg"));
+
+    let resolver = ImageResolver::new(&settings).unwrap();
+    assert_eq!(dimensions(&resolver, &png), (400, 200));
+
+    settings.max_width = Some(100);
+    let resolver = ImageResolver::new(&settings).unwrap();
+    assert_eq!(dimensions(&resolver, &png), (100, 50));
+    assert_eq!(dimensions(&resolver, &jpeg), (100, 50));
+
+    settings.max_width = None;
+    settings.max_height = Some(500);
+    let resolver = ImageResolver::new(&settings).unwrap();
+    assert_eq!(dimensions(&resolver, &jpeg), (400, 200));
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_export_images() {
+    let (dir, document) = document();
+    let settings = Settings::default();
+    let buf = export_epub(&document, &settings, &BookMeta::new("书", &settings.export)).unwrap();
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(buf)).unwrap();
+    assert!(archive.by_name("OEBPS/images/image_0000.png").is_ok());
+    assert!(archive.by_name("OEBPS/images/image_0001.jpg").is_ok());
+
+    let mut chapter = String::new();
+    std::io::Read::read_to_string(
+        &mut archive.by_name("OEBPS/chapter_0001.xhtml").unwrap(),
+        &mut chapter,
+    )
+    .unwrap();
+    assert!(chapter.contains(
+        "<div class=\"illustration\"><img src=\"images/image_0001.jpg\" alt=\"插图01\" /></div>"
+    ));
+    std::fs::remove_dir_all(dir).unwrap();
+}
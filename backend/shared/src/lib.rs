@@ -1,2 +1,16 @@
+pub mod aozora;
+pub mod cleanup;
+pub mod convert;
+pub mod detect;
+pub mod document;
+pub mod epubcheck;
+pub mod export;
+pub mod images;
+pub mod lint;
+pub mod notes;
+pub mod search;
+pub mod settings;
+pub mod stats;
 pub mod toc;
 pub mod types;
+pub mod typography;
@@ -0,0 +1,247 @@
+// Lint looks for the mistakes detection leaves in a toc, which are tedious to find by scrolling the tree:
+// chapter numbers out of order, missing numbers, duplicate titles, empty or tiny chapters, and headings the
+// source repeats. Numbers are compared in document order among nodes at the same depth and numbered alike,
+// e.g. "第#章" apart from "第#回", and may restart at 1 under a new parent. Where a fix is likely, a finding
+// carries it as toc edits, which the editor applies as they are.
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use crate::{
+    detect::{number, numbering, renumber},
+    document::{count_words, Document},
+    settings::LintSettings,
+    toc::{NodeKind, TocEdit, TocNode},
+};
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Warning,
+    Info,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LintRule {
+    NumberJump, // the number skips ahead, but the next one follows the previous, so it is likely a typo
+    NumberBackwards, // the number is not greater than the previous one
+    MissingNumbers, // numbers are skipped, likely a heading was missed
+    DuplicateTitle, // the title is the same as the one of an earlier node
+    RepeatedHeading, // the title is the same as the one of the node right before it
+    EmptyChapter, // no text besides the heading
+    ShortChapter, // fewer words than `lint.short_chapter_words`
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Finding {
+    pub node: usize,
+    pub rule: LintRule,
+    pub severity: Severity,
+    pub message: String,
+    pub fix: Option<Fix>,
+}
+
+/// A suggested fix, applied as toc edits in order.
+#[derive(Debug, Clone, Serialize)]
+pub struct Fix {
+    pub description: String,
+    pub edits: Vec<TocEdit>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LintReport {
+    pub nodes: usize,
+    pub findings: Vec<Finding>, // in document order of their nodes
+}
+
+#[derive(Debug, Clone)]
+pub struct Linter {
+    short_chapter_words: u128,
+}
+
+impl Linter {
+    pub fn new(settings: &LintSettings) -> Self {
+        Linter {
+            short_chapter_words: settings.short_chapter_words as u128,
+        }
+    }
+
+    pub fn lint(&self, document: &Document) -> LintReport {
+        let nodes: Vec<(usize, &TocNode)> = document.toc.iter().collect();
+        let mut findings = Vec::new();
+        self.lint_numbers(&nodes, &mut findings);
+        self.lint_titles(&nodes, &mut findings);
+        self.lint_lengths(document, &nodes, &mut findings);
+        let order: HashMap<usize, usize> = nodes
+            .iter()
+            .enumerate()
+            .map(|(i, (_, x))| (x.id, i))
+            .collect();
+        findings.sort_by_key(|x| order[&x.node]);
+        LintReport {
+            nodes: nodes.len(),
+            findings,
+        }
+    }
+
+    fn lint_numbers(&self, nodes: &[(usize, &TocNode)], findings: &mut Vec<Finding>) {
+        // Numbered nodes of each sequence in document order, as (node, number)
+        let mut sequences: HashMap<(usize, String), Vec<(&TocNode, u64)>> = HashMap::new();
+        for (i, (depth, node)) in nodes.iter().enumerate() {
+            // Repeated headings are reported, and fixed, on their own
+            if i > 0 && nodes[i - 1].1.title.trim() == node.title.trim() {
+                continue;
+            }
+            if let Some(n) = number(&node.title) {
+                sequences
+                    .entry((*depth, numbering(&node.title)))
+                    .or_default()
+                    .push((node, n));
+            }
+        }
+        for sequence in sequences.values() {
+            let mut previous: Option<(&TocNode, u64)> = None;
+            for (i, (node, n)) in sequence.iter().copied().enumerate() {
+                let Some((last, expected)) = previous.map(|(x, n)| (x, n + 1)) else {
+                    previous = Some((node, n));
+                    continue;
+                };
+                let restart = n == 1 && last.parent != node.parent;
+                if n == expected || restart {
+                    previous = Some((node, n));
+                    continue;
+                }
+                let next = sequence.get(i + 1).map(|x| x.1);
+                if next == Some(expected + 1) {
+                    findings.push(Finding {
+                        node: node.id,
+                        rule: LintRule::NumberJump,
+                        severity: Severity::Warning,
+                        message: format!(
+                            "is numbered {} between {} and {}",
+                            n,
+                            expected - 1,
+                            expected + 1
+                        ),
+                        fix: renumber(&node.title, expected).map(|title| Fix {
+                            description: format!("renumber to `{}`", title),
+                            edits: vec![TocEdit::Retitle { id: node.id, title }],
+                        }),
+                    });
+                    previous = Some((node, expected));
+                    continue;
+                }
+                match n < expected {
+                    true => findings.push(Finding {
+                        node: node.id,
+                        rule: LintRule::NumberBackwards,
+                        severity: Severity::Warning,
+                        message: format!("is numbered {} after {}", n, expected - 1),
+                        fix: None,
+                    }),
+                    false => findings.push(Finding {
+                        node: node.id,
+                        rule: LintRule::MissingNumbers,
+                        severity: Severity::Warning,
+                        message: match n - expected {
+                            1 => format!("number {} is missing before it", expected),
+                            _ => format!("numbers {} to {} are missing before it", expected, n - 1),
+                        },
+                        fix: None,
+                    }),
+                }
+                previous = Some((node, n));
+            }
+        }
+    }
+
+    fn lint_titles(&self, nodes: &[(usize, &TocNode)], findings: &mut Vec<Finding>) {
+        let mut seen: HashMap<&str, usize> = HashMap::new(); // title -> id of its first node
+        for (i, (_, node)) in nodes.iter().enumerate() {
+            let title = node.title.trim();
+            if title.is_empty() {
+                continue;
+            }
+            let previous = i.checked_sub(1).map(|x| nodes[x].1);
+            if previous.is_some_and(|x| x.title.trim() == title) {
+                findings.push(Finding {
+                    node: node.id,
+                    rule: LintRule::RepeatedHeading,
+                    severity: Severity::Warning,
+                    message: format!("repeats the heading `{}` of the node before it", title),
+                    fix: Some(Fix {
+                        description: "merge into the node before it".to_string(),
+                        edits: vec![TocEdit::Merge { id: node.id }],
+                    }),
+                });
+            } else if let Some(first) = seen.get(title) {
+                findings.push(Finding {
+                    node: node.id,
+                    rule: LintRule::DuplicateTitle,
+                    severity: Severity::Warning,
+                    message: format!("has the same title `{}` as node `{}`", title, first),
+                    fix: None,
+                });
+            }
+            seen.entry(title).or_insert(node.id);
+        }
+    }
+
+    fn lint_lengths(
+        &self,
+        document: &Document,
+        nodes: &[(usize, &TocNode)],
+        findings: &mut Vec<Finding>,
+    ) {
+        for (i, (_, node)) in nodes.iter().enumerate() {
+            // Volumes only have an intro of their own, and hidden nodes are short by nature
+            if !node.children.is_empty() || matches!(node.kind, NodeKind::Volume | NodeKind::Hidden)
+            {
+                continue;
+            }
+            // Repeated headings are reported, and fixed, on their own
+            if findings
+                .iter()
+                .any(|x| x.node == node.id && x.rule == LintRule::RepeatedHeading)
+            {
+                continue;
+            }
+            let body = document
+                .node_text(node)
+                .unwrap_or_default()
+                .split_once('\n')
+                .map(|x| x.1)
+                .unwrap_or_default();
+            let words = count_words(body);
+            let merge = (i > 0).then(|| Fix {
+                description: "merge into the node before it".to_string(),
+                edits: vec![TocEdit::Merge { id: node.id }],
+            });
+            if words == 0 {
+                findings.push(Finding {
+                    node: node.id,
+                    rule: LintRule::EmptyChapter,
+                    severity: Severity::Warning,
+                    message: "has no text besides its heading".to_string(),
+                    fix: Some(Fix {
+                        description: "remove the node".to_string(),
+                        edits: vec![TocEdit::Remove { id: node.id }],
+                    }),
+                });
+            } else if words < self.short_chapter_words {
+                findings.push(Finding {
+                    node: node.id,
+                    rule: LintRule::ShortChapter,
+                    severity: Severity::Info,
+                    message: format!("has only {} words", words),
+                    fix: merge,
+                });
+            }
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use super::{LintRule, Linter, Severity};
+use crate::{
+    document::Document,
+    settings::{LintSettings, Settings},
+    toc::{Toc, TocEdit},
+};
+
+const BODY: &str = "这是一段足够长的正文。";
+
+fn parse(headings: &[&str]) -> Document {
+    let text: String = headings
+        .iter()
+        .map(|x| match *x {
+            x if x.ends_with("离别") => format!("{}\n短\n", x),
+            "第二章 终局" => format!("{}\n\n", x),
+            _ => format!("{}\n{}\n", x, BODY),
+        })
+        .collect();
+    let mut document = Document::new(text);
+    document.detect(&Settings::default().detection).unwrap();
+    document
+}
+
+fn linter() -> Linter {
+    Linter::new(&LintSettings {
+        short_chapter_words: 5,
+    })
+}
+
+fn node_id(document: &Document, title: &str) -> usize {
+    document
+        .toc
+        .iter()
+        .find(|(_, x)| x.title == title)
+        .unwrap()
+        .1
+        .id
+}
+
+#[test]
+fn test_lint() {
+    let document = parse(&[
+        "第一卷 开端",
+        "第一章 出发",
+        "第二章 相遇",
+        "第九章 风波",
+        "第四章 离别",
+        "第七章 重逢",
+        "第八章 远行",
+        "第六章 回首",
+        "第二卷 归来",
+        "第一章 出发",
+        "第二章 终局",
+        "后记",
+        "后记",
+    ]);
+    let report = linter().lint(&document);
+    assert_eq!(report.nodes, 13);
+    let found: Vec<_> = report
+        .findings
+        .iter()
+        .map(|x| (document.toc.get(x.node).unwrap().title.as_str(), x.rule))
+        .collect();
+    assert_eq!(
+        found,
+        vec![
+            ("第九章 风波", LintRule::NumberJump),
+            ("第四章 离别", LintRule::ShortChapter),
+            ("第七章 重逢", LintRule::MissingNumbers),
+            ("第六章 回首", LintRule::NumberBackwards),
+            ("第一章 出发", LintRule::DuplicateTitle),
+            ("第二章 终局", LintRule::EmptyChapter),
+            ("后记", LintRule::RepeatedHeading),
+        ]
+    );
+    assert_eq!(report.findings[1].severity, Severity::Info);
+    assert_eq!(
+        report.findings[2].message,
+        "numbers 5 to 6 are missing before it"
+    );
+}
+
+#[test]
+fn test_fixes() {
+    let mut document = parse(&[
+        "第一章 出发",
+        "第二章 相遇",
+        "第九章 风波",
+        "第四章 离别",
+        "后记",
+        "后记",
+    ]);
+    let report = linter().lint(&document);
+    let fixes: Vec<_> = report
+        .findings
+        .iter()
+        .filter_map(|x| x.fix.as_ref())
+        .collect();
+    assert_eq!(fixes.len(), 3);
+    assert!(matches!(
+        &fixes[0].edits[..],
+        [TocEdit::Retitle { title, .. }] if title == "第三章 风波"
+    ));
+    assert!(matches!(
+        &fixes[1].edits[..],
+        [TocEdit::Merge { id }] if *id == node_id(&document, "第四章 离别")
+    ));
+    for fix in fixes.iter() {
+        for edit in fix.edits.iter() {
+            document.toc.apply(edit).unwrap();
+        }
+    }
+    let titles: Vec<_> = document.toc.iter().map(|(_, x)| x.title.clone()).collect();
+    assert_eq!(
+        titles,
+        vec!["第一章 出发", "第二章 相遇", "第三章 风波", "后记"]
+    );
+    assert!(document
+        .node_text(document.toc.get(node_id(&document, "第三章 风波")).unwrap())
+        .unwrap()
+        .ends_with("短\n"));
+    assert!(linter().lint(&document).findings.is_empty());
+}
+
+#[test]
+fn test_restart_and_disabled() {
+    let document = parse(&[
+        "第一卷 开端",
+        "第一章 出发",
+        "第二卷 归来",
+        "第一章 启程",
+        "第二章 离别",
+    ]);
+    let report = Linter::new(&LintSettings {
+        short_chapter_words: 0,
+    })
+    .lint(&document);
+    assert!(report.findings.is_empty(), "{:?}", report.findings);
+}
@@ -0,0 +1,16 @@
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum NotesError {
+    #[error("the pattern `{key}` is invalid: {source}")]
+    InvalidPattern {
+        key: &'static str,
+        source: regex::Error,
+    },
+
+    #[error("the pattern `{key}` must have a `{group}` group")]
+    MissingGroup {
+        key: &'static str,
+        group: &'static str,
+    },
+}
@@ -0,0 +1,333 @@
+// Annotations of translated novels: an inline marker like "（注1）" refers to a note body line like "注1：..."
+// at the end of the chapter, or at the end of the book. Each node range is scanned on its own, markers are
+// linked to the bodies of their own node first, then to the ones of the last node (the end of the book).
+// A label may be used by several bodies, e.g. notes numbered per section, each marker takes the next body of
+// its label which is not linked yet, and shares the first one once there is none.
+// Markers and bodies without a counterpart are reported with their positions, and left in the text as they are.
+// Reports scan the source text for positions, exports scan the cleaned paragraphs of the chapters they render.
+
+use std::collections::{HashMap, HashSet};
+
+use regex::Regex;
+use serde::Serialize;
+
+use crate::{document::Document, export::Chapter, settings::NotesSettings};
+
+pub use self::error::NotesError;
+
+mod error;
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Footnote {
+    pub id: String, // unique in its chapter, e.g. `note-1`
+    pub label: String,
+    pub text: String,
+}
+
+/// A piece of paragraph text, a noteref if `note` is the id of a footnote,
+/// or an image if `image` is its path in the book, with `text` as the alt text.
+/// Typesetting adds ruby to the text as `ruby`, and sets short numbers `upright` in vertical text.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Span {
+    pub text: String,
+    pub note: Option<String>,
+    pub id: Option<String>, // id of the noteref, for backlinks
+    pub image: Option<String>,
+    pub ruby: Option<String>,
+    pub upright: bool, // tate-chu-yoko
+}
+
+impl Span {
+    pub fn text(text: &str) -> Self {
+        Span {
+            text: text.to_string(),
+            note: None,
+            id: None,
+            image: None,
+            ruby: None,
+            upright: false,
+        }
+    }
+    /// Plain text of the span, with the ruby in brackets, for formats without ruby.
+    pub fn plain(&self) -> String {
+        match &self.ruby {
+            Some(ruby) => format!("{}（{}）", self.text, ruby),
+            None => self.text.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UnmatchedKind {
+    Marker, // a marker without a note body
+    Note,   // a note body which no marker refers to
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Unmatched {
+    pub kind: UnmatchedKind,
+    pub label: String,
+    pub node: Option<usize>, // None for the text before the first node
+    pub line: usize,         // 1-based line number in the document
+    pub offset: usize,       // byte offset in the document
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct NoteReport {
+    pub linked: usize, // markers linked to a note body
+    pub unmatched: Vec<Unmatched>,
+}
+
+/// Linked notes of a node.
+#[derive(Debug, Default)]
+struct Linked {
+    notes: Vec<Footnote>,
+    refs: HashMap<String, Vec<String>>, // label -> footnote id of each of its markers, in order
+    consumed: HashSet<usize>, // indexes of the bodies in this node which are rendered as footnotes
+}
+
+#[derive(Debug)]
+struct Scanned<'a> {
+    node: Option<usize>,
+    markers: Vec<(&'a str, usize)>,         // (label, offset)
+    bodies: Vec<(&'a str, &'a str, usize)>, // (label, text, offset)
+}
+
+#[derive(Debug, Clone)]
+pub struct Annotator {
+    marker: Regex,
+    body: Regex,
+}
+
+impl Annotator {
+    pub fn new(settings: &NotesSettings) -> Result<Self, NotesError> {
+        let compile = |key: &'static str, pattern: &str, groups: &[&'static str]| {
+            let re =
+                Regex::new(pattern).map_err(|source| NotesError::InvalidPattern { key, source })?;
+            match groups
+                .iter()
+                .find(|group| !re.capture_names().any(|x| x == Some(**group)))
+            {
+                Some(group) => Err(NotesError::MissingGroup { key, group }),
+                None => Ok(re),
+            }
+        };
+        Ok(Annotator {
+            marker: compile("notes.marker", &settings.marker, &["label"])?,
+            body: compile("notes.body", &settings.body, &["label", "text"])?,
+        })
+    }
+
+    /// Link markers to note bodies in the whole document, and report the ones which can not be linked.
+    pub fn check(&self, document: &Document) -> NoteReport {
+        let lines = document.line_index();
+        let scanned: Vec<_> = document
+            .segments()
+            .into_iter()
+            .map(|(node, offset, text)| {
+                let lines = text.split_inclusive('\n').scan(offset, |start, line| {
+                    let offset = *start;
+                    *start += line.len();
+                    Some((offset, line.trim_end_matches(['\r', '\n'])))
+                });
+                self.scan(node, lines)
+            })
+            .collect();
+        self.link(&scanned, |x| lines.line_of(x)).1
+    }
+
+    ///
+    /// Turn linked markers into noterefs in `spans`, and collect the notes of each chapter.
+    /// `paragraphs` are left as they are, for templates which don't render notes.
+    /// Chapters are scanned as they are rendered, cleaned and patched, so bodies are dropped by their index
+    /// among the paragraphs.
+    ///
+    pub fn annotate(&self, chapters: &mut [Chapter]) {
+        let scanned: Vec<_> = chapters
+            .iter()
+            .map(|x| self.scan(x.id, x.paragraphs.iter().map(|x| (0, x.as_str()))))
+            .collect();
+        let (mut linked, _) = self.link(&scanned, |_| 0);
+        for chapter in chapters.iter_mut() {
+            let Some(linked) = linked.remove(&chapter.id) else {
+                continue;
+            };
+            let mut bodies = 0;
+            let mut seen = HashMap::new();
+            let mut count = 0;
+            chapter.spans = chapter
+                .paragraphs
+                .iter()
+                .filter(|x| {
+                    // Bodies rendered as footnotes are dropped from the text, by their index in it
+                    if !self.body.is_match(x) {
+                        return true;
+                    }
+                    bodies += 1;
+                    !linked.consumed.contains(&(bodies - 1))
+                })
+                .map(|x| match self.body.is_match(x) {
+                    true => vec![Span::text(x)],
+                    false => self.split(x, &linked.refs, &mut seen, &mut count),
+                })
+                .collect();
+            chapter.notes = linked.notes;
+        }
+    }
+
+    /// Split a paragraph at linked markers, `seen` counts the markers of each label in the chapter so far.
+    fn split(
+        &self,
+        paragraph: &str,
+        refs: &HashMap<String, Vec<String>>,
+        seen: &mut HashMap<String, usize>,
+        count: &mut usize,
+    ) -> Vec<Span> {
+        let mut spans = Vec::new();
+        let mut last = 0;
+        for captures in self.marker.captures_iter(paragraph) {
+            let label = &captures["label"];
+            let Some(ids) = refs.get(label) else {
+                continue;
+            };
+            let index = seen.entry(label.to_string()).or_default();
+            let Some(id) = ids.get(*index).or(ids.last()) else {
+                continue;
+            };
+            *index += 1;
+            let marker = captures.get(0).unwrap();
+            if marker.start() > last {
+                spans.push(Span::text(&paragraph[last..marker.start()]));
+            }
+            *count += 1;
+            spans.push(Span {
+                text: marker.as_str().to_string(),
+                note: Some(id.to_string()),
+                id: Some(format!("{}-ref-{}", id, count)),
+                image: None,
+                ruby: None,
+                upright: false,
+            });
+            last = marker.end();
+        }
+        if last < paragraph.len() || spans.is_empty() {
+            spans.push(Span::text(&paragraph[last..]));
+        }
+        spans
+    }
+
+    /// Link the markers of scanned segments, `line` gives the line number of an offset for the report.
+    fn link(
+        &self,
+        scanned: &[Scanned],
+        line: impl Fn(usize) -> usize,
+    ) -> (HashMap<Option<usize>, Linked>, NoteReport) {
+        let mut used: HashSet<(usize, usize)> = HashSet::new(); // (segment, body)
+        let mut ids: HashMap<(Option<usize>, usize, usize), String> = HashMap::new(); // (node, segment, body)
+        let mut linked: HashMap<Option<usize>, Linked> = HashMap::new();
+        let mut report = NoteReport::default();
+        let last = scanned.len().saturating_sub(1);
+
+        for (i, segment) in scanned.iter().enumerate() {
+            for (label, offset) in segment.markers.iter() {
+                let find = |unused: bool| {
+                    [i, last].into_iter().find_map(|j| {
+                        scanned[j]
+                            .bodies
+                            .iter()
+                            .enumerate()
+                            .position(|(k, x)| x.0 == *label && !(unused && used.contains(&(j, k))))
+                            .map(|k| (j, k))
+                    })
+                };
+                let found = find(true).or_else(|| find(false));
+                let Some((j, k)) = found else {
+                    report.unmatched.push(Unmatched {
+                        kind: UnmatchedKind::Marker,
+                        label: label.to_string(),
+                        node: segment.node,
+                        line: line(*offset),
+                        offset: *offset,
+                    });
+                    continue;
+                };
+                report.linked += 1;
+                used.insert((j, k));
+                let (_, text, _) = scanned[j].bodies[k];
+                linked
+                    .entry(scanned[j].node)
+                    .or_default()
+                    .consumed
+                    .insert(k);
+                let node = linked.entry(segment.node).or_default();
+                let id = ids
+                    .entry((segment.node, j, k))
+                    .or_insert_with(|| {
+                        // Ids stay unique in the chapter when bodies share a label
+                        let n = node.notes.iter().filter(|x| x.label == *label).count();
+                        let id = match n {
+                            0 => format!("note-{}", label),
+                            _ => format!("note-{}-{}", label, n + 1),
+                        };
+                        node.notes.push(Footnote {
+                            id: id.clone(),
+                            label: label.to_string(),
+                            text: text.to_string(),
+                        });
+                        id
+                    })
+                    .clone();
+                node.refs.entry(label.to_string()).or_default().push(id);
+            }
+        }
+        for (i, segment) in scanned.iter().enumerate() {
+            for (k, (label, _, offset)) in segment.bodies.iter().enumerate() {
+                if !used.contains(&(i, k)) {
+                    report.unmatched.push(Unmatched {
+                        kind: UnmatchedKind::Note,
+                        label: label.to_string(),
+                        node: segment.node,
+                        line: line(*offset),
+                        offset: *offset,
+                    });
+                }
+            }
+        }
+        report.unmatched.sort_by_key(|x| x.offset);
+        (linked, report)
+    }
+
+    /// Find note bodies and markers in the lines of a node, each with the offset it starts at.
+    fn scan<'a>(
+        &self,
+        node: Option<usize>,
+        lines: impl Iterator<Item = (usize, &'a str)>,
+    ) -> Scanned<'a> {
+        let mut scanned = Scanned {
+            node,
+            markers: Vec::new(),
+            bodies: Vec::new(),
+        };
+        for (start, content) in lines {
+            match self.body.captures(content) {
+                Some(captures) => {
+                    let label = captures.name("label").unwrap().as_str();
+                    let text = captures.name("text").unwrap().as_str().trim_end();
+                    scanned.bodies.push((label, text, start));
+                }
+                None => {
+                    for captures in self.marker.captures_iter(content) {
+                        let label = captures.name("label").unwrap().as_str();
+                        let position = start + captures.get(0).unwrap().start();
+                        scanned.markers.push((label, position));
+                    }
+                }
+            }
+        }
+        scanned
+    }
+}
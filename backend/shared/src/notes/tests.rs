@@ -0,0 +1,170 @@
+use super::{Annotator, Footnote, NotesError, Span, Unmatched, UnmatchedKind};
+use crate::{
+    cleanup::Cleaner,
+    document::Document,
+    export::chapters,
+    settings::{CleanupRule, Settings},
+};
+
+fn document() -> Document {
+    let text = "第一章 甲\n甲文（注1）和（注2）。\n注1：第一条。\n\n\
+                第二章 乙\n乙文（注3）（注4）\n\n\
+                第三章 丙\n注3：书末注释。\n注5：多余。\n";
+    let mut document = Document::new(text.to_string());
+    document.detect(&Settings::default().detection).unwrap();
+    document
+}
+
+fn unmatched(
+    kind: UnmatchedKind,
+    label: &str,
+    node: usize,
+    line: usize,
+) -> (UnmatchedKind, String, Option<usize>, usize) {
+    (kind, label.to_string(), Some(node), line)
+}
+
+#[test]
+fn test_check() {
+    let document = document();
+    let ids: Vec<_> = document.toc.iter().map(|(_, x)| x.id).collect();
+    let annotator = Annotator::new(&Settings::default().notes).unwrap();
+    let report = annotator.check(&document);
+    assert_eq!(report.linked, 2);
+    let found: Vec<_> = report
+        .unmatched
+        .iter()
+        .map(|x| (x.kind, x.label.clone(), x.node, x.line))
+        .collect();
+    assert_eq!(
+        found,
+        vec![
+            unmatched(UnmatchedKind::Marker, "2", ids[0], 2),
+            unmatched(UnmatchedKind::Marker, "4", ids[1], 6),
+            unmatched(UnmatchedKind::Note, "5", ids[2], 10),
+        ]
+    );
+    let Unmatched { offset, .. } = &report.unmatched[0];
+    assert!(document.text[*offset..].starts_with("（注2）"));
+}
+
+#[test]
+fn test_annotate() {
+    let settings = Settings::default();
+    let document = document();
+    let mut chapters = chapters(&document, &Cleaner::new(&settings.cleanup).unwrap());
+    let annotator = Annotator::new(&settings.notes).unwrap();
+    annotator.annotate(&mut chapters);
+
+    // Linked bodies are moved out of the text, unlinked markers are kept as they are
+    assert_eq!(
+        chapters[0].spans,
+        vec![vec![
+            Span::text("甲文"),
+            Span {
+                text: "（注1）".to_string(),
+                note: Some("note-1".to_string()),
+                id: Some("note-1-ref-1".to_string()),
+                image: None,
+                ruby: None,
+                upright: false,
+            },
+            Span::text("和（注2）。"),
+        ]]
+    );
+    assert_eq!(
+        chapters[0].notes,
+        vec![Footnote {
+            id: "note-1".to_string(),
+            label: "1".to_string(),
+            text: "第一条。".to_string(),
+        }]
+    );
+    assert_eq!(chapters[0].paragraphs.len(), 2);
+
+    // Notes at the end of the book go with the chapter referring to them
+    assert_eq!(chapters[1].notes.len(), 1);
+    assert_eq!(chapters[1].notes[0].text, "书末注释。");
+    assert_eq!(chapters[2].spans, vec![vec![Span::text("注5：多余。")]]);
+    assert!(chapters[2].notes.is_empty());
+}
+
+#[test]
+fn test_invalid_patterns() {
+    let mut settings = Settings::default().notes;
+    settings.marker = r"\[注\d+\]".to_string();
+    assert!(matches!(
+        Annotator::new(&settings),
+        Err(NotesError::MissingGroup {
+            key: "notes.marker",
+            group: "label"
+        })
+    ));
+    settings.marker = "[".to_string();
+    assert!(matches!(
+        Annotator::new(&settings),
+        Err(NotesError::InvalidPattern { .. })
+    ));
+}
+
+#[test]
+fn test_duplicated_labels() {
+    let settings = Settings::default();
+    let text = "第一章 甲\n上节（注1）\n注1：上节的注。\n下节（注1）\n注1：下节的注。\n注2：一条。\n注2：另一条。\n再提（注2）（注1）\n";
+    let mut document = Document::new(text.to_string());
+    document.detect(&settings.detection).unwrap();
+    let annotator = Annotator::new(&settings.notes).unwrap();
+    let report = annotator.check(&document);
+    assert_eq!(report.linked, 4);
+    let found: Vec<_> = report.unmatched.iter().map(|x| (x.kind, x.line)).collect();
+    assert_eq!(found, vec![(UnmatchedKind::Note, 7)]);
+
+    // Each marker takes its own body, then shares the first one, the body no marker is linked to stays in the text
+    let mut chapters = chapters(&document, &Cleaner::new(&settings.cleanup).unwrap());
+    annotator.annotate(&mut chapters);
+    let notes: Vec<_> = chapters[0]
+        .notes
+        .iter()
+        .map(|x| (x.id.as_str(), x.text.as_str()))
+        .collect();
+    assert_eq!(
+        notes,
+        vec![
+            ("note-1", "上节的注。"),
+            ("note-1-2", "下节的注。"),
+            ("note-2", "一条。")
+        ]
+    );
+    let refs: Vec<_> = chapters[0]
+        .spans
+        .iter()
+        .flatten()
+        .filter_map(|x| x.note.as_deref())
+        .collect();
+    assert_eq!(refs, vec!["note-1", "note-1-2", "note-2", "note-1"]);
+    assert!(chapters[0]
+        .spans
+        .contains(&vec![Span::text("注2：另一条。")]));
+}
+
+#[test]
+fn test_annotate_cleaned() {
+    let mut settings = Settings::default();
+    settings.cleanup.rules.push(CleanupRule {
+        pattern: "(?m)^注1：.*$".to_string(),
+        replace: String::new(),
+    });
+    let text = "第一章 甲\n甲文（注1）和（注2）。\n注1：删去。\n注2：留下。\n";
+    let mut document = Document::new(text.to_string());
+    document.detect(&settings.detection).unwrap();
+
+    // A body dropped by cleanup is not linked, and does not shift the ones after it
+    let mut chapters = chapters(&document, &Cleaner::new(&settings.cleanup).unwrap());
+    Annotator::new(&settings.notes)
+        .unwrap()
+        .annotate(&mut chapters);
+    let notes: Vec<_> = chapters[0].notes.iter().map(|x| x.text.as_str()).collect();
+    assert_eq!(notes, vec!["留下。"]);
+    assert_eq!(chapters[0].spans.len(), 1);
+    assert_eq!(chapters[0].spans[0][0], Span::text("甲文（注1）和"));
+}
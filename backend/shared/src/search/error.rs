@@ -0,0 +1,21 @@
+use thiserror::Error;
+
+use crate::{document::DocumentError, toc::TocError};
+
+#[derive(Error, Debug)]
+pub enum SearchError {
+    #[error("the search pattern is invalid: {0}")]
+    InvalidPattern(#[from] regex::Error),
+
+    #[error("the search pattern is empty")]
+    EmptyPattern,
+
+    #[error("no replacement is given")]
+    NoReplacement,
+
+    #[error(transparent)]
+    Toc(#[from] TocError),
+
+    #[error(transparent)]
+    Document(#[from] DocumentError),
+}
@@ -0,0 +1,233 @@
+// Find and replace over the whole book, or over the subtree of a node. The text searched is what editors see:
+// the text of each node with its patch applied, without the heading line, which is rendered from the title.
+// Replacements are not written into the source text but stored as patches of their nodes, so they can be
+// reviewed and reverted hunk by hunk, and are applied again on export.
+//
+// Width-insensitive matching folds fullwidth ASCII, e.g. "ＡＢＣ１２３", and the ideographic space to their
+// halfwidth forms, in the text and the pattern alike. Folded characters of a regex pattern are literals,
+// e.g. "（注\d+）" still matches the fullwidth brackets. Matches are reported at their offsets in the text as
+// it is.
+
+use std::borrow::Cow;
+
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    document::Document,
+    toc::{Toc, TocError},
+};
+
+pub use self::error::SearchError;
+
+mod error;
+
+#[cfg(test)]
+mod tests;
+
+/// Characters of context on each side of a match, within its line.
+pub const CONTEXT_CHARS: usize = 20;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SearchQuery {
+    pub pattern: String,
+    #[serde(default)]
+    pub regex: bool, // the pattern is literal otherwise
+    #[serde(default)]
+    pub case_insensitive: bool,
+    #[serde(default)]
+    pub width_insensitive: bool,
+    #[serde(default)]
+    pub scope: Option<usize>, // a node whose subtree is searched, the whole book if missing
+    #[serde(default)]
+    pub replacement: Option<String>, // previewed on matches, supports `$1` style capture references
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SearchResult {
+    pub matches: usize,
+    pub nodes: Vec<NodeMatches>, // in document order, the ones with matches
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct NodeMatches {
+    pub node: usize,
+    pub title: String,
+    pub matches: Vec<Match>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Match {
+    pub start: usize, // byte offsets into the patched text of the node
+    pub end: usize,
+    pub line: usize, // 1-based, of the patched text of the node
+    pub before: String,
+    pub text: String,
+    pub after: String,
+    pub replacement: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ReplaceReport {
+    pub nodes: Vec<usize>, // patched nodes
+    pub replacements: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct Searcher {
+    regex: Regex,
+    width_insensitive: bool,
+    scope: Option<usize>,
+    replacement: Option<String>,
+}
+
+impl Searcher {
+    pub fn new(query: &SearchQuery) -> Result<Self, SearchError> {
+        if query.pattern.is_empty() {
+            return Err(SearchError::EmptyPattern);
+        }
+        let pattern = match (query.regex, query.width_insensitive) {
+            (true, true) => fold_pattern(&query.pattern),
+            (true, false) => query.pattern.clone(),
+            (false, true) => regex::escape(&fold(&query.pattern).0),
+            (false, false) => regex::escape(&query.pattern),
+        };
+        let regex = RegexBuilder::new(&pattern)
+            .case_insensitive(query.case_insensitive)
+            .build()?;
+        Ok(Searcher {
+            regex,
+            width_insensitive: query.width_insensitive,
+            scope: query.scope,
+            replacement: query.replacement.clone(),
+        })
+    }
+
+    pub fn search(&self, document: &Document) -> Result<SearchResult, SearchError> {
+        let mut result = SearchResult::default();
+        for id in self.nodes(document)? {
+            let node = document.toc.get(id).unwrap();
+            let matches = self.find(&document.patched_text(node)?);
+            if matches.is_empty() {
+                continue;
+            }
+            result.matches += matches.len();
+            result.nodes.push(NodeMatches {
+                node: id,
+                title: node.title.clone(),
+                matches,
+            });
+        }
+        Ok(result)
+    }
+
+    /// Replace all matches by `replacement` of the query, recorded as patches of their nodes.
+    pub fn replace(&self, document: &mut Document) -> Result<ReplaceReport, SearchError> {
+        if self.replacement.is_none() {
+            return Err(SearchError::NoReplacement);
+        }
+        let result = self.search(document)?;
+        let mut report = ReplaceReport::default();
+        for node in result.nodes {
+            let mut text = document.patched_text(document.toc.get(node.node).unwrap())?;
+            for x in node.matches.iter().rev() {
+                text.replace_range(x.start..x.end, x.replacement.as_deref().unwrap_or_default());
+            }
+            document.edit_text(node.node, &text)?;
+            report.nodes.push(node.node);
+            report.replacements += node.matches.len();
+        }
+        Ok(report)
+    }
+
+    /// Ids of the searched nodes in document order.
+    fn nodes(&self, document: &Document) -> Result<Vec<usize>, SearchError> {
+        if let Some(scope) = self.scope.filter(|x| !document.toc.contains(*x)) {
+            return Err(TocError::NodeNotFound(scope).into());
+        }
+        Ok(document
+            .toc
+            .iter()
+            .map(|(_, x)| x.id)
+            .filter(|x| {
+                self.scope
+                    .is_none_or(|scope| document.toc.is_ancestor(scope, *x))
+            })
+            .collect())
+    }
+
+    /// Matches in the text of a node, after its heading line.
+    fn find(&self, text: &str) -> Vec<Match> {
+        let heading = text.find('\n').map_or(text.len(), |x| x + 1);
+        let body = &text[heading..];
+        let (haystack, offsets) = match self.width_insensitive {
+            true => {
+                let (folded, offsets) = fold(body);
+                (Cow::Owned(folded), Some(offsets))
+            }
+            false => (Cow::Borrowed(body), None),
+        };
+        let offset = |x: usize| heading + offsets.as_ref().map_or(x, |offsets| offsets[x]);
+        self.regex
+            .captures_iter(&haystack)
+            .filter(|x| !x[0].is_empty())
+            .map(|captures| {
+                let found = captures.get(0).unwrap();
+                let (start, end) = (offset(found.start()), offset(found.end()));
+                let line_start = text[..start].rfind('\n').map_or(0, |x| x + 1);
+                let line_end = text[end..].find('\n').map_or(text.len(), |x| end + x);
+                let before = &text[line_start..start];
+                let skip = before.chars().count().saturating_sub(CONTEXT_CHARS);
+                Match {
+                    start,
+                    end,
+                    line: text[..start].matches('\n').count() + 1,
+                    before: before.chars().skip(skip).collect(),
+                    text: text[start..end].to_string(),
+                    after: text[end..line_end].chars().take(CONTEXT_CHARS).collect(),
+                    replacement: self.replacement.as_ref().map(|replacement| {
+                        let mut expanded = String::new();
+                        captures.expand(replacement, &mut expanded);
+                        expanded
+                    }),
+                }
+            })
+            .collect()
+    }
+}
+
+///
+/// Fold fullwidth ASCII and the ideographic space to their halfwidth forms. Returns the folded text, and for each
+/// of its bytes, and its end, the offset in the text.
+///
+fn fold(text: &str) -> (String, Vec<usize>) {
+    let mut folded = String::with_capacity(text.len());
+    let mut offsets = Vec::with_capacity(text.len() + 1);
+    for (i, c) in text.char_indices() {
+        let c = fold_char(c);
+        offsets.extend(std::iter::repeat_n(i, c.len_utf8()));
+        folded.push(c);
+    }
+    offsets.push(text.len());
+    (folded, offsets)
+}
+
+/// Fold a regex pattern, folded characters are escaped so fullwidth brackets don't turn into syntax.
+fn fold_pattern(pattern: &str) -> String {
+    let mut folded = String::with_capacity(pattern.len());
+    for c in pattern.chars() {
+        match fold_char(c) {
+            x if x != c => folded.push_str(&regex::escape(x.encode_utf8(&mut [0; 4]))),
+            _ => folded.push(c),
+        }
+    }
+    folded
+}
+
+fn fold_char(c: char) -> char {
+    match c {
+        '\u{FF01}'..='\u{FF5E}' => char::from_u32(c as u32 - 0xFEE0).unwrap(),
+        '\u{3000}' => ' ',
+        _ => c,
+    }
+}
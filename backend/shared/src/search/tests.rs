@@ -0,0 +1,190 @@
+use super::{SearchError, SearchQuery, Searcher};
+use crate::{
+    document::Document,
+    settings::Settings,
+    toc::{Toc, TocError},
+};
+
+fn document() -> Document {
+    let mut document = Document::new(
+        "第一卷 开端\n序\n第一章 出发\nＡＢＣ　走了\n又见abc\n第二章 相遇\n遇见Abc，见到abd\n第二卷 归来\n第三章 ABC\nabc\n"
+            .to_string(),
+    );
+    document.detect(&Settings::default().detection).unwrap();
+    document
+}
+
+fn query(pattern: &str) -> SearchQuery {
+    SearchQuery {
+        pattern: pattern.to_string(),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_search() {
+    let document = document();
+    let result = Searcher::new(&query("abc"))
+        .unwrap()
+        .search(&document)
+        .unwrap();
+    // The heading "第三章 ABC" is not searched, nor matched case-sensitively
+    assert_eq!(result.matches, 2);
+    let titles: Vec<_> = result.nodes.iter().map(|x| x.title.as_str()).collect();
+    assert_eq!(titles, vec!["第一章 出发", "第三章 ABC"]);
+    let found = &result.nodes[0].matches[0];
+    assert_eq!(found.line, 3);
+    assert_eq!(
+        (
+            found.before.as_str(),
+            found.text.as_str(),
+            found.after.as_str()
+        ),
+        ("又见", "abc", "")
+    );
+
+    let result = Searcher::new(&SearchQuery {
+        case_insensitive: true,
+        width_insensitive: true,
+        ..query("abc ")
+    })
+    .unwrap()
+    .search(&document)
+    .unwrap();
+    assert_eq!(result.matches, 1);
+    let found = &result.nodes[0].matches[0];
+    assert_eq!(found.text, "ＡＢＣ　");
+    assert_eq!((found.line, found.after.as_str()), (2, "走了"));
+    let node = document.toc.get(result.nodes[0].node).unwrap();
+    let text = document.patched_text(node).unwrap();
+    assert_eq!(&text[found.start..found.end], "ＡＢＣ　");
+
+    let result = Searcher::new(&SearchQuery {
+        regex: true,
+        case_insensitive: true,
+        ..query("ab[cd]")
+    })
+    .unwrap()
+    .search(&document)
+    .unwrap();
+    assert_eq!(result.matches, 4);
+    // Literal patterns escape regex syntax
+    let result = Searcher::new(&query("ab[cd]"))
+        .unwrap()
+        .search(&document)
+        .unwrap();
+    assert_eq!(result.matches, 0);
+}
+
+#[test]
+fn test_search_regex_width_insensitive() {
+    let mut document = Document::new("第一章 注释\n甲（注1）乙(注2)丙注3\n".to_string());
+    document.detect(&Settings::default().detection).unwrap();
+    let result = Searcher::new(&SearchQuery {
+        regex: true,
+        width_insensitive: true,
+        ..query(r"（注\d+）")
+    })
+    .unwrap()
+    .search(&document)
+    .unwrap();
+    // Fullwidth brackets of the pattern are literals, of either width, not a group
+    let found: Vec<_> = result.nodes[0]
+        .matches
+        .iter()
+        .map(|x| x.text.as_str())
+        .collect();
+    assert_eq!(found, vec!["（注1）", "(注2)"]);
+
+    // Regex syntax of the pattern keeps working
+    let result = Searcher::new(&SearchQuery {
+        regex: true,
+        width_insensitive: true,
+        ..query(r"注[２3]")
+    })
+    .unwrap()
+    .search(&document)
+    .unwrap();
+    assert_eq!(result.matches, 2);
+}
+
+#[test]
+fn test_search_scope() {
+    let document = document();
+    let volume = document.toc.children()[1];
+    let result = Searcher::new(&SearchQuery {
+        scope: Some(volume),
+        ..query("abc")
+    })
+    .unwrap()
+    .search(&document)
+    .unwrap();
+    let titles: Vec<_> = result.nodes.iter().map(|x| x.title.as_str()).collect();
+    assert_eq!(titles, vec!["第三章 ABC"]);
+
+    let searcher = Searcher::new(&SearchQuery {
+        scope: Some(999),
+        ..query("abc")
+    })
+    .unwrap();
+    assert!(matches!(
+        searcher.search(&document),
+        Err(SearchError::Toc(TocError::NodeNotFound(999)))
+    ));
+}
+
+#[test]
+fn test_replace() {
+    let mut document = document();
+    let searcher = Searcher::new(&SearchQuery {
+        regex: true,
+        case_insensitive: true,
+        replacement: Some("<$1>".to_string()),
+        ..query("ab(c)")
+    })
+    .unwrap();
+    let preview = searcher.search(&document).unwrap();
+    assert_eq!(
+        preview.nodes[1].matches[0].replacement.as_deref(),
+        Some("<c>")
+    );
+    assert_eq!(preview.nodes[1].matches[0].after, "，见到abd");
+
+    let report = searcher.replace(&mut document).unwrap();
+    assert_eq!(report.replacements, 3);
+    assert_eq!(report.nodes.len(), 3);
+    let node = document.toc.get(report.nodes[1]).unwrap();
+    assert_eq!(
+        document.patched_text(node).unwrap(),
+        "第二章 相遇\n遇见<c>，见到abd\n"
+    );
+    // The source text is left alone, each replacement can be reverted
+    assert_eq!(
+        document.node_text(node),
+        Some("第二章 相遇\n遇见Abc，见到abd\n")
+    );
+    let preview = document.revert_hunk(report.nodes[1], 0).unwrap();
+    assert!(preview.hunks.is_empty());
+    assert_eq!(searcher.search(&document).unwrap().matches, 1);
+
+    let searcher = Searcher::new(&query("abc")).unwrap();
+    assert!(matches!(
+        searcher.replace(&mut document),
+        Err(SearchError::NoReplacement)
+    ));
+}
+
+#[test]
+fn test_invalid_query() {
+    assert!(matches!(
+        Searcher::new(&query("")),
+        Err(SearchError::EmptyPattern)
+    ));
+    assert!(matches!(
+        Searcher::new(&SearchQuery {
+            regex: true,
+            ..query("(")
+        }),
+        Err(SearchError::InvalidPattern(_))
+    ));
+}
@@ -0,0 +1,57 @@
+use std::{fmt, path::PathBuf};
+
+use thiserror::Error;
+
+/// Where a setting came from, used to point users at the offending line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Origin {
+    File { path: PathBuf, line: Option<usize> },
+    Environment(String),
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Origin::File {
+                path,
+                line: Some(line),
+            } => write!(f, "{}:{}", path.display(), line),
+            Origin::File { path, line: None } => write!(f, "{}", path.display()),
+            Origin::Environment(var) => write!(f, "environment variable `{}`", var),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Issue {
+    pub key: String,
+    pub message: String,
+    pub origin: Option<Origin>,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.origin {
+            Some(origin) => write!(f, "{}: `{}` {}", origin, self.key, self.message),
+            None => write!(f, "`{}` {}", self.key, self.message),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum SettingsError {
+    #[error("failed to read settings file `{}`: {source}", path.display())]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("{origin}: {message}")]
+    Parse { origin: Origin, message: String },
+
+    #[error("invalid settings:\n{}", .0.iter().map(|x| format!("  {}", x)).collect::<Vec<_>>().join("\n"))]
+    Invalid(Vec<Issue>),
+
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
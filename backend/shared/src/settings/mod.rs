@@ -0,0 +1,587 @@
+// Settings are layered, each layer overrides the one before it:
+// built-in defaults < user config file < project config file < environment variables.
+// The GUI, the embedded server and the cli all go through `SettingsLoader`, so they agree on the result.
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    path::{Path, PathBuf},
+};
+
+use config::{Config, ConfigError, Environment, File, FileFormat};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    convert::Variant,
+    detect::Preset,
+    toc::NodeKind,
+    typography::{PageDirection, Ruby, WritingMode},
+};
+
+pub use self::error::{Issue, Origin, SettingsError};
+
+mod error;
+
+#[cfg(test)]
+mod tests;
+
+const DEFAULTS: &str = include_str!("default.toml");
+
+pub const ENV_PREFIX: &str = "WBOOK";
+pub const PROJECT_FILE_NAME: &str = "wbook.toml";
+/// Templates shipped with the exporter, others must be provided via `export.template_dir`.
+pub const BUILTIN_TEMPLATES: &[&str] = &["default"];
+/// The deepest heading level a detection rule could produce.
+pub const MAX_HEADING_LEVEL: usize = 5;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Settings {
+    pub detection: DetectionSettings,
+    pub cleanup: CleanupSettings,
+    pub notes: NotesSettings,
+    pub images: ImageSettings,
+    pub lint: LintSettings,
+    pub stats: StatsSettings,
+    pub export: ExportSettings,
+    pub kindle: KindleSettings,
+    pub server: ServerSettings,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DetectionSettings {
+    pub max_title_length: usize,
+    pub min_score: f32, // candidates of a detection preview are accepted from this confidence on
+    pub aozora: bool, // headings, page breaks and ruby from Aozora Bunko markup, if the text has any
+    #[serde(default)]
+    pub presets: Vec<String>, // names of built-in presets, their rules follow `rules`
+    #[serde(default)]
+    pub rules: Vec<HeadingRule>,
+    #[serde(default)]
+    pub kinds: Vec<KindRule>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HeadingRule {
+    pub pattern: String,
+    pub level: usize, // 0 is the top level of the toc
+    #[serde(default)]
+    pub kind: Option<NodeKind>, // used when no kind rule matches the title, default to chapter
+}
+
+///
+/// Decide the kind of a detected heading by its title, e.g. afterwords are back matter.
+///
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KindRule {
+    pub pattern: String,
+    pub kind: NodeKind,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CleanupSettings {
+    pub trim_lines: bool,
+    pub collapse_blank_lines: bool,
+    pub rules: Vec<CleanupRule>,
+}
+
+///
+/// Annotations, an inline `marker` refers to a `body` line with the same `label` group.
+///
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NotesSettings {
+    pub enabled: bool,
+    pub marker: String, // with a `label` group
+    pub body: String,   // with `label` and `text` groups, matched against whole lines
+}
+
+///
+/// Images referenced from the text, e.g. `[img:cover.jpg]`, resolved against `dirs` next to the source file.
+///
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageSettings {
+    pub enabled: bool,
+    pub markers: Vec<String>, // with a `name` group, the file name with or without an extension
+    pub dirs: Vec<PathBuf>,   // searched in order, relative ones are relative to the source file
+    pub max_width: Option<u32>, // larger jpeg and png images are downscaled, keeping the aspect ratio
+    pub max_height: Option<u32>,
+}
+
+/// Checks of the toc, see `lint`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LintSettings {
+    pub short_chapter_words: usize, // leaf chapters with fewer words are reported, 0 to disable
+}
+
+/// Book statistics, see `stats`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatsSettings {
+    pub words_per_minute: usize, // reading speed, CJK characters count as words
+    pub outlier_ratio: f32, // chapters this many times longer or shorter than the median are outliers
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CleanupRule {
+    pub pattern: String,
+    #[serde(default)]
+    pub replace: String, // supports `$1` style capture references
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportSettings {
+    pub template: String,
+    pub template_dir: Option<PathBuf>,
+    pub language: String,
+    pub author: Option<String>,
+    pub output_dir: Option<PathBuf>,
+    pub check: bool, // check EPUB books after export, see `epubcheck`
+    #[serde(default)]
+    pub convert: Variant, // simplified/traditional Chinese conversion of the text
+    #[serde(default)]
+    pub phrases: Vec<Phrase>, // taking precedence over the built-in conversion tables
+    pub typography: TypographySettings,
+}
+
+/// Book-wide CJK typography, see `typography`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TypographySettings {
+    pub writing_mode: WritingMode,
+    pub page_direction: Option<PageDirection>, // follows the writing mode if missing
+    pub tate_chu_yoko: usize, // max digits of numbers set upright in vertical text, 0 to disable
+    pub line_break: bool,     // strict CJK line breaking
+    pub punctuation_trim: bool, // trim the spacing of fullwidth punctuation at line starts
+    pub ruby: Ruby,
+}
+
+/// A phrase mapping of the Chinese conversion, e.g. `内存` to `記憶體`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Phrase {
+    pub from: String,
+    pub to: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KindleSettings {
+    pub enabled: bool,
+    pub username: Option<String>,
+    #[serde(skip_serializing)]
+    pub password: Option<String>,
+    pub extension: String,
+    pub file_timeout: usize,
+    pub amazon_url: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerSettings {
+    pub host: String,
+    pub port: Option<u16>, // pick an unused port when not set
+    pub max_jobs: usize,   // conversion jobs running at once, the others wait in queue
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Config::builder()
+            .add_source(File::from_str(DEFAULTS, FileFormat::Toml))
+            .build()
+            .and_then(|x| x.try_deserialize())
+            .expect("built-in default settings must be valid")
+    }
+}
+
+impl Settings {
+    /// Load settings from the default user file, the project file in `project_dir` and the process environment.
+    pub fn load(project_dir: Option<&Path>) -> Result<Self, SettingsError> {
+        let mut loader = SettingsLoader::new();
+        if let Some(dir) = project_dir {
+            loader = loader.project_dir(dir);
+        }
+        loader.load()
+    }
+
+    /// Check the values which can not be expressed by types, e.g. regex patterns.
+    /// Returned issues have no origin, `SettingsLoader` fills it in.
+    pub fn validate(&self) -> Vec<Issue> {
+        let mut issues = Vec::new();
+        let mut issue = |key: String, message: String| {
+            issues.push(Issue {
+                key,
+                message,
+                origin: None,
+            })
+        };
+
+        if self.detection.max_title_length == 0 {
+            issue(
+                "detection.max_title_length".into(),
+                "must be greater than 0".into(),
+            );
+        }
+        if !(0.0..=1.0).contains(&self.detection.min_score) {
+            issue(
+                "detection.min_score".into(),
+                "must be between 0 and 1".into(),
+            );
+        }
+        for (i, name) in self.detection.presets.iter().enumerate() {
+            if Preset::get(name).is_err() {
+                issue(
+                    format!("detection.presets[{}]", i),
+                    format!(
+                        "is not a built-in preset, which are: {}",
+                        Preset::names().collect::<Vec<_>>().join(", ")
+                    ),
+                );
+            }
+        }
+        for (i, rule) in self.detection.rules.iter().enumerate() {
+            if let Err(e) = regex::Regex::new(&rule.pattern) {
+                issue(
+                    format!("detection.rules[{}].pattern", i),
+                    format!("is not a valid regular expression: {}", e),
+                );
+            }
+            if rule.level > MAX_HEADING_LEVEL {
+                issue(
+                    format!("detection.rules[{}].level", i),
+                    format!("must not be greater than {}", MAX_HEADING_LEVEL),
+                );
+            }
+        }
+
+        for (i, rule) in self.detection.kinds.iter().enumerate() {
+            if let Err(e) = regex::Regex::new(&rule.pattern) {
+                issue(
+                    format!("detection.kinds[{}].pattern", i),
+                    format!("is not a valid regular expression: {}", e),
+                );
+            }
+        }
+
+        for (i, rule) in self.cleanup.rules.iter().enumerate() {
+            if let Err(e) = regex::Regex::new(&rule.pattern) {
+                issue(
+                    format!("cleanup.rules[{}].pattern", i),
+                    format!("is not a valid regular expression: {}", e),
+                );
+            }
+        }
+
+        for (key, pattern, groups) in [
+            ("notes.marker", &self.notes.marker, &["label"][..]),
+            ("notes.body", &self.notes.body, &["label", "text"][..]),
+        ] {
+            match regex::Regex::new(pattern) {
+                Ok(re) => {
+                    for group in groups {
+                        if !re.capture_names().any(|x| x == Some(*group)) {
+                            issue(key.into(), format!("must have a `{}` group", group));
+                        }
+                    }
+                }
+                Err(e) => issue(
+                    key.into(),
+                    format!("is not a valid regular expression: {}", e),
+                ),
+            }
+        }
+
+        for (i, pattern) in self.images.markers.iter().enumerate() {
+            match regex::Regex::new(pattern) {
+                Ok(re) if !re.capture_names().any(|x| x == Some("name")) => issue(
+                    format!("images.markers[{}]", i),
+                    "must have a `name` group".into(),
+                ),
+                Ok(_) => {}
+                Err(e) => issue(
+                    format!("images.markers[{}]", i),
+                    format!("is not a valid regular expression: {}", e),
+                ),
+            }
+        }
+        for (key, value) in [
+            ("images.max_width", self.images.max_width),
+            ("images.max_height", self.images.max_height),
+        ] {
+            if value == Some(0) {
+                issue(key.into(), "must be greater than 0".into());
+            }
+        }
+
+        if self.stats.words_per_minute == 0 {
+            issue(
+                "stats.words_per_minute".into(),
+                "must be greater than 0".into(),
+            );
+        }
+        if self.stats.outlier_ratio <= 1.0 {
+            issue(
+                "stats.outlier_ratio".into(),
+                "must be greater than 1".into(),
+            );
+        }
+
+        match &self.export.template_dir {
+            Some(dir) if !dir.join(&self.export.template).is_dir() => issue(
+                "export.template".into(),
+                format!(
+                    "`{}` is not found in template dir `{}`",
+                    self.export.template,
+                    dir.display()
+                ),
+            ),
+            None if !BUILTIN_TEMPLATES.contains(&self.export.template.as_str()) => issue(
+                "export.template".into(),
+                format!(
+                    "`{}` is not a built-in template, available: {}",
+                    self.export.template,
+                    BUILTIN_TEMPLATES.join(", ")
+                ),
+            ),
+            _ => {}
+        }
+        if self.export.language.trim().is_empty() {
+            issue("export.language".into(), "must not be empty".into());
+        }
+        for (i, phrase) in self.export.phrases.iter().enumerate() {
+            if phrase.from.is_empty() {
+                issue(
+                    format!("export.phrases[{}].from", i),
+                    "must not be empty".into(),
+                );
+            }
+        }
+
+        if self.kindle.enabled {
+            if self.kindle.username.is_none() {
+                issue(
+                    "kindle.username".into(),
+                    "is required when kindle delivery is enabled".into(),
+                );
+            }
+            if self.kindle.password.is_none() {
+                issue(
+                    "kindle.password".into(),
+                    "is required when kindle delivery is enabled".into(),
+                );
+            }
+        }
+        if self.kindle.file_timeout == 0 {
+            issue(
+                "kindle.file_timeout".into(),
+                "must be greater than 0".into(),
+            );
+        }
+
+        if self.server.host.parse::<IpAddr>().is_err() {
+            issue(
+                "server.host".into(),
+                format!("`{}` is not a valid ip address", self.server.host),
+            );
+        }
+        if self.server.max_jobs == 0 {
+            issue("server.max_jobs".into(), "must be greater than 0".into());
+        }
+
+        issues
+    }
+}
+
+/// The per-user settings file, e.g. `~/.config/wbook/config.toml` on Linux.
+pub fn default_user_file() -> Option<PathBuf> {
+    dirs::config_dir().map(|x| x.join("wbook").join("config.toml"))
+}
+
+#[derive(Debug, Clone)]
+pub struct SettingsLoader {
+    user_file: Option<PathBuf>,
+    project_file: Option<PathBuf>,
+    env: Option<HashMap<String, String>>, // None means the process environment
+}
+
+impl Default for SettingsLoader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SettingsLoader {
+    pub fn new() -> Self {
+        SettingsLoader {
+            user_file: default_user_file(),
+            project_file: None,
+            env: None,
+        }
+    }
+
+    pub fn user_file(mut self, path: Option<PathBuf>) -> Self {
+        self.user_file = path;
+        self
+    }
+
+    pub fn project_file(mut self, path: Option<PathBuf>) -> Self {
+        self.project_file = path;
+        self
+    }
+
+    pub fn project_dir(self, dir: &Path) -> Self {
+        self.project_file(Some(dir.join(PROJECT_FILE_NAME)))
+    }
+
+    /// Use the given variables instead of the process environment.
+    pub fn env(mut self, vars: HashMap<String, String>) -> Self {
+        self.env = Some(vars);
+        self
+    }
+
+    pub fn load(&self) -> Result<Settings, SettingsError> {
+        // Missing files are skipped, the remaining ones are kept for locating errors.
+        let mut files = Vec::new();
+        for path in [&self.user_file, &self.project_file].into_iter().flatten() {
+            match std::fs::read_to_string(path) {
+                Ok(content) => files.push((path.clone(), content)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    return Err(SettingsError::Io {
+                        path: path.clone(),
+                        source: e,
+                    })
+                }
+            }
+        }
+
+        let mut builder = Config::builder().add_source(File::from_str(DEFAULTS, FileFormat::Toml));
+        for (path, content) in files.iter() {
+            // Parse per file, so a syntax error could be reported with its path.
+            if let Err(e) = content.parse::<toml::Table>() {
+                return Err(SettingsError::Parse {
+                    origin: Origin::File {
+                        path: path.clone(),
+                        line: e.span().map(|x| line_of(content, x.start)),
+                    },
+                    message: e.message().to_string(),
+                });
+            }
+            builder = builder.add_source(File::from_str(content, FileFormat::Toml));
+        }
+        builder = builder.add_source(
+            Environment::with_prefix(ENV_PREFIX)
+                .prefix_separator("_")
+                .separator("__")
+                .try_parsing(true)
+                .source(self.env.clone()),
+        );
+
+        let settings: Settings = builder
+            .build()
+            .and_then(|x| x.try_deserialize())
+            .map_err(|e| self.map_error(e, &files))?;
+
+        let issues = settings.validate();
+        if !issues.is_empty() {
+            return Err(SettingsError::Invalid(
+                issues
+                    .into_iter()
+                    .map(|x| Issue {
+                        origin: self.locate(&x.key, &files),
+                        ..x
+                    })
+                    .collect(),
+            ));
+        }
+        Ok(settings)
+    }
+
+    fn map_error(&self, err: ConfigError, files: &[(PathBuf, String)]) -> SettingsError {
+        let (key, message) = match err {
+            ConfigError::Type {
+                key: Some(key),
+                unexpected,
+                expected,
+                ..
+            } => (
+                key,
+                format!("has invalid type {}, expected {}", unexpected, expected),
+            ),
+            ConfigError::NotFound(key) => (key, "is missing".to_string()),
+            e => return SettingsError::Other(anyhow::anyhow!(e)),
+        };
+        SettingsError::Invalid(vec![Issue {
+            origin: self.locate(&key, files),
+            key,
+            message,
+        }])
+    }
+
+    /// Find the layer which provides `key`, from the highest priority to the lowest.
+    fn locate(&self, key: &str, files: &[(PathBuf, String)]) -> Option<Origin> {
+        let var = env_var_of(key);
+        let from_env = match &self.env {
+            Some(vars) => vars.keys().any(|x| x.eq_ignore_ascii_case(&var)),
+            None => std::env::var_os(&var).is_some(),
+        };
+        if from_env {
+            return Some(Origin::Environment(var));
+        }
+        files.iter().rev().find_map(|(path, content)| {
+            find_line(content, key).map(|line| Origin::File {
+                path: path.clone(),
+                line: Some(line),
+            })
+        })
+    }
+}
+
+/// `server.port` -> `WBOOK_SERVER__PORT`
+fn env_var_of(key: &str) -> String {
+    let key = key.split('[').next().unwrap_or(key);
+    format!("{}_{}", ENV_PREFIX, key.replace('.', "__").to_uppercase())
+}
+
+fn line_of(content: &str, offset: usize) -> usize {
+    content[..offset.min(content.len())].matches('\n').count() + 1
+}
+
+/// Find the 1-based line which assigns the dotted `key` (e.g. `detection.rules[1].pattern`) in a toml document.
+/// Falls back to the line of the closest parent key, e.g. an inline array.
+fn find_line(content: &str, key: &str) -> Option<usize> {
+    fn normalize(x: &str) -> String {
+        x.split('.')
+            .map(|x| x.trim().trim_matches(|c| c == '"' || c == '\''))
+            .collect::<Vec<_>>()
+            .join(".")
+    }
+
+    let mut table = String::new();
+    let mut array_counts: HashMap<String, usize> = HashMap::new();
+    let mut best: Option<(usize, usize)> = None; // (matched length, line)
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        let path = if let Some(header) = line.strip_prefix("[[") {
+            let name = normalize(header.split("]]").next().unwrap_or_default());
+            let count = array_counts.entry(name.clone()).or_insert(0);
+            table = format!("{}[{}]", name, count);
+            *count += 1;
+            table.clone()
+        } else if let Some(header) = line.strip_prefix('[') {
+            table = normalize(header.split(']').next().unwrap_or_default());
+            table.clone()
+        } else if let Some((lhs, _)) = line.split_once('=') {
+            if line.starts_with('#') {
+                continue;
+            }
+            match table.is_empty() {
+                true => normalize(lhs),
+                false => format!("{}.{}", table, normalize(lhs)),
+            }
+        } else {
+            continue;
+        };
+        if path == key {
+            return Some(i + 1);
+        }
+        let is_parent = key.starts_with(&path) && key[path.len()..].starts_with(['.', '[']);
+        if is_parent && best.is_none_or(|(len, _)| path.len() > len) {
+            best = Some((path.len(), i + 1));
+        }
+    }
+    best.map(|(_, line)| line)
+}
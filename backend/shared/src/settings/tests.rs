@@ -0,0 +1,168 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use super::{find_line, Origin, Settings, SettingsError, SettingsLoader};
+
+fn write_file(name: &str, content: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("wbook-settings-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    std::fs::write(&path, content).unwrap();
+    path
+}
+
+fn loader() -> SettingsLoader {
+    SettingsLoader::new().user_file(None).env(HashMap::new())
+}
+
+#[test]
+fn test_defaults() {
+    let settings = loader().load().unwrap();
+    assert_eq!(settings.export.template, "default");
+    assert_eq!(settings.server.host, "127.0.0.1");
+    assert_eq!(settings.server.port, None);
+    assert_eq!(settings.detection.presets, vec!["zh"]);
+    assert!(settings.detection.rules.is_empty());
+    assert!(settings.detection.kinds.is_empty());
+    assert!(Settings::default().validate().is_empty());
+}
+
+#[test]
+fn test_layers() {
+    let user = write_file(
+        "config.toml",
+        "[export]\nlanguage = \"zh-TW\"\nauthor = \"user\"\n\n[server]\nport = 1000\n",
+    );
+    let project = write_file("wbook.toml", "[export]\nauthor = \"project\"\n");
+    let settings = loader()
+        .user_file(Some(user))
+        .project_file(Some(project))
+        .env(HashMap::from([(
+            "WBOOK_SERVER__PORT".to_string(),
+            "2000".to_string(),
+        )]))
+        .load()
+        .unwrap();
+    assert_eq!(settings.export.language, "zh-TW");
+    assert_eq!(settings.export.author.as_deref(), Some("project"));
+    assert_eq!(settings.server.port, Some(2000));
+}
+
+#[test]
+fn test_project_rules_replace_defaults() {
+    let project = write_file(
+        "wbook.toml",
+        "[[detection.rules]]\npattern = '^Chapter \\d+'\nlevel = 0\n",
+    );
+    let settings = loader().project_file(Some(project)).load().unwrap();
+    assert_eq!(settings.detection.rules.len(), 1);
+    assert_eq!(settings.detection.rules[0].pattern, "^Chapter \\d+");
+}
+
+#[test]
+fn test_invalid_regex_is_located() {
+    let project = write_file(
+        "wbook.toml",
+        "[[detection.rules]]\npattern = 'ok'\nlevel = 0\n\n[[detection.rules]]\npattern = '(broken'\nlevel = 1\n",
+    );
+    let err = loader()
+        .project_file(Some(project.clone()))
+        .load()
+        .unwrap_err();
+    match err {
+        SettingsError::Invalid(issues) => {
+            assert_eq!(issues.len(), 1);
+            assert_eq!(issues[0].key, "detection.rules[1].pattern");
+            assert_eq!(
+                issues[0].origin,
+                Some(Origin::File {
+                    path: project,
+                    line: Some(6)
+                })
+            );
+        }
+        e => panic!("unexpected error: {}", e),
+    }
+}
+
+#[test]
+fn test_unknown_preset_is_located() {
+    let project = write_file("wbook.toml", "[detection]\npresets = [\"zh\", \"fr\"]\n");
+    let err = loader()
+        .project_file(Some(project.clone()))
+        .load()
+        .unwrap_err();
+    match err {
+        SettingsError::Invalid(issues) => {
+            assert_eq!(issues.len(), 1);
+            assert_eq!(issues[0].key, "detection.presets[1]");
+            assert!(issues[0].message.contains("zh, en, ja, ko"));
+        }
+        e => panic!("unexpected error: {}", e),
+    }
+}
+
+#[test]
+fn test_invalid_type_is_located() {
+    let user = write_file(
+        "config.toml",
+        "[server]\nhost = \"127.0.0.1\"\nport = \"abc\"\n",
+    );
+    let err = loader().user_file(Some(user.clone())).load().unwrap_err();
+    match err {
+        SettingsError::Invalid(issues) => {
+            assert_eq!(issues[0].key, "server.port");
+            assert_eq!(
+                issues[0].origin,
+                Some(Origin::File {
+                    path: user,
+                    line: Some(3)
+                })
+            );
+        }
+        e => panic!("unexpected error: {}", e),
+    }
+}
+
+#[test]
+fn test_env_origin() {
+    let err = loader()
+        .env(HashMap::from([(
+            "WBOOK_SERVER__HOST".to_string(),
+            "localhost:80".to_string(),
+        )]))
+        .load()
+        .unwrap_err();
+    match err {
+        SettingsError::Invalid(issues) => assert_eq!(
+            issues[0].origin,
+            Some(Origin::Environment("WBOOK_SERVER__HOST".to_string()))
+        ),
+        e => panic!("unexpected error: {}", e),
+    }
+}
+
+#[test]
+fn test_syntax_error_has_line() {
+    let user = write_file("config.toml", "[export]\nlanguage = \"zh-CN\"\nauthor = \n");
+    let err = loader().user_file(Some(user.clone())).load().unwrap_err();
+    match err {
+        SettingsError::Parse { origin, .. } => assert_eq!(
+            origin,
+            Origin::File {
+                path: user,
+                line: Some(3)
+            }
+        ),
+        e => panic!("unexpected error: {}", e),
+    }
+}
+
+#[test]
+fn test_find_line() {
+    let content = "a = 1\n[b]\nc = 2 # c\n'd'.e = 3\n";
+    assert_eq!(find_line(content, "a"), Some(1));
+    assert_eq!(find_line(content, "b.c"), Some(3));
+    assert_eq!(find_line(content, "b.d.e"), Some(4));
+    assert_eq!(find_line(content, "b.x"), Some(2));
+    assert_eq!(find_line(content, "x"), None);
+}
@@ -0,0 +1,170 @@
+// Statistics of a book, built on the words and ranges measured on its toc. Words of a node are the ones of
+// its own text, between its heading and the next heading, so they add up level by level without counting
+// nested chapters twice. Chapters are the leaves of the toc which end up in the book, i.e. not hidden; their
+// length distribution points at likely detection mistakes, a chapter many times longer than the others
+// usually hides a missed heading.
+
+use serde::Serialize;
+
+use crate::{
+    document::{Document, LineIndex},
+    settings::StatsSettings,
+    toc::{NodeKind, TocNode},
+};
+
+#[cfg(test)]
+mod tests;
+
+/// Chapters listed as the longest and shortest ones.
+pub const TOP_CHAPTERS: usize = 5;
+/// Buckets of the chapter length distribution, fewer for short books.
+pub const BUCKETS: usize = 10;
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BookStats {
+    pub words: u128,          // of all nodes
+    pub hidden_words: u128, // of hidden nodes, which are left out of the book and its reading time
+    pub reading_minutes: u64, // rounded up
+    pub nodes: usize,
+    pub chapters: usize,
+    pub levels: Vec<LevelStats>, // by depth, from the top level
+    pub distribution: Distribution,
+    pub outliers: Vec<Outlier>, // in document order
+    pub longest: Vec<ChapterStats>,
+    pub shortest: Vec<ChapterStats>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LevelStats {
+    pub depth: usize,
+    pub nodes: usize,
+    pub words: u128,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ChapterStats {
+    pub node: usize,
+    pub title: String,
+    pub depth: usize,
+    pub words: u128,
+    pub line: usize, // 1-based, of the heading
+}
+
+/// Chapter lengths in words.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Distribution {
+    pub min: u128,
+    pub max: u128,
+    pub mean: f64,
+    pub median: u128,
+    pub std_dev: f64,
+    pub buckets: Vec<Bucket>,
+}
+
+/// Chapters of `from..to` words, the last bucket includes `to`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Bucket {
+    pub from: u128,
+    pub to: u128,
+    pub chapters: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Outlier {
+    #[serde(flatten)]
+    pub chapter: ChapterStats,
+    pub ratio: f32, // words relative to the median chapter
+}
+
+impl BookStats {
+    pub fn new(document: &Document, settings: &StatsSettings) -> Self {
+        let mut stats = BookStats::default();
+        let mut chapters = Vec::new();
+        let lines = document.line_index();
+        for (depth, node) in document.toc.iter() {
+            stats.nodes += 1;
+            stats.words += node.meta.words;
+            if stats.levels.len() <= depth {
+                stats.levels.push(LevelStats {
+                    depth,
+                    nodes: 0,
+                    words: 0,
+                });
+            }
+            stats.levels[depth].nodes += 1;
+            stats.levels[depth].words += node.meta.words;
+            if node.kind == NodeKind::Hidden {
+                stats.hidden_words += node.meta.words;
+            } else if node.children.is_empty() {
+                chapters.push(chapter(&lines, depth, node));
+            }
+        }
+        let words = stats.words - stats.hidden_words;
+        stats.reading_minutes = words.div_ceil(settings.words_per_minute as u128) as u64;
+        stats.chapters = chapters.len();
+        stats.distribution = distribution(&chapters);
+
+        let median = stats.distribution.median as f32;
+        if median > 0.0 {
+            stats.outliers = chapters
+                .iter()
+                .map(|x| (x, x.words as f32 / median))
+                .filter(|x| x.1 >= settings.outlier_ratio || x.1 * settings.outlier_ratio <= 1.0)
+                .map(|(x, ratio)| Outlier {
+                    chapter: x.clone(),
+                    ratio,
+                })
+                .collect();
+        }
+        // Stable sorts, so chapters of the same length keep their document order
+        chapters.sort_by_key(|x| std::cmp::Reverse(x.words));
+        stats.longest = chapters.iter().take(TOP_CHAPTERS).cloned().collect();
+        chapters.sort_by_key(|x| x.words);
+        stats.shortest = chapters.iter().take(TOP_CHAPTERS).cloned().collect();
+        stats
+    }
+}
+
+fn chapter(lines: &LineIndex, depth: usize, node: &TocNode) -> ChapterStats {
+    ChapterStats {
+        node: node.id,
+        title: node.title.clone(),
+        depth,
+        words: node.meta.words,
+        line: lines.line_of(node.meta.range.0 as usize),
+    }
+}
+
+fn distribution(chapters: &[ChapterStats]) -> Distribution {
+    let mut words: Vec<u128> = chapters.iter().map(|x| x.words).collect();
+    words.sort_unstable();
+    let (Some(min), Some(max)) = (words.first().copied(), words.last().copied()) else {
+        return Distribution::default();
+    };
+    let mean = words.iter().sum::<u128>() as f64 / words.len() as f64;
+    let variance = words
+        .iter()
+        .map(|x| (*x as f64 - mean).powi(2))
+        .sum::<f64>()
+        / words.len() as f64;
+
+    let width = (max - min + 1).div_ceil(BUCKETS as u128);
+    let mut buckets: Vec<Bucket> = (0..(max - min + 1).div_ceil(width))
+        .map(|i| Bucket {
+            from: min + i * width,
+            to: (min + (i + 1) * width).min(max),
+            chapters: 0,
+        })
+        .collect();
+    for x in words.iter() {
+        buckets[((x - min) / width) as usize].chapters += 1;
+    }
+    Distribution {
+        min,
+        max,
+        mean,
+        median: words[words.len() / 2],
+        std_dev: variance.sqrt(),
+        buckets,
+    }
+}
@@ -0,0 +1,105 @@
+use super::{BookStats, Bucket, LevelStats};
+use crate::{
+    document::Document,
+    settings::{Settings, StatsSettings},
+};
+
+fn stats(chapters: &[(&str, usize)]) -> BookStats {
+    let text: String = chapters
+        .iter()
+        .map(|(title, words)| format!("{}\n{}\n", title, "字".repeat(*words)))
+        .collect();
+    let mut document = Document::new(text);
+    document.detect(&Settings::default().detection).unwrap();
+    BookStats::new(
+        &document,
+        &StatsSettings {
+            words_per_minute: 100,
+            outlier_ratio: 3.0,
+        },
+    )
+}
+
+#[test]
+fn test_stats() {
+    let stats = stats(&[
+        ("第一卷 开端", 10),
+        ("第一章 出发", 100),
+        ("第二章 相遇", 120),
+        ("第三章 风波", 900),
+        ("第二卷 归来", 0),
+        ("第四章 离别", 110),
+        ("第五章 重逢", 50),
+        ("后记", 80),
+    ]);
+    assert_eq!(stats.nodes, 8);
+    assert_eq!(stats.words, 1370);
+    assert_eq!(stats.hidden_words, 0);
+    assert_eq!(stats.reading_minutes, 14);
+    assert_eq!(stats.chapters, 6);
+    assert_eq!(
+        stats.levels,
+        vec![
+            LevelStats {
+                depth: 0,
+                nodes: 2,
+                words: 10
+            },
+            LevelStats {
+                depth: 1,
+                nodes: 6,
+                words: 1360
+            },
+        ]
+    );
+
+    let distribution = &stats.distribution;
+    assert_eq!((distribution.min, distribution.max), (50, 900));
+    assert_eq!(distribution.median, 110);
+    assert!((distribution.mean - 1360.0 / 6.0).abs() < 1e-6);
+    assert_eq!(distribution.buckets.len(), 10);
+    assert_eq!(
+        distribution.buckets[0],
+        Bucket {
+            from: 50,
+            to: 136,
+            chapters: 5
+        }
+    );
+    assert_eq!(distribution.buckets[9].to, 900);
+    assert_eq!(distribution.buckets[9].chapters, 1);
+
+    let outliers: Vec<_> = stats
+        .outliers
+        .iter()
+        .map(|x| x.chapter.title.as_str())
+        .collect();
+    assert_eq!(outliers, vec!["第三章 风波"]);
+    let longest: Vec<_> = stats.longest.iter().map(|x| x.title.as_str()).collect();
+    assert_eq!(
+        longest,
+        vec![
+            "第三章 风波",
+            "第二章 相遇",
+            "第四章 离别",
+            "第一章 出发",
+            "后记"
+        ]
+    );
+    assert_eq!(stats.shortest[0].title, "第五章 重逢");
+    assert_eq!(stats.shortest[0].line, 13);
+}
+
+#[test]
+fn test_hidden_and_empty() {
+    let stats = stats(&[("第一章 出发", 100), ("第一章 上架感言", 300)]);
+    assert_eq!(stats.hidden_words, 300);
+    assert_eq!(stats.reading_minutes, 1);
+    assert_eq!(stats.chapters, 1);
+    assert!(stats.outliers.is_empty());
+
+    let stats = BookStats::new(&Document::new(String::new()), &Settings::default().stats);
+    assert_eq!(stats.nodes, 0);
+    assert!(stats.levels.is_empty());
+    assert!(stats.distribution.buckets.is_empty());
+}
@@ -0,0 +1,251 @@
+// Edits are the serializable form of the `Toc` operations, shared by the tauri commands and the http api.
+// Unlike the trait methods, which silently ignore unknown ids, `apply` validates its input first.
+
+use serde::{Deserialize, Serialize};
+
+use super::{error::TocError, NodeKind, Note, Toc, TocNode, TocRoot, TreeNodeMeta};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum TocEdit {
+    Add {
+        title: String,
+        range: (u128, u128),
+        parent: Option<usize>,
+    },
+    Remove {
+        id: usize,
+    },
+    Retitle {
+        id: usize,
+        title: String,
+    },
+    SetKind {
+        id: usize,
+        kind: NodeKind,
+    },
+    Tag {
+        id: usize,
+        tag: String,
+    },
+    Untag {
+        id: usize,
+        tag: String,
+    },
+    SetAttribute {
+        id: usize,
+        key: String,
+        value: Option<String>, // None removes the attribute
+    },
+    AddNote {
+        id: usize,
+        text: String,
+    },
+    ResolveNote {
+        id: usize,
+        index: usize,
+    },
+    RemoveNote {
+        id: usize,
+        index: usize,
+    },
+    Move {
+        id: usize,
+        to: Position,
+    },
+    Merge {
+        id: usize, // merged into the node before it in document order, see `TocRoot::merge`
+    },
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Position {
+    Up,
+    Down,
+    Left,  // become the last child of the previous sibling
+    Right, // become a sibling of the parent
+    Before { target: usize },
+    After { target: usize },
+    BelongTo { parent: usize },
+}
+
+impl TocRoot {
+    /// Whether `ancestor` is `id` itself or one of its ancestors.
+    pub fn is_ancestor(&self, ancestor: usize, id: usize) -> bool {
+        let mut current = Some(id);
+        while let Some(x) = current {
+            if x == ancestor {
+                return true;
+            }
+            current = self.get(x).and_then(|x| x.parent);
+        }
+        false
+    }
+
+    /// Apply an edit, returns the id of the edited (or added) node.
+    pub fn apply(&mut self, edit: &TocEdit) -> Result<usize, TocError> {
+        let id = match edit {
+            TocEdit::Add {
+                title,
+                range,
+                parent,
+            } => {
+                return self
+                    .add_with_meta(title, Some(TreeNodeMeta::new(0, *range)), *parent)
+                    .map(|x| x.id)
+            }
+            TocEdit::Remove { id }
+            | TocEdit::Retitle { id, .. }
+            | TocEdit::SetKind { id, .. }
+            | TocEdit::Tag { id, .. }
+            | TocEdit::Untag { id, .. }
+            | TocEdit::SetAttribute { id, .. }
+            | TocEdit::AddNote { id, .. }
+            | TocEdit::ResolveNote { id, .. }
+            | TocEdit::RemoveNote { id, .. }
+            | TocEdit::Move { id, .. } => *id,
+            TocEdit::Merge { id } => return self.merge(*id),
+        };
+        if !self.contains(id) {
+            return Err(TocError::NodeNotFound(id));
+        }
+        match edit {
+            TocEdit::Add { .. } | TocEdit::Merge { .. } => unreachable!(),
+            TocEdit::Remove { id } => self.remove(*id),
+            TocEdit::Retitle { id, title } => self.get_mut(*id).unwrap().title = title.clone(),
+            TocEdit::SetKind { id, kind } => self.get_mut(*id).unwrap().kind = *kind,
+            TocEdit::Move { id, to } => self.move_to(*id, *to)?,
+            _ => self.edit_meta(id, edit)?,
+        }
+        Ok(id)
+    }
+
+    ///
+    /// Merge a node into the node before it in document order, which takes over its range, words, tags and
+    /// notes, and the attributes it has not set itself. Children of the merged node take its place.
+    /// A patch is relative to the text of its own node, so neither node may be patched, the patch of the node
+    /// merged into would no longer fit its longer text.
+    /// Returns the id of the node merged into.
+    ///
+    pub fn merge(&mut self, id: usize) -> Result<usize, TocError> {
+        if !self.contains(id) {
+            return Err(TocError::NodeNotFound(id));
+        }
+        let into = self
+            .iter()
+            .map(|(_, x)| x.id)
+            .take_while(|x| *x != id)
+            .last()
+            .ok_or(TocError::InvalidMerge {
+                id,
+                reason: "there is no node before it",
+            })?;
+        if self.container[id].patch.is_some() {
+            return Err(TocError::InvalidMerge {
+                id,
+                reason: "its patch must be reverted first",
+            });
+        }
+        if self.container[into].patch.is_some() {
+            return Err(TocError::InvalidMerge {
+                id,
+                reason: "the patch of the node before it must be reverted first",
+            });
+        }
+        let node = self.unwrap(id)?;
+        let meta = &mut self.container[into].meta;
+        meta.range.1 = meta.range.1.max(node.meta.range.1);
+        meta.words += node.meta.words;
+        meta.tags.extend(node.meta.tags);
+        for (key, value) in node.meta.attributes {
+            meta.attributes.entry(key).or_insert(value);
+        }
+        meta.notes.extend(node.meta.notes);
+        Ok(into)
+    }
+
+    /// Remove a node but not its children, which take its place. Returns the removed node.
+    pub fn unwrap(&mut self, id: usize) -> Result<TocNode, TocError> {
+        if !self.contains(id) {
+            return Err(TocError::NodeNotFound(id));
+        }
+        let node = self.container.remove(id);
+        self.uids.remove(&node.uid);
+        let siblings = match node.parent {
+            Some(parent) => &mut self.container[parent].children,
+            None => &mut self.children,
+        };
+        let index = siblings.iter().position(|x| *x == id).unwrap();
+        siblings.splice(index..=index, node.children.iter().copied());
+        for child in node.children.iter() {
+            self.container[*child].parent = node.parent;
+        }
+        Ok(node)
+    }
+
+    fn edit_meta(&mut self, id: usize, edit: &TocEdit) -> Result<(), TocError> {
+        let meta = &mut self.get_mut(id).unwrap().meta;
+        let note = |notes: &[Note], index: usize| match index < notes.len() {
+            true => Ok(index),
+            false => Err(TocError::NoteNotFound { id, index }),
+        };
+        match edit {
+            TocEdit::Tag { tag, .. } => {
+                meta.tags.insert(tag.clone());
+            }
+            TocEdit::Untag { tag, .. } => {
+                meta.tags.remove(tag);
+            }
+            TocEdit::SetAttribute { key, value, .. } => match value {
+                Some(value) => {
+                    meta.attributes.insert(key.clone(), value.clone());
+                }
+                None => {
+                    meta.attributes.remove(key);
+                }
+            },
+            TocEdit::AddNote { text, .. } => meta.notes.push(Note {
+                text: text.clone(),
+                resolved: false,
+            }),
+            TocEdit::ResolveNote { index, .. } => {
+                let index = note(&meta.notes, *index)?;
+                meta.notes[index].resolved = true;
+            }
+            TocEdit::RemoveNote { index, .. } => {
+                let index = note(&meta.notes, *index)?;
+                meta.notes.remove(index);
+            }
+            _ => unreachable!("not a meta edit"),
+        }
+        Ok(())
+    }
+
+    fn move_to(&mut self, id: usize, to: Position) -> Result<(), TocError> {
+        let target = match to {
+            Position::Before { target } | Position::After { target } => Some(target),
+            Position::BelongTo { parent } => Some(parent),
+            _ => None,
+        };
+        if let Some(target) = target {
+            if !self.contains(target) {
+                return Err(TocError::NodeNotFound(target));
+            }
+            // A node can not be moved next to or under itself or its descendants
+            if self.is_ancestor(id, target) {
+                return Err(TocError::InvalidMove { id, target });
+            }
+        }
+        match to {
+            Position::Up => self.move_up(id),
+            Position::Down => self.move_down(id),
+            Position::Left => self.move_left(id),
+            Position::Right => self.move_right(id),
+            Position::Before { target } => self.move_before(id, target),
+            Position::After { target } => self.move_after(id, target),
+            Position::BelongTo { parent } => self.move_belong_to(id, parent),
+        }
+        Ok(())
+    }
+}
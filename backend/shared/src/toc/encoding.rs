@@ -1,12 +1,19 @@
+use std::collections::{HashMap, HashSet};
+
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
-use super::{Toc, TocNode, TocRoot, TreeNodeMeta};
+use super::{error::TocError, NodeKind, Toc, TocNode, TocRoot, TreeNodeMeta};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct JSONNode {
     pub id: usize,
+    #[serde(default = "Uuid::new_v4")] // missing in projects saved before uids were added
+    pub uid: Uuid,
     pub title: String,
+    #[serde(default)] // missing in projects saved before kinds were added
+    pub kind: NodeKind,
     pub patch: Option<String>, // git-diff like patch content, to be applied while document is split.
     pub meta: TreeNodeMeta,
     pub children: Vec<JSONNode>,
@@ -14,6 +21,9 @@ pub struct JSONNode {
 
 pub type JSONRoot = Vec<JSONNode>;
 
+/// Vacant ids a loaded toc may have beyond its node count, e.g. of removed nodes, before it is renumbered.
+const MAX_VACANT_IDS: usize = 1024;
+
 impl From<&TocRoot> for JSONRoot {
     fn from(toc: &TocRoot) -> Self {
         let mut root = JSONRoot::new();
@@ -24,22 +34,76 @@ impl From<&TocRoot> for JSONRoot {
     }
 }
 
-// impl From<JSONRoot> for TocRoot {
-//     fn from(json: JSONRoot) -> Self {
-//         let mut toc = TocRoot::new();
-//         // 1. iterate all nodes and insert them into the toc
-//         for json_node in json.iter() {
-//             toc.add_with_meta(&json_node.title, Some(json_node.meta.clone()), None);
-//         }
-//         toc
-//     }
-// }
+impl TryFrom<JSONRoot> for TocRoot {
+    type Error = TocError;
+
+    // Node ids and uids are kept as they are, so references to them stay valid after a reload. Ids are slab
+    // keys though, a toc whose ids are far beyond its node count, e.g. edited by hand, would allocate a slot
+    // for each of them, so it is renumbered in the order of its ids instead, and only uids stay valid.
+    fn try_from(json: JSONRoot) -> Result<Self, Self::Error> {
+        fn collect(
+            nodes: Vec<JSONNode>,
+            parent: Option<usize>,
+            entries: &mut Vec<(usize, TocNode)>,
+            seen: &mut (HashSet<usize>, HashSet<Uuid>),
+        ) -> Result<Vec<usize>, TocError> {
+            let mut ids = Vec::with_capacity(nodes.len());
+            for node in nodes {
+                if !seen.0.insert(node.id) {
+                    return Err(TocError::DuplicateNodeId(node.id));
+                }
+                if !seen.1.insert(node.uid) {
+                    return Err(TocError::DuplicateNodeUid(node.uid));
+                }
+                let children = collect(node.children, Some(node.id), entries, seen)?;
+                entries.push((
+                    node.id,
+                    TocNode {
+                        id: node.id,
+                        uid: node.uid,
+                        title: node.title,
+                        kind: node.kind,
+                        patch: node.patch,
+                        meta: node.meta,
+                        parent,
+                        children,
+                    },
+                ));
+                ids.push(node.id);
+            }
+            Ok(ids)
+        }
+
+        let mut entries = Vec::new();
+        let mut children = collect(json, None, &mut entries, &mut Default::default())?;
+        let max = entries.iter().map(|x| x.0).max().unwrap_or_default();
+        if max >= entries.len() * 2 + MAX_VACANT_IDS {
+            let mut ids: Vec<_> = entries.iter().map(|x| x.0).collect();
+            ids.sort_unstable();
+            let ids: HashMap<usize, usize> = ids.into_iter().zip(0..).collect();
+            for (id, node) in entries.iter_mut() {
+                *id = ids[id];
+                node.id = *id;
+                node.parent = node.parent.map(|x| ids[&x]);
+                node.children.iter_mut().for_each(|x| *x = ids[x]);
+            }
+            children.iter_mut().for_each(|x| *x = ids[x]);
+        }
+        Ok(TocRoot {
+            children,
+            uids: entries.iter().map(|(id, x)| (x.uid, *id)).collect(),
+            container: entries.into_iter().collect(),
+        })
+    }
+}
 
 impl JSONNode {
-    fn from_toc_node(toc: &TocRoot, toc_node: &TocNode) -> Self {
+    pub fn from_toc_node(toc: &TocRoot, toc_node: &TocNode) -> Self {
         let mut node = JSONNode {
             id: toc_node.id,
+            uid: toc_node.uid,
             title: toc_node.title.clone(),
+            kind: toc_node.kind,
             patch: toc_node.patch.clone(),
             meta: toc_node.meta.clone(),
             children: Vec::new(),
@@ -50,10 +114,6 @@ impl JSONNode {
         }
         node
     }
-
-    // fn to_toc_node(toc: &mut TocRoot, node: &Self) -> TocNode {
-    //     toc.container.insert(val)
-    // }
 }
 
 impl Serialize for TocRoot {
@@ -65,9 +125,24 @@ impl Serialize for TocRoot {
     }
 }
 
+impl<'de> Deserialize<'de> for TocRoot {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        TocRoot::try_from(JSONRoot::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+    }
+}
+
 impl TocRoot {
     pub fn dump(&self) -> Result<String> {
         let buf = simd_json::to_string(self)?;
         Ok(buf)
     }
+
+    pub fn load(buf: &str) -> Result<Self> {
+        let mut buf = buf.as_bytes().to_vec();
+        let toc = simd_json::from_slice(&mut buf)?;
+        Ok(toc)
+    }
 }
@@ -1,10 +1,32 @@
 use thiserror::Error;
+use uuid::Uuid;
 
 #[derive(Error, Debug)]
 pub enum TocError {
     #[error("the parent id: `{0}` is not exist in container")]
     NodeParentNotFound(usize),
 
+    #[error("the node id: `{0}` is not exist in container")]
+    NodeNotFound(usize),
+
+    #[error("the node uid: `{0}` is not exist in container")]
+    UidNotFound(Uuid),
+
+    #[error("the node: `{id}` can not be moved relative to its descendant: `{target}`")]
+    InvalidMove { id: usize, target: usize },
+
+    #[error("the node: `{id}` has no note at index `{index}`")]
+    NoteNotFound { id: usize, index: usize },
+
+    #[error("the node: `{id}` can not be merged, {reason}")]
+    InvalidMerge { id: usize, reason: &'static str },
+
+    #[error("the node id: `{0}` is duplicated")]
+    DuplicateNodeId(usize),
+
+    #[error("the node uid: `{0}` is duplicated")]
+    DuplicateNodeUid(Uuid),
+
     #[error(transparent)]
     Other(#[from] anyhow::Error),
 }
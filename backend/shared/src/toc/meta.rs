@@ -0,0 +1,112 @@
+// Besides the measured words and range, editors keep their own data on nodes: tags such as
+// "needs proofreading", review notes, and free-form attributes. Some attributes are understood by export,
+// see the `ATTR_*` keys, the others are kept as they are.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use serde::{Deserialize, Serialize};
+
+use super::{TocNode, TocRoot};
+
+/// Output file name of the node in the book, e.g. `prologue.xhtml`.
+pub const ATTR_FILE_NAME: &str = "file_name";
+/// Extra css class of the rendered chapter.
+pub const ATTR_CLASS: &str = "class";
+/// Editing status, e.g. `draft`, `proofread`, `done`.
+pub const ATTR_STATUS: &str = "status";
+
+// Meta info for a node
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TreeNodeMeta {
+    pub words: u128,
+    pub range: (u128, u128), // a triple of (start, end)
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub file: usize, // id of the source file of combined documents
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub tags: BTreeSet<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub attributes: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub notes: Vec<Note>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Note {
+    pub text: String,
+    #[serde(default)]
+    pub resolved: bool,
+}
+
+fn is_zero(x: &usize) -> bool {
+    *x == 0
+}
+
+impl TreeNodeMeta {
+    pub fn new(words: u128, range: (u128, u128)) -> Self {
+        TreeNodeMeta {
+            words,
+            range,
+            ..Default::default()
+        }
+    }
+
+    pub fn attribute(&self, key: &str) -> Option<&str> {
+        self.attributes.get(key).map(|x| x.as_str())
+    }
+
+    pub fn file_name(&self) -> Option<&str> {
+        self.attribute(ATTR_FILE_NAME)
+    }
+
+    pub fn class(&self) -> Option<&str> {
+        self.attribute(ATTR_CLASS)
+    }
+
+    pub fn status(&self) -> Option<&str> {
+        self.attribute(ATTR_STATUS)
+    }
+
+    pub fn open_notes(&self) -> impl Iterator<Item = &Note> {
+        self.notes.iter().filter(|x| !x.resolved)
+    }
+}
+
+impl TocRoot {
+    /// Nodes tagged with `tag`, in document order.
+    pub fn tagged<'a>(&'a self, tag: &'a str) -> impl Iterator<Item = &'a TocNode> {
+        self.iter()
+            .map(|x| x.1)
+            .filter(move |x| x.meta.tags.contains(tag))
+    }
+
+    /// Nodes having the attribute, with the given value if it is `Some`, in document order.
+    pub fn with_attribute<'a>(
+        &'a self,
+        key: &'a str,
+        value: Option<&'a str>,
+    ) -> impl Iterator<Item = &'a TocNode> {
+        self.iter().map(|x| x.1).filter(move |x| {
+            x.meta
+                .attribute(key)
+                .is_some_and(|x| value.is_none_or(|value| x == value))
+        })
+    }
+
+    /// Nodes with unresolved notes, in document order.
+    pub fn with_open_notes(&self) -> impl Iterator<Item = &TocNode> {
+        self.iter()
+            .map(|x| x.1)
+            .filter(|x| x.meta.open_notes().next().is_some())
+    }
+
+    /// Number of nodes per tag, e.g. to track how many chapters still need proofreading.
+    pub fn tag_counts(&self) -> BTreeMap<&str, usize> {
+        let mut counts = BTreeMap::new();
+        for (_, node) in self.iter() {
+            for tag in node.meta.tags.iter() {
+                *counts.entry(tag.as_str()).or_default() += 1;
+            }
+        }
+        counts
+    }
+}
@@ -1,29 +1,65 @@
 // A toc is a vector of TreeNodes. Each TreeNode has a title, a range, and a vector of children.
 // All node can swap places with their siblings, and can be moved up or down in the tree.
+//
+// Ids are keys of the slab, which reuses the keys of removed nodes, so a node also has a uid. It is random, kept
+// through saving and loading, and never reused, for references from outside of the toc, e.g. bookmarks.
+
+use std::collections::HashMap;
 
 use slab::Slab;
 
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
-use self::error::TocError;
+pub use self::edit::{Position, TocEdit};
+pub use self::encoding::{JSONNode, JSONRoot};
+pub use self::error::TocError;
+pub use self::meta::{Note, TreeNodeMeta, ATTR_CLASS, ATTR_FILE_NAME, ATTR_STATUS};
 
+mod edit;
 mod encoding;
 mod error;
+mod meta;
 
 #[cfg(test)]
 mod tests;
 
-// Meta info for a node
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct TreeNodeMeta {
-    pub words: u128,
-    pub range: (u128, u128), // a triple of (start, end)
+///
+/// How a node is placed in the book. Front and back matter are marked as landmarks,
+/// hidden nodes are left out of the nav, but their text stays in the book.
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeKind {
+    Volume,
+    #[default]
+    Chapter,
+    Front, // preface, foreword, author's note before the story
+    Back,  // afterword, postscript
+    Hidden,
+}
+
+impl NodeKind {
+    /// The serialized name, e.g. `front`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            NodeKind::Volume => "volume",
+            NodeKind::Chapter => "chapter",
+            NodeKind::Front => "front",
+            NodeKind::Back => "back",
+            NodeKind::Hidden => "hidden",
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TocNode {
     pub id: usize, // id is the index of the node in the slab
+    #[serde(default = "Uuid::new_v4")]
+    pub uid: Uuid, // stable id, unlike `id` it is not reused after the node is removed, not to be changed
     pub title: String,
+    #[serde(default)]
+    pub kind: NodeKind,
     pub patch: Option<String>, // git-diff like patch content, to be applied while document is split.
     pub meta: TreeNodeMeta,
     #[serde(skip_serializing)]
@@ -32,10 +68,41 @@ pub struct TocNode {
     pub children: Vec<usize>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct TocRoot {
     children: Vec<usize>,
     container: Slab<TocNode>,
+    uids: HashMap<Uuid, usize>, // uid -> id of every node in the container
+}
+
+impl TocRoot {
+    /// Ids of the top level nodes, in order.
+    pub fn children(&self) -> &[usize] {
+        &self.children
+    }
+
+    /// Iterate all nodes in document order (depth first, pre-order), along with their depth.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &TocNode)> {
+        let mut stack: Vec<(usize, usize)> = self.children.iter().rev().map(|x| (0, *x)).collect();
+        std::iter::from_fn(move || {
+            let (depth, id) = stack.pop()?;
+            let node = self.container.get(id)?;
+            stack.extend(node.children.iter().rev().map(|x| (depth + 1, *x)));
+            Some((depth, node))
+        })
+    }
+
+    /// Find a node by its stable id.
+    pub fn get_by_uid(&self, uid: Uuid) -> Option<&TocNode> {
+        self.uids.get(&uid).and_then(|x| self.container.get(*x))
+    }
+
+    /// The id of a node by its stable id.
+    pub fn id_of(&self, uid: Uuid) -> Result<usize, TocError> {
+        self.get_by_uid(uid)
+            .map(|x| x.id)
+            .ok_or(TocError::UidNotFound(uid))
+    }
 }
 
 pub trait Toc {
@@ -71,6 +138,7 @@ impl Toc for TocRoot {
         TocRoot {
             children: Vec::new(),
             container: Slab::new(),
+            uids: HashMap::new(),
         }
     }
 
@@ -80,7 +148,7 @@ impl Toc for TocRoot {
         range: (u128, u128),
         parent: Option<usize>,
     ) -> Result<&TocNode, TocError> {
-        self.add_with_meta(title, Some(TreeNodeMeta { words: 0, range }), parent)
+        self.add_with_meta(title, Some(TreeNodeMeta::new(0, range)), parent)
     }
 
     fn add_with_meta(
@@ -95,17 +163,15 @@ impl Toc for TocRoot {
         let id = {
             let entry = self.container.vacant_entry();
             let key = entry.key();
+            let uid = Uuid::new_v4();
+            self.uids.insert(uid, key);
             self.container.insert(TocNode {
                 id: key,
+                uid,
                 title: title.to_string(),
+                kind: NodeKind::default(),
                 patch: None,
-                meta: match meta {
-                    Some(meta) => meta,
-                    None => TreeNodeMeta {
-                        words: 0,
-                        range: (0, 0),
-                    },
-                },
+                meta: meta.unwrap_or_default(),
                 parent,
                 children: Vec::new(),
             });
@@ -124,11 +190,12 @@ impl Toc for TocRoot {
         if !self.contains(id) {
             return;
         }
-        let node = self.container.remove(id);
-        // Remove node children
-        for child_id in node.children.iter() {
-            self.remove(*child_id);
+        // Remove node children first, they look their parent up to leave it
+        for child_id in self.container[id].children.clone() {
+            self.remove(child_id);
         }
+        let node = self.container.remove(id);
+        self.uids.remove(&node.uid);
         if let Some(parent_id) = node.parent {
             let parent = self.get_mut(parent_id).unwrap();
             parent.children.retain(|&x| x != id);
@@ -188,16 +255,17 @@ impl Toc for TocRoot {
         let parent = self.container.get_mut(parent_id).unwrap();
         parent.children.retain(|&x| x != id);
         let grand_parent_id = parent.parent;
-        let new_parent_id = {
-            if grand_parent_id.is_none() {
+        let new_parent_id = match grand_parent_id {
+            None => {
                 // node's parent is a child of root node
                 let root_children: &mut Vec<usize> = self.children.as_mut();
                 root_children.push(id);
                 None
-            } else {
-                let grand_parent = self.get_mut(grand_parent_id.unwrap()).unwrap();
+            }
+            Some(grand_parent_id) => {
+                let grand_parent = self.get_mut(grand_parent_id).unwrap();
                 grand_parent.children.push(id);
-                grand_parent_id
+                Some(grand_parent_id)
             }
         };
         let node = self.get_mut(id).unwrap();
@@ -1,4 +1,6 @@
-use super::{Toc, TocRoot, TreeNodeMeta};
+use super::{
+    NodeKind, Position, Toc, TocEdit, TocError, TocRoot, TreeNodeMeta, ATTR_FILE_NAME, ATTR_STATUS,
+};
 
 #[test]
 fn test_toc_new() {
@@ -29,6 +31,7 @@ fn test_toc_add_with_meta() {
             Some(TreeNodeMeta {
                 words: 0,
                 range: (0, 0),
+                ..Default::default()
             }),
             None,
         )
@@ -59,6 +62,7 @@ fn test_toc_add_with_meta_and_parent() {
             Some(TreeNodeMeta {
                 words: 0,
                 range: (0, 0),
+                ..Default::default()
             }),
             None,
         )
@@ -69,6 +73,7 @@ fn test_toc_add_with_meta_and_parent() {
         Some(TreeNodeMeta {
             words: 0,
             range: (0, 0),
+            ..Default::default()
         }),
         Some(node_id),
     )
@@ -90,6 +95,7 @@ fn test_remove() {
             Some(TreeNodeMeta {
                 words: 0,
                 range: (0, 0),
+                ..Default::default()
             }),
             None,
         )
@@ -109,6 +115,7 @@ fn test_move_up() {
             Some(TreeNodeMeta {
                 words: 0,
                 range: (0, 0),
+                ..Default::default()
             }),
             None,
         )
@@ -120,6 +127,7 @@ fn test_move_up() {
             Some(TreeNodeMeta {
                 words: 0,
                 range: (0, 0),
+                ..Default::default()
             }),
             None,
         )
@@ -139,6 +147,7 @@ fn test_move_down() {
             Some(TreeNodeMeta {
                 words: 0,
                 range: (0, 0),
+                ..Default::default()
             }),
             None,
         )
@@ -150,6 +159,7 @@ fn test_move_down() {
             Some(TreeNodeMeta {
                 words: 0,
                 range: (0, 0),
+                ..Default::default()
             }),
             None,
         )
@@ -169,6 +179,7 @@ fn test_move_right() {
             Some(TreeNodeMeta {
                 words: 0,
                 range: (0, 0),
+                ..Default::default()
             }),
             None,
         )
@@ -180,6 +191,7 @@ fn test_move_right() {
             Some(TreeNodeMeta {
                 words: 0,
                 range: (0, 0),
+                ..Default::default()
             }),
             Some(node_id),
         )
@@ -202,6 +214,7 @@ fn test_move_left() {
             Some(TreeNodeMeta {
                 words: 0,
                 range: (0, 0),
+                ..Default::default()
             }),
             None,
         )
@@ -213,6 +226,7 @@ fn test_move_left() {
             Some(TreeNodeMeta {
                 words: 0,
                 range: (0, 0),
+                ..Default::default()
             }),
             Some(node_id),
         )
@@ -229,15 +243,19 @@ fn test_move_left() {
 ///
 /// Test move_before fn with 5 nodes,  Move node5 before node1
 /// Before move:
+/// ```text
 /// - node1
 /// - node2 - node3 - node5
 ///         - node4
+/// ```
 ///
 /// After move:
+/// ```text
 /// - node5
 /// - node1
 /// - node2 - node3
 ///         - node4
+/// ```
 ///
 #[test]
 fn test_move_before() {
@@ -262,15 +280,19 @@ fn test_move_before() {
 ///
 /// Test move_after fn with 5 nodes,  Move node5 after node2
 /// Before move:
+/// ```text
 /// - node1
 /// - node2 - node3 - node5
 ///         - node4
+/// ```
 ///
 /// After move:
+/// ```text
 /// - node1
 /// - node2 - node3
 ///         - node4
 /// - node5
+/// ```
 ///
 #[test]
 fn test_move_after() {
@@ -295,13 +317,17 @@ fn test_move_after() {
 ///
 /// Test move_belong_to fn with 5 nodes,  Move node2 belong to node1
 /// Before move:
+/// ```text
 /// - node1
 /// - node2 - node3 - node5
 ///         - node4
+/// ```
 ///
 /// After move:
+/// ```text
 /// - node1 - node2 - node3 - node5
 ///                 - node4
+/// ```
 ///
 #[test]
 fn test_move_belong_to() {
@@ -351,6 +377,340 @@ fn test_contains() {
 fn test_dump() {
     let mut toc = TocRoot::new();
     toc.add("test", (0, 0), None).unwrap();
+    let uid = toc.get(0).unwrap().uid;
     let buf = toc.dump().unwrap();
-    assert_eq!(buf, "[{\"id\":0,\"title\":\"test\",\"patch\":null,\"meta\":{\"words\":0,\"range\":[0,0]},\"children\":[]}]");
+    assert_eq!(buf, format!("[{{\"id\":0,\"uid\":\"{}\",\"title\":\"test\",\"kind\":\"chapter\",\"patch\":null,\"meta\":{{\"words\":0,\"range\":[0,0]}},\"children\":[]}}]", uid));
+}
+
+#[test]
+fn test_load() {
+    let mut toc = TocRoot::new();
+    let node_id1 = toc.add("node1", (0, 0), None).unwrap().id;
+    let node_id2 = toc.add("node2", (0, 0), None).unwrap().id;
+    let node_id3 = toc.add("node3", (0, 0), Some(node_id2)).unwrap().id;
+    toc.remove(node_id1);
+    let loaded = TocRoot::load(&toc.dump().unwrap()).unwrap();
+    assert_eq!(loaded.children, vec![node_id2]);
+    assert!(!loaded.contains(node_id1));
+    assert_eq!(loaded.get(node_id3).unwrap().parent, Some(node_id2));
+    assert_eq!(loaded.dump().unwrap(), toc.dump().unwrap());
+}
+
+#[test]
+fn test_load_duplicated_id() {
+    let buf = "[{\"id\":0,\"title\":\"a\",\"patch\":null,\"meta\":{\"words\":0,\"range\":[0,0]},\"children\":[{\"id\":0,\"title\":\"b\",\"patch\":null,\"meta\":{\"words\":0,\"range\":[0,0]},\"children\":[]}]}]";
+    assert!(TocRoot::load(buf).is_err());
+}
+
+#[test]
+fn test_load_sparse_ids() {
+    let buf = "[{\"id\":1099511627776,\"title\":\"a\",\"patch\":null,\"meta\":{\"words\":0,\"range\":[0,0]},\"children\":[{\"id\":5000,\"title\":\"b\",\"patch\":null,\"meta\":{\"words\":0,\"range\":[0,0]},\"children\":[]}]}]";
+    let loaded = TocRoot::load(buf).unwrap();
+    // Renumbered in the order of the ids, instead of allocating a slot for each id below them
+    assert_eq!(loaded.children, vec![1]);
+    assert_eq!(loaded.get(1).unwrap().children, vec![0]);
+    assert_eq!(loaded.get(0).unwrap().parent, Some(1));
+    assert_eq!(loaded.get(0).unwrap().title, "b");
+    let uid = loaded.get(0).unwrap().uid;
+    assert_eq!(loaded.id_of(uid).unwrap(), 0);
+}
+
+#[test]
+fn test_uid() {
+    let mut toc = TocRoot::new();
+    let node_id1 = toc.add("node1", (0, 0), None).unwrap().id;
+    let uid1 = toc.get(node_id1).unwrap().uid;
+    toc.remove(node_id1);
+    // The slab reuses the id, but not the uid
+    let node = toc.add("node2", (0, 0), None).unwrap();
+    assert_eq!(node.id, node_id1);
+    assert_ne!(node.uid, uid1);
+    let uid2 = node.uid;
+    assert!(toc.get_by_uid(uid1).is_none());
+    assert!(matches!(toc.id_of(uid1), Err(TocError::UidNotFound(x)) if x == uid1));
+    assert_eq!(toc.id_of(uid2).unwrap(), node_id1);
+
+    let loaded = TocRoot::load(&toc.dump().unwrap()).unwrap();
+    assert_eq!(loaded.get_by_uid(uid2).unwrap().title, "node2");
+
+    // Children removed with their parent, and merged nodes, are gone from the index too
+    let child = toc.add("child", (0, 0), Some(node_id1)).unwrap().uid;
+    let next = toc.add("node3", (0, 0), None).unwrap();
+    let (next_id, next_uid) = (next.id, next.uid);
+    toc.merge(next_id).unwrap();
+    assert!(toc.get_by_uid(next_uid).is_none());
+    toc.remove(node_id1);
+    assert!(toc.get_by_uid(uid2).is_none());
+    assert!(toc.get_by_uid(child).is_none());
+
+    // Projects saved before uids were added get fresh ones
+    let buf = "[{\"id\":0,\"title\":\"a\",\"patch\":null,\"meta\":{\"words\":0,\"range\":[0,0]},\"children\":[{\"id\":1,\"title\":\"b\",\"patch\":null,\"meta\":{\"words\":0,\"range\":[0,0]},\"children\":[]}]}]";
+    let loaded = TocRoot::load(buf).unwrap();
+    assert_ne!(loaded.get(0).unwrap().uid, loaded.get(1).unwrap().uid);
+
+    let buf = format!("[{{\"id\":0,\"uid\":\"{0}\",\"title\":\"a\",\"patch\":null,\"meta\":{{\"words\":0,\"range\":[0,0]}},\"children\":[{{\"id\":1,\"uid\":\"{0}\",\"title\":\"b\",\"patch\":null,\"meta\":{{\"words\":0,\"range\":[0,0]}},\"children\":[]}}]}}]", uid2);
+    assert!(TocRoot::load(&buf).is_err());
+}
+
+#[test]
+fn test_iter() {
+    let mut toc = TocRoot::new();
+    let node_id1 = toc.add("node1", (0, 0), None).unwrap().id;
+    let node_id2 = toc.add("node2", (0, 0), Some(node_id1)).unwrap().id;
+    let node_id3 = toc.add("node3", (0, 0), None).unwrap().id;
+    let nodes: Vec<_> = toc.iter().map(|(depth, x)| (depth, x.id)).collect();
+    assert_eq!(nodes, vec![(0, node_id1), (1, node_id2), (0, node_id3)]);
+}
+
+#[test]
+fn test_apply() {
+    let mut toc = TocRoot::new();
+    let node_id1 = toc
+        .apply(&TocEdit::Add {
+            title: "node1".to_string(),
+            range: (0, 10),
+            parent: None,
+        })
+        .unwrap();
+    let node_id2 = toc.add("node2", (10, 20), Some(node_id1)).unwrap().id;
+    toc.apply(&TocEdit::Retitle {
+        id: node_id2,
+        title: "renamed".to_string(),
+    })
+    .unwrap();
+    assert_eq!(toc.get(node_id2).unwrap().title, "renamed");
+    toc.apply(&TocEdit::Move {
+        id: node_id2,
+        to: Position::Before { target: node_id1 },
+    })
+    .unwrap();
+    assert_eq!(toc.children, vec![node_id2, node_id1]);
+    toc.apply(&TocEdit::Remove { id: node_id2 }).unwrap();
+    assert!(!toc.contains(node_id2));
+    assert!(matches!(
+        toc.apply(&TocEdit::Remove { id: node_id2 }),
+        Err(TocError::NodeNotFound(_))
+    ));
+}
+
+#[test]
+fn test_apply_rejects_move_into_descendant() {
+    let mut toc = TocRoot::new();
+    let node_id1 = toc.add("node1", (0, 0), None).unwrap().id;
+    let node_id2 = toc.add("node2", (0, 0), Some(node_id1)).unwrap().id;
+    assert!(matches!(
+        toc.apply(&TocEdit::Move {
+            id: node_id1,
+            to: Position::BelongTo { parent: node_id2 },
+        }),
+        Err(TocError::InvalidMove { .. })
+    ));
+    assert!(toc.is_ancestor(node_id1, node_id2));
+    assert!(!toc.is_ancestor(node_id2, node_id1));
+}
+
+#[test]
+fn test_merge() {
+    let mut toc = TocRoot::new();
+    let node_id1 = toc.add("node1", (0, 10), None).unwrap().id;
+    let node_id2 = toc.add("node2", (10, 20), None).unwrap().id;
+    let node_id3 = toc.add("node3", (20, 30), Some(node_id2)).unwrap().id;
+    toc.get_mut(node_id1).unwrap().meta.words = 3;
+    toc.get_mut(node_id2).unwrap().meta.words = 4;
+    assert_eq!(
+        toc.apply(&TocEdit::Merge { id: node_id2 }).unwrap(),
+        node_id1
+    );
+    assert!(!toc.contains(node_id2));
+    assert_eq!(toc.children, vec![node_id1, node_id3]);
+    assert_eq!(toc.get(node_id3).unwrap().parent, None);
+    let node = toc.get(node_id1).unwrap();
+    assert_eq!(node.meta.range, (0, 20));
+    assert_eq!(node.meta.words, 7);
+    assert!(matches!(
+        toc.merge(node_id1),
+        Err(TocError::InvalidMerge { id, .. }) if id == node_id1
+    ));
+}
+
+#[test]
+fn test_merge_keeps_meta() {
+    let mut toc = TocRoot::new();
+    let node_id1 = toc.add("node1", (0, 10), None).unwrap().id;
+    let node_id2 = toc.add("node2", (10, 20), None).unwrap().id;
+    let edits = [
+        TocEdit::Tag {
+            id: node_id1,
+            tag: "a".to_string(),
+        },
+        TocEdit::Tag {
+            id: node_id2,
+            tag: "b".to_string(),
+        },
+        TocEdit::SetAttribute {
+            id: node_id1,
+            key: ATTR_STATUS.to_string(),
+            value: Some("draft".to_string()),
+        },
+        TocEdit::SetAttribute {
+            id: node_id2,
+            key: ATTR_STATUS.to_string(),
+            value: Some("done".to_string()),
+        },
+        TocEdit::SetAttribute {
+            id: node_id2,
+            key: ATTR_FILE_NAME.to_string(),
+            value: Some("two".to_string()),
+        },
+        TocEdit::AddNote {
+            id: node_id2,
+            text: "check".to_string(),
+        },
+    ];
+    for edit in edits.iter() {
+        toc.apply(edit).unwrap();
+    }
+
+    // A patched node is refused, its patch would not fit the text of the node merged into
+    toc.get_mut(node_id2).unwrap().patch = Some("@@ -1 +1 @@\n-a\n+b\n".to_string());
+    assert!(matches!(
+        toc.merge(node_id2),
+        Err(TocError::InvalidMerge { id, .. }) if id == node_id2
+    ));
+    assert!(toc.contains(node_id2));
+
+    toc.get_mut(node_id2).unwrap().patch = None;
+
+    // So is a merge into a patched node, its patch would not fit the longer text
+    toc.get_mut(node_id1).unwrap().patch = Some("@@ -1 +1 @@\n-a\n+b\n".to_string());
+    assert!(matches!(
+        toc.merge(node_id2),
+        Err(TocError::InvalidMerge { id, .. }) if id == node_id2
+    ));
+    assert!(toc.contains(node_id2));
+    assert_eq!(toc.get(node_id1).unwrap().meta.range, (0, 10));
+
+    toc.get_mut(node_id1).unwrap().patch = None;
+    toc.merge(node_id2).unwrap();
+    let meta = &toc.get(node_id1).unwrap().meta;
+    assert_eq!(meta.tags.iter().collect::<Vec<_>>(), vec!["a", "b"]);
+    assert_eq!(meta.attributes[ATTR_STATUS], "draft");
+    assert_eq!(meta.attributes[ATTR_FILE_NAME], "two");
+    assert_eq!(meta.notes[0].text, "check");
+}
+
+#[test]
+fn test_node_kind() {
+    let mut toc = TocRoot::new();
+    let node_id1 = toc.add("序言", (0, 0), None).unwrap().id;
+    assert_eq!(toc.get(node_id1).unwrap().kind, NodeKind::Chapter);
+    let edit: TocEdit = serde_json::from_str(&format!(
+        "{{\"op\":\"set_kind\",\"id\":{},\"kind\":\"front\"}}",
+        node_id1
+    ))
+    .unwrap();
+    toc.apply(&edit).unwrap();
+    assert_eq!(toc.get(node_id1).unwrap().kind, NodeKind::Front);
+
+    let loaded = TocRoot::load(&toc.dump().unwrap()).unwrap();
+    assert_eq!(loaded.get(node_id1).unwrap().kind, NodeKind::Front);
+    // Projects saved before kinds were added
+    let loaded = TocRoot::load("[{\"id\":3,\"title\":\"a\",\"patch\":null,\"meta\":{\"words\":0,\"range\":[0,0]},\"children\":[]}]").unwrap();
+    assert_eq!(loaded.get(3).unwrap().kind, NodeKind::Chapter);
+}
+
+#[test]
+fn test_meta_edits_and_queries() {
+    let mut toc = TocRoot::new();
+    let node_id1 = toc.add("node1", (0, 10), None).unwrap().id;
+    let node_id2 = toc.add("node2", (10, 20), Some(node_id1)).unwrap().id;
+    let node_id3 = toc.add("node3", (20, 30), None).unwrap().id;
+    let edits = [
+        TocEdit::Tag {
+            id: node_id3,
+            tag: "proofread".to_string(),
+        },
+        TocEdit::Tag {
+            id: node_id2,
+            tag: "proofread".to_string(),
+        },
+        TocEdit::SetAttribute {
+            id: node_id2,
+            key: ATTR_STATUS.to_string(),
+            value: Some("done".to_string()),
+        },
+        TocEdit::SetAttribute {
+            id: node_id1,
+            key: ATTR_FILE_NAME.to_string(),
+            value: Some("prologue.xhtml".to_string()),
+        },
+        TocEdit::AddNote {
+            id: node_id1,
+            text: "typo in line 3".to_string(),
+        },
+        TocEdit::AddNote {
+            id: node_id1,
+            text: "check the name".to_string(),
+        },
+        TocEdit::ResolveNote {
+            id: node_id1,
+            index: 0,
+        },
+    ];
+    for edit in edits.iter() {
+        toc.apply(edit).unwrap();
+    }
+    let tagged: Vec<_> = toc.tagged("proofread").map(|x| x.id).collect();
+    assert_eq!(tagged, vec![node_id2, node_id3]);
+    let done: Vec<_> = toc
+        .with_attribute(ATTR_STATUS, Some("done"))
+        .map(|x| x.id)
+        .collect();
+    assert_eq!(done, vec![node_id2]);
+    assert_eq!(toc.with_attribute(ATTR_STATUS, Some("draft")).count(), 0);
+    assert_eq!(
+        toc.get(node_id1).unwrap().meta.file_name(),
+        Some("prologue.xhtml")
+    );
+    let notes: Vec<_> = toc.with_open_notes().map(|x| x.id).collect();
+    assert_eq!(notes, vec![node_id1]);
+    assert_eq!(toc.tag_counts().get("proofread"), Some(&2));
+
+    // Everything survives dump and load
+    let loaded = TocRoot::load(&toc.dump().unwrap()).unwrap();
+    assert_eq!(
+        loaded.get(node_id1).unwrap().meta,
+        toc.get(node_id1).unwrap().meta
+    );
+    assert_eq!(
+        loaded.get(node_id2).unwrap().meta,
+        toc.get(node_id2).unwrap().meta
+    );
+
+    toc.apply(&TocEdit::Untag {
+        id: node_id3,
+        tag: "proofread".to_string(),
+    })
+    .unwrap();
+    toc.apply(&TocEdit::SetAttribute {
+        id: node_id2,
+        key: ATTR_STATUS.to_string(),
+        value: None,
+    })
+    .unwrap();
+    toc.apply(&TocEdit::RemoveNote {
+        id: node_id1,
+        index: 1,
+    })
+    .unwrap();
+    assert_eq!(toc.tagged("proofread").count(), 1);
+    assert_eq!(toc.with_attribute(ATTR_STATUS, None).count(), 0);
+    assert_eq!(toc.with_open_notes().count(), 0);
+    assert!(matches!(
+        toc.apply(&TocEdit::ResolveNote {
+            id: node_id1,
+            index: 5,
+        }),
+        Err(TocError::NoteNotFound { index: 5, .. })
+    ));
 }
@@ -1 +1,7 @@
 pub struct Port(pub u16);
+
+/// The secret the embedded server requires, generated on every launch.
+pub struct ServerToken(pub String);
+
+/// Why the settings failed to load, the app runs with the defaults then.
+pub struct SettingsError(pub Option<String>);
@@ -0,0 +1,211 @@
+// CJK typography of generated books: vertical writing with right-to-left page progression,
+// tate-chu-yoko (short numbers set upright in vertical lines), strict line breaking, trimmed punctuation
+// spacing and ruby. The options are book-wide, they go into the OPF spine and a stylesheet which is
+// appended to the one of the template, while ruby and tate-chu-yoko are marked up in the chapter spans.
+//
+// Ruby follows the Aozora Bunko notation: `｜base《reading》`, or `漢字《かんじ》` for a run of kanji
+// when `ruby` is `all`. The latter is off by default, since Chinese text uses 《》 for book titles.
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::{export::Chapter, notes::Span, settings::TypographySettings};
+
+#[cfg(test)]
+mod tests;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WritingMode {
+    #[default]
+    Horizontal,
+    Vertical, // top to bottom, lines from right to left
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PageDirection {
+    Ltr,
+    Rtl,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Ruby {
+    Off,
+    #[default]
+    Explicit, // `｜base《reading》` only
+    All, // also `漢字《かんじ》`
+}
+
+const EXPLICIT_RUBY: &str = r"[｜|](?P<base>[^｜|《》\n]+)《(?P<ruby>[^《》\n]+)》";
+const ALL_RUBY: &str =
+    r"(?:[｜|](?P<base>[^｜|《》\n]+)|(?P<kanji>[\p{Han}々〆ヵヶ]+))《(?P<ruby>[^《》\n]+)》";
+
+impl TypographySettings {
+    /// The page progression, right-to-left for vertical writing unless it is set.
+    pub fn direction(&self) -> PageDirection {
+        match (self.page_direction, self.writing_mode) {
+            (Some(direction), _) => direction,
+            (None, WritingMode::Vertical) => PageDirection::Rtl,
+            (None, WritingMode::Horizontal) => PageDirection::Ltr,
+        }
+    }
+
+    /// Rules appended to the stylesheet of the template, empty if no option asks for any.
+    pub fn stylesheet(&self) -> String {
+        let vertical = self.writing_mode == WritingMode::Vertical;
+        let mut rules = Vec::new();
+        if vertical {
+            rules.push(
+                "html {\n  writing-mode: vertical-rl;\n  -epub-writing-mode: vertical-rl;\n  \
+                 -webkit-writing-mode: vertical-rl;\n}\n\n\
+                 div.illustration img {\n  max-width: none;\n  max-height: 100%;\n}",
+            );
+        }
+        if self.line_break {
+            rules.push(
+                "body {\n  line-break: strict;\n  -epub-line-break: strict;\n  \
+                 -webkit-line-break: strict;\n  word-break: normal;\n  overflow-wrap: break-word;\n}",
+            );
+        }
+        if self.punctuation_trim {
+            rules.push("body {\n  text-spacing-trim: trim-start;\n}");
+        }
+        if self.ruby != Ruby::Off {
+            rules.push("ruby {\n  ruby-position: over;\n}\n\nrt {\n  font-size: 0.5em;\n}");
+        }
+        if vertical && self.tate_chu_yoko > 0 {
+            rules.push(
+                "span.tcy {\n  text-combine-upright: all;\n  -epub-text-combine: horizontal;\n  \
+                 -webkit-text-combine: horizontal;\n}",
+            );
+        }
+        match rules.is_empty() {
+            true => String::new(),
+            false => format!("\n/* typography */\n\n{}\n", rules.join("\n\n")),
+        }
+    }
+}
+
+/// Marks up ruby and tate-chu-yoko in chapter spans.
+#[derive(Debug, Clone)]
+pub struct Typesetter {
+    ruby: Option<Regex>,
+    tate_chu_yoko: usize, // 0 if the text is horizontal, or it is disabled
+}
+
+impl Typesetter {
+    pub fn new(settings: &TypographySettings) -> Self {
+        let ruby = match settings.ruby {
+            Ruby::Off => None,
+            Ruby::Explicit => Some(EXPLICIT_RUBY),
+            Ruby::All => Some(ALL_RUBY),
+        };
+        Typesetter {
+            ruby: ruby.map(|x| Regex::new(x).unwrap()),
+            tate_chu_yoko: match settings.writing_mode {
+                WritingMode::Vertical => settings.tate_chu_yoko,
+                WritingMode::Horizontal => 0,
+            },
+        }
+    }
+
+    /// Split text spans at ruby and upright numbers, noterefs and images are left as they are.
+    pub fn typeset(&self, chapters: &mut [Chapter]) {
+        if self.ruby.is_none() && self.tate_chu_yoko == 0 {
+            return;
+        }
+        for spans in chapters.iter_mut().flat_map(|x| x.spans.iter_mut()) {
+            let mut split = Vec::with_capacity(spans.len());
+            for span in spans.drain(..) {
+                match span.note.is_some() || span.image.is_some() {
+                    true => split.push(span),
+                    false => self.split(&span.text, &mut split),
+                }
+            }
+            *spans = split;
+        }
+    }
+
+    fn split(&self, text: &str, spans: &mut Vec<Span>) {
+        let mut last = 0;
+        for captures in self.ruby.iter().flat_map(|x| x.captures_iter(text)) {
+            let whole = captures.get(0).unwrap();
+            let base = captures.name("base").or(captures.name("kanji")).unwrap();
+            self.upright(&text[last..whole.start()], spans);
+            spans.push(Span {
+                ruby: Some(captures["ruby"].to_string()),
+                ..Span::text(base.as_str())
+            });
+            last = whole.end();
+        }
+        match (last, &text[last..]) {
+            (0, "") => spans.push(Span::text("")), // keep empty paragraphs
+            (_, rest) => self.upright(rest, spans),
+        }
+    }
+
+    /// Set numbers of at most `tate_chu_yoko` digits, and `!!`, `!?` and the like, upright.
+    fn upright(&self, text: &str, spans: &mut Vec<Span>) {
+        if text.is_empty() {
+            return;
+        }
+        let mut last = 0;
+        if self.tate_chu_yoko > 0 {
+            for (start, end) in runs(text) {
+                let run = &text[start..end];
+                let is_number = run.bytes().all(|x| x.is_ascii_digit());
+                let neighbors = [text[..start].chars().last(), text[end..].chars().next()];
+                // Parts of words and decimals, e.g. `A4` or `3.14`, are left sideways
+                let standalone = !neighbors
+                    .iter()
+                    .flatten()
+                    .any(|x| x.is_ascii_alphanumeric() || matches!(x, '.' | ',' | '%'));
+                let fits = match is_number {
+                    true => run.len() <= self.tate_chu_yoko,
+                    false => run.len() == 2,
+                };
+                if !standalone || !fits {
+                    continue;
+                }
+                if start > last {
+                    spans.push(Span::text(&text[last..start]));
+                }
+                spans.push(Span {
+                    upright: true,
+                    ..Span::text(run)
+                });
+                last = end;
+            }
+        }
+        if last < text.len() {
+            spans.push(Span::text(&text[last..]));
+        }
+    }
+}
+
+/// Runs of ascii digits, and of `!` and `?`, as byte ranges.
+fn runs(text: &str) -> Vec<(usize, usize)> {
+    let mut runs: Vec<(usize, usize)> = Vec::new();
+    let class = |x: u8| match x {
+        b'0'..=b'9' => 1,
+        b'!' | b'?' => 2,
+        _ => 0,
+    };
+    let bytes = text.as_bytes();
+    let mut start = None;
+    for i in 0..=bytes.len() {
+        let current = bytes.get(i).map_or(0, |x| class(*x));
+        if let Some(s) = start {
+            if current != class(bytes[s]) {
+                runs.push((s, i));
+                start = None;
+            }
+        }
+        if start.is_none() && current != 0 {
+            start = Some(i);
+        }
+    }
+    runs
+}
@@ -0,0 +1,167 @@
+use std::io::{Cursor, Read};
+
+use super::{PageDirection, Ruby, Typesetter, WritingMode};
+use crate::{
+    document::Document,
+    epubcheck::check_epub,
+    export::{export, BookMeta, Format},
+    notes::Span,
+    settings::{Settings, TypographySettings},
+};
+
+fn typography() -> TypographySettings {
+    Settings::default().export.typography
+}
+
+fn vertical() -> TypographySettings {
+    TypographySettings {
+        writing_mode: WritingMode::Vertical,
+        ..typography()
+    }
+}
+
+fn typeset(settings: &TypographySettings, text: &str) -> Vec<Span> {
+    let mut spans = Vec::new();
+    Typesetter::new(settings).split(text, &mut spans);
+    spans
+}
+
+fn ruby(text: &str, ruby: &str) -> Span {
+    Span {
+        ruby: Some(ruby.to_string()),
+        ..Span::text(text)
+    }
+}
+
+fn upright(text: &str) -> Span {
+    Span {
+        upright: true,
+        ..Span::text(text)
+    }
+}
+
+#[test]
+fn test_direction() {
+    assert_eq!(typography().direction(), PageDirection::Ltr);
+    assert_eq!(vertical().direction(), PageDirection::Rtl);
+    let settings = TypographySettings {
+        page_direction: Some(PageDirection::Ltr),
+        ..vertical()
+    };
+    assert_eq!(settings.direction(), PageDirection::Ltr);
+}
+
+#[test]
+fn test_stylesheet() {
+    let css = typography().stylesheet();
+    assert!(!css.contains("writing-mode"));
+    assert!(css.contains("line-break: strict;"));
+    assert!(css.contains("text-spacing-trim: trim-start;"));
+    assert!(css.contains("rt {"));
+    assert!(!css.contains("text-combine-upright"));
+
+    let css = vertical().stylesheet();
+    assert!(css.contains("writing-mode: vertical-rl;"));
+    assert!(css.contains("text-combine-upright: all;"));
+
+    let settings = TypographySettings {
+        line_break: false,
+        punctuation_trim: false,
+        ruby: Ruby::Off,
+        ..typography()
+    };
+    assert_eq!(settings.stylesheet(), "");
+}
+
+#[test]
+fn test_ruby() {
+    let text = "｜東京《とうきょう》の漢字《かんじ》と《三国演义》";
+    assert_eq!(
+        typeset(&typography(), text),
+        vec![
+            ruby("東京", "とうきょう"),
+            Span::text("の漢字《かんじ》と《三国演义》"),
+        ]
+    );
+    let settings = TypographySettings {
+        ruby: Ruby::All,
+        ..typography()
+    };
+    assert_eq!(
+        typeset(&settings, text),
+        vec![
+            ruby("東京", "とうきょう"),
+            Span::text("の"),
+            ruby("漢字", "かんじ"),
+            Span::text("と《三国演义》"),
+        ]
+    );
+    assert_eq!(typeset(&typography(), ""), vec![Span::text("")]);
+}
+
+#[test]
+fn test_tate_chu_yoko() {
+    assert_eq!(
+        typeset(&typography(), "第12話"),
+        vec![Span::text("第12話")],
+        "horizontal text is left as it is"
+    );
+    assert_eq!(
+        typeset(&vertical(), "第12話、2024年、A4判、3.5倍、本当!?"),
+        vec![
+            Span::text("第"),
+            upright("12"),
+            Span::text("話、2024年、A4判、3.5倍、本当"),
+            upright("!?"),
+        ]
+    );
+    assert_eq!(
+        typeset(&vertical(), "｜12《じゅうに》時の5分"),
+        vec![
+            ruby("12", "じゅうに"),
+            Span::text("時の"),
+            upright("5"),
+            Span::text("分")
+        ]
+    );
+}
+
+#[test]
+fn test_export() {
+    let mut settings = Settings::default();
+    settings.export.typography = vertical();
+    let mut document = Document::new(
+        "第一章 始まり\n｜東京《とうきょう》まで12時間!!（注1）\n注1：遠い\n".to_string(),
+    );
+    document.detect(&settings.detection).unwrap();
+    let book = BookMeta::new("本", &settings.export);
+
+    let epub = export(&document, &settings, &book, Format::Epub).unwrap();
+    let report = check_epub(&epub);
+    assert!(report.issues.is_empty(), "{:?}", report.issues);
+    let mut archive = zip::ZipArchive::new(Cursor::new(epub)).unwrap();
+    let mut read = |name: &str| {
+        let mut text = String::new();
+        archive
+            .by_name(name)
+            .unwrap()
+            .read_to_string(&mut text)
+            .unwrap();
+        text
+    };
+    assert!(read("OEBPS/content.opf").contains("page-progression-direction=\"rtl\""));
+    assert!(read("OEBPS/stylesheet.css").contains("writing-mode: vertical-rl;"));
+    let chapter = read("OEBPS/chapter_0000.xhtml");
+    assert!(chapter.contains(
+        "<p><ruby>東京<rt>とうきょう</rt></ruby>まで<span class=\"tcy\">12</span>時間\
+         <span class=\"tcy\">!!</span><a epub:type=\"noteref\""
+    ));
+
+    let markdown = export(&document, &settings, &book, Format::Markdown).unwrap();
+    let markdown = String::from_utf8(markdown).unwrap();
+    assert!(
+        markdown.contains("東京（とうきょう）まで12時間\\!\\![^c0-note-1]"),
+        "{}",
+        markdown
+    );
+}
@@ -0,0 +1,414 @@
+use std::{collections::HashMap, path::PathBuf, sync::Mutex};
+
+use shared::{
+    detect::{Candidate, Heading},
+    document::{Document, PatchPreview, ReimportReport, PROJECT_EXTENSION},
+    epubcheck::{check_file, CheckReport},
+    images::{ImageReport, ImageResolver},
+    lint::{LintReport, Linter},
+    notes::{Annotator, NoteReport},
+    search::{ReplaceReport, SearchQuery, SearchResult, Searcher},
+    settings::Settings,
+    stats::BookStats,
+    toc::{JSONNode, JSONRoot, NodeKind, Position, Toc, TocEdit, TocError},
+};
+use tauri::{State, Window};
+use uuid::Uuid;
+
+use super::error::CommandError;
+
+///
+/// Opened documents, keyed by the label of the window which opened them.
+///
+#[derive(Debug, Default)]
+pub struct DocumentState(Mutex<HashMap<String, Document>>);
+
+impl DocumentState {
+    fn with<T>(
+        &self,
+        window: &Window,
+        f: impl FnOnce(&mut Document) -> Result<T, CommandError>,
+    ) -> Result<T, CommandError> {
+        let mut documents = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let document = documents
+            .get_mut(window.label())
+            .ok_or(CommandError::NoDocument);
+        document
+            .and_then(f)
+            .inspect_err(|e| tracing::error!("{:?}", e))
+    }
+
+    fn insert(&self, window: &Window, document: Document) {
+        let mut documents = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        documents.insert(window.label().to_string(), document);
+    }
+
+    pub fn remove(&self, label: &str) {
+        let mut documents = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        documents.remove(label);
+    }
+}
+
+///
+/// Open a document in the current window, replacing the opened one.
+/// A project file saved by `save_document` is loaded as is, other files are treated as text and detected.
+///
+#[tauri::command]
+pub async fn open_document(
+    window: Window,
+    documents: State<'_, DocumentState>,
+    settings: State<'_, Settings>,
+    path: String,
+) -> Result<JSONRoot, CommandError> {
+    let path = PathBuf::from(path);
+    let document = match path.extension().is_some_and(|x| x == PROJECT_EXTENSION) {
+        true => Document::load(&path),
+        false => Document::open(&path).and_then(|mut x| {
+            x.detect(&settings.detection)?;
+            Ok(x)
+        }),
+    }
+    .inspect_err(|e| tracing::error!("{:?}", e))?;
+    let toc = JSONRoot::from(&document.toc);
+    documents.insert(&window, document);
+    Ok(toc)
+}
+
+///
+/// Open text files as volumes of one book in the current window, replacing the opened document.
+/// Each file becomes a top-level volume titled by its file name, with its detected headings as children.
+///
+#[tauri::command]
+pub async fn open_documents(
+    window: Window,
+    documents: State<'_, DocumentState>,
+    settings: State<'_, Settings>,
+    paths: Vec<String>,
+) -> Result<JSONRoot, CommandError> {
+    let paths: Vec<_> = paths.into_iter().map(PathBuf::from).collect();
+    let document = Document::open_files(&paths)
+        .and_then(|mut x| {
+            x.detect(&settings.detection)?;
+            Ok(x)
+        })
+        .inspect_err(|e| tracing::error!("{:?}", e))?;
+    let toc = JSONRoot::from(&document.toc);
+    documents.insert(&window, document);
+    Ok(toc)
+}
+
+///
+/// Replace the text of the opened document by a newer version of its source file, keeping the edited toc.
+/// Nodes are matched to the new chapters by their content, new chapters are added. The project refers to
+/// the new file from then on.
+///
+#[tauri::command]
+pub async fn reimport_document(
+    window: Window,
+    documents: State<'_, DocumentState>,
+    settings: State<'_, Settings>,
+    path: String,
+) -> Result<ReimportReport, CommandError> {
+    let path = PathBuf::from(path);
+    let text = Document::open(&path)
+        .inspect_err(|e| tracing::error!("{:?}", e))?
+        .text;
+    documents.with(&window, |x| {
+        let report = x.reimport(text, &settings.detection)?;
+        x.source = Some(path);
+        Ok(report)
+    })
+}
+
+///
+/// Detect and score headings without touching the toc, the candidates to keep are passed to `accept_headings`.
+///
+#[tauri::command]
+pub fn preview_detection(
+    window: Window,
+    documents: State<DocumentState>,
+    settings: State<Settings>,
+) -> Result<Vec<Candidate>, CommandError> {
+    documents.with(&window, |x| Ok(x.preview(&settings.detection)?))
+}
+
+///
+/// Replace the toc by one built from accepted headings, returns the new tree.
+///
+#[tauri::command]
+pub fn accept_headings(
+    window: Window,
+    documents: State<DocumentState>,
+    headings: Vec<Heading>,
+) -> Result<JSONRoot, CommandError> {
+    documents.with(&window, |x| {
+        x.accept(&headings)?;
+        Ok(JSONRoot::from(&x.toc))
+    })
+}
+
+#[tauri::command]
+pub fn get_toc(window: Window, documents: State<DocumentState>) -> Result<JSONRoot, CommandError> {
+    documents.with(&window, |x| Ok(JSONRoot::from(&x.toc)))
+}
+
+///
+/// Add a node, returns the added node.
+///
+#[tauri::command]
+pub fn add_toc_node(
+    window: Window,
+    documents: State<DocumentState>,
+    title: String,
+    range: (u128, u128),
+    parent: Option<usize>,
+) -> Result<JSONNode, CommandError> {
+    documents.with(&window, |x| {
+        let id = x.apply_edits(&[TocEdit::Add {
+            title,
+            range,
+            parent,
+        }])?[0];
+        Ok(JSONNode::from_toc_node(&x.toc, x.toc.get(id).unwrap()))
+    })
+}
+
+///
+/// Remove a node and all of its children, returns the updated tree.
+///
+#[tauri::command]
+pub fn remove_toc_node(
+    window: Window,
+    documents: State<DocumentState>,
+    id: usize,
+) -> Result<JSONRoot, CommandError> {
+    edit(&window, &documents, TocEdit::Remove { id })
+}
+
+#[tauri::command]
+pub fn move_toc_node(
+    window: Window,
+    documents: State<DocumentState>,
+    id: usize,
+    to: Position,
+) -> Result<JSONRoot, CommandError> {
+    edit(&window, &documents, TocEdit::Move { id, to })
+}
+
+#[tauri::command]
+pub fn retitle_toc_node(
+    window: Window,
+    documents: State<DocumentState>,
+    id: usize,
+    title: String,
+) -> Result<JSONRoot, CommandError> {
+    edit(&window, &documents, TocEdit::Retitle { id, title })
+}
+
+#[tauri::command]
+pub fn set_toc_node_kind(
+    window: Window,
+    documents: State<DocumentState>,
+    id: usize,
+    kind: NodeKind,
+) -> Result<JSONRoot, CommandError> {
+    edit(&window, &documents, TocEdit::SetKind { id, kind })
+}
+
+///
+/// Apply edits in order, e.g. tags, attributes and notes. Either all of them are applied or, if one fails, none.
+///
+#[tauri::command]
+pub fn apply_toc_edits(
+    window: Window,
+    documents: State<DocumentState>,
+    edits: Vec<TocEdit>,
+) -> Result<JSONRoot, CommandError> {
+    documents.with(&window, |x| {
+        x.apply_edits(&edits)?;
+        Ok(JSONRoot::from(&x.toc))
+    })
+}
+
+///
+/// Ids of the nodes tagged with `tag`, in document order.
+///
+#[tauri::command]
+pub fn get_tagged_nodes(
+    window: Window,
+    documents: State<DocumentState>,
+    tag: String,
+) -> Result<Vec<usize>, CommandError> {
+    documents.with(&window, |x| Ok(x.toc.tagged(&tag).map(|x| x.id).collect()))
+}
+
+///
+/// The text of a node with its patch applied, and the hunks of the patch to highlight.
+///
+#[tauri::command]
+pub fn get_node_text(
+    window: Window,
+    documents: State<DocumentState>,
+    id: usize,
+) -> Result<PatchPreview, CommandError> {
+    documents.with(&window, |x| Ok(x.preview_patch(id)?))
+}
+
+///
+/// Store the edited text of a node, including its heading line, as a patch against the source text.
+///
+#[tauri::command]
+pub fn edit_node_text(
+    window: Window,
+    documents: State<DocumentState>,
+    id: usize,
+    text: String,
+) -> Result<PatchPreview, CommandError> {
+    documents.with(&window, |x| Ok(x.edit_text(id, &text)?))
+}
+
+///
+/// Undo a hunk of the patch of a node, by its index in the preview.
+///
+#[tauri::command]
+pub fn revert_patch_hunk(
+    window: Window,
+    documents: State<DocumentState>,
+    id: usize,
+    index: usize,
+) -> Result<PatchPreview, CommandError> {
+    documents.with(&window, |x| Ok(x.revert_hunk(id, index)?))
+}
+
+///
+/// Find matches of a pattern in the text of the book, or of a subtree, grouped by node.
+///
+#[tauri::command]
+pub fn search_text(
+    window: Window,
+    documents: State<DocumentState>,
+    query: SearchQuery,
+) -> Result<SearchResult, CommandError> {
+    let searcher = Searcher::new(&query)?;
+    documents.with(&window, |x| Ok(searcher.search(x)?))
+}
+
+///
+/// Replace the matches of a pattern, recorded as patches of their nodes.
+///
+#[tauri::command]
+pub fn replace_text(
+    window: Window,
+    documents: State<DocumentState>,
+    query: SearchQuery,
+) -> Result<ReplaceReport, CommandError> {
+    let searcher = Searcher::new(&query)?;
+    documents.with(&window, |x| Ok(searcher.replace(x)?))
+}
+
+///
+/// A node and its subtree by its stable id, which stays valid after other nodes are removed.
+///
+#[tauri::command]
+pub fn get_node_by_uid(
+    window: Window,
+    documents: State<DocumentState>,
+    uid: Uuid,
+) -> Result<JSONNode, CommandError> {
+    documents.with(&window, |x| {
+        let node = x.toc.get_by_uid(uid).ok_or(TocError::UidNotFound(uid))?;
+        Ok(JSONNode::from_toc_node(&x.toc, node))
+    })
+}
+
+///
+/// Link annotation markers to note bodies, and report the ones which can not be linked.
+///
+#[tauri::command]
+pub fn check_notes(
+    window: Window,
+    documents: State<DocumentState>,
+    settings: State<Settings>,
+) -> Result<NoteReport, CommandError> {
+    let annotator = Annotator::new(&settings.notes)?;
+    documents.with(&window, |x| Ok(annotator.check(x)))
+}
+
+///
+/// Resolve image markers against the files next to the source text, and report the ones which can not be resolved.
+///
+#[tauri::command]
+pub fn check_images(
+    window: Window,
+    documents: State<DocumentState>,
+    settings: State<Settings>,
+) -> Result<ImageReport, CommandError> {
+    let resolver = ImageResolver::new(&settings.images)?;
+    documents.with(&window, |x| Ok(resolver.check(x)))
+}
+
+///
+/// Check the toc for numbering gaps, duplicate titles and empty chapters, findings may carry edits to fix them.
+///
+#[tauri::command]
+pub fn lint_toc(
+    window: Window,
+    documents: State<DocumentState>,
+    settings: State<Settings>,
+) -> Result<LintReport, CommandError> {
+    let linter = Linter::new(&settings.lint);
+    documents.with(&window, |x| Ok(linter.lint(x)))
+}
+
+///
+/// Word counts, chapter lengths and reading time of the book, from the toc.
+///
+#[tauri::command]
+pub fn get_book_stats(
+    window: Window,
+    documents: State<DocumentState>,
+    settings: State<Settings>,
+) -> Result<BookStats, CommandError> {
+    documents.with(&window, |x| Ok(BookStats::new(x, &settings.stats)))
+}
+
+///
+/// Check an EPUB file for conformance issues, it does not have to be exported by us.
+///
+#[tauri::command]
+pub async fn check_epub(path: String) -> Result<CheckReport, CommandError> {
+    check_file(&PathBuf::from(path))
+        .map_err(CommandError::from)
+        .inspect_err(|e| tracing::error!("{:?}", e))
+}
+
+///
+/// Save the document as a project file, next to the source text by default. Returns the saved path.
+///
+#[tauri::command]
+pub async fn save_document(
+    window: Window,
+    documents: State<'_, DocumentState>,
+    path: Option<String>,
+) -> Result<String, CommandError> {
+    documents.with(&window, |x| {
+        let path = match (path, &x.source) {
+            (Some(path), _) => PathBuf::from(path),
+            (None, Some(source)) => source.with_extension(PROJECT_EXTENSION),
+            (None, None) => return Err(shared::document::DocumentError::NoSource.into()),
+        };
+        x.save(&path)?;
+        Ok(path.to_string_lossy().to_string())
+    })
+}
+
+fn edit(
+    window: &Window,
+    documents: &DocumentState,
+    edit: TocEdit,
+) -> Result<JSONRoot, CommandError> {
+    documents.with(window, |x| {
+        x.apply_edits(&[edit])?;
+        Ok(JSONRoot::from(&x.toc))
+    })
+}
@@ -0,0 +1,94 @@
+use serde::{ser::SerializeStruct, Serialize};
+use shared::{
+    document::DocumentError, epubcheck::CheckError, images::ImageError, notes::NotesError,
+    search::SearchError, toc::TocError,
+};
+use thiserror::Error;
+
+///
+/// Errors returned to the frontend as `{ kind, message }`, so it could branch on `kind`
+/// instead of matching the message text.
+///
+#[derive(Error, Debug)]
+pub enum CommandError {
+    #[error("no document is opened in this window")]
+    NoDocument,
+
+    #[error(transparent)]
+    Document(DocumentError),
+
+    #[error(transparent)]
+    Toc(#[from] TocError),
+
+    #[error(transparent)]
+    Notes(#[from] NotesError),
+
+    #[error(transparent)]
+    Image(#[from] ImageError),
+
+    #[error(transparent)]
+    Check(#[from] CheckError),
+
+    #[error(transparent)]
+    Search(SearchError),
+}
+
+/// Toc errors of a document change, e.g. edits, keep their own kinds.
+impl From<DocumentError> for CommandError {
+    fn from(e: DocumentError) -> Self {
+        match e {
+            DocumentError::Toc(e) => CommandError::Toc(e),
+            e => CommandError::Document(e),
+        }
+    }
+}
+
+/// Toc and document errors of a search keep their own kinds.
+impl From<SearchError> for CommandError {
+    fn from(e: SearchError) -> Self {
+        match e {
+            SearchError::Toc(e) => CommandError::Toc(e),
+            SearchError::Document(e) => e.into(),
+            e => CommandError::Search(e),
+        }
+    }
+}
+
+impl CommandError {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            CommandError::NoDocument => "no_document",
+            CommandError::Document(DocumentError::Io { .. }) => "io",
+            CommandError::Document(DocumentError::Detect(_)) => "invalid_detection_rule",
+            CommandError::Document(DocumentError::HunkNotFound { .. }) => "hunk_not_found",
+            CommandError::Document(
+                DocumentError::InvalidPatch { .. } | DocumentError::PatchConflict(_),
+            ) => "invalid_patch",
+            CommandError::Document(_) => "invalid_document",
+            CommandError::Toc(
+                TocError::NodeNotFound(_)
+                | TocError::NodeParentNotFound(_)
+                | TocError::UidNotFound(_),
+            ) => "node_not_found",
+            CommandError::Toc(TocError::NoteNotFound { .. }) => "note_not_found",
+            CommandError::Toc(TocError::InvalidMove { .. }) => "invalid_move",
+            CommandError::Toc(_) => "invalid_toc",
+            CommandError::Notes(_) => "invalid_notes_rule",
+            CommandError::Image(_) => "invalid_image_rule",
+            CommandError::Check(CheckError::Io { .. }) => "io",
+            CommandError::Search(_) => "invalid_search",
+        }
+    }
+}
+
+impl Serialize for CommandError {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut state = serializer.serialize_struct("CommandError", 2)?;
+        state.serialize_field("kind", self.kind())?;
+        state.serialize_field("message", &self.to_string())?;
+        state.end()
+    }
+}
@@ -1,8 +1,17 @@
 use crate::macros::wrap_error;
 use serde::{Deserialize, Serialize};
-use shared::types::Port;
+use shared::{
+    detect::Preset,
+    settings::Settings,
+    types::{Port, ServerToken, SettingsError},
+};
 use tauri::AppHandle;
 use tracing::debug;
+
+pub mod document;
+mod error;
+pub mod server;
+
 // Learn more about Tauri commands at https://tauri.app/v1/guides/features/command
 #[tauri::command]
 pub fn greet(name: &str) -> String {
@@ -17,6 +26,40 @@ pub fn get_port(port: tauri::State<Port>) -> u16 {
     port.0
 }
 
+///
+/// This command is used to get the token the backend server requires on every request,
+/// as the `Authorization: Bearer <token>` header, or the `token` query for websocket and links.
+///
+#[tauri::command]
+pub fn get_server_token(token: tauri::State<ServerToken>) -> String {
+    token.0.clone()
+}
+
+///
+/// This command is used to get the effective settings, the same ones the backend server uses.
+///
+#[tauri::command]
+pub fn get_settings(settings: tauri::State<Settings>) -> Settings {
+    settings.inner().clone()
+}
+
+///
+/// This command is used to get why the settings failed to load, if they did. The app runs with the defaults then,
+/// so the frontend should tell the user rather than let their settings be silently ignored.
+///
+#[tauri::command]
+pub fn get_settings_error(error: tauri::State<SettingsError>) -> Option<String> {
+    error.0.clone()
+}
+
+///
+/// This command is used to list the built-in detection presets, which are picked by name in `detection.presets`.
+///
+#[tauri::command]
+pub fn get_detection_presets() -> Vec<Preset> {
+    Preset::all()
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct NotificationParams {
     pub title: String,
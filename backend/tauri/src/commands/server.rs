@@ -0,0 +1,78 @@
+use std::sync::Mutex;
+
+use server::{ServerHandle, ServerStatus};
+use tauri::{AppHandle, Manager, State};
+
+/// Emitted to all windows whenever the status of the backend server changes.
+pub const SERVER_STATUS_EVENT: &str = "server-status";
+
+///
+/// The backend server, `None` if it failed to start or is shut down.
+///
+#[derive(Debug)]
+pub struct ServerState {
+    status: Mutex<ServerStatus>,
+    handle: Mutex<Option<ServerHandle>>,
+}
+
+impl ServerState {
+    pub fn new(status: ServerStatus, handle: Option<ServerHandle>) -> Self {
+        ServerState {
+            status: Mutex::new(status),
+            handle: Mutex::new(handle),
+        }
+    }
+
+    ///
+    /// Emit the current status, e.g. a startup failure, then forward its changes to the frontend until the
+    /// server is gone. Windows loaded later should ask `get_server_status` for the current one.
+    ///
+    pub fn watch(&self, app: AppHandle) {
+        let status = self
+            .status
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        emit(&app, status);
+        let handle = self.handle.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(mut receiver) = handle.as_ref().map(|x| x.status()) else {
+            return;
+        };
+        tauri::async_runtime::spawn(async move {
+            while receiver.changed().await.is_ok() {
+                let status = receiver.borrow_and_update().clone();
+                let state = app.state::<ServerState>();
+                *state.status.lock().unwrap_or_else(|e| e.into_inner()) = status.clone();
+                emit(&app, status);
+            }
+        });
+    }
+
+    /// Stop the server gracefully, it is a no-op if it is not running.
+    pub fn shutdown(&self) {
+        let handle = self.handle.lock().unwrap_or_else(|e| e.into_inner()).take();
+        if let Some(handle) = handle {
+            tauri::async_runtime::block_on(handle.shutdown());
+        }
+    }
+}
+
+fn emit(app: &AppHandle, status: ServerStatus) {
+    tracing::info!("backend server status: {:?}", status);
+    if let Err(e) = app.emit_all(SERVER_STATUS_EVENT, status) {
+        tracing::error!("failed to emit server status: {}", e);
+    }
+}
+
+///
+/// This command is used to get the current status of the backend server,
+/// later changes are emitted as the `server-status` event.
+///
+#[tauri::command]
+pub fn get_server_status(state: State<ServerState>) -> ServerStatus {
+    state
+        .status
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+}
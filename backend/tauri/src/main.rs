@@ -1,7 +1,16 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
-use shared::types::Port;
+use commands::{document::DocumentState, server::ServerState};
+use server::ServerStatus;
+use shared::{
+    settings::Settings,
+    types::{Port, ServerToken, SettingsError},
+};
+use tauri::{
+    utils::config::{AppUrl, WindowUrl},
+    Assets, Context, Manager, RunEvent,
+};
 
 mod commands;
 mod logging;
@@ -19,17 +28,115 @@ fn main() {
     // Everything before here runs in both app and crash reporter processes
     let _guard = sentry_tauri::minidump::init(&client);
     logging::init().expect("failed to initialize logging");
-    let port = portpicker::pick_unused_port().expect("failed to find unused port");
-    tauri::async_runtime::spawn(server::start(port));
+    let (settings, settings_error) = match Settings::load(None) {
+        Ok(settings) => (settings, None),
+        Err(e) => {
+            tracing::error!("failed to load settings, fallback to defaults: {}", e);
+            (Settings::default(), Some(e.to_string()))
+        }
+    };
+    // Port 0 lets the system pick a free one, the bound port is reported back by the handle
+    let port = settings.server.port.unwrap_or(0);
+    let context = tauri::generate_context!();
+    let auth = server::Auth::new(app_origins(&context));
+    let token = ServerToken(auth.token.clone());
+    let (status, handle) =
+        match tauri::async_runtime::block_on(server::start(settings.clone(), port, auth)) {
+            Ok(handle) => (
+                ServerStatus::Ready {
+                    addr: handle.addr(),
+                },
+                Some(handle),
+            ),
+            Err(e) => {
+                tracing::error!("failed to start the backend server: {}", e);
+                (
+                    ServerStatus::Failed {
+                        message: e.to_string(),
+                    },
+                    None,
+                )
+            }
+        };
+    let port = handle.as_ref().map_or(port, |x| x.addr().port());
     // Everything after here runs in only the app process
     tauri::Builder::default()
         .manage(Port(port))
+        .manage(token)
+        .manage(ServerState::new(status, handle))
+        .manage(settings)
+        .manage(SettingsError(settings_error))
+        .manage(DocumentState::default())
         .invoke_handler(tauri::generate_handler![
             commands::greet,
             commands::get_port,
-            commands::send_notification
+            commands::get_server_token,
+            commands::server::get_server_status,
+            commands::get_settings,
+            commands::get_settings_error,
+            commands::get_detection_presets,
+            commands::send_notification,
+            commands::document::open_document,
+            commands::document::open_documents,
+            commands::document::reimport_document,
+            commands::document::preview_detection,
+            commands::document::accept_headings,
+            commands::document::get_toc,
+            commands::document::add_toc_node,
+            commands::document::remove_toc_node,
+            commands::document::move_toc_node,
+            commands::document::retitle_toc_node,
+            commands::document::set_toc_node_kind,
+            commands::document::apply_toc_edits,
+            commands::document::get_tagged_nodes,
+            commands::document::get_node_text,
+            commands::document::edit_node_text,
+            commands::document::revert_patch_hunk,
+            commands::document::search_text,
+            commands::document::replace_text,
+            commands::document::get_node_by_uid,
+            commands::document::check_notes,
+            commands::document::check_images,
+            commands::document::lint_toc,
+            commands::document::get_book_stats,
+            commands::document::check_epub,
+            commands::document::save_document
         ])
+        .setup(|app| {
+            app.state::<ServerState>().watch(app.handle());
+            Ok(())
+        })
+        .on_window_event(|event| {
+            if let tauri::WindowEvent::Destroyed = event.event() {
+                let window = event.window();
+                window.state::<DocumentState>().remove(window.label());
+            }
+        })
         .plugin(sentry_tauri::plugin())
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(context)
+        .expect("error while building tauri application")
+        .run(|app, event| {
+            if let RunEvent::Exit = event {
+                app.state::<ServerState>().shutdown();
+            }
+        });
+}
+
+///
+/// Origins the webview loads the frontend from, the only ones the server allows in CORS.
+///
+fn app_origins<A: Assets>(context: &Context<A>) -> Vec<String> {
+    // `tauri://localhost` on macOS and Linux, `http(s)://tauri.localhost` on Windows
+    let mut origins = vec![
+        "tauri://localhost".to_string(),
+        "https://tauri.localhost".to_string(),
+        "http://tauri.localhost".to_string(),
+    ];
+    // The dev server, only loaded in debug builds
+    if cfg!(debug_assertions) {
+        if let AppUrl::Url(WindowUrl::External(url)) = &context.config().build.dev_path {
+            origins.push(url.origin().ascii_serialization());
+        }
+    }
+    origins
 }
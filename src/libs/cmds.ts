@@ -9,3 +9,411 @@ export type SendNotificationParams = {
 export function sendNotification(params: SendNotificationParams) {
   return invoke<void>('send_notification', { params })
 }
+
+export function getPort() {
+  return invoke<number>('get_port')
+}
+
+/**
+ * The secret of the embedded server, send it as `Authorization: Bearer <token>`,
+ * or as the `token` query for websocket and links.
+ */
+export function getServerToken() {
+  return invoke<string>('get_server_token')
+}
+
+/** Why the settings failed to load, null if they loaded. The app runs with the defaults otherwise. */
+export function getSettingsError() {
+  return invoke<string | null>('get_settings_error')
+}
+
+export type ServerStatus =
+  | { state: 'stopped' }
+  | { state: 'ready'; addr: string }
+  | { state: 'failed'; message: string }
+
+/** Emitted once the server started or failed to, then whenever the status changes. */
+export const SERVER_STATUS_EVENT = 'server-status'
+
+export function getServerStatus() {
+  return invoke<ServerStatus>('get_server_status')
+}
+
+export type Note = {
+  text: string
+  resolved: boolean
+}
+
+export type TreeNodeMeta = {
+  words: number
+  range: [number, number]
+  tags?: string[]
+  // well-known keys: `file_name`, `class`, `status`
+  attributes?: Record<string, string>
+  notes?: Note[]
+  file?: number // source file of combined documents, 0 if missing
+}
+
+export type NodeKind = 'volume' | 'chapter' | 'front' | 'back' | 'hidden'
+
+export type JSONNode = {
+  id: number // reused after the node is removed
+  uid: string // stable, for references kept outside of the toc
+  title: string
+  kind: NodeKind
+  patch: string | null
+  meta: TreeNodeMeta
+  children: JSONNode[]
+}
+
+export type JSONRoot = JSONNode[]
+
+export type Position =
+  | { type: 'up' | 'down' | 'left' | 'right' }
+  | { type: 'before' | 'after'; target: number }
+  | { type: 'belong_to'; parent: number }
+
+export type CommandError = {
+  kind:
+    | 'no_document'
+    | 'io'
+    | 'invalid_detection_rule'
+    | 'invalid_document'
+    | 'node_not_found'
+    | 'note_not_found'
+    | 'invalid_move'
+    | 'invalid_toc'
+    | 'hunk_not_found'
+    | 'invalid_patch' // the patch is malformed, or no longer fits the text
+    | 'invalid_search'
+    | 'invalid_notes_rule'
+    | 'invalid_image_rule'
+  message: string
+}
+
+export function openDocument(path: string) {
+  return invoke<JSONRoot>('open_document', { path })
+}
+
+export function openDocuments(paths: string[]) {
+  return invoke<JSONRoot>('open_documents', { paths })
+}
+
+export type ReimportReport = {
+  matched: number
+  changed: { node: number; title: string; similarity: number; patched: boolean }[] // changed upstream
+  added: number[] // ids of the nodes of new chapters
+  missing: { node: number; title: string; words: number }[] // kept with an empty range, to be removed by hand
+}
+
+/**
+ * Replace the text by a newer version of the source file, keeping the edited toc,
+ * new chapters are added. Fetch the toc again with `getToc` afterwards.
+ */
+export function reimportDocument(path: string) {
+  return invoke<ReimportReport>('reimport_document', { path })
+}
+
+export type Heading = {
+  title: string
+  level: number
+  kind: NodeKind
+  rule: number // index of the matched rule
+  line: number // 1-based
+  offset: number // byte offset of the line start
+}
+
+export type Candidate = Heading & {
+  score: number // 0 to 1
+  signals: {
+    line: number
+    blank_lines: number
+    numbering: number
+    gap: number
+  }
+  accepted: boolean // the score reaches `detection.min_score`
+}
+
+export type HeadingRule = {
+  pattern: string
+  level: number // 0 is the top level of the toc
+  kind?: NodeKind
+}
+
+export type Preset = {
+  name: string // picked in `detection.presets`
+  description: string
+  rules: HeadingRule[]
+  kinds: { pattern: string; kind: NodeKind }[]
+}
+
+export function getDetectionPresets() {
+  return invoke<Preset[]>('get_detection_presets')
+}
+
+/** Detect and score headings without touching the toc. */
+export function previewDetection() {
+  return invoke<Candidate[]>('preview_detection')
+}
+
+/** Replace the toc by one built from the kept candidates, which may be edited. */
+export function acceptHeadings(headings: Heading[]) {
+  return invoke<JSONRoot>('accept_headings', { headings })
+}
+
+export function getToc() {
+  return invoke<JSONRoot>('get_toc')
+}
+
+export function addTocNode(
+  title: string,
+  range: [number, number],
+  parent?: number
+) {
+  return invoke<JSONNode>('add_toc_node', { title, range, parent })
+}
+
+export function removeTocNode(id: number) {
+  return invoke<JSONRoot>('remove_toc_node', { id })
+}
+
+export function moveTocNode(id: number, to: Position) {
+  return invoke<JSONRoot>('move_toc_node', { id, to })
+}
+
+export function retitleTocNode(id: number, title: string) {
+  return invoke<JSONRoot>('retitle_toc_node', { id, title })
+}
+
+export function setTocNodeKind(id: number, kind: NodeKind) {
+  return invoke<JSONRoot>('set_toc_node_kind', { id, kind })
+}
+
+export type TocEdit =
+  | { op: 'add'; title: string; range: [number, number]; parent: number | null }
+  | { op: 'remove'; id: number }
+  | { op: 'retitle'; id: number; title: string }
+  | { op: 'set_kind'; id: number; kind: NodeKind }
+  | { op: 'tag' | 'untag'; id: number; tag: string }
+  | { op: 'set_attribute'; id: number; key: string; value: string | null }
+  | { op: 'add_note'; id: number; text: string }
+  | { op: 'resolve_note' | 'remove_note'; id: number; index: number }
+  | { op: 'move'; id: number; to: Position }
+  | { op: 'merge'; id: number } // into the node before it in document order
+
+export function applyTocEdits(edits: TocEdit[]) {
+  return invoke<JSONRoot>('apply_toc_edits', { edits })
+}
+
+export type DiffLine = {
+  kind: 'context' | 'insert' | 'delete'
+  text: string // with its line break
+}
+
+export type DiffHunk = {
+  old_start: number // 1-based lines of the original text
+  old_lines: number
+  new_start: number // 1-based lines of the patched text
+  new_lines: number
+  lines: DiffLine[]
+}
+
+export type PatchPreview = {
+  node: number
+  text: string // patched, including the heading line
+  hunks: DiffHunk[] // empty without a patch
+}
+
+export function getNodeText(id: number) {
+  return invoke<PatchPreview>('get_node_text', { id })
+}
+
+/** Store the whole edited text of a node, including its heading line, as a patch. */
+export function editNodeText(id: number, text: string) {
+  return invoke<PatchPreview>('edit_node_text', { id, text })
+}
+
+/** Undo a hunk of the patch, by its index in `PatchPreview.hunks`. */
+export function revertPatchHunk(id: number, index: number) {
+  return invoke<PatchPreview>('revert_patch_hunk', { id, index })
+}
+
+export type SearchQuery = {
+  pattern: string
+  regex?: boolean
+  case_insensitive?: boolean
+  width_insensitive?: boolean
+  scope?: number | null // a node whose subtree is searched
+  replacement?: string | null // supports `$1` style capture references
+}
+
+export type Match = {
+  start: number // byte offsets into the patched text of the node
+  end: number
+  line: number
+  before: string
+  text: string
+  after: string
+  replacement: string | null
+}
+
+export type SearchResult = {
+  matches: number
+  nodes: { node: number; title: string; matches: Match[] }[]
+}
+
+export type ReplaceReport = {
+  nodes: number[]
+  replacements: number
+}
+
+export function searchText(query: SearchQuery) {
+  return invoke<SearchResult>('search_text', { query })
+}
+
+/** Replacements are stored as patches, each of them can be reverted as a hunk. */
+export function replaceText(query: SearchQuery) {
+  return invoke<ReplaceReport>('replace_text', { query })
+}
+
+export function getNodeByUid(uid: string) {
+  return invoke<JSONNode>('get_node_by_uid', { uid })
+}
+
+export function getTaggedNodes(tag: string) {
+  return invoke<number[]>('get_tagged_nodes', { tag })
+}
+
+export type Unmatched = {
+  kind: 'marker' | 'note'
+  label: string
+  node: number | null
+  line: number
+  offset: number
+}
+
+export type NoteReport = {
+  linked: number
+  unmatched: Unmatched[]
+}
+
+export function checkNotes() {
+  return invoke<NoteReport>('check_notes')
+}
+
+export type Image = {
+  name: string
+  path: string
+  href: string
+  mime: string
+}
+
+export type ImageProblem =
+  | { type: 'missing' | 'invalid_name' }
+  | { type: 'unsupported' | 'invalid'; mime: string }
+
+export type ImageIssue = {
+  name: string
+  problem: ImageProblem
+  node: number | null
+  line: number
+  offset: number
+}
+
+export type ImageReport = {
+  found: Image[]
+  issues: ImageIssue[]
+}
+
+export function checkImages() {
+  return invoke<ImageReport>('check_images')
+}
+
+export type LintRule =
+  | 'number_jump'
+  | 'number_backwards'
+  | 'missing_numbers'
+  | 'duplicate_title'
+  | 'repeated_heading'
+  | 'empty_chapter'
+  | 'short_chapter'
+
+export type Finding = {
+  node: number
+  rule: LintRule
+  severity: 'warning' | 'info'
+  message: string
+  fix: { description: string; edits: TocEdit[] } | null // apply with `applyTocEdits`
+}
+
+export type LintReport = {
+  nodes: number
+  findings: Finding[]
+}
+
+export function lintToc() {
+  return invoke<LintReport>('lint_toc')
+}
+
+export type ChapterStats = {
+  node: number
+  title: string
+  depth: number
+  words: number
+  line: number // 1-based, of the heading
+}
+
+export type BookStats = {
+  words: number
+  hidden_words: number // left out of the book and its reading time
+  reading_minutes: number
+  nodes: number
+  chapters: number // leaves of the toc which are not hidden
+  levels: { depth: number; nodes: number; words: number }[]
+  distribution: {
+    min: number
+    max: number
+    mean: number
+    median: number
+    std_dev: number
+    buckets: { from: number; to: number; chapters: number }[]
+  }
+  outliers: (ChapterStats & { ratio: number })[] // words relative to the median
+  longest: ChapterStats[]
+  shortest: ChapterStats[]
+}
+
+export function getBookStats() {
+  return invoke<BookStats>('get_book_stats')
+}
+
+export type CheckIssue = {
+  severity: 'error' | 'warning'
+  category:
+    | 'archive'
+    | 'mimetype'
+    | 'container'
+    | 'package'
+    | 'manifest'
+    | 'spine'
+    | 'nav'
+    | 'ncx'
+    | 'xhtml'
+    | 'id'
+  file: string | null // path in the archive
+  message: string
+}
+
+export type CheckReport = {
+  files: number
+  issues: CheckIssue[]
+}
+
+/** Check any EPUB file, exported books are checked automatically. */
+export function checkEpub(path: string) {
+  return invoke<CheckReport>('check_epub', { path })
+}
+
+export function saveDocument(path?: string) {
+  return invoke<string>('save_document', { path })
+}
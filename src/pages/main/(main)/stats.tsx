@@ -0,0 +1,171 @@
+import {
+  Card,
+  CardContent,
+  CardDescription,
+  CardHeader,
+  CardTitle
+} from '@/components/ui/card'
+import {
+  Table,
+  TableBody,
+  TableCell,
+  TableHead,
+  TableHeader,
+  TableRow
+} from '@/components/ui/table'
+import { getBookStats, type BookStats, type ChapterStats } from '@/libs/cmds'
+import { useEffect, useState } from 'react'
+
+function formatMinutes(minutes: number) {
+  const hours = Math.floor(minutes / 60)
+  return hours > 0 ? `${hours} h ${minutes % 60} min` : `${minutes} min`
+}
+
+function ChapterTable({
+  chapters,
+  ratio
+}: {
+  chapters: (ChapterStats & { ratio?: number })[]
+  ratio?: boolean
+}) {
+  return (
+    <Table>
+      <TableHeader>
+        <TableRow>
+          <TableHead>Chapter</TableHead>
+          <TableHead className="text-right">Line</TableHead>
+          <TableHead className="text-right">Words</TableHead>
+          {ratio && <TableHead className="text-right">× median</TableHead>}
+        </TableRow>
+      </TableHeader>
+      <TableBody>
+        {chapters.map((x) => (
+          <TableRow key={x.node}>
+            <TableCell>{x.title || '(untitled)'}</TableCell>
+            <TableCell className="text-right">{x.line}</TableCell>
+            <TableCell className="text-right">
+              {x.words.toLocaleString()}
+            </TableCell>
+            {ratio && (
+              <TableCell className="text-right">
+                {x.ratio?.toFixed(1)}
+              </TableCell>
+            )}
+          </TableRow>
+        ))}
+      </TableBody>
+    </Table>
+  )
+}
+
+export default function StatsPage() {
+  const [stats, setStats] = useState<BookStats | null>(null)
+  const [error, setError] = useState<string | null>(null)
+
+  useEffect(() => {
+    getBookStats()
+      .then(setStats)
+      .catch((e) => setError(e.message ?? String(e)))
+  }, [])
+
+  if (error) {
+    return <div className="p-6 text-destructive">{error}</div>
+  }
+  if (!stats) {
+    return null
+  }
+
+  const { distribution } = stats
+  const largest = Math.max(1, ...distribution.buckets.map((x) => x.chapters))
+  return (
+    <div className="grid gap-4 p-6 md:grid-cols-2">
+      <Card>
+        <CardHeader>
+          <CardTitle>{stats.words.toLocaleString()} words</CardTitle>
+          <CardDescription>
+            {stats.chapters} chapters in {stats.nodes} nodes, about{' '}
+            {formatMinutes(stats.reading_minutes)} to read
+            {stats.hidden_words > 0 &&
+              `, ${stats.hidden_words.toLocaleString()} words hidden`}
+          </CardDescription>
+        </CardHeader>
+        <CardContent>
+          <Table>
+            <TableHeader>
+              <TableRow>
+                <TableHead>Level</TableHead>
+                <TableHead className="text-right">Nodes</TableHead>
+                <TableHead className="text-right">Words</TableHead>
+              </TableRow>
+            </TableHeader>
+            <TableBody>
+              {stats.levels.map((x) => (
+                <TableRow key={x.depth}>
+                  <TableCell>{x.depth + 1}</TableCell>
+                  <TableCell className="text-right">{x.nodes}</TableCell>
+                  <TableCell className="text-right">
+                    {x.words.toLocaleString()}
+                  </TableCell>
+                </TableRow>
+              ))}
+            </TableBody>
+          </Table>
+        </CardContent>
+      </Card>
+
+      <Card>
+        <CardHeader>
+          <CardTitle>Chapter length</CardTitle>
+          <CardDescription>
+            Median {distribution.median.toLocaleString()}, mean{' '}
+            {Math.round(distribution.mean).toLocaleString()}, standard deviation{' '}
+            {Math.round(distribution.std_dev).toLocaleString()} words
+          </CardDescription>
+        </CardHeader>
+        <CardContent className="flex h-48 items-end gap-1">
+          {distribution.buckets.map((x) => (
+            <div
+              key={x.from}
+              className="flex-1 rounded-t bg-primary"
+              style={{ height: `${(x.chapters / largest) * 100}%` }}
+              title={`${x.from.toLocaleString()} – ${x.to.toLocaleString()} words: ${x.chapters} chapters`}
+            />
+          ))}
+        </CardContent>
+      </Card>
+
+      {stats.outliers.length > 0 && (
+        <Card className="md:col-span-2">
+          <CardHeader>
+            <CardTitle>Outliers</CardTitle>
+            <CardDescription>
+              Far longer or shorter than the median chapter, a long one usually
+              hides a missed heading.
+            </CardDescription>
+          </CardHeader>
+          <CardContent>
+            <ChapterTable chapters={stats.outliers} ratio />
+          </CardContent>
+        </Card>
+      )}
+
+      <Card>
+        <CardHeader>
+          <CardTitle>Longest chapters</CardTitle>
+        </CardHeader>
+        <CardContent>
+          <ChapterTable chapters={stats.longest} />
+        </CardContent>
+      </Card>
+
+      <Card>
+        <CardHeader>
+          <CardTitle>Shortest chapters</CardTitle>
+        </CardHeader>
+        <CardContent>
+          <ChapterTable chapters={stats.shortest} />
+        </CardContent>
+      </Card>
+    </div>
+  )
+}